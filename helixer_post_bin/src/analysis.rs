@@ -1,26 +1,289 @@
-use crate::analysis::extractor::{BasePredictionExtractor, ComparisonExtractor};
-use crate::analysis::gff_conv::hmm_solution_to_gff;
-use crate::analysis::hmm::{HmmStateRegion, PredictionHmm};
+use crate::analysis::extractor::{
+    BasePredictionExtractor, ComparisonExtractor, ComparisonIterator,
+};
+use crate::analysis::fasta_conv::{hmm_solution_to_fasta, TranscriptFastaWriter};
+use crate::analysis::genetic_code::GeneticCode;
+use crate::analysis::gff_conv::{hmm_solution_to_gff, WindowContext};
+use crate::analysis::hints::Hints;
+use crate::analysis::hmm::{HmmConfig, HmmTranscript, PredictionHmm};
+use crate::analysis::progress::ProgressReporter;
 use crate::analysis::rater::{SequenceRater, SequenceRating};
+use crate::analysis::report::{ReportLevel, ReportStrand, StatsReport};
 use crate::analysis::window::BasePredictionWindowThresholdIterator;
+use crate::bed::{BedWriter, RegionBedWriter};
 use crate::gff::GffWriter;
-use crate::results::conv::{ArrayConvInto, ClassPrediction, PhasePrediction};
-use crate::results::{Sequence, Species};
+use crate::results::conv::{ArrayConvInto, Bases, ClassPrediction, PhasePrediction};
+use crate::results::{DataLocation, Error, ErrorKind, Result, Sequence, Species};
+use crate::{debug, info, warn};
+use std::collections::{BTreeMap, VecDeque};
 use std::io::Write;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
 
+pub mod evaluation;
 pub mod extractor;
+pub mod fasta_conv;
+pub mod gene_model;
+pub mod genetic_code;
 pub mod gff_conv;
+pub mod hints;
 pub mod hmm;
+pub mod progress;
 pub mod rater;
+pub mod reference;
+pub mod report;
+#[cfg(test)]
+mod test_window;
+pub mod tracks;
+pub mod trainer;
 pub mod window;
 
+/// Window and candidate region settings, shared by annotation and training
+#[derive(Clone, Copy)]
+pub struct RegionSettings {
+    pub window_size: usize,
+    pub edge_threshold: f32,
+    pub peak_threshold: f32,
+    pub min_coding_length: usize,
+    pub threads: usize,
+}
+
+/// Outputs written as each sequence is annotated. The GFF writer may differ between species, the
+/// others are shared by all species
+pub struct AnnotationOutputs<'o, W: Write, F: Write, B: Write> {
+    pub gff_writer: &'o mut GffWriter<W>,
+    pub fasta_writer: &'o mut TranscriptFastaWriter<F>,
+    pub bed_writer: &'o mut Option<BedWriter<B>>,
+    pub region_writer: &'o mut Option<RegionBedWriter<B>>,
+    pub stats_report: &'o mut Option<StatsReport>,
+}
+
+// The strand of a sequence being annotated
+#[derive(Clone, Copy)]
+struct SequenceStrand<'s> {
+    species: &'s Species,
+    seq: &'s Sequence,
+    rev: bool,
+}
+
 pub struct Analyzer<'a, TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>> {
     bp_extractor: BasePredictionExtractor<'a, TC, TP>,
     comp_extractor: ComparisonExtractor<'a>,
-    window_size: usize,
-    edge_threshold: f32,
-    peak_threshold: f32,
-    min_coding_length: usize,
+    settings: RegionSettings,
+    hmm_config: HmmConfig,
+    hints: Hints,
+    skip_failed_windows: bool,
+    skipped_count: AtomicUsize,
+}
+
+// Windows queued per thread before waiting for the oldest to be written, to keep threads busy when window sizes vary
+const WINDOWS_PER_THREAD: usize = 4;
+
+type WindowBases = Vec<(Bases, ClassPrediction, PhasePrediction)>;
+
+type WindowSolution = Option<Vec<Vec<HmmTranscript>>>;
+
+// A window to solve, with its context within the sequence
+struct WindowJob {
+    bp_vec: WindowBases,
+    at_sequence_start: bool,
+    at_sequence_end: bool,
+    hint_flags: Vec<u8>,
+}
+
+fn solve_window(
+    window: WindowJob,
+    hmm_config: &HmmConfig,
+    genetic_code: &GeneticCode,
+) -> WindowSolution {
+    let mut hmm = PredictionHmm::new(window.bp_vec, hmm_config, genetic_code);
+    hmm.set_sequence_bounds(window.at_sequence_start, window.at_sequence_end);
+    hmm.set_hint_flags(window.hint_flags);
+    hmm.solve().map(|solution| solution.trace_genes())
+}
+
+// Solve each window on one of the given number of threads, returning the solutions in window order
+fn solve_windows(
+    windows: Vec<WindowJob>,
+    threads: usize,
+    hmm_config: &HmmConfig,
+    genetic_code: &GeneticCode,
+) -> Vec<WindowSolution> {
+    if threads <= 1 || windows.len() <= 1 {
+        return windows
+            .into_iter()
+            .map(|window| solve_window(window, hmm_config, genetic_code))
+            .collect();
+    }
+
+    let jobs: Vec<Mutex<Option<WindowJob>>> =
+        windows.into_iter().map(|w| Mutex::new(Some(w))).collect();
+    let solutions: Vec<Mutex<WindowSolution>> = jobs.iter().map(|_| Mutex::new(None)).collect();
+    let next_job = AtomicUsize::new(0);
+
+    std::thread::scope(|scope| {
+        for _ in 0..std::cmp::min(threads, jobs.len()) {
+            scope.spawn(|| loop {
+                let idx = next_job.fetch_add(1, Ordering::Relaxed);
+                if idx >= jobs.len() {
+                    break;
+                }
+
+                let window = jobs[idx].lock().unwrap().take().unwrap();
+                *solutions[idx].lock().unwrap() = solve_window(window, hmm_config, genetic_code);
+            });
+        }
+    });
+
+    solutions
+        .into_iter()
+        .map(|solution| solution.into_inner().unwrap())
+        .collect()
+}
+
+// Windows waiting to be solved, handed out to the worker threads as they're pushed. Solutions come back in whatever
+// order the windows finish, so they're held until every window pushed before them has been taken. With one thread,
+// windows are solved as they're pushed
+struct WindowQueue<'q> {
+    hmm_config: &'q HmmConfig,
+    job_sender: Option<Sender<(usize, WindowJob, &'q GeneticCode)>>,
+    solution_receiver: Option<Receiver<(usize, std::thread::Result<WindowSolution>)>>,
+    solutions: BTreeMap<usize, std::thread::Result<WindowSolution>>,
+    pushed_count: usize,
+    taken_count: usize,
+}
+
+impl<'q> WindowQueue<'q> {
+    // Queues a window, to be solved with the genetic code of its sequence
+    fn push(&mut self, window: WindowJob, genetic_code: &'q GeneticCode) {
+        let idx = self.pushed_count;
+        self.pushed_count += 1;
+
+        match self.job_sender.as_ref() {
+            Some(job_sender) => job_sender
+                .send((idx, window, genetic_code))
+                .expect("window solver threads stopped"),
+            None => {
+                let solution = solve_window(window, self.hmm_config, genetic_code);
+                self.solutions.insert(idx, Ok(solution));
+            }
+        }
+    }
+
+    // Windows pushed whose solutions haven't been taken yet
+    fn get_pending_count(&self) -> usize {
+        self.pushed_count - self.taken_count
+    }
+
+    // Takes the solution of the oldest pending window, or None if it isn't solved yet and wait isn't set. A window
+    // which panicked while being solved panics here, on the thread writing the output
+    fn take_next(&mut self, wait: bool) -> Option<WindowSolution> {
+        let idx = self.taken_count;
+        debug_assert!(idx < self.pushed_count);
+
+        while !self.solutions.contains_key(&idx) {
+            let solution_receiver = self.solution_receiver.as_ref()?;
+            let (solved_idx, solution) = if wait {
+                solution_receiver
+                    .recv()
+                    .expect("window solver threads stopped")
+            } else {
+                match solution_receiver.try_recv() {
+                    Ok(solved) => solved,
+                    Err(_) => return None,
+                }
+            };
+            self.solutions.insert(solved_idx, solution);
+        }
+
+        self.taken_count += 1;
+        match self.solutions.remove(&idx).unwrap() {
+            Ok(solution) => Some(solution),
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+// Runs f with a window queue solving on the given number of threads. The threads stop once f returns, dropping the
+// queue, or unwinds
+fn with_window_queue<'q, T>(
+    threads: usize,
+    hmm_config: &'q HmmConfig,
+    f: impl FnOnce(&mut WindowQueue<'q>) -> T,
+) -> T {
+    let new_queue = |job_sender, solution_receiver| WindowQueue {
+        hmm_config,
+        job_sender,
+        solution_receiver,
+        solutions: BTreeMap::new(),
+        pushed_count: 0,
+        taken_count: 0,
+    };
+
+    if threads <= 1 {
+        return f(&mut new_queue(None, None));
+    }
+
+    let (job_sender, job_receiver) = mpsc::channel::<(usize, WindowJob, &'q GeneticCode)>();
+    let (solution_sender, solution_receiver) = mpsc::channel();
+    let job_receiver = Mutex::new(job_receiver);
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            let job_receiver = &job_receiver;
+            let solution_sender = solution_sender.clone();
+            scope.spawn(move || loop {
+                // The lock is only held while waiting for a window, not while solving it
+                let job = job_receiver.lock().unwrap().recv();
+                let (idx, window, genetic_code) = match job {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+
+                let solution = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                    solve_window(window, hmm_config, genetic_code)
+                }));
+                if solution_sender.send((idx, solution)).is_err() {
+                    break;
+                }
+            });
+        }
+
+        // The queue is dropped before the threads are joined, so they see that no more windows are coming
+        let mut queue = new_queue(Some(job_sender), Some(solution_receiver));
+        f(&mut queue)
+    })
+}
+
+// A queued window, with what's needed to rate and write it once solved
+struct QueuedWindow {
+    start_pos: usize,
+    end_pos: usize,
+    peak: f32,
+    letters: Option<Vec<u8>>, // Only kept to write sequences
+}
+
+// A strand whose windows are queued, written once the strands before it have been
+struct QueuedStrand<'s, 'c> {
+    strand: SequenceStrand<'s>,
+    rater: SequenceRater<ComparisonIterator<'c>>,
+    windows: VecDeque<QueuedWindow>,
+    read_all: bool,
+    read_error: Option<Error>, // Reported when the strand is written, after the windows read before it
+    started: bool,
+    first_gene_idx: usize,
+    window_count: usize,
+}
+
+// The strands of the sequences being annotated which haven't been written yet, and what's been written so far
+struct WriteState<'s, 'c, 'r> {
+    strands: VecDeque<QueuedStrand<'s, 'c>>,
+    gene_idx: usize,
+    fwd_seq_rating: Option<SequenceRating>,
+    fwd_rating: &'r mut SequenceRating,
+    rev_rating: &'r mut SequenceRating,
 }
 
 impl<'a, TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>>
@@ -29,18 +292,22 @@ impl<'a, TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>>
     pub fn new(
         bp_extractor: BasePredictionExtractor<'a, TC, TP>,
         comp_extractor: ComparisonExtractor<'a>,
-        window_size: usize,
-        edge_threshold: f32,
-        peak_threshold: f32,
-        min_coding_length: usize,
+        settings: RegionSettings,
+        hmm_config: HmmConfig,
+        hints: Hints,
+        skip_failed_windows: bool,
     ) -> Analyzer<'a, TC, TP> {
         Analyzer {
             bp_extractor,
             comp_extractor,
-            window_size,
-            edge_threshold,
-            peak_threshold,
-            min_coding_length,
+            settings: RegionSettings {
+                threads: std::cmp::max(settings.threads, 1),
+                ..settings
+            },
+            hmm_config,
+            hints,
+            skip_failed_windows,
+            skipped_count: AtomicUsize::new(0),
         }
     }
 
@@ -48,153 +315,542 @@ impl<'a, TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>>
         self.comp_extractor.has_ref()
     }
 
-    fn process_sequence_1d<W: Write>(
+    /// Number of windows without an HMM solution, and strands cut short by unreadable data, which were skipped
+    pub fn get_skipped_count(&self) -> usize {
+        self.skipped_count.load(Ordering::Relaxed)
+    }
+
+    // If failed windows are skipped, logs the error and what is skipped, otherwise returns it. Output errors are never
+    // skipped
+    fn skip_or_fail(&self, error: Error, skipped: &str) -> Result<()> {
+        if self.skip_failed_windows {
+            warn!("{} - skipping {}", error, skipped);
+            self.skipped_count.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    fn add_rejected_regions<B: Write>(
         &self,
-        species: &Species,
         seq: &Sequence,
         rev: bool,
-        bp_iter: BasePredictionWindowThresholdIterator<TC, TP>,
+        rejected: Vec<(usize, usize, f32)>,
+        region_writer: &mut Option<RegionBedWriter<B>>,
+    ) {
+        if let Some(region_writer) = region_writer.as_mut() {
+            for (start_pos, end_pos, peak) in rejected {
+                region_writer.add_region(
+                    seq.get_name(),
+                    rev,
+                    seq.get_length(),
+                    start_pos..end_pos,
+                    peak,
+                    None,
+                );
+            }
+        }
+    }
+
+    // Places the hints on a window read from the strand, and keeps its letters if sequences are written
+    fn prepare_window(
+        &self,
+        strand: SequenceStrand,
+        bp_vec: WindowBases,
+        start_pos: usize,
+        peak: f32,
+        fasta_enabled: bool,
+    ) -> (WindowJob, QueuedWindow) {
+        let SequenceStrand { seq, rev, .. } = strand;
+        let end_pos = start_pos + bp_vec.len();
+        let has_hints = self.hints.has_sequence(seq.get_name());
+
+        // Base letters are only needed to place hints and write sequences
+        let letters = if has_hints || fasta_enabled {
+            bp_vec
+                .iter()
+                .map(|(bases, _, _)| bases.get_letter())
+                .collect::<Vec<_>>()
+        } else {
+            Vec::new()
+        };
+        let hint_flags = if has_hints {
+            self.hints
+                .get_window_flags(seq.get_name(), rev, seq.get_length(), start_pos, &letters)
+        } else {
+            Vec::new()
+        };
+
+        let window = WindowJob {
+            bp_vec,
+            at_sequence_start: start_pos == 0,
+            at_sequence_end: end_pos == seq.get_length() as usize,
+            hint_flags,
+        };
+        let queued = QueuedWindow {
+            start_pos,
+            end_pos,
+            peak,
+            letters: if fasta_enabled { Some(letters) } else { None },
+        };
+
+        (window, queued)
+    }
+
+    // Rate and output a solved window
+    fn write_window<W: Write, F: Write, B: Write>(
+        &self,
+        strand: SequenceStrand,
+        window: QueuedWindow,
+        solution: WindowSolution,
         gene_idx: &mut usize,
-        rater: &mut SequenceRater,
-        gff_writer: &mut GffWriter<W>,
-    ) -> (usize, usize) {
-        let mut window_count = 0;
-        let mut window_length_total = 0;
+        rater: &mut SequenceRater<ComparisonIterator>,
+        outputs: &mut AnnotationOutputs<W, F, B>,
+    ) -> Result<()> {
+        let SequenceStrand { species, seq, rev } = strand;
+        let QueuedWindow {
+            start_pos,
+            end_pos,
+            peak,
+            letters,
+        } = window;
 
-        for (bp_vec, _total_vec, start_pos, _peak) in bp_iter {
-            window_count += 1;
-            window_length_total += bp_vec.len();
+        let window_location = || DataLocation::new(seq.get_name(), rev, start_pos);
+        let window = WindowContext {
+            species: species.get_name(),
+            sequence: seq.get_name(),
+            rev,
+            position: start_pos,
+            sequence_length: seq.get_length(),
+            min_coding_length: self.settings.min_coding_length,
+        };
 
-            let end_pos = start_pos + bp_vec.len();
+        let genes = match solution {
+            Some(genes) => genes,
+            None => {
+                let error = Error::new(ErrorKind::HmmFailure(end_pos - start_pos))
+                    .with_location(window_location());
+                return self.skip_or_fail(error, "window");
+            }
+        };
 
-            println!("Solving a window from {} to {} (length: {})", start_pos, end_pos, bp_vec.len());
+        // Only the best path's transcript of each gene is rated
+        for (gene_regions, coding_length) in genes.iter().map(|transcripts| &transcripts[0]) {
+            rater.rate_regions(
+                start_pos,
+                &gene_regions,
+                *coding_length < self.settings.min_coding_length,
+            );
+        }
 
-            let hmm = PredictionHmm::new(bp_vec);
-            let maybe_solution = hmm.solve();
+        if let Some(region_writer) = outputs.region_writer.as_mut() {
+            let gene_count = genes
+                .iter()
+                .filter(|transcripts| transcripts[0].1 >= self.settings.min_coding_length)
+                .count();
+            region_writer.add_region(
+                seq.get_name(),
+                rev,
+                seq.get_length(),
+                start_pos..end_pos,
+                peak,
+                Some(gene_count),
+            );
+        }
 
-            if let Some(solution) = maybe_solution {
-                //solution.dump(start_pos);
-                let solution_regions = solution.trace_regions();
-                let genes = HmmStateRegion::split_genes(solution_regions);
+        if let Some(letters) = letters {
+            let genetic_code = self.hmm_config.get_genetic_code(seq.get_name());
+            let transcript_sequences =
+                hmm_solution_to_fasta(&genes, &letters, genetic_code, &window, *gene_idx);
+            outputs
+                .fasta_writer
+                .write_transcripts(&transcript_sequences)
+                .map_err(|err| Error::new(ErrorKind::Io(err)).with_location(window_location()))?;
+        }
 
-                for (gene_regions, coding_length) in genes.iter() {
-                    rater.rate_regions(
-                        start_pos,
-                        &gene_regions,
-                        *coding_length < self.min_coding_length,
-                    );
+        let gff_records = hmm_solution_to_gff(
+            genes,
+            &window,
+            "Helixer",
+            outputs.gff_writer.get_format(),
+            gene_idx,
+        );
+        outputs
+            .gff_writer
+            .write_records(&gff_records)
+            .map_err(|err| Error::new(ErrorKind::Io(err)).with_location(window_location()))?;
+        if let Some(bed_writer) = outputs.bed_writer.as_mut() {
+            bed_writer.add_gff_records(&gff_records);
+        }
+
+        Ok(())
+    }
+
+    // Rate a strand whose windows have all been written, and report its rating
+    fn finish_strand<W: Write, F: Write, B: Write>(
+        &self,
+        queued: QueuedStrand,
+        state: &mut WriteState,
+        outputs: &mut AnnotationOutputs<W, F, B>,
+        progress: &mut ProgressReporter,
+    ) -> Result<()> {
+        let SequenceStrand { species, seq, rev } = queued.strand;
+
+        if let Some(error) = queued.read_error {
+            self.skip_or_fail(error, "the rest of the strand")?;
+        }
+
+        progress.finish_strand(queued.window_count, state.gene_idx - queued.first_gene_idx);
+
+        let (seq_rating, mut comp_iter) = queued.rater.calculate_stats_with_iterator();
+        if let Some(error) = comp_iter.take_error() {
+            self.skip_or_fail(error, "the rest of the strand's rating")?;
+        }
+        println!(
+            "{} for Sequence {} - ID {}",
+            if rev { "Reverse" } else { "Forward" },
+            seq.get_name(),
+            seq.get_id().inner()
+        );
+        seq_rating.dump(self.comp_extractor.has_ref());
+
+        if !rev {
+            if let Some(stats_report) = outputs.stats_report.as_mut() {
+                stats_report.add_rating(
+                    ReportLevel::Sequence,
+                    Some(species.get_name()),
+                    Some(seq.get_name()),
+                    ReportStrand::Forward,
+                    &seq_rating,
+                );
+            }
+
+            state.fwd_rating.accumulate(&seq_rating);
+            state.fwd_seq_rating = Some(seq_rating);
+            return Ok(());
+        }
+
+        if let Some(stats_report) = outputs.stats_report.as_mut() {
+            let mut both_rating = SequenceRating::new();
+            if let Some(fwd_seq_rating) = state.fwd_seq_rating.take() {
+                both_rating.accumulate(&fwd_seq_rating);
+            }
+            both_rating.accumulate(&seq_rating);
+
+            for (strand, rating) in [
+                (ReportStrand::Reverse, &seq_rating),
+                (ReportStrand::Both, &both_rating),
+            ] {
+                stats_report.add_rating(
+                    ReportLevel::Sequence,
+                    Some(species.get_name()),
+                    Some(seq.get_name()),
+                    strand,
+                    rating,
+                );
+            }
+        }
+
+        state.rev_rating.accumulate(&seq_rating);
+
+        Ok(())
+    }
+
+    // Write the queued windows in order, while they're solved, then finish each strand once all its windows are read
+    // and written. Waits for windows to be solved while more than max_pending are queued
+    fn write_solved_windows<W: Write, F: Write, B: Write>(
+        &self,
+        queue: &mut WindowQueue,
+        state: &mut WriteState,
+        max_pending: usize,
+        outputs: &mut AnnotationOutputs<W, F, B>,
+        progress: &mut ProgressReporter,
+    ) -> Result<()> {
+        while let Some(current) = state.strands.front_mut() {
+            let SequenceStrand { seq, rev, .. } = current.strand;
+
+            if !current.started {
+                // Genes are numbered within each sequence, from the forward strand on
+                if !rev {
+                    outputs
+                        .gff_writer
+                        .write_region_header(seq.get_name(), seq.get_length())
+                        .map_err(|err| {
+                            Error::new(ErrorKind::Io(err)).with_location(DataLocation::new(
+                                seq.get_name(),
+                                rev,
+                                0,
+                            ))
+                        })?;
+                    state.gene_idx = 1;
                 }
+                progress.start_strand(seq, rev);
+                current.first_gene_idx = state.gene_idx;
+                current.started = true;
+            }
 
-                let gff_records = hmm_solution_to_gff(
-                    genes,
-                    species.get_name(),
-                    seq.get_name(),
-                    "Helixer",
-                    rev,
-                    start_pos,
-                    seq.get_length(),
-                    self.min_coding_length,
-                    gene_idx,
+            if !current.windows.is_empty() {
+                let wait = queue.get_pending_count() > max_pending;
+                let solution = match queue.take_next(wait) {
+                    Some(solution) => solution,
+                    None => return Ok(()),
+                };
+                let window = current.windows.pop_front().unwrap();
+                let end_pos = window.end_pos;
+
+                self.write_window(
+                    current.strand,
+                    window,
+                    solution,
+                    &mut state.gene_idx,
+                    &mut current.rater,
+                    outputs,
+                )?;
+
+                current.window_count += 1;
+                progress.update(
+                    end_pos,
+                    current.window_count,
+                    state.gene_idx - current.first_gene_idx,
                 );
-                gff_writer
-                    .write_records(&gff_records)
-                    .expect("Failed to write to GFF");
+            } else if current.read_all {
+                let finished = state.strands.pop_front().unwrap();
+                self.finish_strand(finished, state, outputs, progress)?;
             } else {
-                panic!(
-                    "No solution at {} {} - {}",
-                    seq.get_name(),
-                    start_pos,
-                    end_pos
-                );
+                // The rest of the strand's windows haven't been read yet
+                return Ok(());
             }
         }
 
-        (window_count, window_length_total)
+        Ok(())
     }
 
-    pub fn process_sequence<W: Write>(
+    /// Annotates the sequences of a species, returning the number and total length of the windows solved. Windows
+    /// from all the sequences and both strands are solved in parallel if configured, but rated and written in order,
+    /// so the output is the same whatever the number of threads
+    pub fn process_sequences<W: Write, F: Write, B: Write>(
         &self,
         species: &Species,
-        seq: &Sequence,
+        seqs: &[&Sequence],
         fwd_rating: &mut SequenceRating,
         rev_rating: &mut SequenceRating,
-        gff_writer: &mut GffWriter<W>,
-    ) -> (usize, usize) {
-        let id = seq.get_id();
-        println!(
-            "  BP_Extractor for Sequence {} - ID {}",
-            seq.get_name(),
-            id.inner()
-        );
+        outputs: &mut AnnotationOutputs<W, F, B>,
+        progress: &mut ProgressReporter,
+    ) -> Result<(usize, usize)> {
+        let threads = self.settings.threads;
+        let max_pending = if threads > 1 {
+            threads * WINDOWS_PER_THREAD
+        } else {
+            0
+        };
+        let fasta_enabled = outputs.fasta_writer.is_enabled();
+        let keep_rejected = outputs
+            .region_writer
+            .as_ref()
+            .is_some_and(|writer| writer.get_include_rejected());
 
-        let mut gene_idx = 1;
+        let mut window_count = 0;
+        let mut window_length_total = 0;
 
-        let fwd_bp_iter = BasePredictionWindowThresholdIterator::new(
-            self.bp_extractor.fwd_iterator(id),
-            self.window_size,
-            self.edge_threshold,
-            self.peak_threshold,
-        )
-        .unwrap();
+        with_window_queue(threads, &self.hmm_config, |queue| {
+            let mut state = WriteState {
+                strands: VecDeque::new(),
+                gene_idx: 1,
+                fwd_seq_rating: None,
+                fwd_rating,
+                rev_rating,
+            };
 
-        let mut fwd_comp_rater = SequenceRater::new(
-            self.comp_extractor.fwd_iterator(id),
-            seq.get_length() as usize,
-        );
+            for seq in seqs {
+                let id = seq.get_id();
+                info!(
+                    "  BP_Extractor for Sequence {} - ID {}",
+                    seq.get_name(),
+                    id.inner()
+                );
+                let genetic_code = self.hmm_config.get_genetic_code(seq.get_name());
 
-        let (fwd_window_count, fwd_window_length_total) = self.process_sequence_1d(
-            species,
-            seq,
-            false,
-            fwd_bp_iter,
-            &mut gene_idx,
-            &mut fwd_comp_rater,
-            gff_writer,
-        );
-        let fwd_seq_rating = fwd_comp_rater.calculate_stats();
-        println!(
-            "Forward for Sequence {} - ID {}",
-            seq.get_name(),
-            id.inner()
-        );
-        fwd_seq_rating.dump(self.comp_extractor.has_ref());
-
-        fwd_rating.accumulate(&fwd_seq_rating);
-
-        let rev_bp_iter = BasePredictionWindowThresholdIterator::new(
-            self.bp_extractor.rev_iterator(id),
-            self.window_size,
-            self.edge_threshold,
-            self.peak_threshold,
-        )
-        .unwrap();
-        let mut rev_comp_rater = SequenceRater::new(
-            self.comp_extractor.rev_iterator(id),
-            seq.get_length() as usize,
-        );
+                for rev in [false, true] {
+                    let strand = SequenceStrand { species, seq, rev };
+                    let (bp_iter, comp_iter) = if rev {
+                        (
+                            self.bp_extractor.rev_iterator(id),
+                            self.comp_extractor.rev_iterator(id),
+                        )
+                    } else {
+                        (
+                            self.bp_extractor.fwd_iterator(id),
+                            self.comp_extractor.fwd_iterator(id),
+                        )
+                    };
+                    let mut bp_iter =
+                        BasePredictionWindowThresholdIterator::new(
+                            bp_iter,
+                            self.settings.window_size,
+                            self.settings.edge_threshold,
+                            self.settings.peak_threshold,
+                        )
+                        .ok_or_else(|| {
+                            Error::new(ErrorKind::TruncatedDataset)
+                                .with_location(DataLocation::new(seq.get_name(), rev, 0))
+                        })?;
+                    if keep_rejected {
+                        bp_iter.keep_rejected();
+                    }
 
-        let (rev_window_count, rev_window_length_total) = self.process_sequence_1d(
-            species,
-            seq,
-            true,
-            rev_bp_iter,
-            &mut gene_idx,
-            &mut rev_comp_rater,
-            gff_writer,
-        );
-        let rev_seq_rating = rev_comp_rater.calculate_stats();
-        println!(
-            "Reverse for Sequence {} - ID {}",
-            seq.get_name(),
-            id.inner()
-        );
-        rev_seq_rating.dump(self.comp_extractor.has_ref());
+                    state.strands.push_back(QueuedStrand {
+                        strand,
+                        rater: SequenceRater::new(comp_iter, seq.get_length() as usize),
+                        windows: VecDeque::new(),
+                        read_all: false,
+                        read_error: None,
+                        started: false,
+                        first_gene_idx: 1,
+                        window_count: 0,
+                    });
+
+                    while let Some((bp_vec, _total_vec, start_pos, peak)) = bp_iter.next() {
+                        self.add_rejected_regions(
+                            seq,
+                            rev,
+                            bp_iter.take_rejected(),
+                            outputs.region_writer,
+                        );
 
-        rev_rating.accumulate(&rev_seq_rating);
+                        window_count += 1;
+                        window_length_total += bp_vec.len();
+
+                        debug!(
+                            "Solving a window from {} to {} (length: {})",
+                            start_pos,
+                            start_pos + bp_vec.len(),
+                            bp_vec.len()
+                        );
+
+                        let (window, queued) =
+                            self.prepare_window(strand, bp_vec, start_pos, peak, fasta_enabled);
+                        queue.push(window, genetic_code);
+                        state.strands.back_mut().unwrap().windows.push_back(queued);
+
+                        self.write_solved_windows(
+                            queue,
+                            &mut state,
+                            max_pending,
+                            outputs,
+                            progress,
+                        )?;
+                    }
+
+                    self.add_rejected_regions(
+                        seq,
+                        rev,
+                        bp_iter.take_rejected(),
+                        outputs.region_writer,
+                    );
 
-        (
-            fwd_window_count + rev_window_count,
-            fwd_window_length_total + rev_window_length_total,
-        )
+                    // The windows read before any error are still solved if it's skipped
+                    let read_strand = state.strands.back_mut().unwrap();
+                    read_strand.read_error = bp_iter.take_error();
+                    read_strand.read_all = true;
+                }
+            }
+
+            self.write_solved_windows(queue, &mut state, 0, outputs, progress)
+        })?;
+
+        Ok((window_count, window_length_total))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::genetic_code::GeneticCode;
+    use crate::analysis::hmm::HmmConfig;
+    use crate::analysis::test_window::get_one_hot_window;
+    use crate::analysis::{solve_window, with_window_queue, WindowJob, WindowSolution};
+
+    // Windows with one single exon gene each, of varying lengths so threads finish them out of order
+    fn get_gene_windows() -> Vec<WindowJob> {
+        (0..24)
+            .map(|idx| {
+                let codon_count = if idx % 3 == 0 {
+                    200 + idx * 10
+                } else {
+                    10 + idx
+                };
+                let sequence = [
+                    "CTCCTTCTCCTTCCTCTTCC",
+                    "ATG",
+                    &"GCC".repeat(codon_count),
+                    "TAA",
+                    "CCTTCTCCTTCTCCTTCCTC",
+                ]
+                .concat();
+                let coding_end = 20 + 3 * (codon_count + 2);
+
+                WindowJob {
+                    bp_vec: get_one_hot_window(&sequence, |position| {
+                        if (20..coding_end).contains(&position) {
+                            [0.1, 0.1, 0.7, 0.1]
+                        } else {
+                            [0.7, 0.1, 0.1, 0.1]
+                        }
+                    }),
+                    at_sequence_start: false,
+                    at_sequence_end: false,
+                    hint_flags: Vec::new(),
+                }
+            })
+            .collect()
+    }
+
+    // The bounds of each region of each gene's best transcript
+    fn get_gene_bounds(solution: &WindowSolution) -> Vec<Vec<(usize, usize)>> {
+        solution
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|transcripts| {
+                transcripts[0]
+                    .0
+                    .iter()
+                    .map(|region| (region.get_start_pos(), region.get_end_pos()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn window_queue_keeps_push_order() {
+        let hmm_config = HmmConfig::new();
+        let genetic_code = GeneticCode::from_id(1).unwrap();
+
+        let expected: Vec<_> = get_gene_windows()
+            .into_iter()
+            .map(|window| get_gene_bounds(&solve_window(window, &hmm_config, &genetic_code)))
+            .collect();
+        assert!(expected.iter().all(|genes| genes.len() == 1));
+
+        for threads in [1, 4] {
+            let solved = with_window_queue(threads, &hmm_config, |queue| {
+                let mut solved = Vec::new();
+                for window in get_gene_windows() {
+                    queue.push(window, &genetic_code);
+                    while let Some(solution) = queue.take_next(queue.get_pending_count() > 3) {
+                        solved.push(get_gene_bounds(&solution));
+                        if queue.get_pending_count() == 0 {
+                            break;
+                        }
+                    }
+                }
+                while queue.get_pending_count() > 0 {
+                    solved.push(get_gene_bounds(&queue.take_next(true).unwrap()));
+                }
+                solved
+            });
+
+            assert_eq!(solved, expected, "{} threads", threads);
+        }
     }
 }
@@ -0,0 +1,394 @@
+use crate::analysis::gene_model::TranscriptModel;
+use std::collections::{HashMap, HashSet};
+
+const SINGLE_EXON_MIN_OVERLAP: f64 = 0.8; // Fraction of the longer transcript, as in gffcompare
+
+/// Reference and predicted feature counts at one level, with how many of each were matched
+#[derive(Clone, Copy, Default)]
+pub struct FeatureCounts {
+    ref_total: usize,
+    ref_matched: usize,
+    pred_total: usize,
+    pred_matched: usize,
+}
+
+impl FeatureCounts {
+    pub fn get_ref_total(&self) -> usize {
+        self.ref_total
+    }
+
+    pub fn get_ref_matched(&self) -> usize {
+        self.ref_matched
+    }
+
+    pub fn get_pred_total(&self) -> usize {
+        self.pred_total
+    }
+
+    pub fn get_pred_matched(&self) -> usize {
+        self.pred_matched
+    }
+
+    /// Fraction of the reference features matched by a prediction
+    pub fn get_sensitivity(&self) -> f64 {
+        self.ref_matched as f64 / self.ref_total as f64
+    }
+
+    /// Fraction of the predicted features matching the reference
+    pub fn get_precision(&self) -> f64 {
+        self.pred_matched as f64 / self.pred_total as f64
+    }
+
+    pub fn get_f1(&self) -> f64 {
+        let sensitivity = self.get_sensitivity();
+        let precision = self.get_precision();
+        2.0 * sensitivity * precision / (sensitivity + precision)
+    }
+
+    fn from_sets<T: Eq + std::hash::Hash>(
+        ref_set: &HashSet<T>,
+        pred_set: &HashSet<T>,
+    ) -> FeatureCounts {
+        let matched = ref_set.intersection(pred_set).count();
+        FeatureCounts {
+            ref_total: ref_set.len(),
+            ref_matched: matched,
+            pred_total: pred_set.len(),
+            pred_matched: matched,
+        }
+    }
+}
+
+// Sequence and strand, which all matched features share
+type Location<'a> = (&'a str, &'a str);
+
+fn get_location(model: &TranscriptModel) -> Location<'_> {
+    (model.get_sequence(), model.get_strand().as_str())
+}
+
+// Unique exons, introns, start and stop codons of a set of transcripts
+fn exon_set(models: &[TranscriptModel]) -> HashSet<(Location<'_>, u64, u64)> {
+    models
+        .iter()
+        .flat_map(|model| {
+            model
+                .get_exons()
+                .iter()
+                .map(move |(start, end)| (get_location(model), *start, *end))
+        })
+        .collect()
+}
+
+fn intron_set(models: &[TranscriptModel]) -> HashSet<(Location<'_>, u64, u64)> {
+    models
+        .iter()
+        .flat_map(|model| {
+            model
+                .get_introns()
+                .into_iter()
+                .map(move |(start, end)| (get_location(model), start, end))
+        })
+        .collect()
+}
+
+fn codon_set(models: &[TranscriptModel], stop: bool) -> HashSet<(Location<'_>, u64)> {
+    models
+        .iter()
+        .filter_map(|model| {
+            let position = if stop {
+                model.get_stop_codon_position()
+            } else {
+                model.get_start_codon_position()
+            };
+            position.map(|position| (get_location(model), position))
+        })
+        .collect()
+}
+
+// Single exon transcripts match if they overlap by most of the longer length
+fn single_exons_match(a: (u64, u64), b: (u64, u64)) -> bool {
+    let overlap_start = std::cmp::max(a.0, b.0);
+    let overlap_end = std::cmp::min(a.1, b.1);
+    if overlap_end < overlap_start {
+        return false;
+    }
+
+    let longest = std::cmp::max(a.1 - a.0 + 1, b.1 - b.0 + 1);
+    (overlap_end - overlap_start + 1) as f64 >= SINGLE_EXON_MIN_OVERLAP * longest as f64
+}
+
+// Flags each transcript in 'models' which matches a transcript in 'others', as an identical intron chain for multi
+// exon transcripts, or a single exon transcript with enough overlap
+fn match_transcripts(models: &[TranscriptModel], others: &[TranscriptModel]) -> Vec<bool> {
+    let other_chains: HashSet<(Location, Vec<(u64, u64)>)> = others
+        .iter()
+        .filter(|other| other.get_exons().len() > 1)
+        .map(|other| (get_location(other), other.get_introns()))
+        .collect();
+
+    // Single exon transcripts by location, sorted by start, with the longest length for a bounded search
+    let mut other_singles: HashMap<Location, Vec<(u64, u64)>> = HashMap::new();
+    for other in others.iter().filter(|other| other.get_exons().len() == 1) {
+        other_singles
+            .entry(get_location(other))
+            .or_default()
+            .push(other.get_exons()[0]);
+    }
+    let mut max_single_length = 0;
+    for singles in other_singles.values_mut() {
+        singles.sort();
+        for (start, end) in singles.iter() {
+            max_single_length = std::cmp::max(max_single_length, end - start + 1);
+        }
+    }
+
+    models
+        .iter()
+        .map(|model| {
+            let location = get_location(model);
+
+            if model.get_exons().len() > 1 {
+                return other_chains.contains(&(location, model.get_introns()));
+            }
+
+            let exon = model.get_exons()[0];
+            let singles = match other_singles.get(&location) {
+                Some(singles) => singles,
+                None => return false,
+            };
+
+            let first = singles.partition_point(|(start, _)| start + max_single_length <= exon.0);
+            singles[first..]
+                .iter()
+                .take_while(|(start, _)| *start <= exon.1)
+                .any(|other_exon| single_exons_match(exon, *other_exon))
+        })
+        .collect()
+}
+
+// Counts the multi exon transcripts, and those whose intron chain is found in the other set
+fn count_intron_chains(models: &[TranscriptModel], others: &[TranscriptModel]) -> (usize, usize) {
+    let other_chains: HashSet<(Location, Vec<(u64, u64)>)> = others
+        .iter()
+        .filter(|other| other.get_exons().len() > 1)
+        .map(|other| (get_location(other), other.get_introns()))
+        .collect();
+
+    let multi_exon: Vec<&TranscriptModel> = models
+        .iter()
+        .filter(|model| model.get_exons().len() > 1)
+        .collect();
+    let matched = multi_exon
+        .iter()
+        .filter(|model| other_chains.contains(&(get_location(model), model.get_introns())))
+        .count();
+
+    (multi_exon.len(), matched)
+}
+
+// Counts the genes, and those with at least one matched transcript
+fn count_genes(models: &[TranscriptModel], matched: &[bool]) -> (usize, usize) {
+    let mut genes: HashMap<(&str, &str), bool> = HashMap::new();
+    for (model, is_matched) in models.iter().zip(matched.iter()) {
+        *genes
+            .entry((model.get_sequence(), model.get_gene_id()))
+            .or_insert(false) |= *is_matched;
+    }
+
+    (
+        genes.len(),
+        genes.values().filter(|is_matched| **is_matched).count(),
+    )
+}
+
+/// Feature level accuracy of predicted transcripts against a reference, in the style of gffcompare. Exons, introns
+/// and start / stop codons are compared as unique features, needing an exact match of both ends (or the position,
+/// for codons). Intron chains need every intron of a multi exon transcript to match. Transcripts match on their
+/// intron chain, or for single exon transcripts an overlap of 80% of the longer. A gene matches if any of its
+/// transcripts does
+pub struct FeatureEvaluation {
+    exon: FeatureCounts,
+    intron: FeatureCounts,
+    intron_chain: FeatureCounts,
+    transcript: FeatureCounts,
+    gene: FeatureCounts,
+    start_codon: FeatureCounts,
+    stop_codon: FeatureCounts,
+}
+
+impl FeatureEvaluation {
+    pub fn evaluate(
+        reference: &[TranscriptModel],
+        predicted: &[TranscriptModel],
+    ) -> FeatureEvaluation {
+        let (ref_chains, ref_chains_matched) = count_intron_chains(reference, predicted);
+        let (pred_chains, pred_chains_matched) = count_intron_chains(predicted, reference);
+
+        let ref_matched = match_transcripts(reference, predicted);
+        let pred_matched = match_transcripts(predicted, reference);
+
+        let (ref_genes, ref_genes_matched) = count_genes(reference, &ref_matched);
+        let (pred_genes, pred_genes_matched) = count_genes(predicted, &pred_matched);
+
+        FeatureEvaluation {
+            exon: FeatureCounts::from_sets(&exon_set(reference), &exon_set(predicted)),
+            intron: FeatureCounts::from_sets(&intron_set(reference), &intron_set(predicted)),
+            intron_chain: FeatureCounts {
+                ref_total: ref_chains,
+                ref_matched: ref_chains_matched,
+                pred_total: pred_chains,
+                pred_matched: pred_chains_matched,
+            },
+            transcript: FeatureCounts {
+                ref_total: reference.len(),
+                ref_matched: ref_matched.iter().filter(|m| **m).count(),
+                pred_total: predicted.len(),
+                pred_matched: pred_matched.iter().filter(|m| **m).count(),
+            },
+            gene: FeatureCounts {
+                ref_total: ref_genes,
+                ref_matched: ref_genes_matched,
+                pred_total: pred_genes,
+                pred_matched: pred_genes_matched,
+            },
+            start_codon: FeatureCounts::from_sets(
+                &codon_set(reference, false),
+                &codon_set(predicted, false),
+            ),
+            stop_codon: FeatureCounts::from_sets(
+                &codon_set(reference, true),
+                &codon_set(predicted, true),
+            ),
+        }
+    }
+
+    /// Counts for each level, by name
+    pub fn get_levels(&self) -> [(&'static str, FeatureCounts); 7] {
+        [
+            ("Exon", self.exon),
+            ("Intron", self.intron),
+            ("Intron chain", self.intron_chain),
+            ("Transcript", self.transcript),
+            ("Gene", self.gene),
+            ("Start codon", self.start_codon),
+            ("Stop codon", self.stop_codon),
+        ]
+    }
+
+    pub fn dump(&self) {
+        println!(
+            "{:>16}\t{:>12}\t{:>12}\t{:>12}\t{:>12}\t{:>12}\t{:>12}\t{:>12}",
+            "Level", "Ref", "Ref Matched", "Pred", "Pred Matched", "Sensitivity", "Precision", "F1"
+        );
+
+        for (name, counts) in self.get_levels().iter() {
+            println!(
+                "{:>16}\t{:12}\t{:12}\t{:12}\t{:12}\t{:12.5}\t{:12.5}\t{:12.5}",
+                name,
+                counts.ref_total,
+                counts.ref_matched,
+                counts.pred_total,
+                counts.pred_matched,
+                counts.get_sensitivity(),
+                counts.get_precision(),
+                counts.get_f1()
+            );
+        }
+
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::evaluation::FeatureEvaluation;
+    use crate::analysis::gene_model::{load_gff_models, TranscriptModel};
+
+    // Transcripts of one GFF3 mRNA per (ID, strand, exons, coding span) entry
+    fn load_models(
+        name: &str,
+        transcripts: &[(&str, &str, &[(u64, u64)], Option<(u64, u64)>)],
+    ) -> Vec<TranscriptModel> {
+        let mut lines = Vec::new();
+        for (id, strand, exons, coding) in transcripts {
+            let record = |feature: &str, start: u64, end: u64, attributes: String| {
+                format!(
+                    "chr1\t.\t{}\t{}\t{}\t.\t{}\t0\t{}",
+                    feature, start, end, strand, attributes
+                )
+            };
+            let (start, end) = (exons[0].0, exons[exons.len() - 1].1);
+            lines.push(record(
+                "mRNA",
+                start,
+                end,
+                format!("ID={};Parent=gene_{}", id, id),
+            ));
+            for (start, end) in exons.iter() {
+                lines.push(record("exon", *start, *end, format!("Parent={}", id)));
+            }
+            if let Some((start, end)) = coding {
+                lines.push(record("CDS", *start, *end, format!("Parent={}", id)));
+            }
+        }
+
+        let path = std::env::temp_dir().join(format!("helixer_post_test_{}.gff", name));
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        load_gff_models(&path).unwrap()
+    }
+
+    #[test]
+    fn test_feature_counts() {
+        let reference = load_models(
+            "evaluation_reference",
+            &[
+                ("r1", "+", &[(100, 200), (300, 400)], Some((150, 350))),
+                ("r2", "-", &[(1000, 1300)], Some((1000, 1250))),
+            ],
+        );
+        let predicted = load_models(
+            "evaluation_predicted",
+            &[
+                // Same intron chain and CDS with a shorter first exon, a single exon overlapping by over 80% and
+                // an unmatched non coding transcript
+                ("p1", "+", &[(120, 200), (300, 400)], Some((150, 350))),
+                ("p2", "-", &[(1000, 1290)], Some((1000, 1250))),
+                ("p3", "+", &[(5000, 5100)], None),
+            ],
+        );
+
+        let evaluation = FeatureEvaluation::evaluate(&reference, &predicted);
+        let counts: Vec<(&str, [usize; 4])> = evaluation
+            .get_levels()
+            .iter()
+            .map(|(name, counts)| {
+                (
+                    *name,
+                    [
+                        counts.get_ref_total(),
+                        counts.get_ref_matched(),
+                        counts.get_pred_total(),
+                        counts.get_pred_matched(),
+                    ],
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            counts,
+            vec![
+                ("Exon", [3, 1, 4, 1]),
+                ("Intron", [1, 1, 1, 1]),
+                ("Intron chain", [1, 1, 1, 1]),
+                ("Transcript", [2, 2, 3, 2]),
+                ("Gene", [2, 2, 3, 2]),
+                ("Start codon", [2, 2, 2, 2]),
+                ("Stop codon", [2, 2, 2, 2]),
+            ]
+        );
+
+        let transcript = evaluation.get_levels()[3].1;
+        assert_eq!(transcript.get_sensitivity(), 1.0);
+        assert!((transcript.get_f1() - 0.8).abs() < 1e-9);
+    }
+}
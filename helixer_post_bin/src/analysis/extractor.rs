@@ -1,8 +1,58 @@
+use crate::analysis::reference::{get_reference_from_label, ReferenceAnnotation};
 use crate::results::conv::{
     ArrayConvInto, Bases, ClassPrediction, ClassReference, PhasePrediction, PhaseReference,
 };
 use crate::results::iter::{BlockedDataset2D, BlockedDataset2DIter};
-use crate::results::{HelixerResults, Result, SequenceID, SpeciesID};
+use crate::results::{
+    DataLocation, Error, ErrorKind, HelixerResults, Result, SequenceID, SpeciesID,
+};
+use crate::warn;
+use hdf5::Dataset;
+use std::path::Path;
+
+// Location of a base on one strand of a sequence, for errors
+fn get_location(
+    helixer_res: &HelixerResults,
+    sequence_id: SequenceID,
+    rev: bool,
+    position: usize,
+) -> DataLocation {
+    let sequence = helixer_res.get_index().get_sequence_by_id(sequence_id);
+    DataLocation::new(sequence.get_name(), rev, position)
+}
+
+// The datasets which ended before the others, for a truncated dataset error
+#[derive(Default)]
+struct EndedDatasets {
+    names: Vec<String>,
+    file: Option<String>, // Of the first HDF5 dataset
+}
+
+impl EndedDatasets {
+    fn add(&mut self, dataset: &Dataset) {
+        self.names.push(dataset.name());
+        self.file.get_or_insert_with(|| dataset.filename());
+    }
+
+    fn add_name(&mut self, name: &str) {
+        self.names.push(name.to_string());
+    }
+
+    fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    fn into_error(self, location: DataLocation) -> Error {
+        let error = Error::new(ErrorKind::TruncatedDataset)
+            .with_dataset(&self.names.join(", "))
+            .with_location(location);
+
+        match self.file {
+            Some(file) => error.with_file(Path::new(&file)),
+            None => error,
+        }
+    }
+}
 
 // Hard coded as Bases / Predictions for now. Would make more sense to have all base-level datasets optionally available (and seekable)
 pub struct BasePredictionExtractor<
@@ -54,11 +104,11 @@ impl<'a> BasePredictionExtractor<'a, i8, i8> {
                 }))
             }
             (Some(_), None) => {
-                eprintln!("Warning: Ref Class predictions without Ref Phase predictions");
+                warn!("Ref Class predictions without Ref Phase predictions");
                 Ok(None)
             }
             (None, Some(_)) => {
-                eprintln!("Warning: Ref Phase predictions without Ref Class predictions");
+                warn!("Ref Phase predictions without Ref Class predictions");
                 Ok(None)
             }
             (None, None) => Ok(None),
@@ -121,6 +171,10 @@ pub struct BasePredictionIterator<
 
     class_pred_iter: BlockedDataset2DIter<'a, TC, ClassPrediction>,
     phase_pred_iter: BlockedDataset2DIter<'a, TP, PhasePrediction>,
+
+    position: usize,
+    finished: bool,
+    error: Option<Error>, // Ended the iteration early, see take_error
 }
 
 impl<'a, TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>>
@@ -143,6 +197,9 @@ impl<'a, TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>>
             base_iter,
             class_pred_iter,
             phase_pred_iter,
+            position: 0,
+            finished: false,
+            error: None,
         }
     }
 
@@ -161,6 +218,27 @@ impl<'a, TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>>
     pub fn get_rc(&self) -> bool {
         self.rc
     }
+
+    /// The error which ended the iteration early, if any: a block which couldn't be read, or datasets of
+    /// different lengths
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    fn get_location(&self) -> DataLocation {
+        get_location(
+            self.extractor.helixer_res,
+            self.sequence_id,
+            self.rc,
+            self.position,
+        )
+    }
+
+    fn fail(&mut self, error: Error) -> Option<(Bases, ClassPrediction, PhasePrediction)> {
+        self.error = Some(error);
+        self.finished = true;
+        None
+    }
 }
 
 impl<'a, TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>> Iterator
@@ -169,23 +247,50 @@ impl<'a, TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>>
     type Item = (Bases, ClassPrediction, PhasePrediction);
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
         let bases = self.base_iter.next();
         let class_pred = self.class_pred_iter.next();
         let phase_pred = self.phase_pred_iter.next();
 
-        if bases.is_none() && class_pred.is_none() && phase_pred.is_none() {
-            return None;
+        // A block which can't be read ends its dataset, so is checked before the lengths
+        let read_error = self
+            .base_iter
+            .take_error()
+            .or_else(|| self.class_pred_iter.take_error())
+            .or_else(|| self.phase_pred_iter.take_error());
+        if let Some(error) = read_error {
+            let error = error.with_location(self.get_location());
+            return self.fail(error);
         }
 
-        if bases.is_none() || class_pred.is_none() || phase_pred.is_none() {
-            panic!("Different lengths in base/class_pred/phase_pred iterators")
+        match (bases, class_pred, phase_pred) {
+            (Some(bases), Some(class_pred), Some(phase_pred)) => {
+                self.position += 1;
+                Some((bases, class_pred, phase_pred))
+            }
+            (None, None, None) => {
+                self.finished = true;
+                None
+            }
+            (bases, class_pred, phase_pred) => {
+                let mut ended = EndedDatasets::default();
+                if bases.is_none() {
+                    ended.add(self.base_iter.get_dataset());
+                }
+                if class_pred.is_none() {
+                    ended.add(self.class_pred_iter.get_dataset());
+                }
+                if phase_pred.is_none() {
+                    ended.add(self.phase_pred_iter.get_dataset());
+                }
+
+                let error = ended.into_error(self.get_location());
+                self.fail(error)
+            }
         }
-
-        let bases = bases.expect("Unexpected end of base iter");
-        let class_pred = class_pred.expect("Unexpected end of class_pred iter");
-        let phase_pred = phase_pred.expect("Unexpected end of phase_pred iter");
-
-        return Some((bases, class_pred, phase_pred));
     }
 }
 
@@ -196,6 +301,9 @@ pub struct ComparisonExtractor<'a> {
     class_ref_blocked_dataset: Option<BlockedDataset2D<'a, i8, ClassReference>>,
     phase_ref_blocked_dataset: Option<BlockedDataset2D<'a, i8, PhaseReference>>,
 
+    // Used in place of the reference datasets if given
+    reference_annotation: Option<ReferenceAnnotation>,
+
     class_pred_blocked_dataset: BlockedDataset2D<'a, f32, ClassPrediction>,
     phase_pred_blocked_dataset: BlockedDataset2D<'a, f32, PhasePrediction>,
 }
@@ -212,14 +320,47 @@ impl<'a> ComparisonExtractor<'a> {
             helixer_res,
             class_ref_blocked_dataset,
             phase_ref_blocked_dataset,
+            reference_annotation: None,
             class_pred_blocked_dataset,
             phase_pred_blocked_dataset,
         })
     }
 
-    pub fn fwd_iterator(&'a self, sequence_id: SequenceID) -> ComparisonIterator<'a> {
-        let sequence = self.helixer_res.get_index().get_sequence_by_id(sequence_id);
+    /// Compares with a reference annotation loaded from a GFF / GTF, instead of any reference in the genome file
+    pub fn new_with_reference(
+        helixer_res: &'a HelixerResults,
+        reference_annotation: ReferenceAnnotation,
+    ) -> Result<ComparisonExtractor<'a>> {
+        let class_pred_blocked_dataset = helixer_res.get_class_predictions()?;
+        let phase_pred_blocked_dataset = helixer_res.get_phase_predictions()?;
 
+        Ok(ComparisonExtractor {
+            helixer_res,
+            class_ref_blocked_dataset: None,
+            phase_ref_blocked_dataset: None,
+            reference_annotation: Some(reference_annotation),
+            class_pred_blocked_dataset,
+            phase_pred_blocked_dataset,
+        })
+    }
+
+    // Per base labels of the reference annotation for one strand of a sequence, if there is one
+    fn get_reference_labels(
+        &self,
+        sequence_id: SequenceID,
+        rev: bool,
+    ) -> Option<std::vec::IntoIter<u8>> {
+        self.reference_annotation
+            .as_ref()
+            .map(|reference_annotation| {
+                let sequence = self.helixer_res.get_index().get_sequence_by_id(sequence_id);
+                reference_annotation
+                    .get_labels(sequence.get_name(), rev, sequence.get_length())
+                    .into_iter()
+            })
+    }
+
+    pub fn fwd_iterator(&'a self, sequence_id: SequenceID) -> ComparisonIterator<'a> {
         let class_ref_iter = self
             .class_ref_blocked_dataset
             .as_ref()
@@ -234,7 +375,6 @@ impl<'a> ComparisonExtractor<'a> {
 
         ComparisonIterator::new(
             self,
-            sequence.get_species_id(),
             sequence_id,
             false,
             class_ref_iter,
@@ -245,8 +385,6 @@ impl<'a> ComparisonExtractor<'a> {
     }
 
     pub fn rev_iterator(&'a self, sequence_id: SequenceID) -> ComparisonIterator<'a> {
-        let sequence = self.helixer_res.get_index().get_sequence_by_id(sequence_id);
-
         let class_ref_iter = self
             .class_ref_blocked_dataset
             .as_ref()
@@ -261,7 +399,6 @@ impl<'a> ComparisonExtractor<'a> {
 
         ComparisonIterator::new(
             self,
-            sequence.get_species_id(),
             sequence_id,
             true,
             class_ref_iter,
@@ -272,10 +409,19 @@ impl<'a> ComparisonExtractor<'a> {
     }
 
     pub fn has_ref(&self) -> bool {
-        self.class_ref_blocked_dataset.is_some() && self.phase_ref_blocked_dataset.is_some()
+        (self.class_ref_blocked_dataset.is_some() && self.phase_ref_blocked_dataset.is_some())
+            || self.reference_annotation.is_some()
     }
 }
 
+/// Reference class and phase, then ML class and phase predictions, for a single base
+pub type ComparisonItem = (
+    ClassReference,
+    PhaseReference,
+    ClassPrediction,
+    PhasePrediction,
+);
+
 pub struct ComparisonIterator<'a> {
     extractor: &'a ComparisonExtractor<'a>,
 
@@ -287,15 +433,20 @@ pub struct ComparisonIterator<'a> {
 
     class_ref_iter: Option<BlockedDataset2DIter<'a, i8, ClassReference>>,
     phase_ref_iter: Option<BlockedDataset2DIter<'a, i8, PhaseReference>>,
+    reference_label_iter: Option<std::vec::IntoIter<u8>>,
 
     class_pred_iter: BlockedDataset2DIter<'a, f32, ClassPrediction>,
     phase_pred_iter: BlockedDataset2DIter<'a, f32, PhasePrediction>,
+
+    position: usize,
+    finished: bool,
+    error: Option<Error>, // Ended the iteration early, see take_error
 }
 
 impl<'a> ComparisonIterator<'a> {
+    // The species and any reference annotation labels for the strand are looked up from the extractor
     fn new(
         extractor: &'a ComparisonExtractor<'a>,
-        species_id: SpeciesID,
         sequence_id: SequenceID,
         rc: bool,
         class_ref_iter: Option<BlockedDataset2DIter<'a, i8, ClassReference>>,
@@ -303,6 +454,12 @@ impl<'a> ComparisonIterator<'a> {
         class_pred_iter: BlockedDataset2DIter<'a, f32, ClassPrediction>,
         phase_pred_iter: BlockedDataset2DIter<'a, f32, PhasePrediction>,
     ) -> ComparisonIterator<'a> {
+        let species_id = extractor
+            .helixer_res
+            .get_index()
+            .get_sequence_by_id(sequence_id)
+            .get_species_id();
+        let reference_label_iter = extractor.get_reference_labels(sequence_id, rc);
         let has_ref = extractor.has_ref();
         ComparisonIterator {
             extractor,
@@ -312,8 +469,12 @@ impl<'a> ComparisonIterator<'a> {
             has_ref,
             class_ref_iter,
             phase_ref_iter,
+            reference_label_iter,
             class_pred_iter,
             phase_pred_iter,
+            position: 0,
+            finished: false,
+            error: None,
         }
     }
 
@@ -332,56 +493,123 @@ impl<'a> ComparisonIterator<'a> {
     pub fn get_rc(&self) -> bool {
         self.rc
     }
+
+    /// The error which ended the iteration early, if any: a block which couldn't be read, or datasets of
+    /// different lengths
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    fn get_location(&self) -> DataLocation {
+        get_location(
+            self.extractor.helixer_res,
+            self.sequence_id,
+            self.rc,
+            self.position,
+        )
+    }
+
+    // Takes the error of the first dataset whose block couldn't be read, if any
+    fn take_read_error(&mut self) -> Option<Error> {
+        self.class_pred_iter
+            .take_error()
+            .or_else(|| self.phase_pred_iter.take_error())
+            .or_else(|| {
+                self.class_ref_iter
+                    .as_mut()
+                    .and_then(|iter| iter.take_error())
+            })
+            .or_else(|| {
+                self.phase_ref_iter
+                    .as_mut()
+                    .and_then(|iter| iter.take_error())
+            })
+    }
+
+    fn fail(&mut self, error: Error) -> Option<ComparisonItem> {
+        self.error = Some(error);
+        self.finished = true;
+        None
+    }
 }
 
 impl<'a> Iterator for ComparisonIterator<'a> {
-    type Item = (
-        ClassReference,
-        PhaseReference,
-        ClassPrediction,
-        PhasePrediction,
-    );
+    type Item = ComparisonItem;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
         let class_pred = self.class_pred_iter.next();
         let phase_pred = self.phase_pred_iter.next();
 
-        let (class_ref, phase_ref) = if self.has_ref {
-            let class_ref = self.class_ref_iter.as_mut().unwrap().next();
-            let phase_ref = self.phase_ref_iter.as_mut().unwrap().next();
-
-            if class_ref.is_none()
-                && phase_ref.is_none()
-                && class_pred.is_none()
-                && phase_pred.is_none()
-            {
-                return None;
-            }
-
-            if class_ref.is_none()
-                || phase_ref.is_none()
-                || class_pred.is_none()
-                || phase_pred.is_none()
-            {
-                panic!("Different lengths in class_ref/phase_ref/class_pred/phase_pred iterators")
-            }
+        // The datasets which have ended, out of the number in use
+        let mut ended = EndedDatasets::default();
+        if class_pred.is_none() {
+            ended.add(self.class_pred_iter.get_dataset());
+        }
+        if phase_pred.is_none() {
+            ended.add(self.phase_pred_iter.get_dataset());
+        }
 
-            (class_ref.unwrap(), phase_ref.unwrap())
-        } else {
-            if class_pred.is_none() && phase_pred.is_none() {
-                return None;
-            }
+        let (dataset_count, class_ref, phase_ref) =
+            if let Some(reference_label_iter) = self.reference_label_iter.as_mut() {
+                let reference = reference_label_iter.next().map(get_reference_from_label);
+                if reference.is_none() {
+                    ended.add_name("reference annotation");
+                }
+
+                (
+                    3,
+                    reference.map(|(class_ref, _)| class_ref),
+                    reference.map(|(_, phase_ref)| phase_ref),
+                )
+            } else if self.has_ref {
+                let class_ref_iter = self.class_ref_iter.as_mut().unwrap();
+                let class_ref = class_ref_iter.next();
+                if class_ref.is_none() {
+                    ended.add(class_ref_iter.get_dataset());
+                }
+
+                let phase_ref_iter = self.phase_ref_iter.as_mut().unwrap();
+                let phase_ref = phase_ref_iter.next();
+                if phase_ref.is_none() {
+                    ended.add(phase_ref_iter.get_dataset());
+                }
+
+                (4, class_ref, phase_ref)
+            } else {
+                (
+                    2,
+                    Some(ClassReference::default()),
+                    Some(PhaseReference::default()),
+                )
+            };
+
+        // A block which can't be read ends its dataset, so is checked before the lengths
+        if let Some(error) = self.take_read_error() {
+            let error = error.with_location(self.get_location());
+            return self.fail(error);
+        }
 
-            if class_pred.is_none() || phase_pred.is_none() {
-                panic!("Different lengths in class_pred/phase_pred iterators")
-            }
+        if ended.len() == dataset_count {
+            self.finished = true;
+            return None;
+        }
 
-            (ClassReference::default(), PhaseReference::default())
-        };
+        if ended.len() > 0 {
+            let error = ended.into_error(self.get_location());
+            return self.fail(error);
+        }
 
-        let class_pred = class_pred.unwrap();
-        let phase_pred = phase_pred.unwrap();
+        self.position += 1;
 
-        return Some((class_ref, phase_ref, class_pred, phase_pred));
+        Some((
+            class_ref.unwrap(),
+            phase_ref.unwrap(),
+            class_pred.unwrap(),
+            phase_pred.unwrap(),
+        ))
     }
 }
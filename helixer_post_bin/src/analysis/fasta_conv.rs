@@ -0,0 +1,329 @@
+use crate::analysis::genetic_code::GeneticCode;
+use crate::analysis::gff_conv::{get_gene_name, get_transcript_name, WindowContext};
+use crate::analysis::hmm::{HmmAnnotationLabel, HmmStateRegion, HmmTranscript};
+use crate::fasta::FastaWriter;
+use std::io::Write;
+
+/// Spliced sequences of a transcript, in the transcript's orientation (reverse complemented for the reverse strand)
+pub struct TranscriptSequences {
+    name: String,
+    description: String,
+    cds: Vec<u8>,
+    cdna: Vec<u8>,
+    protein: Vec<u8>,
+}
+
+impl TranscriptSequences {
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_cds(&self) -> &[u8] {
+        &self.cds
+    }
+
+    pub fn get_cdna(&self) -> &[u8] {
+        &self.cdna
+    }
+
+    pub fn get_protein(&self) -> &[u8] {
+        &self.protein
+    }
+}
+
+// Translates the CDS, skipping any partial codon at the start of a gene entered part way through a codon. A complete
+// start codon is always methionine, even for alternative start codons, and the final stop is left off, including
+// a context dependent stop which would otherwise translate as an amino acid
+fn translate_cds(
+    cds: &[u8],
+    coding_offset: u64,
+    complete_start: bool,
+    complete_end: bool,
+    genetic_code: &GeneticCode,
+) -> Vec<u8> {
+    let frame = std::cmp::min(((3 - coding_offset % 3) % 3) as usize, cds.len());
+
+    let codons: Vec<&[u8]> = cds[frame..].chunks_exact(3).collect();
+    let mut protein: Vec<u8> = codons
+        .iter()
+        .map(|codon| genetic_code.translate(codon))
+        .collect();
+
+    if complete_start && frame == 0 && cds.len() >= 3 && genetic_code.is_start(&cds[..3]) {
+        protein[0] = b'M';
+    }
+
+    if protein.last() == Some(&b'*')
+        || (complete_end
+            && codons
+                .last()
+                .is_some_and(|codon| genetic_code.is_stop(codon)))
+    {
+        protein.pop();
+    }
+
+    protein
+}
+
+fn generate_transcript_sequences(
+    regions: &[HmmStateRegion],
+    bases: &[u8],
+    genetic_code: &GeneticCode,
+    window: &WindowContext,
+    gene_name: &str,
+    transcript_name: &str,
+) -> TranscriptSequences {
+    let mut cds = Vec::new();
+    let mut cdna = Vec::new();
+
+    for region in regions.iter() {
+        let region_bases = &bases[region.get_start_pos()..region.get_end_pos()];
+
+        match region.get_annotation_label() {
+            HmmAnnotationLabel::UTR5 | HmmAnnotationLabel::UTR3 => {
+                cdna.extend_from_slice(region_bases)
+            }
+            HmmAnnotationLabel::Start | HmmAnnotationLabel::Coding | HmmAnnotationLabel::Stop => {
+                cds.extend_from_slice(region_bases);
+                cdna.extend_from_slice(region_bases);
+            }
+            HmmAnnotationLabel::Intergenic | HmmAnnotationLabel::Intron => {}
+        }
+    }
+
+    let first = &regions[0];
+    let last = &regions[regions.len() - 1];
+
+    let complete_start = regions
+        .iter()
+        .find(|region| region.get_annotation_label() == HmmAnnotationLabel::Coding)
+        .is_some_and(|region| !region.is_truncated_start());
+    let protein = translate_cds(
+        &cds,
+        first.get_coding_offset(),
+        complete_start,
+        !last.is_truncated_end(),
+        genetic_code,
+    );
+
+    // Location in sequence coordinates, as in the GFF
+    let start = (first.get_start_pos() + window.position + 1) as u64;
+    let end = (last.get_end_pos() + window.position) as u64;
+    let (start, end, strand) = if window.rev {
+        (
+            1 + window.sequence_length - end,
+            1 + window.sequence_length - start,
+            "-",
+        )
+    } else {
+        (start, end, "+")
+    };
+
+    let mut description = format!(
+        "gene={} loc={}:{}-{}({})",
+        gene_name, window.sequence, start, end, strand
+    );
+    match (first.is_truncated_start(), last.is_truncated_end()) {
+        (true, true) => description.push_str(" partial=5',3'"),
+        (true, false) => description.push_str(" partial=5'"),
+        (false, true) => description.push_str(" partial=3'"),
+        (false, false) => {}
+    }
+
+    TranscriptSequences {
+        name: transcript_name.to_owned(),
+        description,
+        cds,
+        cdna,
+        protein,
+    }
+}
+
+/// CDS, cDNA and protein sequences for each transcript of the genes in a window, named as in the GFF. Genes and
+/// transcripts are filtered by coding length in the same way as for the GFF, so this must be called with the
+/// same gene index, before hmm_solution_to_gff moves it on
+///
+/// # Arguments
+///
+/// * `bases` - Base letters of the window, in the window's strand orientation
+pub fn hmm_solution_to_fasta(
+    genes: &[Vec<HmmTranscript>],
+    bases: &[u8],
+    genetic_code: &GeneticCode,
+    window: &WindowContext,
+    gene_idx: usize,
+) -> Vec<TranscriptSequences> {
+    let mut all_sequences = Vec::new();
+    let mut gene_idx = gene_idx;
+
+    for transcripts in genes {
+        if transcripts[0].1 < window.min_coding_length {
+            continue;
+        }
+
+        let gene_name = get_gene_name(window.species, window.sequence, gene_idx);
+
+        for (transcript_idx, (transcript_regions, _)) in transcripts
+            .iter()
+            .filter(|(_, coding_length)| *coding_length >= window.min_coding_length)
+            .enumerate()
+        {
+            all_sequences.push(generate_transcript_sequences(
+                transcript_regions,
+                bases,
+                genetic_code,
+                window,
+                &gene_name,
+                &get_transcript_name(&gene_name, transcript_idx),
+            ));
+        }
+
+        gene_idx += 1;
+    }
+
+    all_sequences
+}
+
+/// Optional CDS, cDNA (spliced mRNA) and protein FASTA outputs for the predicted transcripts
+pub struct TranscriptFastaWriter<W: Write> {
+    cds_writer: Option<FastaWriter<W>>,
+    cdna_writer: Option<FastaWriter<W>>,
+    protein_writer: Option<FastaWriter<W>>,
+}
+
+impl<W: Write> TranscriptFastaWriter<W> {
+    pub fn new(
+        cds_writer: Option<FastaWriter<W>>,
+        cdna_writer: Option<FastaWriter<W>>,
+        protein_writer: Option<FastaWriter<W>>,
+    ) -> TranscriptFastaWriter<W> {
+        TranscriptFastaWriter {
+            cds_writer,
+            cdna_writer,
+            protein_writer,
+        }
+    }
+
+    /// True if any FASTA output is wanted, otherwise sequences need not be generated
+    pub fn is_enabled(&self) -> bool {
+        self.cds_writer.is_some() || self.cdna_writer.is_some() || self.protein_writer.is_some()
+    }
+
+    pub fn write_transcripts(
+        &mut self,
+        transcripts: &[TranscriptSequences],
+    ) -> std::io::Result<()> {
+        for transcript in transcripts {
+            if let Some(writer) = self.cds_writer.as_mut() {
+                writer.write_record(&transcript.name, &transcript.description, &transcript.cds)?;
+            }
+            if let Some(writer) = self.cdna_writer.as_mut() {
+                writer.write_record(&transcript.name, &transcript.description, &transcript.cdna)?;
+            }
+            if let Some(writer) = self.protein_writer.as_mut() {
+                writer.write_record(
+                    &transcript.name,
+                    &transcript.description,
+                    &transcript.protein,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::fasta_conv::{hmm_solution_to_fasta, translate_cds};
+    use crate::analysis::genetic_code::GeneticCode;
+    use crate::analysis::gff_conv::WindowContext;
+    use crate::analysis::hmm::{HmmConfig, PredictionHmm};
+    use crate::analysis::test_window::get_one_hot_window;
+
+    #[test]
+    fn test_translate_cds() {
+        let standard = GeneticCode::from_id(1).unwrap();
+
+        assert_eq!(translate_cds(b"ATGGCCTAA", 0, true, true, &standard), b"MA");
+
+        // Alternative starts are methionine only as a complete start codon
+        assert_eq!(translate_cds(b"TTGGCCTAA", 0, true, true, &standard), b"MA");
+        assert_eq!(
+            translate_cds(b"TTGGCCTAA", 0, false, true, &standard),
+            b"LA"
+        );
+
+        // A gene entered one base into a codon skips the rest of it
+        assert_eq!(translate_cds(b"GGGCCTAA", 1, false, true, &standard), b"A");
+        assert_eq!(translate_cds(b"GGCC", 0, true, false, &standard), b"G");
+
+        // Context dependent stops are read through, apart from the final stop of a complete CDS
+        let condylostoma = GeneticCode::from_id(28).unwrap();
+        assert_eq!(
+            translate_cds(b"ATGTAAGCCTAA", 0, true, true, &condylostoma),
+            b"MQA"
+        );
+        assert_eq!(
+            translate_cds(b"ATGTAAGCCTAA", 0, true, false, &condylostoma),
+            b"MQAQ"
+        );
+    }
+
+    #[test]
+    fn test_reverse_strand_sequences() {
+        let genetic_code = GeneticCode::from_id(1).unwrap();
+        let config = HmmConfig::new();
+
+        // A single exon gene, given in the reverse strand's orientation (already reverse complemented)
+        let sequence = [
+            "CTCCTTCTCCTTCCTCTTCC",
+            "ATGGCCGCCGCCGCCGCCGCCGCCGCCGCCGCCTAA",
+            "CCTTCTCCTTCTCCTTCCTC",
+        ]
+        .concat();
+        let window = get_one_hot_window(&sequence, |position| match position {
+            20..=55 => [0.1, 0.1, 0.7, 0.1],
+            _ => [0.7, 0.1, 0.1, 0.1],
+        });
+
+        let mut hmm = PredictionHmm::new(window, &config, &genetic_code);
+        hmm.set_sequence_bounds(false, false);
+        let genes = hmm.solve().unwrap().trace_genes();
+        assert_eq!(genes.len(), 1);
+
+        let window = WindowContext {
+            species: "Sp",
+            sequence: "chr1",
+            rev: true,
+            position: 100,
+            sequence_length: 1000,
+            min_coding_length: 30,
+        };
+        let transcripts =
+            hmm_solution_to_fasta(&genes, sequence.as_bytes(), &genetic_code, &window, 7);
+        assert_eq!(transcripts.len(), 1);
+
+        // The transcript, with a UTR base either side of the CDS, covers strand positions 120 - 157, counted from
+        // the sequence end
+        let transcript = &transcripts[0];
+        assert_eq!(transcript.get_name(), "Sp_chr1_000007.1");
+        assert_eq!(
+            transcript.description,
+            "gene=Sp_chr1_000007 loc=chr1:844-881(-)"
+        );
+        assert_eq!(transcript.get_cds(), &sequence.as_bytes()[20..56]);
+        assert_eq!(transcript.get_cdna(), &sequence.as_bytes()[19..57]);
+        assert_eq!(transcript.get_protein(), b"MAAAAAAAAAA");
+
+        // Genes shorter than the minimum coding length are left out
+        let window = WindowContext {
+            min_coding_length: 40,
+            ..window
+        };
+        assert!(
+            hmm_solution_to_fasta(&genes, sequence.as_bytes(), &genetic_code, &window, 7)
+                .is_empty()
+        );
+    }
+}
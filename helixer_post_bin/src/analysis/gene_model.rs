@@ -0,0 +1,546 @@
+use crate::analysis::extractor::ComparisonItem;
+use crate::gff::GffStrand;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+// Class reference indices, see ClassReference
+const REF_INTERGENIC: usize = 0;
+const REF_CODING: usize = 2;
+const REF_INTRON: usize = 3;
+
+fn invalid_data(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// The exons and coding span of a single transcript, in forward strand coordinates (1 based, inclusive)
+#[derive(Clone)]
+pub struct TranscriptModel {
+    sequence: String,
+    strand: GffStrand,
+    gene_id: String,
+    transcript_id: String,
+    exons: Vec<(u64, u64)>,     // Ascending and non overlapping
+    coding: Option<(u64, u64)>, // First and last coding base, including the stop codon
+    coding_phase: u8, // GFF phase of the 5' coding base, nonzero for 5' partial transcripts
+}
+
+impl TranscriptModel {
+    pub fn get_sequence(&self) -> &str {
+        &self.sequence
+    }
+
+    pub fn get_strand(&self) -> GffStrand {
+        self.strand
+    }
+
+    pub fn get_gene_id(&self) -> &str {
+        &self.gene_id
+    }
+
+    pub fn get_transcript_id(&self) -> &str {
+        &self.transcript_id
+    }
+
+    pub fn get_exons(&self) -> &[(u64, u64)] {
+        &self.exons
+    }
+
+    pub fn get_coding(&self) -> Option<(u64, u64)> {
+        self.coding
+    }
+
+    pub fn get_coding_phase(&self) -> u8 {
+        self.coding_phase
+    }
+
+    pub fn get_start(&self) -> u64 {
+        self.exons[0].0
+    }
+
+    pub fn get_end(&self) -> u64 {
+        self.exons[self.exons.len() - 1].1
+    }
+
+    /// Introns between consecutive exons, in ascending order
+    pub fn get_introns(&self) -> Vec<(u64, u64)> {
+        self.exons
+            .windows(2)
+            .map(|pair| (pair[0].1 + 1, pair[1].0 - 1))
+            .collect()
+    }
+
+    /// Position of the first base of the start codon (the 5' end of the coding span), if coding
+    pub fn get_start_codon_position(&self) -> Option<u64> {
+        self.coding.map(|(start, end)| match self.strand {
+            GffStrand::Forward => start,
+            GffStrand::Reverse => end,
+        })
+    }
+
+    /// Position of the last base of the stop codon (the 3' end of the coding span), if coding
+    pub fn get_stop_codon_position(&self) -> Option<u64> {
+        self.coding.map(|(start, end)| match self.strand {
+            GffStrand::Forward => end,
+            GffStrand::Reverse => start,
+        })
+    }
+}
+
+// Sorts and merges overlapping or adjacent ranges
+fn merge_ranges(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort();
+
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 + 1 => last.1 = std::cmp::max(last.1, end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    merged
+}
+
+// A transcript being read from a GFF / GTF file
+struct TranscriptBuilder {
+    sequence: String,
+    strand: GffStrand,
+    gene_id: String,
+    exons: Vec<(u64, u64)>,
+    parts: Vec<(u64, u64)>, // CDS and UTR records, used as the exons if there are no exon records
+    coding: Option<(u64, u64)>,
+    coding_phase: Option<(u64, u8)>, // Phase of the most 5' CDS record, with its 5' position
+}
+
+// Attributes of a GFF3 (key=value) or GTF (key "value") line
+fn parse_attributes(attributes: &str) -> HashMap<&str, &str> {
+    attributes
+        .split(';')
+        .map(|attribute| attribute.trim())
+        .filter(|attribute| !attribute.is_empty())
+        .filter_map(|attribute| {
+            attribute
+                .split_once('=')
+                .or_else(|| attribute.split_once(' '))
+                .map(|(key, value)| (key.trim(), value.trim().trim_matches('"')))
+        })
+        .collect()
+}
+
+/// Reads the transcripts of a GFF3 or GTF file, from mRNA / transcript, exon, CDS, UTR and start / stop codon
+/// features. Transcripts without exon features take their exons from the CDS and UTR features. Features without a
+/// strand are ignored
+pub fn load_gff_models(path: &Path) -> Result<Vec<TranscriptModel>> {
+    let contents = fs::read_to_string(path)?;
+
+    let mut transcript_ids: Vec<String> = Vec::new();
+    let mut builders: HashMap<String, TranscriptBuilder> = HashMap::new();
+
+    for (idx, line) in contents.lines().enumerate() {
+        let line_no = idx + 1;
+        if line.starts_with("##FASTA") {
+            break;
+        }
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 9 {
+            return Err(invalid_data(format!(
+                "Expected 9 columns on line {} in {}",
+                line_no,
+                path.display()
+            )));
+        }
+
+        let strand = match fields[6] {
+            "+" => GffStrand::Forward,
+            "-" => GffStrand::Reverse,
+            _ => continue,
+        };
+
+        let (start, end) = match (fields[3].parse::<u64>(), fields[4].parse::<u64>()) {
+            (Ok(start), Ok(end)) if start >= 1 && start <= end => (start, end),
+            _ => {
+                return Err(invalid_data(format!(
+                    "Invalid coordinates on line {} in {}",
+                    line_no,
+                    path.display()
+                )))
+            }
+        };
+
+        let attributes = parse_attributes(fields[8]);
+        let is_transcript = matches!(fields[2], "mRNA" | "transcript");
+
+        // GTF lines name their transcript, GFF3 transcripts have an ID and their children a Parent
+        let (parents, gene_id): (Vec<&str>, Option<&str>) =
+            if let Some(transcript_id) = attributes.get("transcript_id") {
+                (vec![*transcript_id], attributes.get("gene_id").copied())
+            } else if is_transcript {
+                match attributes.get("ID") {
+                    Some(id) => (vec![*id], attributes.get("Parent").copied()),
+                    None => continue,
+                }
+            } else {
+                match attributes.get("Parent") {
+                    Some(parents) => (parents.split(',').collect(), None),
+                    None => continue,
+                }
+            };
+
+        for parent in parents {
+            let builder = builders.entry(parent.to_string()).or_insert_with(|| {
+                transcript_ids.push(parent.to_string());
+                TranscriptBuilder {
+                    sequence: fields[0].to_string(),
+                    strand,
+                    gene_id: parent.to_string(),
+                    exons: Vec::new(),
+                    parts: Vec::new(),
+                    coding: None,
+                    coding_phase: None,
+                }
+            });
+
+            if let Some(gene_id) = gene_id {
+                builder.gene_id = gene_id.to_string();
+            }
+
+            match fields[2] {
+                "exon" => builder.exons.push((start, end)),
+                "CDS" | "start_codon" | "stop_codon" => {
+                    builder.parts.push((start, end));
+                    builder.coding = Some(match builder.coding {
+                        Some((coding_start, coding_end)) => (
+                            std::cmp::min(coding_start, start),
+                            std::cmp::max(coding_end, end),
+                        ),
+                        None => (start, end),
+                    });
+
+                    if fields[2] == "CDS" {
+                        let five_prime = match strand {
+                            GffStrand::Forward => start,
+                            GffStrand::Reverse => end,
+                        };
+                        let is_more_five_prime = match (builder.coding_phase, strand) {
+                            (None, _) => true,
+                            (Some((position, _)), GffStrand::Forward) => five_prime < position,
+                            (Some((position, _)), GffStrand::Reverse) => five_prime > position,
+                        };
+                        if is_more_five_prime {
+                            builder.coding_phase =
+                                Some((five_prime, fields[7].parse().unwrap_or(0) % 3));
+                        }
+                    }
+                }
+                "five_prime_UTR" | "three_prime_UTR" | "5UTR" | "3UTR" | "UTR" => {
+                    builder.parts.push((start, end))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut models = Vec::with_capacity(transcript_ids.len());
+
+    for transcript_id in transcript_ids {
+        let builder = builders.remove(&transcript_id).unwrap();
+
+        let exons = if builder.exons.is_empty() {
+            merge_ranges(builder.parts)
+        } else {
+            merge_ranges(builder.exons)
+        };
+
+        if exons.is_empty() {
+            continue;
+        }
+
+        models.push(TranscriptModel {
+            sequence: builder.sequence,
+            strand: builder.strand,
+            gene_id: builder.gene_id,
+            transcript_id,
+            exons,
+            coding: builder.coding,
+            coding_phase: builder.coding_phase.map_or(0, |(_, phase)| phase),
+        });
+    }
+
+    Ok(models)
+}
+
+/// Rebuilds transcripts from the per base class reference (data/y) of one strand of a sequence. Each run of genic
+/// bases is a transcript, with its exons between the intron bases. Transcripts are named
+/// <sequence>_ref_<plus|minus>_<n>, with each its own gene
+///
+/// # Arguments
+///
+/// * `comp_iter` - The reference for each base of the strand, in the strand's orientation
+/// * `rev` - True for the reverse strand, with positions counting from the sequence end
+pub fn models_from_reference<I: Iterator<Item = ComparisonItem>>(
+    comp_iter: I,
+    sequence: &str,
+    rev: bool,
+    sequence_length: u64,
+) -> Vec<TranscriptModel> {
+    // Ranges in strand coordinates (0 based, exclusive end) of the current transcript
+    let mut exons: Vec<(u64, u64)> = Vec::new();
+    let mut coding: Option<(u64, u64)> = None;
+    let mut coding_phase = 0;
+    let mut transcripts = Vec::new();
+
+    for (position, (class_ref, phase_ref, _, _)) in comp_iter.enumerate() {
+        let position = position as u64;
+        let class = class_ref.get_max_idx();
+
+        if class == REF_INTERGENIC {
+            if !exons.is_empty() {
+                transcripts.push((std::mem::take(&mut exons), coding.take(), coding_phase));
+            }
+            continue;
+        }
+
+        if class == REF_INTRON {
+            continue;
+        }
+
+        // Exon bases continue the current exon unless separated from it by intron bases
+        match exons.last_mut() {
+            Some(last) if last.1 == position => last.1 += 1,
+            _ => exons.push((position, position + 1)),
+        }
+
+        if class == REF_CODING {
+            if coding.is_none() {
+                // Phase reference indices are non coding, then phase 0, 1 and 2
+                coding_phase = phase_ref.get_max_idx().saturating_sub(1) as u8;
+            }
+            coding =
+                Some(coding.map_or((position, position + 1), |(start, _)| (start, position + 1)));
+        }
+    }
+
+    if !exons.is_empty() {
+        transcripts.push((exons, coding, coding_phase));
+    }
+
+    let strand = GffStrand::from_rev(rev);
+    let to_forward = |(start, end): (u64, u64)| {
+        if rev {
+            (sequence_length - end + 1, sequence_length - start)
+        } else {
+            (start + 1, end)
+        }
+    };
+
+    transcripts
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (exons, coding, coding_phase))| {
+            let name = format!(
+                "{}_ref_{}_{}",
+                sequence,
+                if rev { "minus" } else { "plus" },
+                idx + 1
+            );
+            let mut exons: Vec<(u64, u64)> = exons.into_iter().map(to_forward).collect();
+            if rev {
+                exons.reverse();
+            }
+
+            TranscriptModel {
+                sequence: sequence.to_string(),
+                strand,
+                gene_id: name.clone(),
+                transcript_id: name,
+                exons,
+                coding: coding.map(to_forward),
+                coding_phase,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::gene_model::load_gff_models;
+    use crate::gff::GffStrand;
+
+    fn write_gff(name: &str, lines: &[[&str; 9]]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("helixer_post_test_{}.gff", name));
+        let contents: Vec<String> = lines.iter().map(|fields| fields.join("\t")).collect();
+        std::fs::write(&path, contents.join("\n")).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_load_gff3_models() {
+        let path = write_gff(
+            "gene_models",
+            &[
+                ["##gff-version 3", "", "", "", "", "", "", "", ""],
+                ["chr1", ".", "gene", "100", "400", ".", "+", ".", "ID=g1"],
+                [
+                    "chr1",
+                    ".",
+                    "mRNA",
+                    "100",
+                    "400",
+                    ".",
+                    "+",
+                    ".",
+                    "ID=t1;Parent=g1",
+                ],
+                [
+                    "chr1",
+                    ".",
+                    "exon",
+                    "100",
+                    "200",
+                    ".",
+                    "+",
+                    ".",
+                    "Parent=t1",
+                ],
+                [
+                    "chr1",
+                    ".",
+                    "exon",
+                    "300",
+                    "400",
+                    ".",
+                    "+",
+                    ".",
+                    "Parent=t1",
+                ],
+                ["chr1", ".", "CDS", "300", "350", ".", "+", "2", "Parent=t1"],
+                ["chr1", ".", "CDS", "150", "200", ".", "+", "0", "Parent=t1"],
+                // No exon records, so the adjacent UTR and CDS form a single exon
+                [
+                    "chr1",
+                    ".",
+                    "mRNA",
+                    "1000",
+                    "1300",
+                    ".",
+                    "-",
+                    ".",
+                    "ID=t2;Parent=g2",
+                ],
+                [
+                    "chr1",
+                    ".",
+                    "five_prime_UTR",
+                    "1251",
+                    "1300",
+                    ".",
+                    "-",
+                    ".",
+                    "Parent=t2",
+                ],
+                [
+                    "chr1",
+                    ".",
+                    "CDS",
+                    "1000",
+                    "1250",
+                    ".",
+                    "-",
+                    "1",
+                    "Parent=t2",
+                ],
+                [
+                    "chr1",
+                    ".",
+                    "CDS",
+                    "2000",
+                    "2100",
+                    ".",
+                    ".",
+                    "0",
+                    "Parent=t3",
+                ],
+            ],
+        );
+        let models = load_gff_models(&path).unwrap();
+        assert_eq!(models.len(), 2);
+
+        let forward = &models[0];
+        assert_eq!(forward.get_transcript_id(), "t1");
+        assert_eq!(forward.get_gene_id(), "g1");
+        assert!(forward.get_strand() == GffStrand::Forward);
+        assert_eq!(forward.get_exons(), [(100, 200), (300, 400)]);
+        assert_eq!(forward.get_introns(), vec![(201, 299)]);
+        assert_eq!(forward.get_coding(), Some((150, 350)));
+        assert_eq!(forward.get_coding_phase(), 0);
+        assert_eq!(forward.get_start_codon_position(), Some(150));
+        assert_eq!(forward.get_stop_codon_position(), Some(350));
+
+        let reverse = &models[1];
+        assert_eq!(reverse.get_gene_id(), "g2");
+        assert!(reverse.get_strand() == GffStrand::Reverse);
+        assert_eq!(reverse.get_exons(), [(1000, 1300)]);
+        assert!(reverse.get_introns().is_empty());
+        assert_eq!(reverse.get_coding_phase(), 1);
+        assert_eq!(reverse.get_start_codon_position(), Some(1250));
+        assert_eq!(reverse.get_stop_codon_position(), Some(1000));
+    }
+
+    #[test]
+    fn test_load_gtf_models() {
+        let attributes = "gene_id \"g3\"; transcript_id \"t3\";";
+        let path = write_gff(
+            "gene_models_gtf",
+            &[
+                ["chr2", ".", "exon", "10", "50", ".", "+", ".", attributes],
+                ["chr2", ".", "exon", "80", "120", ".", "+", ".", attributes],
+                ["chr2", ".", "CDS", "20", "50", ".", "+", "0", attributes],
+                ["chr2", ".", "CDS", "80", "100", ".", "+", "2", attributes],
+                [
+                    "chr2",
+                    ".",
+                    "stop_codon",
+                    "101",
+                    "103",
+                    ".",
+                    "+",
+                    "0",
+                    attributes,
+                ],
+            ],
+        );
+        let models = load_gff_models(&path).unwrap();
+        assert_eq!(models.len(), 1);
+        assert_eq!(models[0].get_transcript_id(), "t3");
+        assert_eq!(models[0].get_gene_id(), "g3");
+        assert_eq!(models[0].get_exons(), [(10, 50), (80, 120)]);
+        assert_eq!(models[0].get_coding(), Some((20, 103)));
+    }
+
+    #[test]
+    fn test_gff_model_errors() {
+        for (name, contents, message) in [
+            (
+                "gene_models_short",
+                "chr2\t.\texon\t10\t50\t.\t+\t.",
+                "Expected 9 columns on line 1",
+            ),
+            (
+                "gene_models_coordinates",
+                "chr2\t.\texon\t50\t10\t.\t+\t.\tParent=t4",
+                "Invalid coordinates on line 1",
+            ),
+        ] {
+            let path = std::env::temp_dir().join(format!("helixer_post_test_{}.gff", name));
+            std::fs::write(&path, contents).unwrap();
+            let err = load_gff_models(&path).err().unwrap();
+            assert!(err.to_string().contains(message), "{}", err);
+        }
+    }
+}
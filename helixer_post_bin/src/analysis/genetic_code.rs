@@ -0,0 +1,390 @@
+// NCBI translation tables, see https://www.ncbi.nlm.nih.gov/Taxonomy/Utils/wprintgc.cgi
+//
+// Codons are ordered as in the NCBI tables, with each base cycling through TCAG:
+//   Base1  = TTTTTTTTTTTTTTTTCCCCCCCCCCCCCCCCAAAAAAAAAAAAAAAAGGGGGGGGGGGGGGGG
+//   Base2  = TTTTCCCCAAAAGGGGTTTTCCCCAAAAGGGGTTTTCCCCAAAAGGGGTTTTCCCCAAAAGGGG
+//   Base3  = TCAGTCAGTCAGTCAGTCAGTCAGTCAGTCAGTCAGTCAGTCAGTCAGTCAGTCAGTCAGTCAG
+//
+// In the starts line, 'M' marks a start codon and '*' a stop codon. Stops which are only
+// marked in the starts line are context dependent (tables 27, 28 and 31): they may end a CDS,
+// but otherwise code for the amino acid in the amino acids line.
+
+const TABLE_BASES: [u8; 4] = [b'T', b'C', b'A', b'G'];
+
+const DEFAULT_GENETIC_CODE: u8 = 1;
+
+struct GeneticCodeTable {
+    id: u8,
+    name: &'static str,
+    amino_acids: &'static str,
+    starts: &'static str,
+}
+
+const GENETIC_CODE_TABLES: [GeneticCodeTable; 27] = [
+    GeneticCodeTable {
+        id: 1,
+        name: "Standard",
+        amino_acids: "FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "---M------**--*----M---------------M----------------------------",
+    },
+    GeneticCodeTable {
+        id: 2,
+        name: "Vertebrate Mitochondrial",
+        amino_acids: "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSS**VVVVAAAADDEEGGGG",
+        starts: "----------**--------------------MMMM----------**---M------------",
+    },
+    GeneticCodeTable {
+        id: 3,
+        name: "Yeast Mitochondrial",
+        amino_acids: "FFLLSSSSYY**CCWWTTTTPPPPHHQQRRRRIIMMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "----------**----------------------MM---------------M------------",
+    },
+    GeneticCodeTable {
+        id: 4,
+        name: "Mold, Protozoan, and Coelenterate Mitochondrial and Mycoplasma/Spiroplasma",
+        amino_acids: "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "--MM------**-------M------------MMMM---------------M------------",
+    },
+    GeneticCodeTable {
+        id: 5,
+        name: "Invertebrate Mitochondrial",
+        amino_acids: "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSSSVVVVAAAADDEEGGGG",
+        starts: "---M------**--------------------MMMM---------------M------------",
+    },
+    GeneticCodeTable {
+        id: 6,
+        name: "Ciliate, Dasycladacean and Hexamita Nuclear",
+        amino_acids: "FFLLSSSSYYQQCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "--------------*--------------------M----------------------------",
+    },
+    GeneticCodeTable {
+        id: 9,
+        name: "Echinoderm and Flatworm Mitochondrial",
+        amino_acids: "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        starts: "----------**-----------------------M---------------M------------",
+    },
+    GeneticCodeTable {
+        id: 10,
+        name: "Euplotid Nuclear",
+        amino_acids: "FFLLSSSSYY**CCCWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "----------**-----------------------M----------------------------",
+    },
+    GeneticCodeTable {
+        id: 11,
+        name: "Bacterial, Archaeal and Plant Plastid",
+        amino_acids: "FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "---M------**--*----M------------MMMM---------------M------------",
+    },
+    GeneticCodeTable {
+        id: 12,
+        name: "Alternative Yeast Nuclear",
+        amino_acids: "FFLLSSSSYY**CC*WLLLSPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "----------**--*----M---------------M----------------------------",
+    },
+    GeneticCodeTable {
+        id: 13,
+        name: "Ascidian Mitochondrial",
+        amino_acids: "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSGGVVVVAAAADDEEGGGG",
+        starts: "---M------**----------------------MM---------------M------------",
+    },
+    GeneticCodeTable {
+        id: 14,
+        name: "Alternative Flatworm Mitochondrial",
+        amino_acids: "FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        starts: "-----------*-----------------------M----------------------------",
+    },
+    GeneticCodeTable {
+        id: 15,
+        name: "Blepharisma Nuclear",
+        amino_acids: "FFLLSSSSYY*QCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "----------*---*--------------------M----------------------------",
+    },
+    GeneticCodeTable {
+        id: 16,
+        name: "Chlorophycean Mitochondrial",
+        amino_acids: "FFLLSSSSYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "----------*---*--------------------M----------------------------",
+    },
+    GeneticCodeTable {
+        id: 21,
+        name: "Trematode Mitochondrial",
+        amino_acids: "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        starts: "----------**-----------------------M---------------M------------",
+    },
+    GeneticCodeTable {
+        id: 22,
+        name: "Scenedesmus obliquus Mitochondrial",
+        amino_acids: "FFLLSS*SYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "------*---*---*--------------------M----------------------------",
+    },
+    GeneticCodeTable {
+        id: 23,
+        name: "Thraustochytrium Mitochondrial",
+        amino_acids: "FF*LSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "--*-------**--*-----------------M--M---------------M------------",
+    },
+    GeneticCodeTable {
+        id: 24,
+        name: "Rhabdopleuridae Mitochondrial",
+        amino_acids: "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG",
+        starts: "---M------**-------M---------------M---------------M------------",
+    },
+    GeneticCodeTable {
+        id: 25,
+        name: "Candidate Division SR1 and Gracilibacteria",
+        amino_acids: "FFLLSSSSYY**CCGWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "---M------**-----------------------M---------------M------------",
+    },
+    GeneticCodeTable {
+        id: 26,
+        name: "Pachysolen tannophilus Nuclear",
+        amino_acids: "FFLLSSSSYY**CC*WLLLAPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "----------**--*----M---------------M----------------------------",
+    },
+    GeneticCodeTable {
+        id: 27,
+        name: "Karyorelict Nuclear",
+        amino_acids: "FFLLSSSSYYQQCCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "--------------*--------------------M----------------------------",
+    },
+    GeneticCodeTable {
+        id: 28,
+        name: "Condylostoma Nuclear",
+        amino_acids: "FFLLSSSSYYQQCCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "----------**--*--------------------M----------------------------",
+    },
+    GeneticCodeTable {
+        id: 29,
+        name: "Mesodinium Nuclear",
+        amino_acids: "FFLLSSSSYYYYCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "--------------*--------------------M----------------------------",
+    },
+    GeneticCodeTable {
+        id: 30,
+        name: "Peritrich Nuclear",
+        amino_acids: "FFLLSSSSYYEECC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "--------------*--------------------M----------------------------",
+    },
+    GeneticCodeTable {
+        id: 31,
+        name: "Blastocrithidia Nuclear",
+        amino_acids: "FFLLSSSSYYEECCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "----------**-----------------------M----------------------------",
+    },
+    GeneticCodeTable {
+        id: 32,
+        name: "Balanophoraceae Plastid",
+        amino_acids: "FFLLSSSSYY*WCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: "---M------*---*----M------------MMMM---------------M------------",
+    },
+    GeneticCodeTable {
+        id: 33,
+        name: "Cephalodiscidae Mitochondrial",
+        amino_acids: "FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG",
+        starts: "---M-------*-------M---------------M---------------M------------",
+    },
+];
+
+fn base_index(base: u8) -> Option<usize> {
+    match base {
+        b'T' | b't' | b'U' | b'u' => Some(0),
+        b'C' | b'c' => Some(1),
+        b'A' | b'a' => Some(2),
+        b'G' | b'g' => Some(3),
+        _ => None,
+    }
+}
+
+fn codon_index(codon: &[u8]) -> Option<usize> {
+    if codon.len() != 3 {
+        return None;
+    }
+
+    let mut idx = 0;
+    for base in codon {
+        idx = idx * 4 + base_index(*base)?;
+    }
+    Some(idx)
+}
+
+fn index_codon(idx: usize) -> [u8; 3] {
+    [
+        TABLE_BASES[idx / 16],
+        TABLE_BASES[(idx / 4) % 4],
+        TABLE_BASES[idx % 4],
+    ]
+}
+
+/// A translation table, giving the amino acid, start and stop codons for each codon
+#[derive(Clone, Copy)]
+pub struct GeneticCode {
+    table: &'static GeneticCodeTable,
+}
+
+impl GeneticCode {
+    /// Looks up an NCBI translation table by number, e.g. 1 for the standard code
+    pub fn from_id(id: u8) -> Option<GeneticCode> {
+        GENETIC_CODE_TABLES
+            .iter()
+            .find(|table| table.id == id)
+            .map(|table| GeneticCode { table })
+    }
+
+    pub fn get_id(&self) -> u8 {
+        self.table.id
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        self.table.name
+    }
+
+    /// The single letter amino acid for a codon, '*' for stop or 'X' for unknown bases
+    pub fn translate(&self, codon: &[u8]) -> u8 {
+        match codon_index(codon) {
+            Some(idx) => self.table.amino_acids.as_bytes()[idx],
+            None => b'X',
+        }
+    }
+
+    pub fn is_start(&self, codon: &[u8]) -> bool {
+        codon_index(codon).is_some_and(|idx| self.table.starts.as_bytes()[idx] == b'M')
+    }
+
+    /// Whether the codon may end a CDS, including context dependent stops
+    pub fn is_stop(&self, codon: &[u8]) -> bool {
+        codon_index(codon).is_some_and(|idx| {
+            self.table.amino_acids.as_bytes()[idx] == b'*'
+                || self.table.starts.as_bytes()[idx] == b'*'
+        })
+    }
+
+    /// Whether the codon is a stop which also codes for an amino acid, so may be read through within a CDS
+    pub fn is_context_stop(&self, codon: &[u8]) -> bool {
+        codon_index(codon).is_some_and(|idx| {
+            self.table.amino_acids.as_bytes()[idx] != b'*'
+                && self.table.starts.as_bytes()[idx] == b'*'
+        })
+    }
+
+    pub fn get_start_codons(&self) -> Vec<[u8; 3]> {
+        (0..64)
+            .map(index_codon)
+            .filter(|codon| self.is_start(codon))
+            .collect()
+    }
+
+    pub fn get_stop_codons(&self) -> Vec<[u8; 3]> {
+        (0..64)
+            .map(index_codon)
+            .filter(|codon| self.is_stop(codon))
+            .collect()
+    }
+
+    pub fn get_all_ids() -> Vec<u8> {
+        let mut ids = GENETIC_CODE_TABLES
+            .iter()
+            .map(|table| table.id)
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids
+    }
+}
+
+impl Default for GeneticCode {
+    fn default() -> Self {
+        GeneticCode::from_id(DEFAULT_GENETIC_CODE).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::genetic_code::{index_codon, GeneticCode, GENETIC_CODE_TABLES};
+
+    #[test]
+    fn test_all_tables() {
+        assert_eq!(GeneticCode::get_all_ids().len(), GENETIC_CODE_TABLES.len());
+
+        for id in GeneticCode::get_all_ids() {
+            let genetic_code = GeneticCode::from_id(id).unwrap();
+            let table = genetic_code.table;
+            assert_eq!(genetic_code.get_id(), id);
+
+            for idx in 0..64 {
+                let codon = index_codon(idx);
+                let amino_acid = table.amino_acids.as_bytes()[idx];
+                let start = table.starts.as_bytes()[idx];
+
+                assert_eq!(genetic_code.translate(&codon), amino_acid);
+                assert_eq!(genetic_code.is_start(&codon), start == b'M');
+                assert_eq!(
+                    genetic_code.is_stop(&codon),
+                    amino_acid == b'*' || start == b'*'
+                );
+                assert_eq!(
+                    genetic_code.is_context_stop(&codon),
+                    amino_acid != b'*' && start == b'*'
+                );
+            }
+
+            assert!(genetic_code.is_start(b"ATG"), "table {}", id);
+            assert!(!genetic_code.get_stop_codons().is_empty(), "table {}", id);
+        }
+    }
+
+    #[test]
+    fn test_standard_code() {
+        let genetic_code = GeneticCode::default();
+        assert_eq!(genetic_code.get_id(), 1);
+
+        assert_eq!(genetic_code.translate(b"ATG"), b'M');
+        assert_eq!(genetic_code.translate(b"TGG"), b'W');
+        assert_eq!(genetic_code.translate(b"GCN"), b'X');
+        assert_eq!(genetic_code.translate(b"TG"), b'X');
+        assert_eq!(
+            genetic_code.get_stop_codons(),
+            vec![*b"TAA", *b"TAG", *b"TGA"]
+        );
+        assert_eq!(
+            genetic_code.get_start_codons(),
+            vec![*b"TTG", *b"CTG", *b"ATG"]
+        );
+        assert!(!genetic_code.is_context_stop(b"TGA"));
+    }
+
+    #[test]
+    fn test_alternative_codes() {
+        let vertebrate_mito = GeneticCode::from_id(2).unwrap();
+        assert_eq!(vertebrate_mito.translate(b"TGA"), b'W');
+        assert!(vertebrate_mito.is_stop(b"AGA"));
+        assert!(vertebrate_mito.is_start(b"ATT"));
+        assert!(!vertebrate_mito.is_start(b"GTT"));
+
+        let bacterial = GeneticCode::from_id(11).unwrap();
+        assert!(bacterial.is_start(b"GTG"));
+        assert!(!bacterial.is_start(b"CTT"));
+
+        assert!(GeneticCode::from_id(7).is_none());
+        assert!(GeneticCode::from_id(34).is_none());
+    }
+
+    #[test]
+    fn test_context_stops() {
+        let karyorelict = GeneticCode::from_id(27).unwrap();
+        assert_eq!(karyorelict.translate(b"TGA"), b'W');
+        assert!(karyorelict.is_stop(b"TGA"));
+        assert!(karyorelict.is_context_stop(b"TGA"));
+        assert!(!karyorelict.is_stop(b"TAA"));
+
+        let condylostoma = GeneticCode::from_id(28).unwrap();
+        for codon in [b"TAA", b"TAG", b"TGA"] {
+            assert!(condylostoma.is_context_stop(codon));
+        }
+        assert_eq!(condylostoma.translate(b"TAA"), b'Q');
+
+        let blastocrithidia = GeneticCode::from_id(31).unwrap();
+        assert_eq!(blastocrithidia.translate(b"TAG"), b'E');
+        assert!(blastocrithidia.is_context_stop(b"TAG"));
+        assert_eq!(blastocrithidia.translate(b"TGA"), b'W');
+        assert!(!blastocrithidia.is_stop(b"TGA"));
+    }
+}
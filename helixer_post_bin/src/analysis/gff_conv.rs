@@ -27,21 +27,152 @@ sequence: String, source: String, feature: GffFeature, start: u64, end: u64, sco
 
  */
 
-use crate::analysis::hmm::{HmmAnnotationLabel, HmmStateRegion};
-use crate::gff::{GffFeature, GffPhase, GffRecord, GffStrand};
+use crate::analysis::hmm::{HmmAnnotationLabel, HmmStateRegion, HmmTranscript};
+use crate::gff::{GffFeature, GffFormat, GffPhase, GffRecord, GffStrand};
+
+// Confidence of one or more regions, combined weighted by region length. The mean posterior is used as
+// the GFF score, with the lowest posterior and summed negative log probability given as attributes
+#[derive(Clone, Copy)]
+struct RegionScores {
+    length: usize,
+    posterior_sum: Option<f64>,
+    min_posterior: Option<f32>,
+    neg_log_prob: f64,
+}
+
+impl RegionScores {
+    fn new() -> RegionScores {
+        RegionScores {
+            length: 0,
+            posterior_sum: None,
+            min_posterior: None,
+            neg_log_prob: 0.0,
+        }
+    }
+
+    fn from_region(region: &HmmStateRegion) -> RegionScores {
+        RegionScores {
+            length: region.len(),
+            posterior_sum: region
+                .get_mean_posterior()
+                .map(|mean| mean as f64 * region.len() as f64),
+            min_posterior: region.get_min_posterior(),
+            neg_log_prob: region.get_neg_log_prob(),
+        }
+    }
+
+    fn add(&mut self, other: &RegionScores) {
+        self.posterior_sum = match (self.posterior_sum, other.posterior_sum) {
+            (Some(sum), Some(other_sum)) => Some(sum + other_sum),
+            (None, other_sum) if self.length == 0 => other_sum,
+            _ => None,
+        };
+        self.min_posterior = match (self.min_posterior, other.min_posterior) {
+            (Some(min), Some(other_min)) => Some(min.min(other_min)),
+            (None, other_min) if self.length == 0 => other_min,
+            _ => None,
+        };
+        self.length += other.length;
+        self.neg_log_prob += other.neg_log_prob;
+    }
+
+    fn get_score(&self) -> Option<f32> {
+        match self.posterior_sum {
+            Some(sum) if self.length > 0 => Some(round_score(sum / self.length as f64)),
+            _ => None,
+        }
+    }
+
+    fn get_attributes(&self) -> String {
+        match self.min_posterior {
+            Some(min) => format!(
+                ";min_posterior={};neg_log_prob={:.2}",
+                round_score(min as f64),
+                self.neg_log_prob
+            ),
+            None => format!(";neg_log_prob={:.2}", self.neg_log_prob),
+        }
+    }
+}
+
+fn round_score(score: f64) -> f32 {
+    ((score * 1000.0).round() / 1000.0) as f32
+}
+
+// Transcript name, UTR5/CDS/UTR3 records, start / stop codon records (GTF only) and scores over the whole transcript
+type TranscriptRecords = (
+    String,
+    Vec<(GffRecord, RegionScores)>,
+    Vec<GffRecord>,
+    RegionScores,
+);
 
-// generate gene, mRNA and exon records, based on UTR5/CDS/UTR3
+// generate gene, mRNA and exon records, based on UTR5/CDS/UTR3 of each named transcript. The gene score
+// is taken from the first (best) transcript
 fn generate_gff_aggregate_records(
-    recs: Vec<GffRecord>,
+    transcripts: Vec<TranscriptRecords>,
     sequence: &str,
     source: &str,
     strand: Option<GffStrand>,
     gene_name: &str,
 ) -> Vec<GffRecord> {
-    if recs.len() == 0 {
+    let transcripts: Vec<_> = transcripts
+        .into_iter()
+        .filter(|(_, recs, _, _)| !recs.is_empty())
+        .collect();
+
+    if transcripts.len() == 0 {
         return Vec::new();
     }
 
+    let gene_start = transcripts
+        .iter()
+        .map(|(_, recs, _, _)| recs[0].0.get_start())
+        .min()
+        .unwrap();
+    let gene_end = transcripts
+        .iter()
+        .map(|(_, recs, _, _)| recs[recs.len() - 1].0.get_end())
+        .max()
+        .unwrap();
+    let gene_scores = transcripts[0].3;
+
+    let mut recs_out = Vec::new();
+
+    let gene_attributes = format!("ID={}{}", gene_name, gene_scores.get_attributes());
+    let gene_rec = GffRecord::new(
+        sequence.to_owned(),
+        source.to_owned(),
+        GffFeature::Gene,
+        gene_start,
+        gene_end,
+        gene_scores.get_score(),
+        strand,
+        None,
+        gene_attributes,
+    );
+    recs_out.push(gene_rec);
+
+    for transcript in transcripts {
+        recs_out.extend(generate_gff_transcript_records(
+            transcript, sequence, source, strand, gene_name,
+        ));
+    }
+
+    recs_out
+}
+
+// generate mRNA and exon records for a single transcript, based on UTR5/CDS/UTR3. Any start / stop codon records
+// follow the transcript's other records, with the stop codon removed from the end of the CDS
+fn generate_gff_transcript_records(
+    transcript: TranscriptRecords,
+    sequence: &str,
+    source: &str,
+    strand: Option<GffStrand>,
+    gene_name: &str,
+) -> Vec<GffRecord> {
+    let (transcript_name, recs, codon_recs, transcript_scores) = transcript;
+
     let mut maybe_transcript_start: Option<u64> = None;
     let mut maybe_transcript_end: Option<u64> = None;
 
@@ -50,7 +181,9 @@ fn generate_gff_aggregate_records(
 
     let mut exon_ranges = Vec::new();
 
-    for rec in recs.iter() {
+    let mut exon_scores = RegionScores::new();
+
+    for (rec, rec_scores) in recs.iter() {
         if maybe_transcript_start == None {
             maybe_transcript_start = Some(rec.get_start());
         }
@@ -58,8 +191,9 @@ fn generate_gff_aggregate_records(
 
         if let (Some(exon_start), Some(exon_end)) = (maybe_exon_start, maybe_exon_end) {
             if exon_end + 1 < rec.get_start() {
-                exon_ranges.push((exon_start, exon_end));
+                exon_ranges.push((exon_start, exon_end, exon_scores));
                 maybe_exon_start = None;
+                exon_scores = RegionScores::new();
             }
         }
 
@@ -67,39 +201,31 @@ fn generate_gff_aggregate_records(
             maybe_exon_start = Some(rec.get_start());
         }
         maybe_exon_end = Some(rec.get_end());
+        exon_scores.add(rec_scores);
     }
 
     if let (Some(exon_start), Some(exon_end)) = (maybe_exon_start, maybe_exon_end) {
-        exon_ranges.push((exon_start, exon_end));
+        exon_ranges.push((exon_start, exon_end, exon_scores));
     }
 
     let transcript_start = maybe_transcript_start.unwrap();
     let transcript_end = maybe_transcript_end.unwrap();
 
-    let mut recs_out = Vec::with_capacity(2 + recs.len() * 2);
+    let mut recs_out = Vec::with_capacity(1 + recs.len() * 2);
 
-    let gene_attributes = format!("ID={}", gene_name);
-    let gene_rec = GffRecord::new(
-        sequence.to_owned(),
-        source.to_owned(),
-        GffFeature::Gene,
-        transcript_start,
-        transcript_end,
-        None,
-        strand,
-        None,
-        gene_attributes,
+    let mrna_attributes = format!(
+        "ID={};Parent={}{}",
+        transcript_name,
+        gene_name,
+        transcript_scores.get_attributes()
     );
-    recs_out.push(gene_rec);
-
-    let mrna_attributes = format!("ID={}.1;Parent={}", gene_name, gene_name);
     let mrna_rec = GffRecord::new(
         sequence.to_owned(),
         source.to_owned(),
         GffFeature::MRNA,
         transcript_start,
         transcript_end,
-        None,
+        transcript_scores.get_score(),
         strand,
         None,
         mrna_attributes,
@@ -110,12 +236,15 @@ fn generate_gff_aggregate_records(
     let mut current_exon_end = None;
     let mut exon_idx = 1;
 
-    for rec in recs {
+    for (rec, _) in recs {
         if current_exon_end.is_none() || current_exon_end.unwrap() < rec.get_start() {
-            let (exon_start, exon_end) = exon_range_iter.next().unwrap();
+            let (exon_start, exon_end, exon_scores) = exon_range_iter.next().unwrap();
             let exon_attributes = format!(
-                "ID={}.1.exon.{};Parent={}.1",
-                gene_name, exon_idx, gene_name
+                "ID={}.exon.{};Parent={}{}",
+                transcript_name,
+                exon_idx,
+                transcript_name,
+                exon_scores.get_attributes()
             );
 
             let exon_rec = GffRecord::new(
@@ -124,7 +253,7 @@ fn generate_gff_aggregate_records(
                 GffFeature::Exon,
                 exon_start,
                 exon_end,
-                None,
+                exon_scores.get_score(),
                 strand,
                 None,
                 exon_attributes,
@@ -139,22 +268,152 @@ fn generate_gff_aggregate_records(
         recs_out.push(rec);
     }
 
+    for codon_rec in codon_recs.iter() {
+        if codon_rec.get_feature() == GffFeature::StopCodon {
+            exclude_from_cds(&mut recs_out, codon_rec);
+        }
+    }
+    recs_out.extend(codon_recs);
+
     recs_out
 }
 
+// Removes the bases of a stop codon part from the end of the CDS record containing it
+fn exclude_from_cds(recs: &mut Vec<GffRecord>, codon_rec: &GffRecord) {
+    let maybe_idx = recs.iter().position(|rec| {
+        rec.get_feature() == GffFeature::CDS && rec.get_end() == codon_rec.get_end()
+    });
+
+    if let Some(idx) = maybe_idx {
+        if recs[idx].get_start() == codon_rec.get_start() {
+            recs.remove(idx);
+        } else {
+            recs[idx].set_end(codon_rec.get_start() - 1);
+        }
+    }
+}
+
+// Ranges (inclusive, 1 based) of the first or last 3 coding bases, which may be split by introns, each with the
+// codon bases before it
+fn get_codon_ranges(coding_ranges: &[(u64, u64)], at_end: bool) -> Vec<(u64, u64, u64)> {
+    let mut ranges = Vec::new();
+    let mut remaining = 3;
+
+    for idx in 0..coding_ranges.len() {
+        if remaining == 0 {
+            break;
+        }
+
+        let (start, end) = if at_end {
+            coding_ranges[coding_ranges.len() - 1 - idx]
+        } else {
+            coding_ranges[idx]
+        };
+
+        let bases = std::cmp::min(remaining, end - start + 1);
+        if at_end {
+            ranges.push((end + 1 - bases, end, remaining - bases));
+        } else {
+            ranges.push((start, start + bases - 1, 3 - remaining));
+        }
+        remaining -= bases;
+    }
+
+    if remaining > 0 {
+        return Vec::new();
+    }
+
+    if at_end {
+        ranges.reverse();
+    }
+
+    ranges
+}
+
+// start_codon / stop_codon records for a transcript, from the bases of the HMM Start and Stop states, which are
+// the first and last 3 coding bases. Codons cut off by a sequence end are left out
+fn generate_codon_records(
+    regions: &[HmmStateRegion],
+    sequence: &str,
+    source: &str,
+    strand: Option<GffStrand>,
+    position: usize,
+    transcript_name: &str,
+) -> Vec<GffRecord> {
+    let coding_regions: Vec<&HmmStateRegion> = regions
+        .iter()
+        .filter(|region| region.get_annotation_label() == HmmAnnotationLabel::Coding)
+        .collect();
+
+    let (first, last) = match (coding_regions.first(), coding_regions.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Vec::new(),
+    };
+
+    let coding_ranges: Vec<(u64, u64)> = coding_regions
+        .iter()
+        .map(|region| {
+            (
+                (region.get_start_pos() + position + 1) as u64,
+                (region.get_end_pos() + position) as u64,
+            )
+        })
+        .collect();
+
+    let mut codons = Vec::new();
+    if !first.is_truncated_start() && first.get_coding_offset() == 0 {
+        codons.push((
+            GffFeature::StartCodon,
+            get_codon_ranges(&coding_ranges, false),
+        ));
+    }
+    if !last.is_truncated_end() {
+        codons.push((
+            GffFeature::StopCodon,
+            get_codon_ranges(&coding_ranges, true),
+        ));
+    }
+
+    let mut recs = Vec::new();
+
+    for (feature, ranges) in codons {
+        for (start, end, codon_offset) in ranges {
+            recs.push(GffRecord::new(
+                sequence.to_owned(),
+                source.to_owned(),
+                feature,
+                start,
+                end,
+                None,
+                strand,
+                Some(GffPhase::from(codon_offset)),
+                format!(
+                    "ID={}.{};Parent={}",
+                    transcript_name, feature, transcript_name
+                ),
+            ));
+        }
+    }
+
+    recs
+}
+
 fn convert_regions_to_gff(
     regions: Vec<HmmStateRegion>,
     sequence: &str,
     source: &str,
     strand: Option<GffStrand>,
     position: usize,
-    gene_name: &str,
-) -> Vec<GffRecord> {
+    transcript_name: &str,
+) -> Vec<(GffRecord, RegionScores)> {
     let mut utr5_idx = 0;
     let mut cds_idx = 0;
     let mut utr3_idx = 0;
 
-    let mut coding_offset = 0;
+    // Non-zero if the gene starts part way through a codon
+    let mut coding_offset = regions
+        .first()
+        .map_or(0, |region| region.get_coding_offset());
 
     let mut region_vec = Vec::new();
 
@@ -166,8 +425,8 @@ fn convert_regions_to_gff(
                 Some((
                     GffFeature::FivePrimeUTR,
                     format!(
-                        "ID={}.1.five_prime_UTR.{};Parent={}.1",
-                        gene_name, utr5_idx, gene_name
+                        "ID={}.five_prime_UTR.{};Parent={}",
+                        transcript_name, utr5_idx, transcript_name
                     ),
                 ))
             }
@@ -176,7 +435,10 @@ fn convert_regions_to_gff(
                 cds_idx += 1;
                 Some((
                     GffFeature::CDS,
-                    format!("ID={}.1.CDS.{};Parent={}.1", gene_name, cds_idx, gene_name),
+                    format!(
+                        "ID={}.CDS.{};Parent={}",
+                        transcript_name, cds_idx, transcript_name
+                    ),
                 ))
             }
             HmmAnnotationLabel::Intron => None,
@@ -186,8 +448,8 @@ fn convert_regions_to_gff(
                 Some((
                     GffFeature::ThreePrimeUTR,
                     format!(
-                        "ID={}.1.three_prime_UTR.{};Parent={}.1",
-                        gene_name, utr3_idx, gene_name
+                        "ID={}.three_prime_UTR.{};Parent={}",
+                        transcript_name, utr3_idx, transcript_name
                     ),
                 ))
             }
@@ -203,19 +465,21 @@ fn convert_regions_to_gff(
                 None
             };
 
+            let scores = RegionScores::from_region(region);
+
             let rec = GffRecord::new(
                 sequence.to_owned(),
                 source.to_owned(),
                 feature,
                 start,
                 end,
-                None,
+                scores.get_score(),
                 strand,
                 phase,
-                attributes,
+                attributes + &scores.get_attributes(),
             );
 
-            region_vec.push(rec);
+            region_vec.push((rec, scores));
 
             if region.get_annotation_label() == HmmAnnotationLabel::Coding {
                 coding_offset += region.len() as u64;
@@ -226,17 +490,85 @@ fn convert_regions_to_gff(
     region_vec
 }
 
-pub fn hmm_solution_to_gff(
-    genes: Vec<(Vec<HmmStateRegion>, usize)>,
-    species: &str,
-    sequence: &str,
-    source: &str,
+// Mark records reaching a truncated gene end (given in forward window coordinates, before any strand swap)
+// as partial, with NCBI style start_range / end_range attributes once in sequence coordinates
+fn mark_partial_records(
+    recs: &mut [GffRecord],
+    truncated_start: Option<u64>,
+    truncated_end: Option<u64>,
     rev: bool,
-    position: usize,
     sequence_length: u64,
-    min_coding_length: usize,
+) {
+    for rec in recs.iter_mut() {
+        let five_prime = truncated_start == Some(rec.get_start());
+        let three_prime = truncated_end == Some(rec.get_end());
+
+        if !five_prime && !three_prime {
+            continue;
+        }
+
+        let (start, end) = if rev {
+            (
+                1 + sequence_length - rec.get_end(),
+                1 + sequence_length - rec.get_start(),
+            )
+        } else {
+            (rec.get_start(), rec.get_end())
+        };
+
+        let (open_start, open_end) = if rev {
+            (three_prime, five_prime)
+        } else {
+            (five_prime, three_prime)
+        };
+
+        rec.add_attribute("partial", "true");
+        if open_start {
+            rec.add_attribute("start_range", &format!(".,{}", start));
+        }
+        if open_end {
+            rec.add_attribute("end_range", &format!("{},.", end));
+        }
+    }
+}
+
+/// Where the genes of a window's HMM solution lie and which of them are output, shared by the GFF and FASTA
+/// conversions so that both name and filter the genes in the same way
+pub struct WindowContext<'a> {
+    pub species: &'a str,
+    pub sequence: &'a str,
+    pub rev: bool,
+    pub position: usize, // Start of the window, in its strand's coordinates
+    pub sequence_length: u64,
+    pub min_coding_length: usize,
+}
+
+/// Gene ID, numbered in order through the sequence (both strands)
+pub fn get_gene_name(species: &str, sequence: &str, gene_idx: usize) -> String {
+    format!("{}_{}_{:06}", species, sequence, gene_idx)
+}
+
+/// Transcript ID, numbered from 1 within the gene
+pub fn get_transcript_name(gene_name: &str, transcript_idx: usize) -> String {
+    format!("{}.{}", gene_name, transcript_idx + 1)
+}
+
+pub fn hmm_solution_to_gff(
+    genes: Vec<Vec<HmmTranscript>>,
+    window: &WindowContext,
+    source: &str,
+    format: GffFormat,
     gene_idx: &mut usize,
 ) -> Vec<GffRecord> {
+    let WindowContext {
+        species,
+        sequence,
+        rev,
+        position,
+        sequence_length,
+        min_coding_length,
+    } = *window;
+
     //        if genes.len() > 1
     //            { println!("{} genes at {} in {}", genes.len(), position, sequence); }
 
@@ -244,19 +576,82 @@ pub fn hmm_solution_to_gff(
 
     let strand = Some(GffStrand::Forward); // Initially generate everything as forward
 
-    for (gene_regions, coding_length) in genes {
-        if coding_length >= min_coding_length {
-            let gene_name = format!("{}_{}_{:06}", species, sequence, *gene_idx);
-            let gene_gff_recs = convert_regions_to_gff(
-                gene_regions,
+    for transcripts in genes {
+        // The gene is kept if the best path's transcript is long enough, alternatives are then checked individually
+        if transcripts[0].1 >= min_coding_length {
+            let gene_name = get_gene_name(species, sequence, *gene_idx);
+
+            // Window coordinates of any gene ends truncated by the sequence ends
+            let truncated_start = transcripts
+                .iter()
+                .filter(|(regions, _)| regions[0].is_truncated_start())
+                .map(|(regions, _)| (regions[0].get_start_pos() + position + 1) as u64)
+                .next();
+            let truncated_end = transcripts
+                .iter()
+                .filter(|(regions, _)| regions[regions.len() - 1].is_truncated_end())
+                .map(|(regions, _)| (regions[regions.len() - 1].get_end_pos() + position) as u64)
+                .next();
+
+            let mut transcript_gff_recs = Vec::with_capacity(transcripts.len());
+
+            for (transcript_idx, (transcript_regions, _)) in transcripts
+                .into_iter()
+                .filter(|(_, coding_length)| *coding_length >= min_coding_length)
+                .enumerate()
+            {
+                // Gene and mRNA scores cover the whole transcript span, including introns
+                let mut transcript_scores = RegionScores::new();
+                for region in transcript_regions.iter() {
+                    transcript_scores.add(&RegionScores::from_region(region));
+                }
+
+                let transcript_name = get_transcript_name(&gene_name, transcript_idx);
+                let codon_recs = if format == GffFormat::Gtf {
+                    generate_codon_records(
+                        &transcript_regions,
+                        sequence,
+                        source,
+                        strand,
+                        position,
+                        &transcript_name,
+                    )
+                } else {
+                    Vec::new()
+                };
+                let gff_recs = convert_regions_to_gff(
+                    transcript_regions,
+                    sequence,
+                    source,
+                    strand,
+                    position,
+                    &transcript_name,
+                );
+                transcript_gff_recs.push((
+                    transcript_name,
+                    gff_recs,
+                    codon_recs,
+                    transcript_scores,
+                ));
+            }
+
+            let mut gene_gff_recs = generate_gff_aggregate_records(
+                transcript_gff_recs,
                 sequence,
                 source,
                 strand,
-                position,
                 &gene_name,
             );
-            let gene_gff_recs =
-                generate_gff_aggregate_records(gene_gff_recs, sequence, source, strand, &gene_name);
+
+            if truncated_start.is_some() || truncated_end.is_some() {
+                mark_partial_records(
+                    &mut gene_gff_recs,
+                    truncated_start,
+                    truncated_end,
+                    rev,
+                    sequence_length,
+                );
+            }
 
             all_gff_recs.extend(gene_gff_recs);
             *gene_idx += 1;
@@ -271,3 +666,143 @@ pub fn hmm_solution_to_gff(
 
     all_gff_recs
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::genetic_code::GeneticCode;
+    use crate::analysis::gff_conv::{hmm_solution_to_gff, WindowContext};
+    use crate::analysis::hmm::{HmmConfig, HmmTranscript, PredictionHmm};
+    use crate::analysis::test_window::get_one_hot_window;
+    use crate::gff::{GffFeature, GffFormat};
+
+    const UTR5: &str = "CTCCTTCTCCTTCCTCTTCC";
+    const UTR3: &str = "CCTTCTCCTTCTCCTTCCTC";
+
+    fn solve_genes(sequence: &str, coding: &[(usize, usize)]) -> Vec<Vec<HmmTranscript>> {
+        let genetic_code = GeneticCode::from_id(1).unwrap();
+        let config = HmmConfig::new();
+
+        let window = get_one_hot_window(sequence, |position| {
+            if coding
+                .iter()
+                .any(|(start, end)| (*start..*end).contains(&position))
+            {
+                [0.1, 0.1, 0.7, 0.1]
+            } else if position > coding[0].0 && position < coding[coding.len() - 1].1 {
+                [0.1, 0.1, 0.1, 0.7]
+            } else {
+                [0.7, 0.1, 0.1, 0.1]
+            }
+        });
+
+        let mut hmm = PredictionHmm::new(window, &config, &genetic_code);
+        hmm.set_sequence_bounds(false, false);
+        hmm.solve().unwrap().trace_genes()
+    }
+
+    // GTF feature, start, end and phase of the records of a window starting 100 bases into a 1000 base sequence
+    fn get_gtf_records(
+        genes: Vec<Vec<HmmTranscript>>,
+        rev: bool,
+    ) -> Vec<(String, u64, u64, String)> {
+        let window = WindowContext {
+            species: "Sp",
+            sequence: "chr1",
+            rev,
+            position: 100,
+            sequence_length: 1000,
+            min_coding_length: 30,
+        };
+        let mut gene_idx = 1;
+
+        hmm_solution_to_gff(genes, &window, "Helixer", GffFormat::Gtf, &mut gene_idx)
+            .iter()
+            .filter(|rec| rec.get_feature() != GffFeature::Gene)
+            .map(|rec| {
+                (
+                    rec.get_feature().as_gtf_str().to_string(),
+                    rec.get_start(),
+                    rec.get_end(),
+                    rec.get_phase()
+                        .map_or(".", |phase| phase.as_str())
+                        .to_string(),
+                )
+            })
+            .collect()
+    }
+
+    fn record(feature: &str, start: u64, end: u64, phase: &str) -> (String, u64, u64, String) {
+        (feature.to_string(), start, end, phase.to_string())
+    }
+
+    #[test]
+    fn test_gtf_codons() {
+        // A single exon gene, with a UTR base either side of the CDS at window positions 20 - 55
+        let sequence = [UTR5, "ATGGCCGCCGCCGCCGCCGCCGCCGCCGCCGCCTAA", UTR3].concat();
+
+        // The stop codon follows the CDS rather than ending it
+        assert_eq!(
+            get_gtf_records(solve_genes(&sequence, &[(20, 56)]), false),
+            vec![
+                record("transcript", 120, 157, "."),
+                record("exon", 120, 157, "."),
+                record("5UTR", 120, 120, "."),
+                record("CDS", 121, 153, "0"),
+                record("3UTR", 157, 157, "."),
+                record("start_codon", 121, 123, "0"),
+                record("stop_codon", 154, 156, "0"),
+            ]
+        );
+
+        // On the reverse strand the window counts back from the sequence end, so the codons swap ends
+        assert_eq!(
+            get_gtf_records(solve_genes(&sequence, &[(20, 56)]), true),
+            vec![
+                record("transcript", 844, 881, "."),
+                record("exon", 844, 881, "."),
+                record("5UTR", 881, 881, "."),
+                record("CDS", 848, 880, "0"),
+                record("3UTR", 844, 844, "."),
+                record("start_codon", 878, 880, "0"),
+                record("stop_codon", 845, 847, "0"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gtf_stop_codon_split_by_intron() {
+        // The TAA stop codon is split T / AA by an intron at window positions 54 - 113
+        let intron = [
+            "GTAAGT",
+            "CTTCCTTCCTCTTCCTTCCTCTTCCTTCCTCTTCCTTCCTCTTCCTTC",
+            "TTTCAG",
+        ]
+        .concat();
+        let sequence = [
+            UTR5,
+            "ATGGCCGCCGCCGCCGCCGCCGCCGCCGCCGCCT",
+            &intron,
+            "AA",
+            UTR3,
+        ]
+        .concat();
+        let genes = solve_genes(&sequence, &[(20, 54), (114, 116)]);
+
+        // The CDS of the second exon is entirely stop codon, so only the stop codon records remain there, the
+        // second with the phase of a codon entered one base in
+        assert_eq!(
+            get_gtf_records(genes, false),
+            vec![
+                record("transcript", 120, 217, "."),
+                record("exon", 120, 154, "."),
+                record("5UTR", 120, 120, "."),
+                record("CDS", 121, 153, "0"),
+                record("exon", 215, 217, "."),
+                record("3UTR", 217, 217, "."),
+                record("start_codon", 121, 123, "0"),
+                record("stop_codon", 154, 154, "0"),
+                record("stop_codon", 215, 216, "2"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,458 @@
+use crate::gff::GffStrand;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+// Per base hint flags, in window coordinates
+pub const HINT_EXON: u8 = 1; // Base is within an exon hint (UTR or coding)
+pub const HINT_CDS: u8 = 2; // Base is within a CDS hint
+pub const HINT_INTRON: u8 = 4; // Base is within an intron hint
+pub const HINT_DONOR: u8 = 8; // An intron hint starts at this base
+pub const HINT_ACCEPTOR: u8 = 16; // An intron hint ends before this base
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum HintKind {
+    Intron,
+    Exon,
+    CDS,
+}
+
+impl HintKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HintKind::Intron => "intron",
+            HintKind::Exon => "exon",
+            HintKind::CDS => "CDS",
+        }
+    }
+
+    // Feature types accepted from GFF / GTF hint files, including AUGUSTUS style hint features
+    fn from_gff_feature(feature: &str) -> Option<HintKind> {
+        match feature {
+            "intron" | "splice_junction" => Some(HintKind::Intron),
+            "exon" | "exonpart" | "ep" | "five_prime_UTR" | "three_prime_UTR" | "UTR" | "5UTR"
+            | "3UTR" => Some(HintKind::Exon),
+            "CDS" | "CDSpart" | "cds" => Some(HintKind::CDS),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Hint {
+    kind: HintKind,
+    start: u64,                // 0 based, inclusive, forward strand coordinates
+    end: u64,                  // 0 based, exclusive
+    strand: Option<GffStrand>, // None for introns on the strand matching their splice site motifs
+}
+
+#[derive(Default)]
+struct SequenceHints {
+    hints: Vec<Hint>, // Sorted by start
+    max_length: u64,  // Longest hint, bounding the search for the hints overlapping a window
+}
+
+/// Intron, exon and CDS hints from external evidence (e.g. RNA-seq junctions or protein alignments),
+/// by sequence name
+pub struct Hints {
+    sequences: HashMap<String, SequenceHints>,
+    count: usize,
+}
+
+fn invalid_data(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn parse_coord(value: Option<&str>, line_no: usize) -> Result<u64> {
+    value
+        .and_then(|v| v.trim().parse().ok())
+        .ok_or_else(|| invalid_data(format!("Invalid coordinate on line {}", line_no)))
+}
+
+fn parse_strand(value: Option<&str>) -> Option<GffStrand> {
+    match value {
+        Some("+") => Some(GffStrand::Forward),
+        Some("-") => Some(GffStrand::Reverse),
+        _ => None,
+    }
+}
+
+// Whether an intron without a strand has splice site motifs (GT / GC .. AG or AT .. AC) on the window's strand,
+// given the positions of its first base and the base after it within the window's letters, None if outside the
+// window. With only one end in the window just the U2 motifs count, as the U12 AC acceptor reads as a GT donor on
+// the opposite strand
+fn has_splice_motifs(letters: &[u8], start: Option<usize>, end: Option<usize>) -> bool {
+    let donor = start.and_then(|start| letters.get(start..start + 2));
+    let acceptor = end.and_then(|end| letters.get(end.checked_sub(2)?..end));
+
+    match (donor, acceptor) {
+        (Some(donor), Some(acceptor)) => {
+            ((donor == b"GT" || donor == b"GC") && acceptor == b"AG")
+                || (donor == b"AT" && acceptor == b"AC")
+        }
+        (Some(donor), None) => donor == b"GT" || donor == b"GC",
+        (None, Some(acceptor)) => acceptor == b"AG",
+        (None, None) => false,
+    }
+}
+
+impl Hints {
+    pub fn new() -> Hints {
+        Hints {
+            sequences: HashMap::new(),
+            count: 0,
+        }
+    }
+
+    /// Loads hints from a file, returning the number loaded. Files ending in .bed are read as splice junctions
+    /// (BED6 with the intron as the feature, or BED12 with the intron between the two blocks), anything else
+    /// as GFF3 / GTF with intron, exon and CDS features
+    pub fn load_file(&mut self, path: &Path) -> Result<usize> {
+        let contents = fs::read_to_string(path)?;
+
+        let is_bed = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("bed"));
+
+        let result = if is_bed {
+            self.load_bed(&contents)
+        } else {
+            self.load_gff(&contents)
+        };
+
+        self.sort_hints();
+
+        result.map_err(|err| invalid_data(format!("{} in hints file {}", err, path.display())))
+    }
+
+    fn load_bed(&mut self, contents: &str) -> Result<usize> {
+        let mut loaded = 0;
+
+        for (idx, line) in contents.lines().enumerate() {
+            let line_no = idx + 1;
+            if line.trim().is_empty()
+                || line.starts_with('#')
+                || line.starts_with("track")
+                || line.starts_with("browser")
+            {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 3 {
+                return Err(invalid_data(format!(
+                    "Expected at least 3 columns on line {}",
+                    line_no
+                )));
+            }
+
+            let mut start = parse_coord(fields.get(1).copied(), line_no)?;
+            let mut end = parse_coord(fields.get(2).copied(), line_no)?;
+            let strand = parse_strand(fields.get(5).copied());
+
+            // BED12 junctions give the flanking exon overhangs as two blocks
+            if fields.len() >= 12 && fields[9].trim() == "2" {
+                let sizes: Vec<&str> = fields[10].trim_end_matches(',').split(',').collect();
+                let starts: Vec<&str> = fields[11].trim_end_matches(',').split(',').collect();
+
+                let first_size = parse_coord(sizes.first().copied(), line_no)?;
+                let second_start = parse_coord(starts.get(1).copied(), line_no)?;
+
+                end = start + second_start;
+                start += first_size;
+            }
+
+            if self.add(fields[0], HintKind::Intron, start, end, strand) {
+                loaded += 1;
+            }
+        }
+
+        Ok(loaded)
+    }
+
+    fn load_gff(&mut self, contents: &str) -> Result<usize> {
+        let mut loaded = 0;
+
+        for (idx, line) in contents.lines().enumerate() {
+            let line_no = idx + 1;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 8 {
+                return Err(invalid_data(format!(
+                    "Expected at least 8 columns on line {}",
+                    line_no
+                )));
+            }
+
+            if let Some(kind) = HintKind::from_gff_feature(fields[2]) {
+                let start = parse_coord(Some(fields[3]), line_no)?;
+                let end = parse_coord(Some(fields[4]), line_no)?;
+                if start == 0 {
+                    return Err(invalid_data(format!(
+                        "GFF coordinates start at 1, found 0 on line {}",
+                        line_no
+                    )));
+                }
+
+                if self.add(
+                    fields[0],
+                    kind,
+                    start - 1,
+                    end,
+                    parse_strand(Some(fields[6])),
+                ) {
+                    loaded += 1;
+                }
+            }
+        }
+
+        Ok(loaded)
+    }
+
+    // Sorts each sequence's hints by start, for get_window_flags
+    fn sort_hints(&mut self) {
+        for sequence_hints in self.sequences.values_mut() {
+            sequence_hints.hints.sort_by_key(|hint| hint.start);
+        }
+    }
+
+    // Empty hints, and exon or CDS hints without a strand, which could force a gene onto either strand, are ignored,
+    // returning false
+    fn add(
+        &mut self,
+        sequence: &str,
+        kind: HintKind,
+        start: u64,
+        end: u64,
+        strand: Option<GffStrand>,
+    ) -> bool {
+        if end <= start || (strand.is_none() && kind != HintKind::Intron) {
+            return false;
+        }
+
+        let sequence_hints = self.sequences.entry(sequence.to_string()).or_default();
+        sequence_hints.hints.push(Hint {
+            kind,
+            start,
+            end,
+            strand,
+        });
+        sequence_hints.max_length = std::cmp::max(sequence_hints.max_length, end - start);
+        self.count += 1;
+
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Number of hints of each kind, for reporting
+    pub fn get_kind_counts(&self) -> Vec<(HintKind, usize)> {
+        [HintKind::Intron, HintKind::Exon, HintKind::CDS]
+            .iter()
+            .map(|kind| {
+                let count = self
+                    .sequences
+                    .values()
+                    .flat_map(|sequence_hints| sequence_hints.hints.iter())
+                    .filter(|hint| hint.kind == *kind)
+                    .count();
+                (*kind, count)
+            })
+            .collect()
+    }
+
+    /// Whether the sequence has any hints, so get_window_flags needs the window's letters
+    pub fn has_sequence(&self, sequence: &str) -> bool {
+        self.sequences.contains_key(sequence)
+    }
+
+    /// Hint flags for each base of a window, in the window's strand orientation. Empty if the sequence has no hints
+    ///
+    /// # Arguments
+    ///
+    /// * `sequence` - The sequence name
+    /// * `rev` - True if the window is on the reverse strand, with positions counting from the sequence end
+    /// * `sequence_length` - Length of the sequence
+    /// * `start_pos` - Start of the window in its strand's coordinates
+    /// * `letters` - Bases of the window in its strand's orientation, to place introns without a strand
+    pub fn get_window_flags(
+        &self,
+        sequence: &str,
+        rev: bool,
+        sequence_length: u64,
+        start_pos: usize,
+        letters: &[u8],
+    ) -> Vec<u8> {
+        let sequence_hints = match self.sequences.get(sequence) {
+            Some(sequence_hints) => sequence_hints,
+            None => return Vec::new(),
+        };
+
+        let window_start = start_pos as u64;
+        let window_end = (start_pos + letters.len()) as u64;
+        let mut flags = vec![0u8; letters.len()];
+
+        let window_strand = GffStrand::from_rev(rev);
+
+        // Window in forward strand coordinates, to find the hints overlapping it
+        let (forward_start, forward_end) = if rev {
+            (
+                sequence_length.saturating_sub(window_end),
+                sequence_length.saturating_sub(window_start),
+            )
+        } else {
+            (window_start, window_end)
+        };
+
+        let hints = &sequence_hints.hints;
+        let first_hint = hints.partition_point(|hint| {
+            hint.start.saturating_add(sequence_hints.max_length) <= forward_start
+        });
+        let last_hint = hints.partition_point(|hint| hint.start < forward_end);
+
+        for hint in hints[first_hint..last_hint.max(first_hint)].iter() {
+            if hint.strand.is_some_and(|strand| strand != window_strand) {
+                continue;
+            }
+
+            // Hint in the window's strand coordinates, so introns always run donor to acceptor
+            let (start, end) = if rev {
+                (
+                    sequence_length.saturating_sub(hint.end),
+                    sequence_length.saturating_sub(hint.start),
+                )
+            } else {
+                (hint.start, hint.end)
+            };
+
+            if end <= window_start || start >= window_end {
+                continue;
+            }
+
+            if hint.strand.is_none() {
+                let in_window = |position: u64| {
+                    (window_start..=window_end)
+                        .contains(&position)
+                        .then(|| (position - window_start) as usize)
+                };
+                if !has_splice_motifs(letters, in_window(start), in_window(end)) {
+                    continue;
+                }
+            }
+
+            let base_flag = match hint.kind {
+                HintKind::Intron => HINT_INTRON,
+                HintKind::Exon => HINT_EXON,
+                HintKind::CDS => HINT_CDS,
+            };
+
+            let first = std::cmp::max(start, window_start) - window_start;
+            let last = std::cmp::min(end, window_end) - window_start;
+            for flag in flags[first as usize..last as usize].iter_mut() {
+                *flag |= base_flag;
+            }
+
+            if hint.kind == HintKind::Intron {
+                if start >= window_start {
+                    flags[(start - window_start) as usize] |= HINT_DONOR;
+                }
+                if end < window_end {
+                    flags[(end - window_start) as usize] |= HINT_ACCEPTOR;
+                }
+            }
+        }
+
+        flags
+    }
+}
+
+impl Default for Hints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::hints::{Hints, HINT_ACCEPTOR, HINT_DONOR, HINT_EXON, HINT_INTRON};
+
+    // 40 bases with a GT .. AG intron over 10..30 on the forward strand
+    fn get_letters(rev: bool) -> Vec<u8> {
+        let forward = [&b"AAAAAAAAAAGT"[..], &[b'A'; 16], b"AGAAAAAAAAAA"].concat();
+        if rev {
+            forward
+                .iter()
+                .rev()
+                .map(|base| match base {
+                    b'A' => b'T',
+                    b'C' => b'G',
+                    b'G' => b'C',
+                    _ => b'A',
+                })
+                .collect()
+        } else {
+            forward
+        }
+    }
+
+    #[test]
+    fn test_unstranded_introns_follow_splice_motifs() {
+        let mut hints = Hints::new();
+        assert_eq!(hints.load_bed("chr1\t10\t30\n").unwrap(), 1);
+
+        let forward = hints.get_window_flags("chr1", false, 40, 0, &get_letters(false));
+        assert_eq!(forward[10], HINT_INTRON | HINT_DONOR);
+        assert_eq!(forward[30], HINT_ACCEPTOR);
+        assert_eq!(
+            forward
+                .iter()
+                .filter(|flags| **flags & HINT_INTRON != 0)
+                .count(),
+            20
+        );
+
+        let reverse = hints.get_window_flags("chr1", true, 40, 0, &get_letters(true));
+        assert!(reverse.iter().all(|flags| *flags == 0));
+
+        // Only the donor is within the window
+        let letters = get_letters(false);
+        let partial = hints.get_window_flags("chr1", false, 40, 5, &letters[5..20]);
+        assert_eq!(partial[5], HINT_INTRON | HINT_DONOR);
+    }
+
+    #[test]
+    fn test_window_flags_find_overlapping_hints() {
+        let gff = "chr1\tx\texon\t1\t5\t.\t+\t.\t.\n\
+                   chr1\tx\texon\t21\t40\t.\t.\t.\t.\n\
+                   chr1\tx\texon\t31\t35\t.\t-\t.\t.\n\
+                   chr1\tx\texon\t36\t38\t.\t+\t.\t.\n";
+
+        let mut hints = Hints::new();
+        assert_eq!(hints.load_gff(gff).unwrap(), 3); // The exon without a strand is ignored
+        hints.sort_hints();
+
+        let letters = get_letters(false);
+        let forward = hints.get_window_flags("chr1", false, 40, 30, &letters[30..]);
+        let exon_bases: Vec<usize> = (0..forward.len())
+            .filter(|position| forward[*position] & HINT_EXON != 0)
+            .collect();
+        assert_eq!(exon_bases, vec![5, 6, 7]);
+
+        // The minus strand exon over 30..35 is at 5..10 counting from the sequence end
+        let reverse = hints.get_window_flags("chr1", true, 40, 0, &get_letters(true)[..20]);
+        let exon_bases: Vec<usize> = (0..reverse.len())
+            .filter(|position| reverse[*position] & HINT_EXON != 0)
+            .collect();
+        assert_eq!(exon_bases, vec![5, 6, 7, 8, 9]);
+    }
+}
@@ -1,7 +1,16 @@
+use crate::analysis::genetic_code::GeneticCode;
+use crate::analysis::hints::{HINT_ACCEPTOR, HINT_CDS, HINT_DONOR, HINT_EXON, HINT_INTRON};
 use crate::results::conv::{Bases, ClassPrediction, PhasePrediction};
+use crate::{debug, info, trace, warn};
 use std::cmp::Ordering;
-use std::collections::BinaryHeap;
+use std::collections::{BinaryHeap, HashMap};
 
+mod config;
+mod site_models;
+
+use config::BASE_PROB_FLOOR;
+pub use config::{HmmConfig, HmmSolver};
+use site_models::SiteModel;
 
 fn convert_raw_pred<const N: usize>(raw_pred: &[f32; N]) -> [f64; N] {
     let mut pred: [f64; N] = [0.0; N];
@@ -72,14 +81,12 @@ impl ClassPredPenalty {
     }
 }
 
-const CLASS_PRED_PROB_FLOOR: f64 = 0.000_000_001; // Prevent infinite penalties
-
-impl From<&ClassPrediction> for ClassPredPenalty {
-    fn from(pred: &ClassPrediction) -> Self {
+impl ClassPredPenalty {
+    fn new(pred: &ClassPrediction, floor: f64) -> Self {
         let raw_pred = pred.get();
 
         let converted_pred = convert_raw_pred(raw_pred);
-        let neg_log_prob = raw_pred_to_neg_log_prob(&converted_pred, CLASS_PRED_PROB_FLOOR);
+        let neg_log_prob = raw_pred_to_neg_log_prob(&converted_pred, floor);
         let penalty = neg_log_prob_to_penalty(&neg_log_prob);
 
         ClassPredPenalty { /*neg_log_prob,*/ penalty }
@@ -111,15 +118,12 @@ impl PhasePredPenalty {
     }
 }
 
-const PHASE_PRED_PROB_FLOOR: f64 = 0.000_000_001; // Prevent infinite penalties
-                                                  //const PHASE_PRED_PROB_FLOOR: f64 = 0.5; // Limit impact of incorrect phase
-
-impl From<&PhasePrediction> for PhasePredPenalty {
-    fn from(pred: &PhasePrediction) -> Self {
+impl PhasePredPenalty {
+    fn new(pred: &PhasePrediction, floor: f64) -> Self {
         let raw_pred = pred.get();
 
         let converted_pred = convert_raw_pred(raw_pred);
-        let neg_log_prob = raw_pred_to_neg_log_prob(&converted_pred, PHASE_PRED_PROB_FLOOR);
+        let neg_log_prob = raw_pred_to_neg_log_prob(&converted_pred, floor);
         let penalty = neg_log_prob_to_penalty(&neg_log_prob);
 
         PhasePredPenalty { /* neg_log_prob, */ penalty }
@@ -178,13 +182,15 @@ impl PredPenalty {
 
 }
 
-const PHASE_RETAIN: f64 = 0.20; // Adjust as needed
-
-const PHASE_DILUTE: f64 = 1.0 - PHASE_RETAIN;
-const PRED_PROB_FLOOR: f64 = 0.000_000_001; // Prevent infinite penalties
+impl PredPenalty {
+    fn new(
+        class_pred: &ClassPrediction,
+        phase_pred: &PhasePrediction,
+        phase_retain: f64,
+        floor: f64,
+    ) -> Self {
+        let phase_dilute = 1.0 - phase_retain;
 
-impl From<(&ClassPrediction, &PhasePrediction)> for PredPenalty {
-    fn from((class_pred, phase_pred): (&ClassPrediction, &PhasePrediction)) -> Self {
         let phase0 = phase_pred.get_phase0() as f64;
         let phase1 = phase_pred.get_phase1() as f64;
         let phase2 = phase_pred.get_phase2() as f64;
@@ -208,9 +214,9 @@ impl From<(&ClassPrediction, &PhasePrediction)> for PredPenalty {
         //let dilution_target = coding / 3.0;
         let dilution_target = coding;
 
-        let phase0 = phase0 * PHASE_RETAIN + dilution_target * PHASE_DILUTE;
-        let phase1 = phase1 * PHASE_RETAIN + dilution_target * PHASE_DILUTE;
-        let phase2 = phase2 * PHASE_RETAIN + dilution_target * PHASE_DILUTE;
+        let phase0 = phase0 * phase_retain + dilution_target * phase_dilute;
+        let phase1 = phase1 * phase_retain + dilution_target * phase_dilute;
+        let phase2 = phase2 * phase_retain + dilution_target * phase_dilute;
 
         /*
                 // Approach 2: rescale total phase to 1, dilute towards 1, then scale by coding
@@ -238,38 +244,19 @@ impl From<(&ClassPrediction, &PhasePrediction)> for PredPenalty {
             class_pred.get_intron() as f64,
         ];
 
-        let neg_log_prob = raw_pred_to_neg_log_prob(&raw_probs, PRED_PROB_FLOOR);
+        let neg_log_prob = raw_pred_to_neg_log_prob(&raw_probs, floor);
         let penalty = neg_log_prob_to_penalty(&neg_log_prob);
 
         PredPenalty { neg_log_prob, penalty }
     }
 }
 
-const BASE_PROB_FLOOR: f64 = 0.000_000_001; // Prevent infinite penalties
-
 #[derive(Clone, Copy)]
 pub struct BasesPenalty { // Ordering is C, A, T, G
     //neg_log_prob: [f64; 4], // Negated log probability, lower value is more likely
     penalty: [f64; 4], // Penalty is adjusted negated log probability with min prob (most likely) subtracted from all
 }
 
-fn min2(a: f64, b: f64) -> f64 {
-    if a < b {
-        a
-    } else {
-        b
-    }
-}
-
-fn min3(a: f64, b: f64, c: f64) -> f64 {
-    let ab = if a < b { a } else { b };
-    if ab < c {
-        ab
-    } else {
-        c
-    }
-}
-
 impl BasesPenalty {
     pub fn get_c(&self) -> f64 {
         self.penalty[0]
@@ -287,6 +274,33 @@ impl BasesPenalty {
         self.penalty[3]
     }
 
+    pub fn get_base(&self, base: u8) -> f64 {
+        match base {
+            b'C' => self.get_c(),
+            b'A' => self.get_a(),
+            b'T' => self.get_t(),
+            b'G' => self.get_g(),
+            _ => panic!("Unexpected base {}", base as char),
+        }
+    }
+
+    // Lowest penalty of the given bases, None if no bases are given
+    fn get_min_of(&self, bases: &[u8]) -> Option<f64> {
+        bases
+            .iter()
+            .map(|base| self.get_base(*base))
+            .reduce(f64::min)
+    }
+
+    // Lowest penalty of the bases not given, None if all bases are given
+    fn get_min_excluding(&self, bases: &[u8]) -> Option<f64> {
+        b"CATG"
+            .iter()
+            .filter(|base| !bases.contains(base))
+            .map(|base| self.get_base(*base))
+            .reduce(f64::min)
+    }
+
     pub fn as_str(&self) -> char {
         if self.penalty[0] < BASE_PROB_FLOOR {
             'C'
@@ -300,18 +314,158 @@ impl BasesPenalty {
     }
 }
 
-impl From<&Bases> for BasesPenalty {
-    fn from(bases: &Bases) -> Self {
+impl BasesPenalty {
+    fn new(bases: &Bases, floor: f64) -> Self {
         let raw_bases = bases.get();
 
         let converted_pred = convert_raw_pred(raw_bases);
-        let neg_log_prob = raw_pred_to_neg_log_prob(&converted_pred, BASE_PROB_FLOOR);
+        let neg_log_prob = raw_pred_to_neg_log_prob(&converted_pred, floor);
         let penalty = neg_log_prob_to_penalty(&neg_log_prob);
 
         BasesPenalty { /*neg_log_prob, */ penalty }
     }
 }
 
+const START0_SLOTS: usize = 3;
+const START1_SLOTS: usize = 3;
+const STOP0_SLOTS: usize = 2;
+const STOP1_SLOTS: usize = 3;
+
+// Start and stop codons of the genetic code, arranged to match the Start and Stop states
+//
+// Start codons are matched exactly, with a Start0 slot for each set of first bases which can be completed by the
+// same second and third bases, and a Start1 slot for each Start0 slot and second base, e.g. with alternative
+// starts for the bacterial code, A and C / G / T, then AT for each. Stop codons are matched exactly in the same
+// way, with a Stop0 slot for each distinct first base and a Stop1 slot for each distinct first two bases, e.g. T
+// and TA / TG for the standard code. Context dependent stops only end a CDS at a Stop2, as they otherwise code
+// for an amino acid
+#[derive(Clone)]
+struct CodonStates {
+    start0_bases: Vec<Vec<u8>>,        // First bases for each Start0 slot
+    start1_prefixes: Vec<(usize, u8)>, // Start0 slot and second base for each Start1 slot
+    start2_bases: Vec<Vec<u8>>,        // Third bases completing a start codon for each Start1 slot
+    stop0_bases: Vec<u8>,              // First base for each Stop0 slot
+    stop1_prefixes: Vec<(usize, u8)>,  // Stop0 slot and second base for each Stop1 slot
+    stop2_bases: Vec<Vec<u8>>,         // Third bases completing a stop codon for each Stop1 slot
+    stop2_hard_bases: Vec<Vec<u8>>, // Third bases which always complete a stop codon, for each Stop1 slot
+}
+
+// Finds the slot with the given key, adding it if not found
+fn get_codon_slot<T: PartialEq>(slots: &mut Vec<T>, key: T) -> usize {
+    match slots.iter().position(|slot| *slot == key) {
+        Some(slot) => slot,
+        None => {
+            slots.push(key);
+            slots.len() - 1
+        }
+    }
+}
+
+impl CodonStates {
+    fn new(genetic_code: &GeneticCode, alternative_starts: bool) -> Result<CodonStates, String> {
+        let start_codons = if alternative_starts {
+            genetic_code.get_start_codons()
+        } else {
+            vec![*b"ATG"]
+        };
+
+        // Second and third bases completing a start codon for each first base
+        let mut first_bases: Vec<u8> = Vec::new();
+        let mut first_base_suffixes: Vec<Vec<[u8; 2]>> = Vec::new();
+        for codon in start_codons.iter() {
+            let idx = get_codon_slot(&mut first_bases, codon[0]);
+            if idx == first_base_suffixes.len() {
+                first_base_suffixes.push(Vec::new());
+            }
+            first_base_suffixes[idx].push([codon[1], codon[2]]);
+        }
+
+        let mut start0_suffixes: Vec<Vec<[u8; 2]>> = Vec::new();
+        let mut start0_bases: Vec<Vec<u8>> = Vec::new();
+        for (base, suffixes) in first_bases.into_iter().zip(first_base_suffixes) {
+            let start0_slot = get_codon_slot(&mut start0_suffixes, suffixes);
+            if start0_slot == start0_bases.len() {
+                start0_bases.push(Vec::new());
+            }
+            start0_bases[start0_slot].push(base);
+        }
+
+        let mut start1_prefixes = Vec::new();
+        let mut start2_bases: Vec<Vec<u8>> = Vec::new();
+        for (start0_slot, suffixes) in start0_suffixes.iter().enumerate() {
+            for suffix in suffixes {
+                let start1_slot = get_codon_slot(&mut start1_prefixes, (start0_slot, suffix[0]));
+                if start1_slot == start2_bases.len() {
+                    start2_bases.push(Vec::new());
+                }
+                start2_bases[start1_slot].push(suffix[1]);
+            }
+        }
+
+        let mut stop0_bases = Vec::new();
+        let mut stop1_prefixes = Vec::new();
+        let mut stop2_bases: Vec<Vec<u8>> = Vec::new();
+        let mut stop2_hard_bases: Vec<Vec<u8>> = Vec::new();
+
+        for codon in genetic_code.get_stop_codons() {
+            let stop0_slot = get_codon_slot(&mut stop0_bases, codon[0]);
+            let stop1_slot = get_codon_slot(&mut stop1_prefixes, (stop0_slot, codon[1]));
+            if stop1_slot == stop2_bases.len() {
+                stop2_bases.push(Vec::new());
+                stop2_hard_bases.push(Vec::new());
+            }
+
+            stop2_bases[stop1_slot].push(codon[2]);
+            if !genetic_code.is_context_stop(&codon) {
+                stop2_hard_bases[stop1_slot].push(codon[2]);
+            }
+        }
+
+        if start0_bases.len() > START0_SLOTS || start1_prefixes.len() > START1_SLOTS {
+            return Err(format!(
+                "Genetic code {} has more start codon prefixes than the HMM supports{}",
+                genetic_code.get_id(),
+                if alternative_starts {
+                    " with alternative starts"
+                } else {
+                    ""
+                }
+            ));
+        }
+        if stop0_bases.is_empty() {
+            return Err(format!(
+                "Genetic code {} has no stop codons",
+                genetic_code.get_id()
+            ));
+        }
+        if stop0_bases.len() > STOP0_SLOTS || stop1_prefixes.len() > STOP1_SLOTS {
+            return Err(format!(
+                "Genetic code {} has more stop codon prefixes than the HMM supports",
+                genetic_code.get_id()
+            ));
+        }
+
+        Ok(CodonStates {
+            start0_bases,
+            start1_prefixes,
+            start2_bases,
+            stop0_bases,
+            stop1_prefixes,
+            stop2_bases,
+            stop2_hard_bases,
+        })
+    }
+
+    // Second bases which continue a possible stop codon from the given Stop0 slot
+    fn get_stop1_bases(&self, stop0_slot: usize) -> Vec<u8> {
+        self.stop1_prefixes
+            .iter()
+            .filter(|(slot, _)| *slot == stop0_slot)
+            .map(|(_, base)| *base)
+            .collect()
+    }
+}
+
 struct TransitionContext<'a> {
     class_pred_pen: &'a [ClassPredPenalty],
     phase_pred_pen: &'a [PhasePredPenalty],
@@ -319,6 +473,9 @@ struct TransitionContext<'a> {
 
     base_pen: &'a [BasesPenalty],
     offset: usize,
+
+    config: &'a HmmConfig,
+    codons: &'a CodonStates,
 }
 
 #[allow(dead_code)]
@@ -329,6 +486,8 @@ impl<'a> TransitionContext<'a> {
         pred_pen: &'a [PredPenalty],
         base_pen: &'a [BasesPenalty],
         offset: usize,
+        config: &'a HmmConfig,
+        codons: &'a CodonStates,
     ) -> TransitionContext<'a> {
         TransitionContext {
             class_pred_pen,
@@ -336,6 +495,8 @@ impl<'a> TransitionContext<'a> {
             pred_pen,
             base_pen,
             offset,
+            config,
+            codons,
         }
     }
 
@@ -376,10 +537,72 @@ impl<'a> TransitionContext<'a> {
         }
     }
 
+    // Penalty from a site model centred on the current position, 0 if there is no model, or too little context
+    fn get_site_model_penalty(&self, model: Option<&SiteModel>) -> f64 {
+        model
+            .and_then(|model| {
+                self.get_ctx(model.get_upstream(), model.get_downstream())
+                    .map(|ctx| model.get_penalty(ctx))
+            })
+            .unwrap_or(0.0)
+    }
+
+    // Penalty from the best placed branch point upstream of an acceptor at the current position
+    fn get_branch_point_penalty(&self) -> f64 {
+        let model = match self
+            .config
+            .site_models
+            .as_ref()
+            .and_then(|m| m.branch_point.as_ref())
+        {
+            Some(model) => model,
+            None => return 0.0,
+        };
+
+        let (min_distance, max_distance) = model.get_distance_range();
+
+        (min_distance..=max_distance)
+            .filter_map(|distance| self.get_ctx(distance, 0))
+            .map(|ctx| model.get_penalty(&ctx[..model.get_length()]))
+            .reduce(f64::min)
+            .unwrap_or(0.0)
+    }
+
+    fn get_donor_model_penalty(&self) -> f64 {
+        self.get_site_model_penalty(
+            self.config
+                .site_models
+                .as_ref()
+                .and_then(|m| m.donor.as_ref()),
+        )
+    }
+
+    fn get_acceptor_model_penalty(&self) -> f64 {
+        self.get_site_model_penalty(
+            self.config
+                .site_models
+                .as_ref()
+                .and_then(|m| m.acceptor.as_ref()),
+        ) + self.get_branch_point_penalty()
+    }
+
+    fn get_start_model_penalty(&self) -> f64 {
+        self.get_site_model_penalty(
+            self.config
+                .site_models
+                .as_ref()
+                .and_then(|m| m.start.as_ref()),
+        )
+    }
+
     fn get_donor_penalty_u2_gt_ag(&self, can_splice: bool) -> Option<f64> {
         if let (Some(ds), true) = (self.get_ctx(0, 2), can_splice) {
             let pen = ds[0].get_g() + ds[1].get_t();
-            Some(pen * DONOR_WEIGHT + DONOR_U2_GT_AG_FIXED_PENALTY)
+            Some(
+                pen * self.config.donor_weight
+                    + self.config.donor_u2_gt_ag_fixed_penalty
+                    + self.get_donor_model_penalty(),
+            )
         } else {
             None
         }
@@ -388,7 +611,7 @@ impl<'a> TransitionContext<'a> {
     fn get_acceptor_penalty_u2_gt_ag(&self) -> Option<f64> {
         if let Some(us) = self.get_ctx(2, 0) {
             let pen = us[0].get_a() + us[1].get_g();
-            Some(pen * ACCEPTOR_WEIGHT)
+            Some(pen * self.config.acceptor_weight + self.get_acceptor_model_penalty())
         } else {
             None
         }
@@ -401,7 +624,11 @@ impl<'a> TransitionContext<'a> {
                 ds[1].get_g() +
                 ds[2].get_g() +
                 ds[3].get_c();
-            Some(pen * DONOR_WEIGHT + DONOR_U2_GC_AG_FIXED_PENALTY)
+            Some(
+                pen * self.config.donor_weight
+                    + self.config.donor_u2_gc_ag_fixed_penalty
+                    + self.get_donor_model_penalty(),
+            )
         } else {
             None
         }
@@ -410,7 +637,7 @@ impl<'a> TransitionContext<'a> {
     fn get_acceptor_penalty_u2_gc_ag(&self) -> Option<f64> {
         if let Some(us) = self.get_ctx(2, 0) {
             let pen = us[0].get_a() + us[1].get_g();
-            Some(pen * ACCEPTOR_WEIGHT)
+            Some(pen * self.config.acceptor_weight + self.get_acceptor_model_penalty())
         } else {
             None
         }
@@ -427,7 +654,17 @@ impl<'a> TransitionContext<'a> {
                 ds[5].get_c() +
                 ds[6].get_t();
 
-            Some(pen * DONOR_WEIGHT + DONOR_U12_AT_AC_FIXED_PENALTY)
+            let model_pen = self.get_site_model_penalty(
+                self.config
+                    .site_models
+                    .as_ref()
+                    .and_then(|m| m.u12_donor.as_ref()),
+            );
+            Some(
+                pen * self.config.donor_weight
+                    + self.config.donor_u12_at_ac_fixed_penalty
+                    + model_pen,
+            )
         } else {
             None
         }
@@ -436,13 +673,21 @@ impl<'a> TransitionContext<'a> {
     fn get_acceptor_penalty_u12_at_ac(&self) -> Option<f64> {
         if let Some(us) = self.get_ctx(2, 0) {
             let pen = us[0].get_a() + us[1].get_c();
-            Some(pen * ACCEPTOR_WEIGHT)
+            let model_pen = self.get_site_model_penalty(
+                self.config
+                    .site_models
+                    .as_ref()
+                    .and_then(|m| m.u12_acceptor.as_ref()),
+            );
+            Some(pen * self.config.acceptor_weight + model_pen)
         } else {
             None
         }
     }
 }
 
+const HMM_ANNOTATION_LABELS: usize = 7;
+
 #[allow(dead_code)]
 #[derive(Clone, Copy, Eq, PartialEq)]
 pub enum HmmAnnotationLabel {
@@ -473,16 +718,22 @@ impl HmmAnnotationLabel {
 enum HmmPrimaryState {
     Intergenic,
     UTR5,
-    Start0, // Possible Start - After A
-    Start1, // Possible Start - After AT
-    Start2, // Possible Start - After ATG
+    Start0a, // Possible Start - After first base, for each Start0 slot (e.g. A)
+    Start0b,
+    Start0c,
+    Start1a, // Possible Start - After second base, for each Start1 slot (e.g. AT)
+    Start1b,
+    Start1c,
+    Start2, // Possible Start - After third base (e.g. ATG)
     Coding0,
     Coding1,
     Coding2,
-    Stop0T,  // Possible Stop - After T
-    Stop1TA, // Possible Stop - After TA
-    Stop1TG, // Possible Stop - After TG
-    Stop2,   // Possible Stop - After TAA / TAG / TGA
+    Stop0a, // Possible Stop - After first base, for each Stop0 slot (e.g. T)
+    Stop0b,
+    Stop1a, // Possible Stop - After second base, for each Stop1 slot (e.g. TA / TG)
+    Stop1b,
+    Stop1c,
+    Stop2, // Possible Stop - After stop codon (e.g. TAA / TAG / TGA)
     UTR3,
 }
 
@@ -491,19 +742,46 @@ impl HmmPrimaryState {
         match self {
             HmmPrimaryState::Intergenic => "Intergenic",
             HmmPrimaryState::UTR5 => "UTR5",
-            HmmPrimaryState::Start0 => "Start0",
-            HmmPrimaryState::Start1 => "Start1",
+            HmmPrimaryState::Start0a => "Start0a",
+            HmmPrimaryState::Start0b => "Start0b",
+            HmmPrimaryState::Start0c => "Start0c",
+            HmmPrimaryState::Start1a => "Start1a",
+            HmmPrimaryState::Start1b => "Start1b",
+            HmmPrimaryState::Start1c => "Start1c",
             HmmPrimaryState::Start2 => "Start2",
             HmmPrimaryState::Coding0 => "Coding0",
             HmmPrimaryState::Coding1 => "Coding1",
             HmmPrimaryState::Coding2 => "Coding2",
-            HmmPrimaryState::Stop0T => "Stop0T",
-            HmmPrimaryState::Stop1TA => "Stop1TA",
-            HmmPrimaryState::Stop1TG => "Stop1TG",
+            HmmPrimaryState::Stop0a => "Stop0a",
+            HmmPrimaryState::Stop0b => "Stop0b",
+            HmmPrimaryState::Stop1a => "Stop1a",
+            HmmPrimaryState::Stop1b => "Stop1b",
+            HmmPrimaryState::Stop1c => "Stop1c",
             HmmPrimaryState::Stop2 => "Stop2",
             HmmPrimaryState::UTR3 => "UTR3",
         }
     }
+
+    fn get_start_slot(self) -> usize {
+        match self {
+            HmmPrimaryState::Start0a | HmmPrimaryState::Start1a => 0,
+            HmmPrimaryState::Start0b | HmmPrimaryState::Start1b => 1,
+            HmmPrimaryState::Start0c | HmmPrimaryState::Start1c => 2,
+            _ => panic!(
+                "Called get_start_slot with non-start state {}",
+                self.to_str()
+            ),
+        }
+    }
+
+    fn get_stop_slot(self) -> usize {
+        match self {
+            HmmPrimaryState::Stop0a | HmmPrimaryState::Stop1a => 0,
+            HmmPrimaryState::Stop0b | HmmPrimaryState::Stop1b => 1,
+            HmmPrimaryState::Stop1c => 2,
+            _ => panic!("Called get_stop_slot with non-stop state {}", self.to_str()),
+        }
+    }
 }
 
 #[derive(Clone, Copy, Eq, PartialEq)]
@@ -533,7 +811,7 @@ impl HmmIntronState {
     }
 }
 
-const HMM_STATES: usize = 73;
+const HMM_STATES: usize = 115;
 
 #[derive(Clone, Copy, Eq, PartialEq, Ord)]
 enum HmmState {
@@ -547,83 +825,258 @@ enum HmmState {
     UTR5IntronU12AtAcDSS = 6,
     UTR5IntronU12AtAc = 7,
 
-    Start0 = 8, // After A
-    Start0IntronU2GtAgDSS = 9,
-    Start0IntronU2GtAg = 10,
-    Start0IntronU2GcAgDSS = 11,
-    Start0IntronU2GcAg = 12,
-    Start0IntronU12AtAcDSS = 13,
-    Start0IntronU12AtAc = 14,
-
-    Start1 = 15, // After AT
-    Start1IntronU2GtAgDSS = 16,
-    Start1IntronU2GtAg = 17,
-    Start1IntronU2GcAgDSS = 18,
-    Start1IntronU2GcAg = 19,
-    Start1IntronU12AtAcDSS = 20,
-    Start1IntronU12AtAc = 21,
-
-    Start2 = 22, // After ATG
-
-    Coding0 = 23,
-    Coding0IntronU2GtAgDSS = 24,
-    Coding0IntronU2GtAg = 25,
-    Coding0IntronU2GcAgDSS = 26,
-    Coding0IntronU2GcAg = 27,
-    Coding0IntronU12AtAcDSS = 28,
-    Coding0IntronU12AtAc = 29,
-
-    Coding1 = 30,
-    Coding1IntronU2GtAgDSS = 31,
-    Coding1IntronU2GtAg = 32,
-    Coding1IntronU2GcAgDSS = 33,
-    Coding1IntronU2GcAg = 34,
-    Coding1IntronU12AtAcDSS = 35,
-    Coding1IntronU12AtAc = 36,
-
-    Coding2 = 37,
-    Coding2IntronU2GtAgDSS = 38,
-    Coding2IntronU2GtAg = 39,
-    Coding2IntronU2GcAgDSS = 40,
-    Coding2IntronU2GcAg = 41,
-    Coding2IntronU12AtAcDSS = 42,
-    Coding2IntronU12AtAc = 43,
-
-    Stop0T = 44,
-    Stop0TIntronU2GtAgDSS = 45,
-    Stop0TIntronU2GtAg = 46,
-    Stop0TIntronU2GcAgDSS = 47,
-    Stop0TIntronU2GcAg = 48,
-    Stop0TIntronU12AtAcDSS = 49,
-    Stop0TIntronU12AtAc = 50,
-
-    Stop1TA = 51,
-    Stop1TAIntronU2GtAgDSS = 52,
-    Stop1TAIntronU2GtAg = 53,
-    Stop1TAIntronU2GcAgDSS = 54,
-    Stop1TAIntronU2GcAg = 55,
-    Stop1TAIntronU12AtAcDSS = 56,
-    Stop1TAIntronU12AtAc = 57,
-
-    Stop1TG = 58,
-    Stop1TGIntronU2GtAgDSS = 59,
-    Stop1TGIntronU2GtAg = 60,
-    Stop1TGIntronU2GcAgDSS = 61,
-    Stop1TGIntronU2GcAg = 62,
-    Stop1TGIntronU12AtAcDSS = 63,
-    Stop1TGIntronU12AtAc = 64,
-
-    Stop2 = 65,
-
-    UTR3 = 66,
-    UTR3IntronU2GtAgDSS = 67,
-    UTR3IntronU2GtAg = 68,
-    UTR3IntronU2GcAgDSS = 69,
-    UTR3IntronU2GcAg = 70,
-    UTR3IntronU12AtAcDSS = 71,
-    UTR3IntronU12AtAc = 72,
+    Start0a = 8, // After first base, for each Start0 slot (e.g. A)
+    Start0aIntronU2GtAgDSS = 9,
+    Start0aIntronU2GtAg = 10,
+    Start0aIntronU2GcAgDSS = 11,
+    Start0aIntronU2GcAg = 12,
+    Start0aIntronU12AtAcDSS = 13,
+    Start0aIntronU12AtAc = 14,
+
+    Start0b = 15,
+    Start0bIntronU2GtAgDSS = 16,
+    Start0bIntronU2GtAg = 17,
+    Start0bIntronU2GcAgDSS = 18,
+    Start0bIntronU2GcAg = 19,
+    Start0bIntronU12AtAcDSS = 20,
+    Start0bIntronU12AtAc = 21,
+
+    Start0c = 22,
+    Start0cIntronU2GtAgDSS = 23,
+    Start0cIntronU2GtAg = 24,
+    Start0cIntronU2GcAgDSS = 25,
+    Start0cIntronU2GcAg = 26,
+    Start0cIntronU12AtAcDSS = 27,
+    Start0cIntronU12AtAc = 28,
+
+    Start1a = 29, // After second base, for each Start1 slot (e.g. AT)
+    Start1aIntronU2GtAgDSS = 30,
+    Start1aIntronU2GtAg = 31,
+    Start1aIntronU2GcAgDSS = 32,
+    Start1aIntronU2GcAg = 33,
+    Start1aIntronU12AtAcDSS = 34,
+    Start1aIntronU12AtAc = 35,
+
+    Start1b = 36,
+    Start1bIntronU2GtAgDSS = 37,
+    Start1bIntronU2GtAg = 38,
+    Start1bIntronU2GcAgDSS = 39,
+    Start1bIntronU2GcAg = 40,
+    Start1bIntronU12AtAcDSS = 41,
+    Start1bIntronU12AtAc = 42,
+
+    Start1c = 43,
+    Start1cIntronU2GtAgDSS = 44,
+    Start1cIntronU2GtAg = 45,
+    Start1cIntronU2GcAgDSS = 46,
+    Start1cIntronU2GcAg = 47,
+    Start1cIntronU12AtAcDSS = 48,
+    Start1cIntronU12AtAc = 49,
+
+    Start2 = 50, // After ATG
+
+    Coding0 = 51,
+    Coding0IntronU2GtAgDSS = 52,
+    Coding0IntronU2GtAg = 53,
+    Coding0IntronU2GcAgDSS = 54,
+    Coding0IntronU2GcAg = 55,
+    Coding0IntronU12AtAcDSS = 56,
+    Coding0IntronU12AtAc = 57,
+
+    Coding1 = 58,
+    Coding1IntronU2GtAgDSS = 59,
+    Coding1IntronU2GtAg = 60,
+    Coding1IntronU2GcAgDSS = 61,
+    Coding1IntronU2GcAg = 62,
+    Coding1IntronU12AtAcDSS = 63,
+    Coding1IntronU12AtAc = 64,
+
+    Coding2 = 65,
+    Coding2IntronU2GtAgDSS = 66,
+    Coding2IntronU2GtAg = 67,
+    Coding2IntronU2GcAgDSS = 68,
+    Coding2IntronU2GcAg = 69,
+    Coding2IntronU12AtAcDSS = 70,
+    Coding2IntronU12AtAc = 71,
+
+    Stop0a = 72,
+    Stop0aIntronU2GtAgDSS = 73,
+    Stop0aIntronU2GtAg = 74,
+    Stop0aIntronU2GcAgDSS = 75,
+    Stop0aIntronU2GcAg = 76,
+    Stop0aIntronU12AtAcDSS = 77,
+    Stop0aIntronU12AtAc = 78,
+
+    Stop0b = 79,
+    Stop0bIntronU2GtAgDSS = 80,
+    Stop0bIntronU2GtAg = 81,
+    Stop0bIntronU2GcAgDSS = 82,
+    Stop0bIntronU2GcAg = 83,
+    Stop0bIntronU12AtAcDSS = 84,
+    Stop0bIntronU12AtAc = 85,
+
+    Stop1a = 86,
+    Stop1aIntronU2GtAgDSS = 87,
+    Stop1aIntronU2GtAg = 88,
+    Stop1aIntronU2GcAgDSS = 89,
+    Stop1aIntronU2GcAg = 90,
+    Stop1aIntronU12AtAcDSS = 91,
+    Stop1aIntronU12AtAc = 92,
+
+    Stop1b = 93,
+    Stop1bIntronU2GtAgDSS = 94,
+    Stop1bIntronU2GtAg = 95,
+    Stop1bIntronU2GcAgDSS = 96,
+    Stop1bIntronU2GcAg = 97,
+    Stop1bIntronU12AtAcDSS = 98,
+    Stop1bIntronU12AtAc = 99,
+
+    Stop1c = 100,
+    Stop1cIntronU2GtAgDSS = 101,
+    Stop1cIntronU2GtAg = 102,
+    Stop1cIntronU2GcAgDSS = 103,
+    Stop1cIntronU2GcAg = 104,
+    Stop1cIntronU12AtAcDSS = 105,
+    Stop1cIntronU12AtAc = 106,
+
+    Stop2 = 107,
+
+    UTR3 = 108,
+    UTR3IntronU2GtAgDSS = 109,
+    UTR3IntronU2GtAg = 110,
+    UTR3IntronU2GcAgDSS = 111,
+    UTR3IntronU2GcAg = 112,
+    UTR3IntronU12AtAcDSS = 113,
+    UTR3IntronU12AtAc = 114,
 }
 
+// All states, in index order
+const HMM_STATE_LIST: [HmmState; HMM_STATES] = [
+    HmmState::Intergenic,
+    HmmState::UTR5,
+    HmmState::UTR5IntronU2GtAgDSS,
+    HmmState::UTR5IntronU2GtAg,
+    HmmState::UTR5IntronU2GcAgDSS,
+    HmmState::UTR5IntronU2GcAg,
+    HmmState::UTR5IntronU12AtAcDSS,
+    HmmState::UTR5IntronU12AtAc,
+    HmmState::Start0a,
+    HmmState::Start0aIntronU2GtAgDSS,
+    HmmState::Start0aIntronU2GtAg,
+    HmmState::Start0aIntronU2GcAgDSS,
+    HmmState::Start0aIntronU2GcAg,
+    HmmState::Start0aIntronU12AtAcDSS,
+    HmmState::Start0aIntronU12AtAc,
+    HmmState::Start0b,
+    HmmState::Start0bIntronU2GtAgDSS,
+    HmmState::Start0bIntronU2GtAg,
+    HmmState::Start0bIntronU2GcAgDSS,
+    HmmState::Start0bIntronU2GcAg,
+    HmmState::Start0bIntronU12AtAcDSS,
+    HmmState::Start0bIntronU12AtAc,
+    HmmState::Start0c,
+    HmmState::Start0cIntronU2GtAgDSS,
+    HmmState::Start0cIntronU2GtAg,
+    HmmState::Start0cIntronU2GcAgDSS,
+    HmmState::Start0cIntronU2GcAg,
+    HmmState::Start0cIntronU12AtAcDSS,
+    HmmState::Start0cIntronU12AtAc,
+    HmmState::Start1a,
+    HmmState::Start1aIntronU2GtAgDSS,
+    HmmState::Start1aIntronU2GtAg,
+    HmmState::Start1aIntronU2GcAgDSS,
+    HmmState::Start1aIntronU2GcAg,
+    HmmState::Start1aIntronU12AtAcDSS,
+    HmmState::Start1aIntronU12AtAc,
+    HmmState::Start1b,
+    HmmState::Start1bIntronU2GtAgDSS,
+    HmmState::Start1bIntronU2GtAg,
+    HmmState::Start1bIntronU2GcAgDSS,
+    HmmState::Start1bIntronU2GcAg,
+    HmmState::Start1bIntronU12AtAcDSS,
+    HmmState::Start1bIntronU12AtAc,
+    HmmState::Start1c,
+    HmmState::Start1cIntronU2GtAgDSS,
+    HmmState::Start1cIntronU2GtAg,
+    HmmState::Start1cIntronU2GcAgDSS,
+    HmmState::Start1cIntronU2GcAg,
+    HmmState::Start1cIntronU12AtAcDSS,
+    HmmState::Start1cIntronU12AtAc,
+    HmmState::Start2,
+    HmmState::Coding0,
+    HmmState::Coding0IntronU2GtAgDSS,
+    HmmState::Coding0IntronU2GtAg,
+    HmmState::Coding0IntronU2GcAgDSS,
+    HmmState::Coding0IntronU2GcAg,
+    HmmState::Coding0IntronU12AtAcDSS,
+    HmmState::Coding0IntronU12AtAc,
+    HmmState::Coding1,
+    HmmState::Coding1IntronU2GtAgDSS,
+    HmmState::Coding1IntronU2GtAg,
+    HmmState::Coding1IntronU2GcAgDSS,
+    HmmState::Coding1IntronU2GcAg,
+    HmmState::Coding1IntronU12AtAcDSS,
+    HmmState::Coding1IntronU12AtAc,
+    HmmState::Coding2,
+    HmmState::Coding2IntronU2GtAgDSS,
+    HmmState::Coding2IntronU2GtAg,
+    HmmState::Coding2IntronU2GcAgDSS,
+    HmmState::Coding2IntronU2GcAg,
+    HmmState::Coding2IntronU12AtAcDSS,
+    HmmState::Coding2IntronU12AtAc,
+    HmmState::Stop0a,
+    HmmState::Stop0aIntronU2GtAgDSS,
+    HmmState::Stop0aIntronU2GtAg,
+    HmmState::Stop0aIntronU2GcAgDSS,
+    HmmState::Stop0aIntronU2GcAg,
+    HmmState::Stop0aIntronU12AtAcDSS,
+    HmmState::Stop0aIntronU12AtAc,
+    HmmState::Stop0b,
+    HmmState::Stop0bIntronU2GtAgDSS,
+    HmmState::Stop0bIntronU2GtAg,
+    HmmState::Stop0bIntronU2GcAgDSS,
+    HmmState::Stop0bIntronU2GcAg,
+    HmmState::Stop0bIntronU12AtAcDSS,
+    HmmState::Stop0bIntronU12AtAc,
+    HmmState::Stop1a,
+    HmmState::Stop1aIntronU2GtAgDSS,
+    HmmState::Stop1aIntronU2GtAg,
+    HmmState::Stop1aIntronU2GcAgDSS,
+    HmmState::Stop1aIntronU2GcAg,
+    HmmState::Stop1aIntronU12AtAcDSS,
+    HmmState::Stop1aIntronU12AtAc,
+    HmmState::Stop1b,
+    HmmState::Stop1bIntronU2GtAgDSS,
+    HmmState::Stop1bIntronU2GtAg,
+    HmmState::Stop1bIntronU2GcAgDSS,
+    HmmState::Stop1bIntronU2GcAg,
+    HmmState::Stop1bIntronU12AtAcDSS,
+    HmmState::Stop1bIntronU12AtAc,
+    HmmState::Stop1c,
+    HmmState::Stop1cIntronU2GtAgDSS,
+    HmmState::Stop1cIntronU2GtAg,
+    HmmState::Stop1cIntronU2GcAgDSS,
+    HmmState::Stop1cIntronU2GcAg,
+    HmmState::Stop1cIntronU12AtAcDSS,
+    HmmState::Stop1cIntronU12AtAc,
+    HmmState::Stop2,
+    HmmState::UTR3,
+    HmmState::UTR3IntronU2GtAgDSS,
+    HmmState::UTR3IntronU2GtAg,
+    HmmState::UTR3IntronU2GcAgDSS,
+    HmmState::UTR3IntronU2GcAg,
+    HmmState::UTR3IntronU12AtAcDSS,
+    HmmState::UTR3IntronU12AtAc,
+];
+
+const START0_STATES: [HmmState; START0_SLOTS] =
+    [HmmState::Start0a, HmmState::Start0b, HmmState::Start0c];
+const START1_STATES: [HmmState; START1_SLOTS] =
+    [HmmState::Start1a, HmmState::Start1b, HmmState::Start1c];
+const STOP0_STATES: [HmmState; STOP0_SLOTS] = [HmmState::Stop0a, HmmState::Stop0b];
+const STOP1_STATES: [HmmState; STOP1_SLOTS] =
+    [HmmState::Stop1a, HmmState::Stop1b, HmmState::Stop1c];
+
 impl std::cmp::PartialOrd for HmmState {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         let s = *self as u8;
@@ -633,69 +1086,91 @@ impl std::cmp::PartialOrd for HmmState {
     }
 }
 
-// Allow/Prevent introns within each state and/or at state transitions
-
-const CAN_SPLICE_UTR5: bool = true;
-const CAN_SPLICE_UTR5_START: bool = true;
-const CAN_SPLICE_START: bool = true;
-const CAN_SPLICE_START_CODING: bool = true;
-const CAN_SPLICE_CODING: bool = true;
-const CAN_SPLICE_CODING_STOP: bool = true;
-const CAN_SPLICE_STOP: bool = true;
-const CAN_SPLICE_STOP_UTR3: bool = true;
-const CAN_SPLICE_UTR3: bool = true;
-
-const START_WEIGHT: f64 = 1_000.0;
-
-const DONOR_U2_GT_AG_FIXED_PENALTY: f64 = 0.0;
-const DONOR_U2_GC_AG_FIXED_PENALTY: f64 = 0.0;
-const DONOR_U12_GT_AG_FIXED_PENALTY: f64 = 0.0;
-const DONOR_U12_AT_AC_FIXED_PENALTY: f64 = 0.0;
-
-const DONOR_WEIGHT: f64 = 1.0;
-
-const ACCEPTOR_WEIGHT: f64 = 1.0;
-
-const STOP_WEIGHT: f64 = 1_000.0;
-
-pub fn show_hmm_config() {
-    println!("HMM Config");
-    println!(
+pub fn show_hmm_config(config: &HmmConfig) {
+    info!("HMM Config");
+    info!(
         "  Splicing Flags: U:{} US:{} S:{} SC:{} C:{} CS:{} S:{} SU:{} U:{}",
-        CAN_SPLICE_UTR5,
-        CAN_SPLICE_UTR5_START,
-        CAN_SPLICE_START,
-        CAN_SPLICE_START_CODING,
-        CAN_SPLICE_CODING,
-        CAN_SPLICE_CODING_STOP,
-        CAN_SPLICE_STOP,
-        CAN_SPLICE_STOP_UTR3,
-        CAN_SPLICE_UTR3
+        config.can_splice_utr5,
+        config.can_splice_utr5_start,
+        config.can_splice_start,
+        config.can_splice_start_coding,
+        config.can_splice_coding,
+        config.can_splice_coding_stop,
+        config.can_splice_stop,
+        config.can_splice_stop_utr3,
+        config.can_splice_utr3
     );
 
-    println!(
+    info!(
         "  Splicing - Weights: Donor {}, Acceptor {}",
-        DONOR_WEIGHT, ACCEPTOR_WEIGHT
+        config.donor_weight, config.acceptor_weight
     );
-    println!(
-        "  Splicing - Fixed Penalties: U2-GT-AG {}, U2-GT-AC {} U12-GT-AG {} U12-AT-AC {}",
-        DONOR_U2_GT_AG_FIXED_PENALTY,
-        DONOR_U2_GC_AG_FIXED_PENALTY,
-        DONOR_U12_GT_AG_FIXED_PENALTY,
-        DONOR_U12_AT_AC_FIXED_PENALTY
+    info!(
+        "  Splicing - Fixed Penalties: U2-GT-AG {}, U2-GT-AC {} U12-AT-AC {}",
+        config.donor_u2_gt_ag_fixed_penalty,
+        config.donor_u2_gc_ag_fixed_penalty,
+        config.donor_u12_at_ac_fixed_penalty
     );
 
-    println!(
+    info!(
         "  Coding - Weights: Start {}, Stop {}",
-        START_WEIGHT, STOP_WEIGHT
+        config.start_weight, config.stop_weight
+    );
+    info!(
+        "  Solver: {}, Posterior Scores: {}",
+        config.solver.as_str(),
+        config.posterior_scores
+    );
+    info!(
+        "  Splice Ban Isoforms: Max {}, Penalty Margin {}",
+        config.max_splice_ban_isoforms, config.splice_ban_penalty_margin
+    );
+    info!(
+        "  Genetic Code: {} ({}), Alternative Starts: {}",
+        config.genetic_code.get_id(),
+        config.genetic_code.get_name(),
+        config.alternative_starts
+    );
+    for (sequence, genetic_code) in config.sequence_genetic_codes.iter() {
+        info!(
+            "  Genetic Code for {}: {} ({})",
+            sequence,
+            genetic_code.get_id(),
+            genetic_code.get_name()
+        );
+    }
+    info!(
+        "  Partial Genes: {}, Penalty {}",
+        config.partial_genes, config.partial_gene_penalty
+    );
+    if let Some(site_models) = config.site_models.as_ref() {
+        info!(
+            "  Site Models: {} ({})",
+            site_models.get_model_names().join(", "),
+            site_models.get_path()
+        );
+    }
+    info!(
+        "  Intron Length: Min {} (U12 {}), Max {}, Distribution {} (Mean {})",
+        config.min_intron_length,
+        config.min_u12_intron_length,
+        config.max_intron_length,
+        config.intron_length_distribution.as_str(),
+        config.intron_mean_length
     );
     //println!("  Phase Mode: Off");
     //println!("  Phase Mode: Additive, with {} prob floor", PHASE_PRED_PROB_FLOOR);
-    println!(
+    info!(
         "  Phase Mode: Implementation 1, Dilute to Total, Retention: {}",
-        PHASE_RETAIN
+        config.phase_retain
+    );
+    info!(
+        "  Probability Floors: Class {}, Phase {}, Combined {}, Bases {}",
+        config.class_pred_prob_floor,
+        config.phase_pred_prob_floor,
+        config.pred_prob_floor,
+        config.base_prob_floor
     );
-    println!();
 }
 
 impl HmmState {
@@ -711,25 +1186,89 @@ impl HmmState {
             HmmState::UTR5IntronU12AtAcDSS => (HmmPrimaryState::UTR5, HmmIntronState::U12AtAcDSS),
             HmmState::UTR5IntronU12AtAc => (HmmPrimaryState::UTR5, HmmIntronState::U12AtAc),
 
-            HmmState::Start0 => (HmmPrimaryState::Start0, HmmIntronState::None),
-            HmmState::Start0IntronU2GtAgDSS => (HmmPrimaryState::Start0, HmmIntronState::U2GtAgDSS),
-            HmmState::Start0IntronU2GtAg => (HmmPrimaryState::Start0, HmmIntronState::U2GtAg),
-            HmmState::Start0IntronU2GcAgDSS => (HmmPrimaryState::Start0, HmmIntronState::U2GcAgDSS),
-            HmmState::Start0IntronU2GcAg => (HmmPrimaryState::Start0, HmmIntronState::U2GcAg),
-            HmmState::Start0IntronU12AtAcDSS => {
-                (HmmPrimaryState::Start0, HmmIntronState::U12AtAcDSS)
+            HmmState::Start0a => (HmmPrimaryState::Start0a, HmmIntronState::None),
+            HmmState::Start0aIntronU2GtAgDSS => {
+                (HmmPrimaryState::Start0a, HmmIntronState::U2GtAgDSS)
+            }
+            HmmState::Start0aIntronU2GtAg => (HmmPrimaryState::Start0a, HmmIntronState::U2GtAg),
+            HmmState::Start0aIntronU2GcAgDSS => {
+                (HmmPrimaryState::Start0a, HmmIntronState::U2GcAgDSS)
+            }
+            HmmState::Start0aIntronU2GcAg => (HmmPrimaryState::Start0a, HmmIntronState::U2GcAg),
+            HmmState::Start0aIntronU12AtAcDSS => {
+                (HmmPrimaryState::Start0a, HmmIntronState::U12AtAcDSS)
+            }
+            HmmState::Start0aIntronU12AtAc => (HmmPrimaryState::Start0a, HmmIntronState::U12AtAc),
+
+            HmmState::Start0b => (HmmPrimaryState::Start0b, HmmIntronState::None),
+            HmmState::Start0bIntronU2GtAgDSS => {
+                (HmmPrimaryState::Start0b, HmmIntronState::U2GtAgDSS)
+            }
+            HmmState::Start0bIntronU2GtAg => (HmmPrimaryState::Start0b, HmmIntronState::U2GtAg),
+            HmmState::Start0bIntronU2GcAgDSS => {
+                (HmmPrimaryState::Start0b, HmmIntronState::U2GcAgDSS)
+            }
+            HmmState::Start0bIntronU2GcAg => (HmmPrimaryState::Start0b, HmmIntronState::U2GcAg),
+            HmmState::Start0bIntronU12AtAcDSS => {
+                (HmmPrimaryState::Start0b, HmmIntronState::U12AtAcDSS)
+            }
+            HmmState::Start0bIntronU12AtAc => (HmmPrimaryState::Start0b, HmmIntronState::U12AtAc),
+
+            HmmState::Start0c => (HmmPrimaryState::Start0c, HmmIntronState::None),
+            HmmState::Start0cIntronU2GtAgDSS => {
+                (HmmPrimaryState::Start0c, HmmIntronState::U2GtAgDSS)
+            }
+            HmmState::Start0cIntronU2GtAg => (HmmPrimaryState::Start0c, HmmIntronState::U2GtAg),
+            HmmState::Start0cIntronU2GcAgDSS => {
+                (HmmPrimaryState::Start0c, HmmIntronState::U2GcAgDSS)
+            }
+            HmmState::Start0cIntronU2GcAg => (HmmPrimaryState::Start0c, HmmIntronState::U2GcAg),
+            HmmState::Start0cIntronU12AtAcDSS => {
+                (HmmPrimaryState::Start0c, HmmIntronState::U12AtAcDSS)
+            }
+            HmmState::Start0cIntronU12AtAc => (HmmPrimaryState::Start0c, HmmIntronState::U12AtAc),
+
+            HmmState::Start1a => (HmmPrimaryState::Start1a, HmmIntronState::None),
+            HmmState::Start1aIntronU2GtAgDSS => {
+                (HmmPrimaryState::Start1a, HmmIntronState::U2GtAgDSS)
+            }
+            HmmState::Start1aIntronU2GtAg => (HmmPrimaryState::Start1a, HmmIntronState::U2GtAg),
+            HmmState::Start1aIntronU2GcAgDSS => {
+                (HmmPrimaryState::Start1a, HmmIntronState::U2GcAgDSS)
+            }
+            HmmState::Start1aIntronU2GcAg => (HmmPrimaryState::Start1a, HmmIntronState::U2GcAg),
+            HmmState::Start1aIntronU12AtAcDSS => {
+                (HmmPrimaryState::Start1a, HmmIntronState::U12AtAcDSS)
+            }
+            HmmState::Start1aIntronU12AtAc => (HmmPrimaryState::Start1a, HmmIntronState::U12AtAc),
+
+            HmmState::Start1b => (HmmPrimaryState::Start1b, HmmIntronState::None),
+            HmmState::Start1bIntronU2GtAgDSS => {
+                (HmmPrimaryState::Start1b, HmmIntronState::U2GtAgDSS)
+            }
+            HmmState::Start1bIntronU2GtAg => (HmmPrimaryState::Start1b, HmmIntronState::U2GtAg),
+            HmmState::Start1bIntronU2GcAgDSS => {
+                (HmmPrimaryState::Start1b, HmmIntronState::U2GcAgDSS)
             }
-            HmmState::Start0IntronU12AtAc => (HmmPrimaryState::Start0, HmmIntronState::U12AtAc),
+            HmmState::Start1bIntronU2GcAg => (HmmPrimaryState::Start1b, HmmIntronState::U2GcAg),
+            HmmState::Start1bIntronU12AtAcDSS => {
+                (HmmPrimaryState::Start1b, HmmIntronState::U12AtAcDSS)
+            }
+            HmmState::Start1bIntronU12AtAc => (HmmPrimaryState::Start1b, HmmIntronState::U12AtAc),
 
-            HmmState::Start1 => (HmmPrimaryState::Start1, HmmIntronState::None),
-            HmmState::Start1IntronU2GtAgDSS => (HmmPrimaryState::Start1, HmmIntronState::U2GtAgDSS),
-            HmmState::Start1IntronU2GtAg => (HmmPrimaryState::Start1, HmmIntronState::U2GtAg),
-            HmmState::Start1IntronU2GcAgDSS => (HmmPrimaryState::Start1, HmmIntronState::U2GcAgDSS),
-            HmmState::Start1IntronU2GcAg => (HmmPrimaryState::Start1, HmmIntronState::U2GcAg),
-            HmmState::Start1IntronU12AtAcDSS => {
-                (HmmPrimaryState::Start1, HmmIntronState::U12AtAcDSS)
+            HmmState::Start1c => (HmmPrimaryState::Start1c, HmmIntronState::None),
+            HmmState::Start1cIntronU2GtAgDSS => {
+                (HmmPrimaryState::Start1c, HmmIntronState::U2GtAgDSS)
+            }
+            HmmState::Start1cIntronU2GtAg => (HmmPrimaryState::Start1c, HmmIntronState::U2GtAg),
+            HmmState::Start1cIntronU2GcAgDSS => {
+                (HmmPrimaryState::Start1c, HmmIntronState::U2GcAgDSS)
             }
-            HmmState::Start1IntronU12AtAc => (HmmPrimaryState::Start1, HmmIntronState::U12AtAc),
+            HmmState::Start1cIntronU2GcAg => (HmmPrimaryState::Start1c, HmmIntronState::U2GcAg),
+            HmmState::Start1cIntronU12AtAcDSS => {
+                (HmmPrimaryState::Start1c, HmmIntronState::U12AtAcDSS)
+            }
+            HmmState::Start1cIntronU12AtAc => (HmmPrimaryState::Start1c, HmmIntronState::U12AtAc),
 
             HmmState::Start2 => (HmmPrimaryState::Start2, HmmIntronState::None),
 
@@ -775,43 +1314,55 @@ impl HmmState {
             }
             HmmState::Coding2IntronU12AtAc => (HmmPrimaryState::Coding2, HmmIntronState::U12AtAc),
 
-            HmmState::Stop0T => (HmmPrimaryState::Stop0T, HmmIntronState::None),
-            HmmState::Stop0TIntronU2GtAgDSS => (HmmPrimaryState::Stop0T, HmmIntronState::U2GtAgDSS),
-            HmmState::Stop0TIntronU2GtAg => (HmmPrimaryState::Stop0T, HmmIntronState::U2GtAg),
-            HmmState::Stop0TIntronU2GcAgDSS => (HmmPrimaryState::Stop0T, HmmIntronState::U2GcAgDSS),
-            HmmState::Stop0TIntronU2GcAg => (HmmPrimaryState::Stop0T, HmmIntronState::U2GcAg),
-            HmmState::Stop0TIntronU12AtAcDSS => {
-                (HmmPrimaryState::Stop0T, HmmIntronState::U12AtAcDSS)
-            }
-            HmmState::Stop0TIntronU12AtAc => (HmmPrimaryState::Stop0T, HmmIntronState::U12AtAc),
-
-            HmmState::Stop1TA => (HmmPrimaryState::Stop1TA, HmmIntronState::None),
-            HmmState::Stop1TAIntronU2GtAgDSS => {
-                (HmmPrimaryState::Stop1TA, HmmIntronState::U2GtAgDSS)
-            }
-            HmmState::Stop1TAIntronU2GtAg => (HmmPrimaryState::Stop1TA, HmmIntronState::U2GtAg),
-            HmmState::Stop1TAIntronU2GcAgDSS => {
-                (HmmPrimaryState::Stop1TA, HmmIntronState::U2GcAgDSS)
+            HmmState::Stop0a => (HmmPrimaryState::Stop0a, HmmIntronState::None),
+            HmmState::Stop0aIntronU2GtAgDSS => (HmmPrimaryState::Stop0a, HmmIntronState::U2GtAgDSS),
+            HmmState::Stop0aIntronU2GtAg => (HmmPrimaryState::Stop0a, HmmIntronState::U2GtAg),
+            HmmState::Stop0aIntronU2GcAgDSS => (HmmPrimaryState::Stop0a, HmmIntronState::U2GcAgDSS),
+            HmmState::Stop0aIntronU2GcAg => (HmmPrimaryState::Stop0a, HmmIntronState::U2GcAg),
+            HmmState::Stop0aIntronU12AtAcDSS => {
+                (HmmPrimaryState::Stop0a, HmmIntronState::U12AtAcDSS)
             }
-            HmmState::Stop1TAIntronU2GcAg => (HmmPrimaryState::Stop1TA, HmmIntronState::U2GcAg),
-            HmmState::Stop1TAIntronU12AtAcDSS => {
-                (HmmPrimaryState::Stop1TA, HmmIntronState::U12AtAcDSS)
+            HmmState::Stop0aIntronU12AtAc => (HmmPrimaryState::Stop0a, HmmIntronState::U12AtAc),
+
+            HmmState::Stop0b => (HmmPrimaryState::Stop0b, HmmIntronState::None),
+            HmmState::Stop0bIntronU2GtAgDSS => (HmmPrimaryState::Stop0b, HmmIntronState::U2GtAgDSS),
+            HmmState::Stop0bIntronU2GtAg => (HmmPrimaryState::Stop0b, HmmIntronState::U2GtAg),
+            HmmState::Stop0bIntronU2GcAgDSS => (HmmPrimaryState::Stop0b, HmmIntronState::U2GcAgDSS),
+            HmmState::Stop0bIntronU2GcAg => (HmmPrimaryState::Stop0b, HmmIntronState::U2GcAg),
+            HmmState::Stop0bIntronU12AtAcDSS => {
+                (HmmPrimaryState::Stop0b, HmmIntronState::U12AtAcDSS)
             }
-            HmmState::Stop1TAIntronU12AtAc => (HmmPrimaryState::Stop1TA, HmmIntronState::U12AtAc),
-
-            HmmState::Stop1TG => (HmmPrimaryState::Stop1TG, HmmIntronState::None),
-            HmmState::Stop1TGIntronU2GtAgDSS => {
-                (HmmPrimaryState::Stop1TG, HmmIntronState::U2GtAgDSS)
+            HmmState::Stop0bIntronU12AtAc => (HmmPrimaryState::Stop0b, HmmIntronState::U12AtAc),
+
+            HmmState::Stop1a => (HmmPrimaryState::Stop1a, HmmIntronState::None),
+            HmmState::Stop1aIntronU2GtAgDSS => (HmmPrimaryState::Stop1a, HmmIntronState::U2GtAgDSS),
+            HmmState::Stop1aIntronU2GtAg => (HmmPrimaryState::Stop1a, HmmIntronState::U2GtAg),
+            HmmState::Stop1aIntronU2GcAgDSS => (HmmPrimaryState::Stop1a, HmmIntronState::U2GcAgDSS),
+            HmmState::Stop1aIntronU2GcAg => (HmmPrimaryState::Stop1a, HmmIntronState::U2GcAg),
+            HmmState::Stop1aIntronU12AtAcDSS => {
+                (HmmPrimaryState::Stop1a, HmmIntronState::U12AtAcDSS)
             }
-            HmmState::Stop1TGIntronU2GtAg => (HmmPrimaryState::Stop1TG, HmmIntronState::U2GtAg),
-            HmmState::Stop1TGIntronU2GcAgDSS => {
-                (HmmPrimaryState::Stop1TG, HmmIntronState::U2GcAgDSS)
+            HmmState::Stop1aIntronU12AtAc => (HmmPrimaryState::Stop1a, HmmIntronState::U12AtAc),
+
+            HmmState::Stop1b => (HmmPrimaryState::Stop1b, HmmIntronState::None),
+            HmmState::Stop1bIntronU2GtAgDSS => (HmmPrimaryState::Stop1b, HmmIntronState::U2GtAgDSS),
+            HmmState::Stop1bIntronU2GtAg => (HmmPrimaryState::Stop1b, HmmIntronState::U2GtAg),
+            HmmState::Stop1bIntronU2GcAgDSS => (HmmPrimaryState::Stop1b, HmmIntronState::U2GcAgDSS),
+            HmmState::Stop1bIntronU2GcAg => (HmmPrimaryState::Stop1b, HmmIntronState::U2GcAg),
+            HmmState::Stop1bIntronU12AtAcDSS => {
+                (HmmPrimaryState::Stop1b, HmmIntronState::U12AtAcDSS)
             }
-            HmmState::Stop1TGIntronU2GcAg => (HmmPrimaryState::Stop1TG, HmmIntronState::U2GcAg),
-            HmmState::Stop1TGIntronU12AtAcDSS => {
-                (HmmPrimaryState::Stop1TG, HmmIntronState::U12AtAcDSS)
+            HmmState::Stop1bIntronU12AtAc => (HmmPrimaryState::Stop1b, HmmIntronState::U12AtAc),
+
+            HmmState::Stop1c => (HmmPrimaryState::Stop1c, HmmIntronState::None),
+            HmmState::Stop1cIntronU2GtAgDSS => (HmmPrimaryState::Stop1c, HmmIntronState::U2GtAgDSS),
+            HmmState::Stop1cIntronU2GtAg => (HmmPrimaryState::Stop1c, HmmIntronState::U2GtAg),
+            HmmState::Stop1cIntronU2GcAgDSS => (HmmPrimaryState::Stop1c, HmmIntronState::U2GcAgDSS),
+            HmmState::Stop1cIntronU2GcAg => (HmmPrimaryState::Stop1c, HmmIntronState::U2GcAg),
+            HmmState::Stop1cIntronU12AtAcDSS => {
+                (HmmPrimaryState::Stop1c, HmmIntronState::U12AtAcDSS)
             }
-            HmmState::Stop1TGIntronU12AtAc => (HmmPrimaryState::Stop1TG, HmmIntronState::U12AtAc),
+            HmmState::Stop1cIntronU12AtAc => (HmmPrimaryState::Stop1c, HmmIntronState::U12AtAc),
 
             HmmState::Stop2 => (HmmPrimaryState::Stop2, HmmIntronState::None),
 
@@ -837,17 +1388,23 @@ impl HmmState {
 
             HmmPrimaryState::UTR5 => HmmAnnotationLabel::UTR5,
 
-            HmmPrimaryState::Start0 | HmmPrimaryState::Start1 | HmmPrimaryState::Start2 => {
-                HmmAnnotationLabel::Coding
-            } //Start,
+            HmmPrimaryState::Start0a
+            | HmmPrimaryState::Start0b
+            | HmmPrimaryState::Start0c
+            | HmmPrimaryState::Start1a
+            | HmmPrimaryState::Start1b
+            | HmmPrimaryState::Start1c
+            | HmmPrimaryState::Start2 => HmmAnnotationLabel::Coding, //Start,
 
             HmmPrimaryState::Coding0 | HmmPrimaryState::Coding1 | HmmPrimaryState::Coding2 => {
                 HmmAnnotationLabel::Coding
             }
 
-            HmmPrimaryState::Stop0T
-            | HmmPrimaryState::Stop1TA
-            | HmmPrimaryState::Stop1TG
+            HmmPrimaryState::Stop0a
+            | HmmPrimaryState::Stop0b
+            | HmmPrimaryState::Stop1a
+            | HmmPrimaryState::Stop1b
+            | HmmPrimaryState::Stop1c
             | HmmPrimaryState::Stop2 => HmmAnnotationLabel::Coding, //Stop,
 
             HmmPrimaryState::UTR3 => HmmAnnotationLabel::UTR3,
@@ -873,13 +1430,32 @@ impl HmmState {
             HmmPrimaryState::UTR5 | HmmPrimaryState::UTR3 =>
                 (pred.get_utr_neg_log_prob(), pred.get_utr_penalty()),
 
-            HmmPrimaryState::Coding0 => (pred.get_coding_phase0_neg_log_prob(), pred.get_coding_phase0_penalty()),
-            HmmPrimaryState::Start0 | HmmPrimaryState::Stop0T =>
-                (pred.get_coding_phase0_neg_log_prob(), pred.get_coding_phase0_penalty()),
-
-            HmmPrimaryState::Coding1 => (pred.get_coding_phase2_penalty(), pred.get_coding_phase2_neg_log_prob()),
-            HmmPrimaryState::Start1 | HmmPrimaryState::Stop1TA | HmmPrimaryState::Stop1TG =>
-                (pred.get_coding_phase2_neg_log_prob(), pred.get_coding_phase2_penalty()),
+            HmmPrimaryState::Coding0 => (
+                pred.get_coding_phase0_neg_log_prob(),
+                pred.get_coding_phase0_penalty(),
+            ),
+            HmmPrimaryState::Start0a
+            | HmmPrimaryState::Start0b
+            | HmmPrimaryState::Start0c
+            | HmmPrimaryState::Stop0a
+            | HmmPrimaryState::Stop0b => (
+                pred.get_coding_phase0_neg_log_prob(),
+                pred.get_coding_phase0_penalty(),
+            ),
+
+            HmmPrimaryState::Coding1 => (
+                pred.get_coding_phase2_neg_log_prob(),
+                pred.get_coding_phase2_penalty(),
+            ),
+            HmmPrimaryState::Start1a
+            | HmmPrimaryState::Start1b
+            | HmmPrimaryState::Start1c
+            | HmmPrimaryState::Stop1a
+            | HmmPrimaryState::Stop1b
+            | HmmPrimaryState::Stop1c => (
+                pred.get_coding_phase2_neg_log_prob(),
+                pred.get_coding_phase2_penalty(),
+            ),
 
             HmmPrimaryState::Coding2 => (pred.get_coding_phase1_neg_log_prob(), pred.get_coding_phase1_penalty()),
             HmmPrimaryState::Start2 | HmmPrimaryState::Stop2 =>
@@ -887,17 +1463,95 @@ impl HmmState {
         }
     }
 
-    fn get_base_count(self) -> usize {
+    // States which a window touching a sequence end may start in, when partial genes are allowed: inside a
+    // UTR, coding sequence or intron, but not part way through a start / stop codon or donor splice site
+    fn is_partial_entry_state(self) -> bool {
+        let (primary, intron) = self.get_component_states();
+
+        let primary_allowed = matches!(
+            primary,
+            HmmPrimaryState::UTR5
+                | HmmPrimaryState::Coding0
+                | HmmPrimaryState::Coding1
+                | HmmPrimaryState::Coding2
+                | HmmPrimaryState::UTR3
+        );
+        let intron_allowed = matches!(
+            intron,
+            HmmIntronState::None
+                | HmmIntronState::U2GtAg
+                | HmmIntronState::U2GcAg
+                | HmmIntronState::U12AtAc
+        );
+
+        primary_allowed && intron_allowed
+    }
+
+    // Codon bases already read before the next coding base, when entering a window in this state
+    fn get_entry_coding_offset(self) -> u64 {
+        let (primary, _) = self.get_component_states();
+
+        match primary {
+            HmmPrimaryState::Coding0 => 1, // Next coding base is the second of a codon
+            HmmPrimaryState::Coding1 => 2,
+            _ => 0,
+        }
+    }
+
+    // Intron DSS states cover all but the last base of the shortest intron, the remaining intron states one base
+    fn get_base_count(self, config: &HmmConfig) -> usize {
         let (_, intron) = self.get_component_states();
 
         match intron {
-            HmmIntronState::U2GtAgDSS => 49,
-            HmmIntronState::U2GcAgDSS => 49,
-            HmmIntronState::U12AtAcDSS => 29,
+            HmmIntronState::U2GtAgDSS => config.min_intron_length - 1,
+            HmmIntronState::U2GcAgDSS => config.min_intron_length - 1,
+            HmmIntronState::U12AtAcDSS => config.min_u12_intron_length - 1,
             _ => 1,
         }
     }
 
+    // Minimum length of the intron containing this state, None if not within an intron
+    fn get_min_intron_length(self, config: &HmmConfig) -> Option<usize> {
+        let (_, intron) = self.get_component_states();
+
+        match intron {
+            HmmIntronState::None => None,
+            HmmIntronState::U12AtAcDSS | HmmIntronState::U12AtAc => {
+                Some(config.min_u12_intron_length)
+            }
+            _ => Some(config.min_intron_length),
+        }
+    }
+
+    // Position of the donor of the intron containing this state, when starting at start_position after a state
+    // in an intron with the given donor position. 0 if not within an intron
+    fn get_intron_start(self, start_position: usize, previous_intron_start: usize) -> usize {
+        let (_, intron) = self.get_component_states();
+
+        match intron {
+            HmmIntronState::None => 0,
+            HmmIntronState::U2GtAgDSS | HmmIntronState::U2GcAgDSS | HmmIntronState::U12AtAcDSS => {
+                start_position
+            }
+            _ => previous_intron_start,
+        }
+    }
+
+    // Intron states after the DSS, covering one base each
+    fn is_intron_body(self) -> bool {
+        let (_, intron) = self.get_component_states();
+
+        matches!(
+            intron,
+            HmmIntronState::U2GtAg | HmmIntronState::U2GcAg | HmmIntronState::U12AtAc
+        )
+    }
+
+    // The DSS state leading into this intron state, which directly precedes it in the state list
+    fn get_intron_dss_state(self) -> HmmState {
+        HMM_STATE_LIST[self as usize - 1]
+    }
+
     // Calculate the common (minimum) penalty for each 'destination' state - based on either DSS (intron start) or primary states with base matches (start/stop)
     // Valid for Intron DSS and all non-intron states
     fn get_common_state_entrance_penalty(
@@ -905,6 +1559,8 @@ impl HmmState {
         trans_ctx: &TransitionContext,
     ) -> Option<f64> {
         let (primary, intron) = self.get_component_states();
+        let config = trans_ctx.config;
+        let codons = trans_ctx.codons;
 
         if intron != HmmIntronState::None {
             match intron {
@@ -926,31 +1582,55 @@ impl HmmState {
 
             HmmPrimaryState::UTR5 => Some(0.0),
 
-            HmmPrimaryState::Start0 => trans_ctx
-                .get_downstream(1)
-                .map(|ds| ds[0].get_a() * START_WEIGHT),
-            HmmPrimaryState::Start1 => trans_ctx
-                .get_downstream(1)
-                .map(|ds| ds[0].get_t() * START_WEIGHT),
-            HmmPrimaryState::Start2 => trans_ctx
-                .get_downstream(1)
-                .map(|ds| ds[0].get_g() * START_WEIGHT),
+            HmmPrimaryState::Start0a | HmmPrimaryState::Start0b | HmmPrimaryState::Start0c => {
+                match (
+                    codons.start0_bases.get(primary.get_start_slot()),
+                    trans_ctx.get_downstream(1),
+                ) {
+                    (Some(bases), Some(ds)) => ds[0]
+                        .get_min_of(bases)
+                        .map(|pen| pen * config.start_weight + trans_ctx.get_start_model_penalty()),
+                    _ => None, // Slot not used by this genetic code
+                }
+            }
+            HmmPrimaryState::Start1a | HmmPrimaryState::Start1b | HmmPrimaryState::Start1c => {
+                match (
+                    codons.start1_prefixes.get(primary.get_start_slot()),
+                    trans_ctx.get_downstream(1),
+                ) {
+                    (Some((_, base)), Some(ds)) => {
+                        Some(ds[0].get_base(*base) * config.start_weight)
+                    }
+                    _ => None, // Slot not used by this genetic code
+                }
+            }
+            HmmPrimaryState::Start2 => Some(0.0), // Third base penalised on the transition from Start1
 
             HmmPrimaryState::Coding0 => trans_ctx
                 .get_downstream(1)
-                .map(|ds| min3(ds[0].get_a(), ds[0].get_c(), ds[0].get_g()) * STOP_WEIGHT),
+                .and_then(|ds| ds[0].get_min_excluding(&codons.stop0_bases))
+                .map(|pen| pen * config.stop_weight),
             HmmPrimaryState::Coding1 => Some(0.0),
             HmmPrimaryState::Coding2 => Some(0.0),
 
-            HmmPrimaryState::Stop0T => trans_ctx
-                .get_downstream(1)
-                .map(|ds| ds[0].get_t() * STOP_WEIGHT),
-            HmmPrimaryState::Stop1TA => trans_ctx
-                .get_downstream(1)
-                .map(|ds| ds[0].get_a() * STOP_WEIGHT),
-            HmmPrimaryState::Stop1TG => trans_ctx
-                .get_downstream(1)
-                .map(|ds| ds[0].get_g() * STOP_WEIGHT),
+            HmmPrimaryState::Stop0a | HmmPrimaryState::Stop0b => {
+                match (
+                    codons.stop0_bases.get(primary.get_stop_slot()),
+                    trans_ctx.get_downstream(1),
+                ) {
+                    (Some(base), Some(ds)) => Some(ds[0].get_base(*base) * config.stop_weight),
+                    _ => None, // Slot not used by this genetic code
+                }
+            }
+            HmmPrimaryState::Stop1a | HmmPrimaryState::Stop1b | HmmPrimaryState::Stop1c => {
+                match (
+                    codons.stop1_prefixes.get(primary.get_stop_slot()),
+                    trans_ctx.get_downstream(1),
+                ) {
+                    (Some((_, base)), Some(ds)) => Some(ds[0].get_base(*base) * config.stop_weight),
+                    _ => None, // Slot not used by this genetic code
+                }
+            }
             HmmPrimaryState::Stop2 => Some(0.0),
 
             HmmPrimaryState::UTR3 => Some(0.0),
@@ -975,7 +1655,72 @@ impl HmmState {
             }
         };
 
-        let (_, intron) = self.get_component_states();
+        let config = trans_ctx.config;
+        let codons = trans_ctx.codons;
+
+        // From a Start0 slot, continue the start codon in any matching Start1 slot
+        let consider_start0_successors = |successors: &mut Vec<(HmmState, f64)>,
+                                          start0_slot: usize,
+                                          other_pen: Option<f64>,
+                                          allow: bool| {
+            for (start1_slot, (prefix_slot, _)) in codons.start1_prefixes.iter().enumerate() {
+                if *prefix_slot == start0_slot {
+                    consider_transition(successors, START1_STATES[start1_slot], other_pen, allow);
+                }
+            }
+        };
+
+        // From a Start1 slot, complete the start codon with one of the third bases for its prefix
+        let consider_start1_successors = |successors: &mut Vec<(HmmState, f64)>,
+                                          start1_slot: usize,
+                                          other_pen: Option<f64>,
+                                          allow: bool| {
+            if let (Some(other_pen), Some(ds)) = (other_pen, trans_ctx.get_downstream(1)) {
+                let start_pen = ds[0]
+                    .get_min_of(&codons.start2_bases[start1_slot])
+                    .map(|pen| other_pen + pen * config.start_weight);
+                consider_transition(successors, HmmState::Start2, start_pen, allow);
+            }
+        };
+
+        // From a Stop0 slot, continue as a possible stop codon in any matching Stop1 slot, or as regular coding
+        let consider_stop0_successors =
+            |successors: &mut Vec<(HmmState, f64)>,
+             stop0_slot: usize,
+             other_pen: Option<f64>,
+             allow_coding: bool| {
+                for (stop1_slot, (prefix_slot, _)) in codons.stop1_prefixes.iter().enumerate() {
+                    if *prefix_slot == stop0_slot {
+                        consider_transition(successors, STOP1_STATES[stop1_slot], other_pen, true);
+                    }
+                }
+
+                if let (Some(other_pen), Some(ds)) = (other_pen, trans_ctx.get_downstream(1)) {
+                    let coding_pen = ds[0]
+                        .get_min_excluding(&codons.get_stop1_bases(stop0_slot))
+                        .map(|pen| other_pen + pen * config.stop_weight);
+                    consider_transition(successors, HmmState::Coding1, coding_pen, allow_coding);
+                }
+            };
+
+        // From a Stop1 slot, complete the stop codon, or continue as regular coding with any codon which isn't
+        // always a stop, so context dependent stops may be read through
+        let consider_stop1_successors =
+            |successors: &mut Vec<(HmmState, f64)>, stop1_slot: usize, other_pen: Option<f64>| {
+                if let (Some(other_pen), Some(ds)) = (other_pen, trans_ctx.get_downstream(1)) {
+                    let stop_pen = ds[0]
+                        .get_min_of(&codons.stop2_bases[stop1_slot])
+                        .map(|pen| other_pen + pen * config.stop_weight);
+                    consider_transition(successors, HmmState::Stop2, stop_pen, true);
+
+                    let coding_pen = ds[0]
+                        .get_min_excluding(&codons.stop2_hard_bases[stop1_slot])
+                        .map(|pen| other_pen + pen * config.stop_weight);
+                    consider_transition(successors, HmmState::Coding2, coding_pen, true);
+                }
+            };
+
+        let (primary, intron) = self.get_component_states();
 
         let acceptor_penalty = match intron {
             HmmIntronState::None => Some(0.0),
@@ -993,24 +1738,26 @@ impl HmmState {
 
             HmmState::UTR5 => {
                 successors.push((self, 0.0));
-                consider_transition(successors, HmmState::Start0, Some(0.0), true);
+                for start0_state in START0_STATES {
+                    consider_transition(successors, start0_state, Some(0.0), true);
+                }
                 consider_transition(
                     successors,
                     HmmState::UTR5IntronU2GtAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_UTR5,
+                    config.can_splice_utr5,
                 );
                 consider_transition(
                     successors,
                     HmmState::UTR5IntronU2GcAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_UTR5,
+                    config.can_splice_utr5,
                 );
                 consider_transition(
                     successors,
                     HmmState::UTR5IntronU12AtAcDSS,
                     Some(0.0),
-                    CAN_SPLICE_UTR5,
+                    config.can_splice_utr5,
                 );
             }
 
@@ -1026,165 +1773,315 @@ impl HmmState {
                     successors,
                     HmmState::UTR5,
                     acceptor_penalty,
-                    CAN_SPLICE_UTR5,
-                );
-                consider_transition(
-                    successors,
-                    HmmState::Start0,
-                    acceptor_penalty,
-                    CAN_SPLICE_UTR5_START,
+                    config.can_splice_utr5,
                 );
+                for start0_state in START0_STATES {
+                    consider_transition(
+                        successors,
+                        start0_state,
+                        acceptor_penalty,
+                        config.can_splice_utr5_start,
+                    );
+                }
             }
 
-            HmmState::Start0 => {
-                consider_transition(successors, HmmState::Start1, Some(0.0), true);
+            HmmState::Start0a => {
+                consider_start0_successors(successors, primary.get_start_slot(), Some(0.0), true);
                 consider_transition(
                     successors,
-                    HmmState::Start0IntronU2GtAgDSS,
+                    HmmState::Start0aIntronU2GtAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_START,
+                    config.can_splice_start,
                 );
                 consider_transition(
                     successors,
-                    HmmState::Start0IntronU2GcAgDSS,
+                    HmmState::Start0aIntronU2GcAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_START,
+                    config.can_splice_start,
                 );
                 consider_transition(
                     successors,
-                    HmmState::Start0IntronU12AtAcDSS,
+                    HmmState::Start0aIntronU12AtAcDSS,
                     Some(0.0),
-                    CAN_SPLICE_START,
+                    config.can_splice_start,
                 );
             }
 
-            HmmState::Start0IntronU2GtAgDSS => successors.push((HmmState::Start0IntronU2GtAg, 0.0)),
-            HmmState::Start0IntronU2GcAgDSS => successors.push((HmmState::Start0IntronU2GcAg, 0.0)),
-            HmmState::Start0IntronU12AtAcDSS => {
-                successors.push((HmmState::Start0IntronU12AtAc, 0.0))
+            HmmState::Start0aIntronU2GtAgDSS => {
+                successors.push((HmmState::Start0aIntronU2GtAg, 0.0))
             }
-
-            HmmState::Start0IntronU2GtAg
-            | HmmState::Start0IntronU2GcAg
-            | HmmState::Start0IntronU12AtAc => {
-                successors.push((self, 0.0));
-                consider_transition(
-                    successors,
-                    HmmState::Start1,
-                    acceptor_penalty,
-                    CAN_SPLICE_START,
-                );
+            HmmState::Start0aIntronU2GcAgDSS => {
+                successors.push((HmmState::Start0aIntronU2GcAg, 0.0))
+            }
+            HmmState::Start0aIntronU12AtAcDSS => {
+                successors.push((HmmState::Start0aIntronU12AtAc, 0.0))
             }
 
-            HmmState::Start1 => {
-                consider_transition(successors, HmmState::Start2, Some(0.0), true);
+            HmmState::Start0b => {
+                consider_start0_successors(successors, primary.get_start_slot(), Some(0.0), true);
                 consider_transition(
                     successors,
-                    HmmState::Start1IntronU2GtAgDSS,
+                    HmmState::Start0bIntronU2GtAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_START,
+                    config.can_splice_start,
                 );
                 consider_transition(
                     successors,
-                    HmmState::Start1IntronU2GcAgDSS,
+                    HmmState::Start0bIntronU2GcAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_START,
+                    config.can_splice_start,
                 );
                 consider_transition(
                     successors,
-                    HmmState::Start1IntronU12AtAcDSS,
+                    HmmState::Start0bIntronU12AtAcDSS,
                     Some(0.0),
-                    CAN_SPLICE_START,
+                    config.can_splice_start,
                 );
             }
 
-            HmmState::Start1IntronU2GtAgDSS => successors.push((HmmState::Start1IntronU2GtAg, 0.0)),
-            HmmState::Start1IntronU2GcAgDSS => successors.push((HmmState::Start1IntronU2GcAg, 0.0)),
-            HmmState::Start1IntronU12AtAcDSS => {
-                successors.push((HmmState::Start1IntronU12AtAc, 0.0))
+            HmmState::Start0bIntronU2GtAgDSS => {
+                successors.push((HmmState::Start0bIntronU2GtAg, 0.0))
             }
-
-            HmmState::Start1IntronU2GtAg
-            | HmmState::Start1IntronU2GcAg
-            | HmmState::Start1IntronU12AtAc => {
-                successors.push((self, 0.0));
-                consider_transition(
-                    successors,
-                    HmmState::Start2,
-                    acceptor_penalty,
-                    CAN_SPLICE_START,
-                );
+            HmmState::Start0bIntronU2GcAgDSS => {
+                successors.push((HmmState::Start0bIntronU2GcAg, 0.0))
+            }
+            HmmState::Start0bIntronU12AtAcDSS => {
+                successors.push((HmmState::Start0bIntronU12AtAc, 0.0))
             }
 
-            HmmState::Coding0 => {
-                consider_transition(successors, HmmState::Coding1, Some(0.0), true);
-
+            HmmState::Start0c => {
+                consider_start0_successors(successors, primary.get_start_slot(), Some(0.0), true);
                 consider_transition(
                     successors,
-                    HmmState::Coding0IntronU2GtAgDSS,
+                    HmmState::Start0cIntronU2GtAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_CODING,
+                    config.can_splice_start,
                 );
                 consider_transition(
                     successors,
-                    HmmState::Coding0IntronU2GcAgDSS,
+                    HmmState::Start0cIntronU2GcAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_CODING,
+                    config.can_splice_start,
                 );
                 consider_transition(
                     successors,
-                    HmmState::Coding0IntronU12AtAcDSS,
+                    HmmState::Start0cIntronU12AtAcDSS,
                     Some(0.0),
-                    CAN_SPLICE_CODING,
+                    config.can_splice_start,
                 );
             }
 
-            HmmState::Coding0IntronU2GtAgDSS => {
-                successors.push((HmmState::Coding0IntronU2GtAg, 0.0))
+            HmmState::Start0cIntronU2GtAgDSS => {
+                successors.push((HmmState::Start0cIntronU2GtAg, 0.0))
             }
-            HmmState::Coding0IntronU2GcAgDSS => {
-                successors.push((HmmState::Coding0IntronU2GcAg, 0.0))
+            HmmState::Start0cIntronU2GcAgDSS => {
+                successors.push((HmmState::Start0cIntronU2GcAg, 0.0))
             }
-            HmmState::Coding0IntronU12AtAcDSS => {
-                successors.push((HmmState::Coding0IntronU12AtAc, 0.0))
+            HmmState::Start0cIntronU12AtAcDSS => {
+                successors.push((HmmState::Start0cIntronU12AtAc, 0.0))
             }
 
-            HmmState::Coding0IntronU2GtAg
-            | HmmState::Coding0IntronU2GcAg
-            | HmmState::Coding0IntronU12AtAc => {
-                successors.push((self, 0.0));
+            HmmState::Start1a => {
+                consider_start1_successors(successors, primary.get_start_slot(), Some(0.0), true);
                 consider_transition(
                     successors,
-                    HmmState::Coding1,
-                    acceptor_penalty,
-                    CAN_SPLICE_CODING,
+                    HmmState::Start1aIntronU2GtAgDSS,
+                    Some(0.0),
+                    config.can_splice_start,
+                );
+                consider_transition(
+                    successors,
+                    HmmState::Start1aIntronU2GcAgDSS,
+                    Some(0.0),
+                    config.can_splice_start,
+                );
+                consider_transition(
+                    successors,
+                    HmmState::Start1aIntronU12AtAcDSS,
+                    Some(0.0),
+                    config.can_splice_start,
                 );
             }
 
-            HmmState::Coding1 => {
-                consider_transition(successors, HmmState::Coding2, Some(0.0), true);
+            HmmState::Start1aIntronU2GtAgDSS => {
+                successors.push((HmmState::Start1aIntronU2GtAg, 0.0))
+            }
+            HmmState::Start1aIntronU2GcAgDSS => {
+                successors.push((HmmState::Start1aIntronU2GcAg, 0.0))
+            }
+            HmmState::Start1aIntronU12AtAcDSS => {
+                successors.push((HmmState::Start1aIntronU12AtAc, 0.0))
+            }
 
+            HmmState::Start1b => {
+                consider_start1_successors(successors, primary.get_start_slot(), Some(0.0), true);
                 consider_transition(
                     successors,
-                    HmmState::Coding1IntronU2GtAgDSS,
+                    HmmState::Start1bIntronU2GtAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_CODING,
+                    config.can_splice_start,
                 );
                 consider_transition(
                     successors,
-                    HmmState::Coding1IntronU2GcAgDSS,
+                    HmmState::Start1bIntronU2GcAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_CODING,
+                    config.can_splice_start,
                 );
                 consider_transition(
                     successors,
-                    HmmState::Coding1IntronU12AtAcDSS,
+                    HmmState::Start1bIntronU12AtAcDSS,
                     Some(0.0),
-                    CAN_SPLICE_CODING,
+                    config.can_splice_start,
                 );
             }
 
-            HmmState::Coding1IntronU2GtAgDSS => {
+            HmmState::Start1bIntronU2GtAgDSS => {
+                successors.push((HmmState::Start1bIntronU2GtAg, 0.0))
+            }
+            HmmState::Start1bIntronU2GcAgDSS => {
+                successors.push((HmmState::Start1bIntronU2GcAg, 0.0))
+            }
+            HmmState::Start1bIntronU12AtAcDSS => {
+                successors.push((HmmState::Start1bIntronU12AtAc, 0.0))
+            }
+
+            HmmState::Start1c => {
+                consider_start1_successors(successors, primary.get_start_slot(), Some(0.0), true);
+                consider_transition(
+                    successors,
+                    HmmState::Start1cIntronU2GtAgDSS,
+                    Some(0.0),
+                    config.can_splice_start,
+                );
+                consider_transition(
+                    successors,
+                    HmmState::Start1cIntronU2GcAgDSS,
+                    Some(0.0),
+                    config.can_splice_start,
+                );
+                consider_transition(
+                    successors,
+                    HmmState::Start1cIntronU12AtAcDSS,
+                    Some(0.0),
+                    config.can_splice_start,
+                );
+            }
+
+            HmmState::Start1cIntronU2GtAgDSS => {
+                successors.push((HmmState::Start1cIntronU2GtAg, 0.0))
+            }
+            HmmState::Start1cIntronU2GcAgDSS => {
+                successors.push((HmmState::Start1cIntronU2GcAg, 0.0))
+            }
+            HmmState::Start1cIntronU12AtAcDSS => {
+                successors.push((HmmState::Start1cIntronU12AtAc, 0.0))
+            }
+
+            HmmState::Start0aIntronU2GtAg
+            | HmmState::Start0aIntronU2GcAg
+            | HmmState::Start0aIntronU12AtAc
+            | HmmState::Start0bIntronU2GtAg
+            | HmmState::Start0bIntronU2GcAg
+            | HmmState::Start0bIntronU12AtAc
+            | HmmState::Start0cIntronU2GtAg
+            | HmmState::Start0cIntronU2GcAg
+            | HmmState::Start0cIntronU12AtAc => {
+                successors.push((self, 0.0));
+                consider_start0_successors(
+                    successors,
+                    primary.get_start_slot(),
+                    acceptor_penalty,
+                    config.can_splice_start,
+                );
+            }
+
+            HmmState::Start1aIntronU2GtAg
+            | HmmState::Start1aIntronU2GcAg
+            | HmmState::Start1aIntronU12AtAc
+            | HmmState::Start1bIntronU2GtAg
+            | HmmState::Start1bIntronU2GcAg
+            | HmmState::Start1bIntronU12AtAc
+            | HmmState::Start1cIntronU2GtAg
+            | HmmState::Start1cIntronU2GcAg
+            | HmmState::Start1cIntronU12AtAc => {
+                successors.push((self, 0.0));
+                consider_start1_successors(
+                    successors,
+                    primary.get_start_slot(),
+                    acceptor_penalty,
+                    config.can_splice_start,
+                );
+            }
+
+            HmmState::Coding0 => {
+                consider_transition(successors, HmmState::Coding1, Some(0.0), true);
+
+                consider_transition(
+                    successors,
+                    HmmState::Coding0IntronU2GtAgDSS,
+                    Some(0.0),
+                    config.can_splice_coding,
+                );
+                consider_transition(
+                    successors,
+                    HmmState::Coding0IntronU2GcAgDSS,
+                    Some(0.0),
+                    config.can_splice_coding,
+                );
+                consider_transition(
+                    successors,
+                    HmmState::Coding0IntronU12AtAcDSS,
+                    Some(0.0),
+                    config.can_splice_coding,
+                );
+            }
+
+            HmmState::Coding0IntronU2GtAgDSS => {
+                successors.push((HmmState::Coding0IntronU2GtAg, 0.0))
+            }
+            HmmState::Coding0IntronU2GcAgDSS => {
+                successors.push((HmmState::Coding0IntronU2GcAg, 0.0))
+            }
+            HmmState::Coding0IntronU12AtAcDSS => {
+                successors.push((HmmState::Coding0IntronU12AtAc, 0.0))
+            }
+
+            HmmState::Coding0IntronU2GtAg
+            | HmmState::Coding0IntronU2GcAg
+            | HmmState::Coding0IntronU12AtAc => {
+                successors.push((self, 0.0));
+                consider_transition(
+                    successors,
+                    HmmState::Coding1,
+                    acceptor_penalty,
+                    config.can_splice_coding,
+                );
+            }
+
+            HmmState::Coding1 => {
+                consider_transition(successors, HmmState::Coding2, Some(0.0), true);
+
+                consider_transition(
+                    successors,
+                    HmmState::Coding1IntronU2GtAgDSS,
+                    Some(0.0),
+                    config.can_splice_coding,
+                );
+                consider_transition(
+                    successors,
+                    HmmState::Coding1IntronU2GcAgDSS,
+                    Some(0.0),
+                    config.can_splice_coding,
+                );
+                consider_transition(
+                    successors,
+                    HmmState::Coding1IntronU12AtAcDSS,
+                    Some(0.0),
+                    config.can_splice_coding,
+                );
+            }
+
+            HmmState::Coding1IntronU2GtAgDSS => {
                 successors.push((HmmState::Coding1IntronU2GtAg, 0.0))
             }
             HmmState::Coding1IntronU2GcAgDSS => {
@@ -1202,31 +2099,33 @@ impl HmmState {
                     successors,
                     HmmState::Coding2,
                     acceptor_penalty,
-                    CAN_SPLICE_CODING,
+                    config.can_splice_coding,
                 );
             }
 
             HmmState::Start2 | HmmState::Coding2 => {
                 consider_transition(successors, HmmState::Coding0, Some(0.0), true);
-                consider_transition(successors, HmmState::Stop0T, Some(0.0), true);
+                for stop0_state in STOP0_STATES {
+                    consider_transition(successors, stop0_state, Some(0.0), true);
+                }
 
                 consider_transition(
                     successors,
                     HmmState::Coding2IntronU2GtAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_CODING,
+                    config.can_splice_coding,
                 );
                 consider_transition(
                     successors,
                     HmmState::Coding2IntronU2GcAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_CODING,
+                    config.can_splice_coding,
                 );
                 consider_transition(
                     successors,
                     HmmState::Coding2IntronU12AtAcDSS,
                     Some(0.0),
-                    CAN_SPLICE_CODING,
+                    config.can_splice_coding,
                 );
             }
 
@@ -1248,219 +2147,199 @@ impl HmmState {
                     successors,
                     HmmState::Coding0,
                     acceptor_penalty,
-                    CAN_SPLICE_CODING,
+                    config.can_splice_coding,
+                );
+                for stop0_state in STOP0_STATES {
+                    consider_transition(
+                        successors,
+                        stop0_state,
+                        acceptor_penalty,
+                        config.can_splice_coding_stop,
+                    );
+                }
+            }
+
+            HmmState::Stop0a =>
+            // Equivalent to Coding0, but potentially the start of a stop codon
+            {
+                consider_stop0_successors(successors, primary.get_stop_slot(), Some(0.0), true);
+
+                consider_transition(
+                    successors,
+                    HmmState::Stop0aIntronU2GtAgDSS,
+                    Some(0.0),
+                    config.can_splice_stop,
                 );
                 consider_transition(
                     successors,
-                    HmmState::Stop0T,
-                    acceptor_penalty,
-                    CAN_SPLICE_CODING_STOP,
+                    HmmState::Stop0aIntronU2GcAgDSS,
+                    Some(0.0),
+                    config.can_splice_stop,
+                );
+                consider_transition(
+                    successors,
+                    HmmState::Stop0aIntronU12AtAcDSS,
+                    Some(0.0),
+                    config.can_splice_stop,
                 );
             }
 
-            HmmState::Stop0T =>
-            // Equivalent to Coding0, but potentially a stop codon (Txx)
-            {
-                consider_transition(successors, HmmState::Stop1TA, Some(0.0), true);
-                consider_transition(successors, HmmState::Stop1TG, Some(0.0), true);
+            HmmState::Stop0aIntronU2GtAgDSS => successors.push((HmmState::Stop0aIntronU2GtAg, 0.0)),
+            HmmState::Stop0aIntronU2GcAgDSS => successors.push((HmmState::Stop0aIntronU2GcAg, 0.0)),
+            HmmState::Stop0aIntronU12AtAcDSS => {
+                successors.push((HmmState::Stop0aIntronU12AtAc, 0.0))
+            }
 
-                if let Some(ds) = trans_ctx.get_downstream(1) {
-                    consider_transition(
-                        successors,
-                        HmmState::Coding1,
-                        Some(min2(ds[0].get_c(), ds[0].get_t()) * STOP_WEIGHT),
-                        true,
-                    );
-                }
+            HmmState::Stop0b =>
+            // Equivalent to Coding0, but potentially the start of a stop codon
+            {
+                consider_stop0_successors(successors, primary.get_stop_slot(), Some(0.0), true);
 
                 consider_transition(
                     successors,
-                    HmmState::Stop0TIntronU2GtAgDSS,
+                    HmmState::Stop0bIntronU2GtAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_STOP,
+                    config.can_splice_stop,
                 );
                 consider_transition(
                     successors,
-                    HmmState::Stop0TIntronU2GcAgDSS,
+                    HmmState::Stop0bIntronU2GcAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_STOP,
+                    config.can_splice_stop,
                 );
                 consider_transition(
                     successors,
-                    HmmState::Stop0TIntronU12AtAcDSS,
+                    HmmState::Stop0bIntronU12AtAcDSS,
                     Some(0.0),
-                    CAN_SPLICE_STOP,
+                    config.can_splice_stop,
                 );
             }
 
-            HmmState::Stop0TIntronU2GtAgDSS => successors.push((HmmState::Stop0TIntronU2GtAg, 0.0)),
-            HmmState::Stop0TIntronU2GcAgDSS => successors.push((HmmState::Stop0TIntronU2GcAg, 0.0)),
-            HmmState::Stop0TIntronU12AtAcDSS => {
-                successors.push((HmmState::Stop0TIntronU12AtAc, 0.0))
+            HmmState::Stop0bIntronU2GtAgDSS => successors.push((HmmState::Stop0bIntronU2GtAg, 0.0)),
+            HmmState::Stop0bIntronU2GcAgDSS => successors.push((HmmState::Stop0bIntronU2GcAg, 0.0)),
+            HmmState::Stop0bIntronU12AtAcDSS => {
+                successors.push((HmmState::Stop0bIntronU12AtAc, 0.0))
             }
 
-            HmmState::Stop0TIntronU2GtAg
-            | HmmState::Stop0TIntronU2GcAg
-            | HmmState::Stop0TIntronU12AtAc => {
+            HmmState::Stop0aIntronU2GtAg
+            | HmmState::Stop0aIntronU2GcAg
+            | HmmState::Stop0aIntronU12AtAc
+            | HmmState::Stop0bIntronU2GtAg
+            | HmmState::Stop0bIntronU2GcAg
+            | HmmState::Stop0bIntronU12AtAc => {
                 successors.push((self, 0.0));
-                consider_transition(successors, HmmState::Stop1TA, acceptor_penalty, true);
-                consider_transition(successors, HmmState::Stop1TG, acceptor_penalty, true);
-
-                if let (Some(acceptor_penalty), Some(ds)) =
-                    (acceptor_penalty, trans_ctx.get_downstream(1))
-                {
-                    consider_transition(
-                        successors,
-                        HmmState::Coding1,
-                        Some(acceptor_penalty + min2(ds[0].get_c(), ds[0].get_t()) * STOP_WEIGHT),
-                        CAN_SPLICE_STOP,
-                    );
-                }
+                consider_stop0_successors(
+                    successors,
+                    primary.get_stop_slot(),
+                    acceptor_penalty,
+                    config.can_splice_stop,
+                );
             }
 
-            HmmState::Stop1TA =>
-            // Equivalent to Coding1, but potentially a stop codon (TAx)
+            HmmState::Stop1a =>
+            // Equivalent to Coding1, but potentially the first two bases of a stop codon
             {
-                if let Some(ds) = trans_ctx.get_downstream(1) {
-                    consider_transition(
-                        successors,
-                        HmmState::Stop2,
-                        Some(min2(ds[0].get_a(), ds[0].get_g()) * STOP_WEIGHT),
-                        true,
-                    );
-                    consider_transition(
-                        successors,
-                        HmmState::Coding2,
-                        Some(min2(ds[0].get_c(), ds[0].get_t()) * STOP_WEIGHT),
-                        true,
-                    );
-                }
+                consider_stop1_successors(successors, primary.get_stop_slot(), Some(0.0));
 
                 consider_transition(
                     successors,
-                    HmmState::Stop1TAIntronU2GtAgDSS,
+                    HmmState::Stop1aIntronU2GtAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_STOP,
+                    config.can_splice_stop,
                 );
                 consider_transition(
                     successors,
-                    HmmState::Stop1TAIntronU2GcAgDSS,
+                    HmmState::Stop1aIntronU2GcAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_STOP,
+                    config.can_splice_stop,
                 );
                 consider_transition(
                     successors,
-                    HmmState::Stop1TAIntronU12AtAcDSS,
+                    HmmState::Stop1aIntronU12AtAcDSS,
                     Some(0.0),
-                    CAN_SPLICE_STOP,
+                    config.can_splice_stop,
                 );
             }
 
-            HmmState::Stop1TAIntronU2GtAgDSS => {
-                successors.push((HmmState::Stop1TAIntronU2GtAg, 0.0))
-            }
-            HmmState::Stop1TAIntronU2GcAgDSS => {
-                successors.push((HmmState::Stop1TAIntronU2GcAg, 0.0))
-            }
-            HmmState::Stop1TAIntronU12AtAcDSS => {
-                successors.push((HmmState::Stop1TAIntronU12AtAc, 0.0))
+            HmmState::Stop1aIntronU2GtAgDSS => successors.push((HmmState::Stop1aIntronU2GtAg, 0.0)),
+            HmmState::Stop1aIntronU2GcAgDSS => successors.push((HmmState::Stop1aIntronU2GcAg, 0.0)),
+            HmmState::Stop1aIntronU12AtAcDSS => {
+                successors.push((HmmState::Stop1aIntronU12AtAc, 0.0))
             }
 
-            HmmState::Stop1TAIntronU2GtAg
-            | HmmState::Stop1TAIntronU2GcAg
-            | HmmState::Stop1TAIntronU12AtAc => {
-                successors.push((self, 0.0));
+            HmmState::Stop1b =>
+            // Equivalent to Coding1, but potentially the first two bases of a stop codon
+            {
+                consider_stop1_successors(successors, primary.get_stop_slot(), Some(0.0));
 
-                if let (Some(acceptor_penalty), Some(ds)) =
-                    (acceptor_penalty, trans_ctx.get_downstream(1))
-                {
-                    consider_transition(
-                        successors,
-                        HmmState::Stop2,
-                        Some(acceptor_penalty + min2(ds[0].get_a(), ds[0].get_g()) * STOP_WEIGHT),
-                        true,
-                    );
-                    consider_transition(
-                        successors,
-                        HmmState::Coding2,
-                        Some(acceptor_penalty + min2(ds[0].get_c(), ds[0].get_t()) * STOP_WEIGHT),
-                        true,
-                    );
-                }
+                consider_transition(
+                    successors,
+                    HmmState::Stop1bIntronU2GtAgDSS,
+                    Some(0.0),
+                    config.can_splice_stop,
+                );
+                consider_transition(
+                    successors,
+                    HmmState::Stop1bIntronU2GcAgDSS,
+                    Some(0.0),
+                    config.can_splice_stop,
+                );
+                consider_transition(
+                    successors,
+                    HmmState::Stop1bIntronU12AtAcDSS,
+                    Some(0.0),
+                    config.can_splice_stop,
+                );
+            }
+
+            HmmState::Stop1bIntronU2GtAgDSS => successors.push((HmmState::Stop1bIntronU2GtAg, 0.0)),
+            HmmState::Stop1bIntronU2GcAgDSS => successors.push((HmmState::Stop1bIntronU2GcAg, 0.0)),
+            HmmState::Stop1bIntronU12AtAcDSS => {
+                successors.push((HmmState::Stop1bIntronU12AtAc, 0.0))
             }
 
-            HmmState::Stop1TG =>
-            // Equivalent to Coding1, but potentially a stop codon (TGx)
+            HmmState::Stop1c =>
+            // Equivalent to Coding1, but potentially the first two bases of a stop codon
             {
-                if let Some(ds) = trans_ctx.get_downstream(1) {
-                    consider_transition(
-                        successors,
-                        HmmState::Stop2,
-                        Some(ds[0].get_a() * STOP_WEIGHT),
-                        true,
-                    );
-                    consider_transition(
-                        successors,
-                        HmmState::Coding2,
-                        Some(min3(ds[0].get_c(), ds[0].get_g(), ds[0].get_t()) * STOP_WEIGHT),
-                        true,
-                    );
-                }
+                consider_stop1_successors(successors, primary.get_stop_slot(), Some(0.0));
 
                 consider_transition(
                     successors,
-                    HmmState::Stop1TGIntronU2GtAgDSS,
+                    HmmState::Stop1cIntronU2GtAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_STOP,
+                    config.can_splice_stop,
                 );
                 consider_transition(
                     successors,
-                    HmmState::Stop1TGIntronU2GcAgDSS,
+                    HmmState::Stop1cIntronU2GcAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_STOP,
+                    config.can_splice_stop,
                 );
                 consider_transition(
                     successors,
-                    HmmState::Stop1TGIntronU12AtAcDSS,
+                    HmmState::Stop1cIntronU12AtAcDSS,
                     Some(0.0),
-                    CAN_SPLICE_STOP,
+                    config.can_splice_stop,
                 );
             }
 
-            HmmState::Stop1TGIntronU2GtAgDSS => {
-                successors.push((HmmState::Stop1TGIntronU2GtAg, 0.0))
-            }
-            HmmState::Stop1TGIntronU2GcAgDSS => {
-                successors.push((HmmState::Stop1TGIntronU2GcAg, 0.0))
-            }
-            HmmState::Stop1TGIntronU12AtAcDSS => {
-                successors.push((HmmState::Stop1TGIntronU12AtAc, 0.0))
+            HmmState::Stop1cIntronU2GtAgDSS => successors.push((HmmState::Stop1cIntronU2GtAg, 0.0)),
+            HmmState::Stop1cIntronU2GcAgDSS => successors.push((HmmState::Stop1cIntronU2GcAg, 0.0)),
+            HmmState::Stop1cIntronU12AtAcDSS => {
+                successors.push((HmmState::Stop1cIntronU12AtAc, 0.0))
             }
 
-            HmmState::Stop1TGIntronU2GtAg
-            | HmmState::Stop1TGIntronU2GcAg
-            | HmmState::Stop1TGIntronU12AtAc => {
+            HmmState::Stop1aIntronU2GtAg
+            | HmmState::Stop1aIntronU2GcAg
+            | HmmState::Stop1aIntronU12AtAc
+            | HmmState::Stop1bIntronU2GtAg
+            | HmmState::Stop1bIntronU2GcAg
+            | HmmState::Stop1bIntronU12AtAc
+            | HmmState::Stop1cIntronU2GtAg
+            | HmmState::Stop1cIntronU2GcAg
+            | HmmState::Stop1cIntronU12AtAc => {
                 successors.push((self, 0.0));
-
-                if let (Some(acceptor_penalty), Some(ds)) =
-                    (acceptor_penalty, trans_ctx.get_downstream(1))
-                {
-                    consider_transition(
-                        successors,
-                        HmmState::Stop2,
-                        Some(acceptor_penalty + ds[0].get_a() * STOP_WEIGHT),
-                        true,
-                    );
-                    consider_transition(
-                        successors,
-                        HmmState::Coding2,
-                        Some(
-                            acceptor_penalty
-                                + min3(ds[0].get_c(), ds[0].get_g(), ds[0].get_t()) * STOP_WEIGHT,
-                        ),
-                        true,
-                    );
-                }
+                consider_stop1_successors(successors, primary.get_stop_slot(), acceptor_penalty);
             }
 
             HmmState::Stop2 => {
@@ -1469,19 +2348,19 @@ impl HmmState {
                     successors,
                     HmmState::UTR3IntronU2GtAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_STOP_UTR3,
+                    config.can_splice_stop_utr3,
                 );
                 consider_transition(
                     successors,
                     HmmState::UTR3IntronU2GcAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_STOP_UTR3,
+                    config.can_splice_stop_utr3,
                 );
                 consider_transition(
                     successors,
                     HmmState::UTR3IntronU12AtAcDSS,
                     Some(0.0),
-                    CAN_SPLICE_STOP_UTR3,
+                    config.can_splice_stop_utr3,
                 );
             }
 
@@ -1492,19 +2371,19 @@ impl HmmState {
                     successors,
                     HmmState::UTR3IntronU2GtAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_UTR3,
+                    config.can_splice_utr3,
                 );
                 consider_transition(
                     successors,
                     HmmState::UTR3IntronU2GcAgDSS,
                     Some(0.0),
-                    CAN_SPLICE_UTR3,
+                    config.can_splice_utr3,
                 );
                 consider_transition(
                     successors,
                     HmmState::UTR3IntronU12AtAcDSS,
                     Some(0.0),
-                    CAN_SPLICE_UTR3,
+                    config.can_splice_utr3,
                 );
             }
 
@@ -1520,7 +2399,7 @@ impl HmmState {
                     successors,
                     HmmState::UTR3,
                     acceptor_penalty,
-                    CAN_SPLICE_UTR3,
+                    config.can_splice_utr3,
                 );
             }
         }
@@ -1538,19 +2417,33 @@ pub struct HmmEval {
 
     accum_penalty: u64, // Accumulated penalty, target to minimise
     trans_penalty: u64, // Transition penalty into this state
-    neg_log_prob: u64   // Negative Log probability of all bases within this state
+    neg_log_prob: u64,  // Negative Log probability of all bases within this state
+
+    intron_start: usize, // Donor position of the intron containing this state, see get_intron_start
+    previous_intron_start: usize, // Donor position for the previous state, to find it among intron candidates
 }
 
+// Donor position of an intron which started before the window, whose length is unknown and so never limited
+const PARTIAL_INTRON_START: usize = usize::MAX;
+
 impl HmmEval {
-    fn new_root() -> HmmEval {
+    fn new_root(state: HmmState, accum_penalty: u64) -> HmmEval {
+        let intron_start = if state.get_annotation_label() == HmmAnnotationLabel::Intron {
+            PARTIAL_INTRON_START
+        } else {
+            0
+        };
+
         HmmEval {
             start_position: 0,
             end_position: 0,
-            state: HmmState::Intergenic,
-            previous_state: HmmState::Intergenic,
-            accum_penalty: 0,
+            state,
+            previous_state: state,
+            accum_penalty,
             trans_penalty: 0,
-            neg_log_prob: 0
+            neg_log_prob: 0,
+            intron_start,
+            previous_intron_start: intron_start,
         }
     }
 
@@ -1561,7 +2454,7 @@ impl HmmEval {
         previous_state: HmmState,
         accum_penalty: u64,
         trans_penalty: u64,
-        neg_log_prob: u64
+        neg_log_prob: u64,
     ) -> HmmEval {
         HmmEval {
             start_position,
@@ -1570,9 +2463,28 @@ impl HmmEval {
             previous_state,
             accum_penalty,
             trans_penalty,
-            neg_log_prob
+            neg_log_prob,
+            intron_start: 0,
+            previous_intron_start: 0,
         }
     }
+
+    // Carries the start of any intron on from the previous state, or starts a new one at a donor
+    fn with_intron_start(mut self, previous_intron_start: usize) -> HmmEval {
+        self.intron_start = self
+            .state
+            .get_intron_start(self.start_position, previous_intron_start);
+        self.previous_intron_start = previous_intron_start;
+        self
+    }
+
+    // Adds the (scaled) intron length penalty for leaving an intron, see PredictionHmm::get_intron_exit_penalty
+    fn with_length_penalty(mut self, length_penalty: u64) -> HmmEval {
+        self.accum_penalty += length_penalty;
+        self.trans_penalty += length_penalty;
+        self.neg_log_prob += length_penalty;
+        self
+    }
 }
 
 impl Ord for HmmEval {
@@ -1592,150 +2504,1434 @@ impl PartialOrd for HmmEval {
     }
 }
 
-const MAX_EVALS: u64 = 100_000_000_000;
-
-pub struct PredictionHmm {
-    class_pred_pen: Vec<ClassPredPenalty>,
-    phase_pred_pen: Vec<PhasePredPenalty>,
-
-    pred_pen: Vec<PredPenalty>,
-    bases_pen: Vec<BasesPenalty>,
+const MAX_EVALS: u64 = 100_000_000_000;
+
+const VITERBI_NO_STATE: u8 = u8::MAX;
+
+const ISOFORM_FLANK: usize = 500; // Intergenic bases either side of a gene included when solving for alternatives
+
+// Splice site which paths may not use, to find alternative transcripts
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum SpliceSite {
+    Donor(usize),    // Position of the first intron base
+    Acceptor(usize), // Position after the last intron base
+}
+
+pub struct PredictionHmm<'a> {
+    config: &'a HmmConfig,
+    codons: CodonStates,
+
+    class_pred_pen: Vec<ClassPredPenalty>,
+    phase_pred_pen: Vec<PhasePredPenalty>,
+
+    pred_pen: Vec<PredPenalty>,
+    bases_pen: Vec<BasesPenalty>,
+
+    best_eval: Vec<Option<HmmEval>>,
+    best_intron_evals: HashMap<usize, Vec<HmmEval>>, // Intron state candidates, see IntronCandidateOrder
+
+    eval_heap: BinaryHeap<HmmEval>,
+
+    banned_splice_site: Option<SpliceSite>,
+
+    intron_candidate_order: IntronCandidateOrder,
+
+    partial_start: bool, // Paths may start in a genic state
+    partial_end: bool,   // Genic states at the end are marked as truncated
+
+    hint_flags: Vec<u8>, // Per base hint flags, empty without hints
+}
+
+impl<'a> PredictionHmm<'a> {
+    pub fn new(
+        bp_vector: Vec<(Bases, ClassPrediction, PhasePrediction)>,
+        config: &'a HmmConfig,
+        genetic_code: &GeneticCode,
+    ) -> PredictionHmm<'a> {
+        let mut class_pred_pen = Vec::with_capacity(bp_vector.len());
+        let mut phase_pred_pen = Vec::with_capacity(bp_vector.len());
+        let mut pred_pen = Vec::with_capacity(bp_vector.len());
+
+        let mut bases_pen = Vec::with_capacity(bp_vector.len());
+
+        for (bases, class_pred, phase_pred) in bp_vector.iter() {
+            class_pred_pen.push(ClassPredPenalty::new(
+                class_pred,
+                config.class_pred_prob_floor,
+            ));
+            phase_pred_pen.push(PhasePredPenalty::new(
+                phase_pred,
+                config.phase_pred_prob_floor,
+            ));
+            pred_pen.push(PredPenalty::new(
+                class_pred,
+                phase_pred,
+                config.phase_retain,
+                config.pred_prob_floor,
+            ));
+
+            bases_pen.push(BasesPenalty::new(bases, config.base_prob_floor));
+        }
+
+        let best_eval = Vec::new(); // Only allocated by the best-first solver
+        let eval_heap = BinaryHeap::new();
+
+        let codons = CodonStates::new(genetic_code, config.alternative_starts)
+            .expect("Genetic codes are checked when set in the HMM config");
+
+        PredictionHmm {
+            config,
+            codons,
+            class_pred_pen,
+            phase_pred_pen,
+            pred_pen,
+            bases_pen,
+            best_eval,
+            best_intron_evals: HashMap::new(),
+            eval_heap,
+            banned_splice_site: None,
+            intron_candidate_order: IntronCandidateOrder::new(config),
+            partial_start: false,
+            partial_end: false,
+            hint_flags: Vec::new(),
+        }
+    }
+
+    /// Sets the external hint flags for each base of the window, see Hints::get_window_flags
+    pub fn set_hint_flags(&mut self, hint_flags: Vec<u8>) {
+        assert!(hint_flags.is_empty() || hint_flags.len() == self.class_pred_pen.len());
+        self.hint_flags = hint_flags;
+    }
+
+    // Penalty for a state covering a base which disagrees with the hints for that base
+    fn get_hint_state_penalty(&self, position: usize, state: HmmState) -> f64 {
+        let flags = match self.hint_flags.get(position) {
+            Some(flags) => *flags,
+            None => return 0.0,
+        };
+
+        let label = state.get_annotation_label();
+        let mut penalty = 0.0;
+
+        if flags & HINT_INTRON != 0 && label != HmmAnnotationLabel::Intron {
+            penalty += self.config.intron_hint_penalty;
+        }
+        if flags & HINT_EXON != 0
+            && (label == HmmAnnotationLabel::Intergenic || label == HmmAnnotationLabel::Intron)
+        {
+            penalty += self.config.exon_hint_penalty;
+        }
+        if flags & HINT_CDS != 0 && label != HmmAnnotationLabel::Coding {
+            penalty += self.config.cds_hint_penalty;
+        }
+
+        penalty
+    }
+
+    /// Records whether the window starts and / or ends at an end of its sequence, where genes may be
+    /// truncated if partial genes are enabled
+    pub fn set_sequence_bounds(&mut self, at_sequence_start: bool, at_sequence_end: bool) {
+        self.partial_start = self.config.partial_genes && at_sequence_start;
+        self.partial_end = self.config.partial_genes && at_sequence_end;
+    }
+
+    // States a path may start in, before the first base, with their penalties
+    fn get_entry_states(&self) -> Vec<(HmmState, u64)> {
+        let mut entry_states = vec![(HmmState::Intergenic, 0)];
+
+        if self.partial_start {
+            let penalty = (self.config.partial_gene_penalty * PENALTY_SCALE) as u64;
+
+            for state in HMM_STATE_LIST.iter() {
+                if state.is_partial_entry_state() {
+                    entry_states.push((*state, penalty));
+                }
+            }
+        }
+
+        entry_states
+    }
+
+    // A copy of the HMM covering only the given positions, optionally with a splice site banned
+    fn new_locus_hmm(
+        &self,
+        start_position: usize,
+        end_position: usize,
+        banned_splice_site: Option<SpliceSite>,
+    ) -> PredictionHmm<'a> {
+        PredictionHmm {
+            config: self.config,
+            codons: self.codons.clone(),
+            class_pred_pen: self.class_pred_pen[start_position..end_position].to_vec(),
+            phase_pred_pen: self.phase_pred_pen[start_position..end_position].to_vec(),
+            pred_pen: self.pred_pen[start_position..end_position].to_vec(),
+            bases_pen: self.bases_pen[start_position..end_position].to_vec(),
+            best_eval: Vec::new(),
+            best_intron_evals: HashMap::new(),
+            eval_heap: BinaryHeap::new(),
+            banned_splice_site,
+            intron_candidate_order: self.intron_candidate_order,
+            partial_start: self.partial_start && start_position == 0,
+            partial_end: self.partial_end && end_position == self.class_pred_pen.len(),
+            hint_flags: if self.hint_flags.is_empty() {
+                Vec::new()
+            } else {
+                self.hint_flags[start_position..end_position].to_vec()
+            },
+        }
+    }
+
+    fn consider_eval(&mut self, eval: HmmEval) {
+        let idx = eval.end_position * HMM_STATES + (eval.state as usize);
+
+        if eval.state.is_intron_body() {
+            let candidates = self.best_intron_evals.entry(idx).or_default();
+            if self.intron_candidate_order.add_candidate(candidates, eval) {
+                self.eval_heap.push(eval);
+            }
+            return;
+        }
+
+        let maybe_old_eval = &self.best_eval[idx];
+
+        if let Some(old_eval) = maybe_old_eval {
+            if eval.accum_penalty >= old_eval.accum_penalty {
+                return;
+            }
+        }
+
+        self.best_eval[idx] = Some(eval);
+        self.eval_heap.push(eval);
+    }
+
+    fn is_eval_current(&self, eval: &HmmEval) -> bool {
+        let idx = eval.end_position * HMM_STATES + (eval.state as usize);
+
+        if eval.state.is_intron_body() {
+            return self.best_intron_evals[&idx].contains(eval);
+        }
+
+        let best_eval = &self.best_eval[idx].expect("Eval from heap not in best_eval");
+
+        best_eval == eval
+    }
+
+    // Columns kept by the Viterbi and forward-backward rings, which must exceed the longest state base count
+    fn get_ring_columns(&self) -> usize {
+        std::cmp::max(
+            self.config.min_intron_length,
+            self.config.min_u12_intron_length,
+        )
+    }
+
+    // Whether the intron which started at intron_start may continue over the base at position
+    fn can_extend_intron(&self, position: usize, intron_start: usize) -> bool {
+        let max_length = self.config.max_intron_length;
+
+        max_length == 0 || get_intron_length(position + 1, intron_start) <= max_length
+    }
+
+    // Scaled length penalty for leaving the intron containing state, which started at intron_start, at position.
+    // None if the intron would be longer than the maximum
+    fn get_intron_exit_penalty(
+        &self,
+        position: usize,
+        state: HmmState,
+        intron_start: usize,
+    ) -> Option<u64> {
+        let min_length = match state.get_min_intron_length(self.config) {
+            Some(min_length) => min_length,
+            None => return Some(0),
+        };
+
+        let length = get_intron_length(position, intron_start);
+        let max_length = self.config.max_intron_length;
+
+        if max_length > 0 && length > max_length {
+            return None;
+        }
+
+        let penalty = self.config.get_intron_length_penalty(length, min_length);
+        Some((penalty * PENALTY_SCALE) as u64)
+    }
+
+    // Scaled intron length penalty for moving from state into next_state at position, None if the intron would
+    // be too long. Only leaving an intron is penalised
+    fn get_successor_length_penalty(
+        &self,
+        position: usize,
+        state: HmmState,
+        intron_start: usize,
+        next_state: HmmState,
+    ) -> Option<u64> {
+        if state.get_annotation_label() != HmmAnnotationLabel::Intron {
+            Some(0)
+        } else if next_state.get_annotation_label() == HmmAnnotationLabel::Intron {
+            self.can_extend_intron(position, intron_start).then_some(0)
+        } else {
+            self.get_intron_exit_penalty(position, state, intron_start)
+        }
+    }
+
+    // Sum of the penalties of the intron bases before each position, as every intron state has the same per
+    // base penalties
+    fn get_intron_penalty_sums(&self) -> Vec<f64> {
+        let len = self.class_pred_pen.len();
+
+        let mut penalty_sums = Vec::with_capacity(len + 1);
+        penalty_sums.push(0.0);
+
+        for position in 0..len {
+            let (_, _, penalty) = self
+                .get_state_span_penalties(position, HmmState::UTR5IntronU2GtAg)
+                .expect("Intron state covers a single base");
+            penalty_sums.push(penalty_sums[position] + penalty);
+        }
+
+        penalty_sums
+    }
+
+    fn populate_successors(
+        &self,
+        position: usize,
+        state: HmmState,
+        successors: &mut Vec<(HmmState, f64)>,
+    ) {
+        let trans_ctx = TransitionContext::new(
+            &self.class_pred_pen,
+            &self.phase_pred_pen,
+            &self.pred_pen,
+            &self.bases_pen,
+            position,
+            self.config,
+            &self.codons,
+        );
+
+        successors.clear();
+        state.populate_successor_states_and_transition_penalties(&trans_ctx, successors);
+
+        let in_intron = state.get_annotation_label() == HmmAnnotationLabel::Intron;
+
+        // Splice sites not given by an intron hint are penalised, for windows with hints
+        if let Some(flags) = self.hint_flags.get(position) {
+            if self.config.unhinted_splice_penalty > 0.0 {
+                for (next_state, trans_penalty) in successors.iter_mut() {
+                    let next_in_intron =
+                        next_state.get_annotation_label() == HmmAnnotationLabel::Intron;
+
+                    let unhinted = if !in_intron && next_in_intron {
+                        flags & HINT_DONOR == 0
+                    } else if in_intron && !next_in_intron {
+                        flags & HINT_ACCEPTOR == 0
+                    } else {
+                        false
+                    };
+
+                    if unhinted {
+                        *trans_penalty += self.config.unhinted_splice_penalty;
+                    }
+                }
+            }
+        }
+
+        if let Some(banned_splice_site) = self.banned_splice_site {
+            successors.retain(|(next_state, _)| {
+                let next_in_intron =
+                    next_state.get_annotation_label() == HmmAnnotationLabel::Intron;
+
+                match banned_splice_site {
+                    SpliceSite::Donor(donor_position) => {
+                        position != donor_position || in_intron || !next_in_intron
+                    }
+                    SpliceSite::Acceptor(acceptor_position) => {
+                        position != acceptor_position || !in_intron || next_in_intron
+                    }
+                }
+            });
+        }
+    }
+
+    // Sum the negative log probability and penalty of the bases covered by a state starting at start_position,
+    // returning the end position too, None if the state would run past the end
+    fn get_state_span_penalties(
+        &self,
+        start_position: usize,
+        state: HmmState,
+    ) -> Option<(usize, f64, f64)> {
+        let end_position = start_position + state.get_base_count(self.config);
+
+        if end_position > self.class_pred_pen.len()
+        // Drop 'long' state picked near end
+        {
+            return None;
+        }
+
+        let mut neg_log_prob = 0.0;
+        let mut penalty = 0.0;
+
+        for pos in start_position..end_position {
+            let (nlg, pen) = state.get_state_penalty(
+                &self.class_pred_pen[pos],
+                &self.phase_pred_pen[pos],
+                &self.pred_pen[pos],
+            );
+            neg_log_prob += nlg;
+            penalty += pen + self.get_hint_state_penalty(pos, state);
+        }
+
+        Some((end_position, neg_log_prob, penalty))
+    }
+
+    // Evaluate moving from the given state (ending at start_position) into the next state, None if the next state would run past the end
+    fn evaluate_successor(
+        &self,
+        start_position: usize,
+        previous_state: HmmState,
+        previous_accum_penalty: u64,
+        previous_intron_start: usize,
+        next_state: HmmState,
+        trans_penalty: f64,
+    ) -> Option<HmmEval> {
+        let (end_position, state_neg_log_prob, state_penalty) =
+            self.get_state_span_penalties(start_position, next_state)?;
+
+        let local_neg_log_prob = trans_penalty + state_neg_log_prob;
+        let local_penalty = trans_penalty + state_penalty;
+
+        let accum_penalty = previous_accum_penalty + ((local_penalty * PENALTY_SCALE) as u64);
+        let scaled_trans_penalty = (trans_penalty * PENALTY_SCALE) as u64;
+        let scaled_neg_log_prob = (local_neg_log_prob * PENALTY_SCALE) as u64;
+
+        let eval = HmmEval::new_successor(
+            start_position,
+            end_position,
+            next_state,
+            previous_state,
+            accum_penalty,
+            scaled_trans_penalty,
+            scaled_neg_log_prob,
+        )
+        .with_intron_start(previous_intron_start);
+
+        Some(eval)
+    }
+
+    fn process_eval(&mut self, eval: &HmmEval) {
+        if !self.is_eval_current(eval) {
+            return;
+        }
+
+        let mut successors = Vec::with_capacity(HMM_STATES);
+        self.populate_successors(eval.end_position, eval.state, &mut successors);
+
+        for (next_state, trans_penalty) in successors {
+            let length_penalty = match self.get_successor_length_penalty(
+                eval.end_position,
+                eval.state,
+                eval.intron_start,
+                next_state,
+            ) {
+                Some(length_penalty) => length_penalty,
+                None => continue,
+            };
+
+            if let Some(next_eval) = self.evaluate_successor(
+                eval.end_position,
+                eval.state,
+                eval.accum_penalty,
+                eval.intron_start,
+                next_state,
+                trans_penalty,
+            ) {
+                self.consider_eval(next_eval.with_length_penalty(length_penalty));
+            }
+        }
+    }
+
+    // Best-first search over (position, state), finishing at the first complete solution taken from the heap
+    fn solve_best_first(&mut self) -> Option<Vec<HmmEval>> {
+        let total_states = (self.class_pred_pen.len() + 1) * HMM_STATES;
+        self.best_eval = vec![None; total_states];
+        self.best_intron_evals.clear();
+        self.eval_heap.clear();
+
+        for (state, accum_penalty) in self.get_entry_states() {
+            self.consider_eval(HmmEval::new_root(state, accum_penalty));
+        }
+
+        let mut evals = 0;
+
+        while evals < MAX_EVALS {
+            if let Some(eval) = self.eval_heap.pop() {
+                if eval.end_position == self.class_pred_pen.len() {
+                    let path = self.trace_best_first_path(eval);
+
+                    self.best_eval = Vec::new();
+                    self.best_intron_evals = HashMap::new();
+                    self.eval_heap = BinaryHeap::new();
+
+                    return Some(path);
+                }
+
+                self.process_eval(&eval);
+            } else {
+                break;
+            } // Nothing left to do
+
+            evals += 1;
+        }
+
+        // MAX_EVALS exceeded - raise limit or window thresholds
+        None
+    }
+
+    // Follow the previous states back from the final eval to the root, returning the evals in sequence order
+    fn trace_best_first_path(&self, final_eval: HmmEval) -> Vec<HmmEval> {
+        let mut path = vec![final_eval];
+        let mut eval = final_eval;
+
+        while eval.end_position > 0 {
+            let idx = eval.start_position * HMM_STATES + (eval.previous_state as usize);
+
+            eval = if eval.previous_state.is_intron_body() {
+                let intron_start = eval.previous_intron_start;
+                *self.best_intron_evals[&idx]
+                    .iter()
+                    .find(|candidate| candidate.intron_start == intron_start)
+                    .expect("Previous eval not in best_intron_evals")
+            } else {
+                self.best_eval[idx].expect("Previous eval not in best_eval")
+            };
+            path.push(eval);
+        }
+
+        path.reverse();
+        path
+    }
+
+    // Viterbi over positions, keeping penalties only for the last few positions (enough for the longest state)
+    // and a single byte previous state for each position and state. Intron states keep every path which could
+    // still turn out best once the intron's length is known, see IntronCandidateOrder, and with an intron length
+    // model the donor position of the path leaving each intron state is kept too
+    fn solve_viterbi(&self) -> Option<Vec<HmmEval>> {
+        let len = self.class_pred_pen.len();
+        let ring_columns = self.get_ring_columns();
+
+        let mut accum_penalties = vec![u64::MAX; ring_columns * HMM_STATES];
+        let mut intron_candidates: Vec<Vec<HmmEval>> = vec![Vec::new(); ring_columns * HMM_STATES];
+        let mut previous_states = vec![VITERBI_NO_STATE; (len + 1) * HMM_STATES];
+
+        let mut intron_exits = IntronExitStarts::new(len, self.config.has_intron_length_model());
+
+        for (state, accum_penalty) in self.get_entry_states() {
+            // Root states
+            if state.is_intron_body() {
+                intron_candidates[state as usize].push(HmmEval::new_root(state, accum_penalty));
+            }
+            accum_penalties[state as usize] = accum_penalty;
+        }
+
+        let mut successors = Vec::with_capacity(HMM_STATES);
+
+        for position in 0..len {
+            let column = (position % ring_columns) * HMM_STATES;
+
+            for state in HMM_STATE_LIST.iter() {
+                let idx = column + *state as usize;
+                let accum_penalty = accum_penalties[idx];
+                if accum_penalty == u64::MAX {
+                    continue;
+                }
+
+                self.populate_successors(position, *state, &mut successors);
+
+                // Paths from the state, and whether each may leave an intron state
+                let mut evals = Vec::new();
+
+                if state.is_intron_body() {
+                    let candidates = std::mem::take(&mut intron_candidates[idx]);
+
+                    // Only the path which is best once its length penalty is added leaves the intron
+                    let best_exit = candidates
+                        .iter()
+                        .filter_map(|candidate| {
+                            let length_penalty = self.get_intron_exit_penalty(
+                                position,
+                                *state,
+                                candidate.intron_start,
+                            )?;
+                            Some((
+                                candidate.intron_start,
+                                candidate.accum_penalty + length_penalty,
+                            ))
+                        })
+                        .min_by_key(|(_, exit_penalty)| *exit_penalty)
+                        .map(|(intron_start, _)| intron_start);
+
+                    if let Some(intron_start) = best_exit {
+                        intron_exits.set(position, *state, intron_start);
+                    }
+
+                    for candidate in candidates {
+                        evals.push((candidate, Some(candidate.intron_start) == best_exit));
+                    }
+                } else {
+                    let start_position = position.saturating_sub(state.get_base_count(self.config));
+                    let mut eval = HmmEval::new_root(*state, accum_penalty);
+                    eval.intron_start = state.get_intron_start(start_position, 0);
+                    evals.push((eval, true));
+                }
+
+                for (eval, may_leave) in evals.iter() {
+                    for (next_state, trans_penalty) in successors.iter().copied() {
+                        if !may_leave && next_state != *state {
+                            continue;
+                        }
+
+                        let length_penalty = match self.get_successor_length_penalty(
+                            position,
+                            *state,
+                            eval.intron_start,
+                            next_state,
+                        ) {
+                            Some(length_penalty) => length_penalty,
+                            None => continue,
+                        };
+
+                        let next_eval = match self.evaluate_successor(
+                            position,
+                            *state,
+                            eval.accum_penalty,
+                            eval.intron_start,
+                            next_state,
+                            trans_penalty,
+                        ) {
+                            Some(next_eval) => next_eval.with_length_penalty(length_penalty),
+                            None => continue,
+                        };
+
+                        let next_column = (next_eval.end_position % ring_columns) * HMM_STATES;
+                        let next_idx = next_column + next_state as usize;
+
+                        if next_state.is_intron_body()
+                            && !self
+                                .intron_candidate_order
+                                .add_candidate(&mut intron_candidates[next_idx], next_eval)
+                        {
+                            continue;
+                        }
+
+                        if next_eval.accum_penalty < accum_penalties[next_idx] {
+                            accum_penalties[next_idx] = next_eval.accum_penalty;
+                            previous_states
+                                [next_eval.end_position * HMM_STATES + next_state as usize] =
+                                *state as u8;
+                        }
+                    }
+                }
+            }
+
+            // Column is complete, reset for reuse by a later position
+            for accum_penalty in accum_penalties[column..column + HMM_STATES].iter_mut() {
+                *accum_penalty = u64::MAX;
+            }
+        }
+
+        let column = (len % ring_columns) * HMM_STATES;
+        let (final_state, final_penalty) = HMM_STATE_LIST
+            .iter()
+            .map(|state| (*state, accum_penalties[column + *state as usize]))
+            .filter(|(_, accum_penalty)| *accum_penalty != u64::MAX)
+            .min_by_key(|(_, accum_penalty)| *accum_penalty)?;
+
+        // A path ending within an intron has no length penalty, so is the best candidate
+        let final_intron_start = intron_candidates[column + final_state as usize]
+            .iter()
+            .find(|candidate| candidate.accum_penalty == final_penalty)
+            .map(|candidate| candidate.intron_start);
+
+        Some(self.trace_viterbi_path(
+            &previous_states,
+            &intron_exits,
+            final_state,
+            final_intron_start,
+        ))
+    }
+
+    // Follow the previous states back from the final state, then rebuild the evals along the path in sequence
+    // order. With an intron length model, the previous state within an intron follows from its donor position
+    fn trace_viterbi_path(
+        &self,
+        previous_states: &[u8],
+        intron_exits: &IntronExitStarts,
+        final_state: HmmState,
+        final_intron_start: Option<usize>,
+    ) -> Vec<HmmEval> {
+        let mut steps = Vec::new();
+
+        let mut position = self.class_pred_pen.len();
+        let mut state = final_state;
+        let mut intron_start = final_intron_start;
+
+        while position > 0 {
+            let start_position = position - state.get_base_count(self.config);
+
+            let previous_state = match intron_start {
+                Some(donor_position) if intron_exits.is_tracked() && state.is_intron_body() => {
+                    let dss_state = state.get_intron_dss_state();
+
+                    if donor_position != PARTIAL_INTRON_START
+                        && start_position == donor_position + dss_state.get_base_count(self.config)
+                    {
+                        dss_state
+                    } else {
+                        state
+                    }
+                }
+                _ => {
+                    HMM_STATE_LIST[previous_states[position * HMM_STATES + state as usize] as usize]
+                }
+            };
+
+            if previous_state.is_intron_body() && !state.is_intron_body() {
+                intron_start = intron_exits.get(start_position, previous_state);
+            }
+
+            steps.push((start_position, previous_state, state));
+
+            position = start_position;
+            state = previous_state;
+        }
+
+        let mut successors = Vec::with_capacity(HMM_STATES);
+
+        let (_, root_penalty) = self
+            .get_entry_states()
+            .into_iter()
+            .find(|(entry_state, _)| *entry_state == state)
+            .expect("Viterbi path does not start in an entry state");
+
+        let mut path = Vec::with_capacity(steps.len() + 1);
+        let mut eval = HmmEval::new_root(state, root_penalty);
+        path.push(eval);
+
+        for (start_position, previous_state, state) in steps.into_iter().rev() {
+            self.populate_successors(start_position, previous_state, &mut successors);
+
+            let trans_penalty = successors
+                .iter()
+                .filter(|(next_state, _)| *next_state == state)
+                .map(|(_, trans_penalty)| *trans_penalty)
+                .reduce(f64::min)
+                .expect("Viterbi path state is not a successor of its previous state");
+            let length_penalty = self
+                .get_successor_length_penalty(
+                    start_position,
+                    previous_state,
+                    eval.intron_start,
+                    state,
+                )
+                .expect("Viterbi path intron is too long");
+
+            eval = self
+                .evaluate_successor(
+                    start_position,
+                    previous_state,
+                    eval.accum_penalty,
+                    eval.intron_start,
+                    state,
+                    trans_penalty,
+                )
+                .expect("Viterbi path state runs past the end")
+                .with_length_penalty(length_penalty);
+            path.push(eval);
+        }
+
+        path
+    }
+
+    // Forward-backward over all paths, treating each path as having weight 2^-penalty. Returns, for each base,
+    // the posterior probability of each annotation label, None if there is no complete path. Intron states sum
+    // over where their intron started or ends with an IntronLengthWindow, so that the maximum intron length and
+    // length distribution apply exactly
+    fn compute_label_posteriors(&self) -> Option<Vec<[f32; HMM_ANNOTATION_LABELS]>> {
+        let len = self.class_pred_pen.len();
+        let ring_columns = self.get_ring_columns();
+
+        let intron_penalty_sums = self.get_intron_penalty_sums();
+        let intron_label = HmmAnnotationLabel::Intron as usize;
+
+        // Forward pass, keeping log2 forward weights for every position and state. These are stored relative
+        // to the best state in each column as f32 to halve the memory needed. An intron state's weight includes
+        // the length penalty for leaving the intron there
+        let mut forward_ring = vec![f64::NEG_INFINITY; ring_columns * HMM_STATES];
+        let mut forward = vec![f32::NEG_INFINITY; (len + 1) * HMM_STATES];
+        let mut forward_offsets = vec![0.0f64; len + 1];
+
+        let mut intron_windows = self.new_intron_length_windows(true);
+
+        for (state, accum_penalty) in self.get_entry_states() {
+            let weight = -(accum_penalty as f64 / PENALTY_SCALE);
+
+            // Root states
+            match intron_windows[state as usize].as_mut() {
+                Some(window) => window.push_partial(weight),
+                None => forward_ring[state as usize] = weight,
+            }
+        }
+
+        let mut successors = Vec::with_capacity(HMM_STATES);
+
+        for position in 0..=len {
+            let column = (position % ring_columns) * HMM_STATES;
+
+            for (idx, maybe_window) in intron_windows.iter_mut().enumerate() {
+                if let Some(window) = maybe_window {
+                    // No length penalty for an intron running past the end
+                    let weight = if position == len {
+                        window.get_end_total(position)
+                    } else {
+                        window.get_total(position)
+                    };
+                    forward_ring[column + idx] = weight - intron_penalty_sums[position];
+                }
+            }
+
+            let column_weights = &forward_ring[column..column + HMM_STATES];
+
+            let offset = column_weights
+                .iter()
+                .copied()
+                .fold(f64::NEG_INFINITY, f64::max);
+            if offset != f64::NEG_INFINITY {
+                forward_offsets[position] = offset;
+                for (idx, weight) in column_weights.iter().enumerate() {
+                    forward[position * HMM_STATES + idx] = (weight - offset) as f32;
+                }
+            }
+
+            if position == len {
+                break;
+            }
+
+            for state in HMM_STATE_LIST.iter() {
+                let weight = forward_ring[column + *state as usize];
+                if weight == f64::NEG_INFINITY {
+                    continue;
+                }
+
+                self.populate_successors(position, *state, &mut successors);
+
+                for (next_state, trans_penalty) in successors.iter().copied() {
+                    if next_state.is_intron_body() {
+                        // Entering the intron after its DSS, continuing it is summed by the window
+                        if !state.is_intron_body() {
+                            intron_windows[next_state as usize]
+                                .as_mut()
+                                .expect("Intron state has a window")
+                                .push(
+                                    position,
+                                    weight - trans_penalty + intron_penalty_sums[position],
+                                );
+                        }
+                        continue;
+                    }
+
+                    if let Some((end_position, _, state_penalty)) =
+                        self.get_state_span_penalties(position, next_state)
+                    {
+                        let next_idx =
+                            (end_position % ring_columns) * HMM_STATES + next_state as usize;
+                        forward_ring[next_idx] = log2_add(
+                            forward_ring[next_idx],
+                            weight - trans_penalty - state_penalty,
+                        );
+                    }
+                }
+            }
+
+            // Column is stored, reset for reuse by a later position
+            for weight in forward_ring[column..column + HMM_STATES].iter_mut() {
+                *weight = f64::NEG_INFINITY;
+            }
+        }
+
+        let final_column = (len % ring_columns) * HMM_STATES;
+        let total_weight = forward_ring[final_column..final_column + HMM_STATES]
+            .iter()
+            .copied()
+            .fold(f64::NEG_INFINITY, log2_add);
+
+        if total_weight == f64::NEG_INFINITY {
+            return None;
+        }
+
+        // Backward pass, only the last few columns are needed. Each transition's posterior is added to the
+        // bases covered by the next state, as differences which are summed afterwards. An intron is added from
+        // the transition into its first intron state until the transition leaving it
+        let mut backward_ring = vec![f64::NEG_INFINITY; ring_columns * HMM_STATES];
+        for weight in backward_ring[final_column..final_column + HMM_STATES].iter_mut() {
+            *weight = 0.0;
+        }
+
+        let mut posterior_deltas = vec![[0.0f64; HMM_ANNOTATION_LABELS]; len + 1];
+
+        let mut intron_windows = self.new_intron_length_windows(false);
+        for window in intron_windows.iter_mut().flatten() {
+            window.set_end(len, -intron_penalty_sums[len]);
+        }
+        let mut intron_pushes = Vec::new();
+
+        for position in (0..len).rev() {
+            let column = (position % ring_columns) * HMM_STATES;
+
+            // Column was last used by a later position which is no longer needed
+            for weight in backward_ring[column..column + HMM_STATES].iter_mut() {
+                *weight = f64::NEG_INFINITY;
+            }
+
+            for state in HMM_STATE_LIST.iter() {
+                let idx = position * HMM_STATES + *state as usize;
+                if forward[idx] == f32::NEG_INFINITY {
+                    continue;
+                }
+                let forward_weight = forward_offsets[position] + forward[idx] as f64;
+
+                let mut backward_weight = f64::NEG_INFINITY;
+
+                self.populate_successors(position, *state, &mut successors);
+
+                for (next_state, trans_penalty) in successors.iter().copied() {
+                    if next_state.is_intron_body() {
+                        // Continuing the intron is summed by the window
+                        if state.is_intron_body() {
+                            continue;
+                        }
+
+                        let window = intron_windows[next_state as usize]
+                            .as_mut()
+                            .expect("Intron state has a window");
+                        let weight = window.get_total(position) + intron_penalty_sums[position]
+                            - trans_penalty;
+                        if weight == f64::NEG_INFINITY {
+                            continue;
+                        }
+                        backward_weight = log2_add(backward_weight, weight);
+
+                        let posterior = (forward_weight + weight - total_weight).exp2();
+                        posterior_deltas[position][intron_label] += posterior;
+                        continue;
+                    }
+
+                    if let Some((end_position, _, state_penalty)) =
+                        self.get_state_span_penalties(position, next_state)
+                    {
+                        let next_backward_weight = backward_ring
+                            [(end_position % ring_columns) * HMM_STATES + next_state as usize];
+                        if next_backward_weight == f64::NEG_INFINITY {
+                            continue;
+                        }
+
+                        let weight = next_backward_weight - trans_penalty - state_penalty;
+                        backward_weight = log2_add(backward_weight, weight);
+
+                        let posterior = (forward_weight + weight - total_weight).exp2();
+                        let label = next_state.get_annotation_label() as usize;
+                        posterior_deltas[position][label] += posterior;
+                        posterior_deltas[end_position][label] -= posterior;
+
+                        if state.is_intron_body() {
+                            posterior_deltas[position][intron_label] -= posterior;
+                        }
+                    }
+                }
+
+                backward_ring[column + *state as usize] = backward_weight;
+
+                if state.is_intron_body() {
+                    intron_pushes.push((*state, backward_weight - intron_penalty_sums[position]));
+                }
+            }
+
+            // Only introns ending at later positions may be summed by states at this position
+            for (state, weight) in intron_pushes.drain(..) {
+                intron_windows[state as usize]
+                    .as_mut()
+                    .expect("Intron state has a window")
+                    .push(position, weight);
+            }
+        }
+
+        // Introns entered before the window
+        for (state, accum_penalty) in self.get_entry_states() {
+            if let Some(window) = &intron_windows[state as usize] {
+                let weight = -(accum_penalty as f64 / PENALTY_SCALE) + window.get_partial_total();
+                posterior_deltas[0][intron_label] += (weight - total_weight).exp2();
+            }
+        }
+
+        let mut label_posteriors = Vec::with_capacity(len);
+        let mut running = [0.0f64; HMM_ANNOTATION_LABELS];
+
+        for deltas in posterior_deltas.iter().take(len) {
+            let mut posteriors = [0.0f32; HMM_ANNOTATION_LABELS];
+            for ((posterior, sum), delta) in posteriors
+                .iter_mut()
+                .zip(running.iter_mut())
+                .zip(deltas.iter())
+            {
+                *sum += delta;
+                *posterior = sum.clamp(0.0, 1.0) as f32;
+            }
+            label_posteriors.push(posteriors);
+        }
+
+        Some(label_posteriors)
+    }
+
+    // A window for each intron state after its DSS, None for other states
+    fn new_intron_length_windows(&self, forward: bool) -> Vec<Option<IntronLengthWindow>> {
+        HMM_STATE_LIST
+            .iter()
+            .map(|state| {
+                state
+                    .is_intron_body()
+                    .then(|| IntronLengthWindow::new(self.config, *state, forward))
+            })
+            .collect()
+    }
+
+    pub fn solve(mut self) -> Option<PredictionHmmSolution<'a>> {
+        let maybe_path = match self.config.solver {
+            HmmSolver::BestFirst => self.solve_best_first(),
+            HmmSolver::Viterbi => self.solve_viterbi(),
+            HmmSolver::Compare => {
+                let maybe_best_first_path = self.solve_best_first();
+                let maybe_viterbi_path = self.solve_viterbi();
+
+                compare_paths(&maybe_best_first_path, &maybe_viterbi_path);
+                maybe_viterbi_path
+            }
+        };
+
+        let path = maybe_path?;
+
+        let label_posteriors = if self.config.posterior_scores {
+            self.compute_label_posteriors()
+        } else {
+            None
+        };
+
+        Some(PredictionHmmSolution::new(self, path, label_posteriors))
+    }
+}
+
+// log2(2^a + 2^b) without leaving the log domain
+fn log2_add(a: f64, b: f64) -> f64 {
+    let (high, low) = if a >= b { (a, b) } else { (b, a) };
+
+    if low == f64::NEG_INFINITY {
+        high
+    } else {
+        high + (low - high).exp2().ln_1p() / std::f64::consts::LN_2
+    }
+}
+
+// Length of an intron which started at intron_start when leaving it at position, 0 for an intron which started
+// before the window
+fn get_intron_length(position: usize, intron_start: usize) -> usize {
+    position.saturating_sub(intron_start)
+}
+
+// Compares paths into the same intron state at the same position, to keep only those which could still turn
+// out best once the intron's length is known. A path whose intron started later is never longer, so stays within
+// a maximum length whenever the other does. Beyond that, a path is better if its penalty stays lower after the
+// largest possible difference in length penalties, which for the geometric distribution follows from the
+// difference in donor positions
+#[derive(Clone, Copy)]
+struct IntronCandidateOrder {
+    length_model: bool, // Without an intron length model only the penalty matters
+    limited: bool,      // Introns have a maximum length
+    penalty_range: f64, // Scaled, allowing for the rounding of each scaled penalty
+    u2_slope: f64,      // Scaled length penalty per base for U2 introns
+    u12_slope: f64,
+}
+
+impl IntronCandidateOrder {
+    fn new(config: &HmmConfig) -> IntronCandidateOrder {
+        IntronCandidateOrder {
+            length_model: config.has_intron_length_model(),
+            limited: config.max_intron_length > 0,
+            penalty_range: config.get_intron_length_penalty_range() * PENALTY_SCALE + 2.0,
+            u2_slope: config.get_intron_length_penalty_slope(config.min_intron_length)
+                * PENALTY_SCALE,
+            u12_slope: config.get_intron_length_penalty_slope(config.min_u12_intron_length)
+                * PENALTY_SCALE,
+        }
+    }
+
+    // Whether eval does at least as well as other, whatever length the intron goes on to have
+    fn is_better(&self, eval: &HmmEval, other: &HmmEval) -> bool {
+        if !self.length_model || eval.intron_start == other.intron_start {
+            return eval.accum_penalty <= other.accum_penalty;
+        }
+
+        // An intron entered before the window has the lowest length penalty and is never too long
+        if other.intron_start == PARTIAL_INTRON_START {
+            return false;
+        }
+        if eval.intron_start == PARTIAL_INTRON_START {
+            return eval.accum_penalty <= other.accum_penalty;
+        }
+
+        // The path from other could outlast eval, then end within the intron with no length penalty
+        if self.limited
+            && (eval.intron_start < other.intron_start || eval.accum_penalty > other.accum_penalty)
+        {
+            return false;
+        }
+
+        let (_, intron) = eval.state.get_component_states();
+        let slope = if intron == HmmIntronState::U12AtAc {
+            self.u12_slope
+        } else {
+            self.u2_slope
+        };
+
+        // Penalties as if both introns started at position 0
+        let adjusted_penalty =
+            |eval: &HmmEval| eval.accum_penalty as f64 - slope * eval.intron_start as f64;
+
+        adjusted_penalty(eval) + self.penalty_range <= adjusted_penalty(other)
+    }
+
+    // Adds a path to the candidates for its position and state unless a candidate is better, and removes the
+    // candidates it is strictly better than. The path with the lowest penalty is always kept, as it is the best
+    // if the window ends within the intron. Returns whether the path was added
+    fn add_candidate(&self, candidates: &mut Vec<HmmEval>, eval: HmmEval) -> bool {
+        let lowest = candidates
+            .iter()
+            .all(|other| eval.accum_penalty < other.accum_penalty);
+
+        if !lowest && candidates.iter().any(|other| self.is_better(other, &eval)) {
+            return false;
+        }
+
+        candidates.retain(|other| {
+            !self.is_better(&eval, other) || eval.accum_penalty >= other.accum_penalty
+        });
+        candidates.push(eval);
+        true
+    }
+}
+
+// Donor position of the path leaving each intron state at each position, for the Viterbi traceback with an
+// intron length model. Only kept for the intron states after a DSS, as u32 to save memory
+struct IntronExitStarts {
+    starts: Vec<u32>, // Empty without an intron length model
+    intron_indices: Vec<usize>,
+    intron_states: usize,
+}
+
+impl IntronExitStarts {
+    fn new(len: usize, tracked: bool) -> IntronExitStarts {
+        let mut intron_indices = vec![usize::MAX; HMM_STATES];
+        let mut intron_states = 0;
+
+        for state in HMM_STATE_LIST.iter().filter(|state| state.is_intron_body()) {
+            intron_indices[*state as usize] = intron_states;
+            intron_states += 1;
+        }
+
+        let starts = if tracked {
+            vec![u32::MAX; (len + 1) * intron_states]
+        } else {
+            Vec::new()
+        };
+
+        IntronExitStarts {
+            starts,
+            intron_indices,
+            intron_states,
+        }
+    }
+
+    fn is_tracked(&self) -> bool {
+        !self.starts.is_empty()
+    }
+
+    fn set(&mut self, position: usize, state: HmmState, intron_start: usize) {
+        if self.is_tracked() {
+            let idx = position * self.intron_states + self.intron_indices[state as usize];
+            self.starts[idx] = if intron_start == PARTIAL_INTRON_START {
+                u32::MAX
+            } else {
+                intron_start as u32
+            };
+        }
+    }
+
+    fn get(&self, position: usize, state: HmmState) -> Option<usize> {
+        if !self.is_tracked() {
+            return None;
+        }
+
+        let idx = position * self.intron_states + self.intron_indices[state as usize];
+        match self.starts[idx] {
+            u32::MAX => Some(PARTIAL_INTRON_START),
+            intron_start => Some(intron_start as usize),
+        }
+    }
+}
+
+// Log2 weights of paths through one intron state, summed over where their intron could start (forward pass) or
+// end (backward pass) as the pass moves along the window. Each weight is pushed at the first (forward) or last
+// (backward) base of the intron state, and moves through the length penalty segments as the intron it would
+// give grows, until the intron is longer than the maximum
+struct IntronLengthWindow {
+    forward: bool,
+    dss_length: usize, // Intron bases before the first intron state
+    max_length: usize, // 0 for no limit
+    segments: Vec<IntronLengthSegment>,
+
+    partial_penalty: f64, // Length penalty for an intron entered before the window
+    partial_weight: f64,  // Forward pass, intron entered before the window
+    pushed_weight: f64,   // Backward pass, all weights for an intron entered before the window
+
+    end: Option<(usize, f64)>, // Backward pass, intron running past the end without a length penalty
+}
 
-    best_eval: Vec<Option<HmmEval>>,
+impl IntronLengthWindow {
+    fn new(config: &HmmConfig, state: HmmState, forward: bool) -> IntronLengthWindow {
+        let min_length = state
+            .get_min_intron_length(config)
+            .expect("Intron state has a minimum length");
+        let direction = if forward { 1.0 } else { -1.0 };
+
+        let segments = config
+            .get_intron_length_penalty_segments(min_length)
+            .into_iter()
+            .map(|(start_length, constant, slope)| {
+                IntronLengthSegment::new(start_length, constant, slope, direction)
+            })
+            .collect();
+
+        IntronLengthWindow {
+            forward,
+            dss_length: state.get_intron_dss_state().get_base_count(config),
+            max_length: config.max_intron_length,
+            segments,
+            partial_penalty: config.get_intron_length_penalty(0, min_length),
+            partial_weight: f64::NEG_INFINITY,
+            pushed_weight: f64::NEG_INFINITY,
+            end: None,
+        }
+    }
 
-    eval_heap: BinaryHeap<HmmEval>,
-}
+    // Length of the intron for a weight pushed at position, when the pass reaches current_position
+    fn get_length(&self, current_position: usize, position: usize) -> usize {
+        let distance = if self.forward {
+            current_position - position
+        } else {
+            position - current_position
+        };
 
-impl PredictionHmm {
-    pub fn new(bp_vector: Vec<(Bases, ClassPrediction, PhasePrediction)>) -> PredictionHmm {
-        let mut class_pred_pen = Vec::with_capacity(bp_vector.len());
-        let mut phase_pred_pen = Vec::with_capacity(bp_vector.len());
-        let mut pred_pen = Vec::with_capacity(bp_vector.len());
+        distance + self.dss_length
+    }
 
-        let mut bases_pen = Vec::with_capacity(bp_vector.len());
+    fn is_allowed(&self, length: usize) -> bool {
+        self.max_length == 0 || length <= self.max_length
+    }
 
-        for (bases, class_pred, phase_pred) in bp_vector.iter() {
-            class_pred_pen.push(class_pred.into());
-            phase_pred_pen.push(phase_pred.into());
-            pred_pen.push((class_pred, phase_pred).into());
+    fn push(&mut self, position: usize, weight: f64) {
+        if weight == f64::NEG_INFINITY {
+            return;
+        }
 
-            bases_pen.push(bases.into());
+        if !self.forward {
+            self.pushed_weight = log2_add(self.pushed_weight, weight);
         }
+        self.segments[0].push(position, weight);
+    }
 
-        let total_states = (bp_vector.len() + 1) * HMM_STATES;
-        let best_eval = vec![None; total_states];
+    fn push_partial(&mut self, weight: f64) {
+        self.partial_weight = log2_add(self.partial_weight, weight);
+    }
 
-        let eval_heap = BinaryHeap::new();
-        PredictionHmm {
-            class_pred_pen,
-            phase_pred_pen,
-            pred_pen,
-            bases_pen,
-            best_eval,
-            eval_heap,
+    fn set_end(&mut self, position: usize, weight: f64) {
+        self.end = Some((position, weight));
+    }
+
+    // Move weights on to the segment for their current length, dropping those whose intron is too long
+    fn advance(&mut self, current_position: usize) {
+        for segment_idx in 0..self.segments.len() {
+            while let Some(position) = self.segments[segment_idx].peek_oldest() {
+                let length = self.get_length(current_position, position);
+
+                if !self.is_allowed(length) {
+                    self.segments[segment_idx].pop_oldest();
+                } else if self
+                    .segments
+                    .get(segment_idx + 1)
+                    .is_some_and(|next_segment| length >= next_segment.start_length)
+                {
+                    let (position, weight) = self.segments[segment_idx]
+                        .pop_oldest()
+                        .expect("Segment is not empty");
+                    self.segments[segment_idx + 1].push(position, weight);
+                } else {
+                    break;
+                }
+            }
         }
     }
 
-    fn consider_eval(&mut self, eval: HmmEval) {
-        let idx = eval.end_position * HMM_STATES + (eval.state as usize);
+    // Sum of the weights with their length penalties, for leaving the intron at (forward) or entering it from
+    // (backward) current_position
+    fn get_total(&mut self, current_position: usize) -> f64 {
+        self.advance(current_position);
 
-        let maybe_old_eval = &self.best_eval[idx];
+        let mut total = self
+            .segments
+            .iter()
+            .map(|segment| segment.get_total(current_position, self.dss_length))
+            .fold(self.partial_weight - self.partial_penalty, log2_add);
 
-        if let Some(old_eval) = maybe_old_eval {
-            if eval.accum_penalty >= old_eval.accum_penalty {
-                return;
+        if let Some((position, weight)) = self.end {
+            if self.is_allowed(self.get_length(current_position, position)) {
+                total = log2_add(total, weight);
             }
         }
 
-        self.best_eval[idx] = Some(eval);
-        self.eval_heap.push(eval);
+        total
     }
 
-    fn is_eval_current(&self, eval: &HmmEval) -> bool {
-        let idx = eval.end_position * HMM_STATES + (eval.state as usize);
-        let best_eval = &self.best_eval[idx].expect("Eval from heap not in best_eval");
+    // Forward pass, sum of the weights without length penalties, for an intron running past the end
+    fn get_end_total(&mut self, current_position: usize) -> f64 {
+        self.advance(current_position);
 
-        best_eval == eval
+        self.segments
+            .iter()
+            .flat_map(|segment| segment.get_weights())
+            .fold(self.partial_weight, log2_add)
     }
 
-    fn process_eval(&mut self, eval: &HmmEval) {
-        if !self.is_eval_current(eval) {
-            return;
-        }
-
-        let trans_ctx = TransitionContext::new(
-            &self.class_pred_pen,
-            &self.phase_pred_pen,
-            &self.pred_pen,
-            &self.bases_pen,
-            eval.end_position,
-        );
-
-        let mut successors = Vec::with_capacity(HMM_STATES);
-        eval.state
-            .populate_successor_states_and_transition_penalties(&trans_ctx, &mut successors);
+    // Backward pass, sum of the weights for an intron entered before the window
+    fn get_partial_total(&self) -> f64 {
+        let end_weight = self.end.map_or(f64::NEG_INFINITY, |(_, weight)| weight);
 
-        for (next_state, trans_penalty) in successors.into_iter() {
-            let mut local_neg_log_prob = trans_penalty;
-            let mut local_penalty = trans_penalty;
+        log2_add(self.pushed_weight - self.partial_penalty, end_weight)
+    }
+}
 
-            let start_position = eval.end_position;
-            let end_position = start_position + next_state.get_base_count();
+// Weights in one intron length penalty segment, where the penalty is `constant + slope * length`. Weights are
+// queued in two stacks, so that the sum of those remaining is known as the oldest leave without subtracting.
+// The sums are of weights adjusted by the slope at their own position, so that a single correction for the
+// current position gives their length penalties
+struct IntronLengthSegment {
+    start_length: usize,
+    constant: f64,
+    slope: f64,
+    direction: f64, // -1 for the backward pass, where lengths grow as positions fall
+
+    newest: Vec<(usize, f64)>,      // Position and weight, newest last
+    newest_sum: f64,                // Sum of the adjusted weights in newest
+    oldest: Vec<(usize, f64, f64)>, // Position, weight and sum of adjusted weights up to this one, oldest last
+}
 
-            if end_position <= self.class_pred_pen.len()
-            // Drop 'long' state picked near end
-            {
-                for pos in start_position..end_position {
-                    let (nlg, pen) = next_state.get_state_penalty(
-                        &self.class_pred_pen[pos],
-                        &self.phase_pred_pen[pos],
-                        &self.pred_pen[pos],
-                    );
-                    local_neg_log_prob += nlg;
-                    local_penalty += pen;
-                }
+impl IntronLengthSegment {
+    fn new(start_length: usize, constant: f64, slope: f64, direction: f64) -> IntronLengthSegment {
+        IntronLengthSegment {
+            start_length,
+            constant,
+            slope,
+            direction,
+            newest: Vec::new(),
+            newest_sum: f64::NEG_INFINITY,
+            oldest: Vec::new(),
+        }
+    }
 
-                let accum_penalty = eval.accum_penalty + ((local_penalty * PENALTY_SCALE) as u64);
-                let scaled_trans_penalty = (trans_penalty * PENALTY_SCALE) as u64;
-                let scaled_neg_log_prob = (local_neg_log_prob * PENALTY_SCALE) as u64;
+    fn get_adjusted_weight(&self, position: usize, weight: f64) -> f64 {
+        weight + self.direction * self.slope * position as f64
+    }
 
-                let next_eval = HmmEval::new_successor(
-                    start_position,
-                    end_position,
-                    next_state,
-                    eval.state,
-                    accum_penalty,
-                    scaled_trans_penalty,
-                    scaled_neg_log_prob
-                );
+    fn push(&mut self, position: usize, weight: f64) {
+        self.newest_sum = log2_add(self.newest_sum, self.get_adjusted_weight(position, weight));
+        self.newest.push((position, weight));
+    }
 
-                self.consider_eval(next_eval);
+    fn peek_oldest(&mut self) -> Option<usize> {
+        if self.oldest.is_empty() {
+            while let Some((position, weight)) = self.newest.pop() {
+                let adjusted_weight = self.get_adjusted_weight(position, weight);
+                let sum = self.oldest.last().map_or(adjusted_weight, |(_, _, sum)| {
+                    log2_add(*sum, adjusted_weight)
+                });
+                self.oldest.push((position, weight, sum));
             }
+            self.newest_sum = f64::NEG_INFINITY;
         }
+
+        self.oldest.last().map(|(position, _, _)| *position)
     }
 
-    pub fn solve(mut self) -> Option<PredictionHmmSolution> {
-        let initial_eval = HmmEval::new_root();
-        self.consider_eval(initial_eval);
+    fn pop_oldest(&mut self) -> Option<(usize, f64)> {
+        self.peek_oldest()?;
+        self.oldest
+            .pop()
+            .map(|(position, weight, _)| (position, weight))
+    }
 
-        let mut evals = 0;
+    fn get_total(&self, current_position: usize, dss_length: usize) -> f64 {
+        let oldest_sum = self
+            .oldest
+            .last()
+            .map_or(f64::NEG_INFINITY, |(_, _, sum)| *sum);
+        let sum = log2_add(self.newest_sum, oldest_sum);
 
-        while evals < MAX_EVALS {
-            if let Some(eval) = self.eval_heap.pop() {
-                if eval.end_position == self.class_pred_pen.len() {
-                    return Some(PredictionHmmSolution::new(self, eval));
-                }
+        if sum == f64::NEG_INFINITY {
+            return sum;
+        }
 
-                self.process_eval(&eval);
-            } else {
-                break;
-            } // Nothing left to do
+        sum - self.direction * self.slope * current_position as f64
+            - self.slope * dss_length as f64
+            - self.constant
+    }
 
-            evals += 1;
-        }
+    fn get_weights(&self) -> impl Iterator<Item = f64> + '_ {
+        self.newest
+            .iter()
+            .map(|(_, weight)| *weight)
+            .chain(self.oldest.iter().map(|(_, weight, _)| *weight))
+    }
+}
 
-        panic!("MAX_EVALS exceeded - raise limit or window thresholds");
-        //        None
+// Report any difference between best-first and Viterbi solutions. Different paths with the same
+// penalty are equally good, so only differing penalties indicate a problem
+fn compare_paths(
+    maybe_best_first_path: &Option<Vec<HmmEval>>,
+    maybe_viterbi_path: &Option<Vec<HmmEval>>,
+) {
+    match (maybe_best_first_path, maybe_viterbi_path) {
+        (Some(best_first_path), Some(viterbi_path)) => {
+            let best_first_eval = best_first_path.last().unwrap();
+            let viterbi_eval = viterbi_path.last().unwrap();
+
+            if best_first_eval.accum_penalty != viterbi_eval.accum_penalty {
+                warn!(
+                    "Solver mismatch: Best-first penalty {}, Viterbi penalty {}",
+                    best_first_eval.accum_penalty, viterbi_eval.accum_penalty
+                );
+            } else if best_first_path != viterbi_path {
+                debug!(
+                    "Solver tie: Best-first and Viterbi paths differ with equal penalty {}",
+                    viterbi_eval.accum_penalty
+                );
+            }
+        }
+        (None, None) => {}
+        _ => warn!("Solver mismatch: Only one of Best-first and Viterbi found a solution"),
     }
 }
 
@@ -1744,19 +3940,49 @@ pub struct HmmStateRegion {
     end_pos: usize,
     annotation_label: HmmAnnotationLabel,
 
-    trans_penalty: u64, // Transition penalty into each state in the region
-    neg_log_prob: u64   // Negative Log probability of all bases within this region
+    neg_log_prob: u64, // Negative Log probability of all bases within this region
+
+    mean_posterior: Option<f32>, // Mean posterior probability of the annotation label over the region's bases
+    min_posterior: Option<f32>, // Lowest posterior probability of the annotation label within the region
+
+    coding_offset: u64, // Codon bases read before the region, for a gene entered part way through
+    truncated_start: bool, // Region continues before the start of the sequence
+    truncated_end: bool, // Region continues past the end of the sequence
 }
 
 impl HmmStateRegion {
-    fn new(start_pos: usize, end_pos: usize, annotation_label: HmmAnnotationLabel,
-           trans_penalty: u64, neg_log_prob: u64) -> HmmStateRegion {
+    fn new(
+        start_pos: usize,
+        end_pos: usize,
+        annotation_label: HmmAnnotationLabel,
+        neg_log_prob: u64,
+    ) -> HmmStateRegion {
         HmmStateRegion {
             start_pos,
             end_pos,
             annotation_label,
-            trans_penalty,
-            neg_log_prob
+            neg_log_prob,
+            mean_posterior: None,
+            min_posterior: None,
+            coding_offset: 0,
+            truncated_start: false,
+            truncated_end: false,
+        }
+    }
+
+    fn set_posteriors(&mut self, label_posteriors: &[[f32; HMM_ANNOTATION_LABELS]]) {
+        let label = self.annotation_label as usize;
+        let posteriors = label_posteriors[self.start_pos..self.end_pos]
+            .iter()
+            .map(|posteriors| posteriors[label]);
+
+        let (sum, min) = posteriors.fold((0.0f64, 1.0f32), |(sum, min), posterior| {
+            (sum + posterior as f64, min.min(posterior))
+        });
+
+        if self.len() > 0 {
+            self.mean_posterior = Some((sum / self.len() as f64) as f32);
+            self.min_posterior = Some(min);
         }
     }
 
@@ -1776,6 +4002,44 @@ impl HmmStateRegion {
         self.end_pos - self.start_pos
     }
 
+    fn shift(&mut self, offset: usize) {
+        self.start_pos += offset;
+        self.end_pos += offset;
+    }
+
+    // Start and end positions of each intron in a gene's regions
+    fn get_introns(regions: &[HmmStateRegion]) -> Vec<(usize, usize)> {
+        regions
+            .iter()
+            .filter(|region| region.annotation_label == HmmAnnotationLabel::Intron)
+            .map(|region| (region.start_pos, region.end_pos))
+            .collect()
+    }
+
+    pub fn get_neg_log_prob(&self) -> f64 {
+        self.neg_log_prob as f64 / PENALTY_SCALE
+    }
+
+    pub fn get_coding_offset(&self) -> u64 {
+        self.coding_offset
+    }
+
+    pub fn is_truncated_start(&self) -> bool {
+        self.truncated_start
+    }
+
+    pub fn is_truncated_end(&self) -> bool {
+        self.truncated_end
+    }
+
+    pub fn get_mean_posterior(&self) -> Option<f32> {
+        self.mean_posterior
+    }
+
+    pub fn get_min_posterior(&self) -> Option<f32> {
+        self.min_posterior
+    }
+
     pub fn split_genes(regions: Vec<HmmStateRegion>) -> Vec<(Vec<HmmStateRegion>, usize)> {
         let mut vec_of_vecs = Vec::new();
 
@@ -1806,20 +4070,37 @@ impl HmmStateRegion {
     }
 }
 
-pub struct PredictionHmmSolution {
-    hmm: PredictionHmm,
-    eval: HmmEval,
+// Regions of a transcript (excluding intergenic) and its coding length
+pub type HmmTranscript = (Vec<HmmStateRegion>, usize);
+
+pub struct PredictionHmmSolution<'a> {
+    hmm: PredictionHmm<'a>,
+    path: Vec<HmmEval>, // From the root to the final eval, in sequence order
+    label_posteriors: Option<Vec<[f32; HMM_ANNOTATION_LABELS]>>, // Per base, if posterior scores are enabled
 }
 
-impl PredictionHmmSolution {
-    fn new(hmm: PredictionHmm, eval: HmmEval) -> PredictionHmmSolution {
-        PredictionHmmSolution { hmm, eval }
+impl<'a> PredictionHmmSolution<'a> {
+    fn new(
+        hmm: PredictionHmm<'a>,
+        path: Vec<HmmEval>,
+        label_posteriors: Option<Vec<[f32; HMM_ANNOTATION_LABELS]>>,
+    ) -> PredictionHmmSolution<'a> {
+        PredictionHmmSolution {
+            hmm,
+            path,
+            label_posteriors,
+        }
+    }
+
+    fn get_final_eval(&self) -> &HmmEval {
+        self.path.last().unwrap()
     }
 
     pub fn trace_regions(&self) -> Vec<HmmStateRegion> {
         let mut regions = Vec::new();
 
-        let mut eval = &self.eval;
+        let mut path_iter = self.path.iter().rev();
+        let mut eval = path_iter.next().unwrap();
         let mut region_end_pos = eval.end_position;
 
         let mut accum_trans_penalty = 0u64;
@@ -1836,15 +4117,19 @@ impl PredictionHmmSolution {
             if eval.state.get_annotation_label() != eval.previous_state.get_annotation_label()
             // If prev state changes annotation label
             {
-                println!("Generate a standard HmmStateRegion S: {} E: {} Label: {} Trans: {} NegLog: {}",
-                         eval.start_position, region_end_pos, eval.state.get_annotation_label().to_str(),
-                         accum_trans_penalty, accum_neg_log_prob);
+                trace!(
+                    "Generate a standard HmmStateRegion S: {} E: {} Label: {} Trans: {} NegLog: {}",
+                    eval.start_position,
+                    region_end_pos,
+                    eval.state.get_annotation_label().to_str(),
+                    accum_trans_penalty,
+                    accum_neg_log_prob
+                );
 
                 regions.push(HmmStateRegion::new(
                     eval.start_position,
                     region_end_pos,
                     eval.state.get_annotation_label(),
-                    accum_trans_penalty,
                     accum_neg_log_prob
                 ));
                 region_end_pos = eval.start_position; // Equivalent to previous_state.end_position
@@ -1852,35 +4137,196 @@ impl PredictionHmmSolution {
                 accum_neg_log_prob = 0;
             }
 
-            let prev_position = eval.start_position;
-            let idx = prev_position * HMM_STATES + (eval.previous_state as usize);
-
-            eval = self.hmm.best_eval.get(idx).unwrap().as_ref().unwrap();
+            eval = path_iter.next().unwrap();
         }
 
         // Drain accumulated intergenic region if non-zero length (almost always)
         if region_end_pos > 0 {
-            println!("Generate a starting Intergenic HmmStateRegion S: {} E: {} Label: {} Trans: {} NegLog: {}",
-                     0, region_end_pos, eval.state.get_annotation_label().to_str(),
-                     accum_trans_penalty, accum_neg_log_prob);
+            trace!("Generate a starting Intergenic HmmStateRegion S: {} E: {} Label: {} Trans: {} NegLog: {}",
+                   0, region_end_pos, eval.state.get_annotation_label().to_str(),
+                   accum_trans_penalty, accum_neg_log_prob);
             regions.push(HmmStateRegion::new(
                 eval.start_position,
                 region_end_pos,
                 eval.state.get_annotation_label(),
-                accum_trans_penalty,
                 accum_neg_log_prob
             ));
         }
 
         regions.reverse();
 
+        // eval is now the root, which is only genic if the window starts part way through a gene
+        if eval.state.get_annotation_label() != HmmAnnotationLabel::Intergenic {
+            if let Some(first_region) = regions.first_mut() {
+                first_region.coding_offset = eval.state.get_entry_coding_offset();
+                first_region.truncated_start = true;
+            }
+        }
+        if let Some(last_region) = regions.last_mut() {
+            if last_region.annotation_label != HmmAnnotationLabel::Intergenic
+                && self.hmm.partial_end
+            {
+                last_region.truncated_end = true;
+            }
+        }
+
+        if let Some(label_posteriors) = &self.label_posteriors {
+            for region in regions.iter_mut() {
+                region.set_posteriors(label_posteriors);
+            }
+        }
+
         regions
     }
 
+    /// Transcripts of each gene in the best path. The first transcript of each gene is from the best path, followed
+    /// by up to max_splice_ban_isoforms - 1 alternatives with different introns, in order of increasing penalty.
+    /// Alternatives come from banning a single splice site of the best transcript at a time, so they are not the K best
+    /// paths
+    pub fn trace_genes(&self) -> Vec<Vec<HmmTranscript>> {
+        let genes = HmmStateRegion::split_genes(self.trace_regions());
+
+        if self.hmm.config.max_splice_ban_isoforms <= 1 {
+            return genes.into_iter().map(|gene| vec![gene]).collect();
+        }
+
+        let len = self.hmm.class_pred_pen.len();
+
+        let gene_spans: Vec<(usize, usize)> = genes
+            .iter()
+            .map(|(regions, _)| (regions[0].start_pos, regions[regions.len() - 1].end_pos))
+            .collect();
+
+        let mut transcripts = Vec::with_capacity(genes.len());
+
+        for (idx, gene) in genes.into_iter().enumerate() {
+            let (gene_start, gene_end) = gene_spans[idx];
+
+            // Loci of neighbouring genes must not overlap
+            let locus_start = if idx == 0 {
+                gene_start.saturating_sub(ISOFORM_FLANK)
+            } else {
+                let previous_end = gene_spans[idx - 1].1;
+                std::cmp::max(
+                    gene_start.saturating_sub(ISOFORM_FLANK),
+                    (previous_end + gene_start) / 2,
+                )
+            };
+            let locus_end = if idx + 1 == gene_spans.len() {
+                std::cmp::min(gene_end + ISOFORM_FLANK, len)
+            } else {
+                let next_start = gene_spans[idx + 1].0;
+                std::cmp::min(gene_end + ISOFORM_FLANK, (gene_end + next_start) / 2)
+            };
+
+            let alternatives = self.find_splice_ban_transcripts(&gene.0, locus_start, locus_end);
+
+            let mut gene_transcripts = vec![gene];
+            gene_transcripts.extend(alternatives);
+            transcripts.push(gene_transcripts);
+        }
+
+        transcripts
+    }
+
+    // Re-solve the locus around a gene with each of its splice sites banned in turn, keeping the distinct
+    // transcripts which overlap the gene and are within the penalty margin of the best path over the locus. This is a
+    // single site ban approximation rather than a list Viterbi search: at most two alternatives per intron are tried,
+    // and paths which only differ from the best one in ways needing two bans at once are missed
+    fn find_splice_ban_transcripts(
+        &self,
+        gene_regions: &[HmmStateRegion],
+        locus_start: usize,
+        locus_end: usize,
+    ) -> Vec<HmmTranscript> {
+        let gene_start = gene_regions[0].start_pos;
+        let gene_end = gene_regions[gene_regions.len() - 1].end_pos;
+        let gene_introns = HmmStateRegion::get_introns(gene_regions);
+
+        if gene_introns.is_empty() {
+            return Vec::new();
+        }
+
+        let best_penalty = match self
+            .hmm
+            .new_locus_hmm(locus_start, locus_end, None)
+            .solve_viterbi()
+        {
+            Some(path) => path.last().unwrap().accum_penalty,
+            None => return Vec::new(),
+        };
+        let max_penalty =
+            best_penalty + (self.hmm.config.splice_ban_penalty_margin * PENALTY_SCALE) as u64;
+
+        let mut candidates = Vec::new();
+
+        for (intron_start, intron_end) in gene_introns.iter() {
+            for banned_splice_site in [
+                SpliceSite::Donor(intron_start - locus_start),
+                SpliceSite::Acceptor(intron_end - locus_start),
+            ] {
+                let hmm = self
+                    .hmm
+                    .new_locus_hmm(locus_start, locus_end, Some(banned_splice_site));
+
+                let path = match hmm.solve_viterbi() {
+                    Some(path) => path,
+                    None => continue,
+                };
+                let penalty = path.last().unwrap().accum_penalty;
+                if penalty > max_penalty {
+                    continue;
+                }
+
+                let mut regions = PredictionHmmSolution::new(hmm, path, None).trace_regions();
+                for region in regions.iter_mut() {
+                    region.shift(locus_start);
+                    if let Some(label_posteriors) = &self.label_posteriors {
+                        region.set_posteriors(label_posteriors);
+                    }
+                }
+
+                // Keep the gene with the most overlap, if any
+                let overlap = |(regions, _): &HmmTranscript| {
+                    let start = std::cmp::max(regions[0].start_pos, gene_start);
+                    let end = std::cmp::min(regions[regions.len() - 1].end_pos, gene_end);
+                    end.saturating_sub(start)
+                };
+                let maybe_transcript = HmmStateRegion::split_genes(regions)
+                    .into_iter()
+                    .filter(|transcript| overlap(transcript) > 0)
+                    .max_by_key(overlap);
+
+                if let Some(transcript) = maybe_transcript {
+                    candidates.push((penalty, transcript));
+                }
+            }
+        }
+
+        candidates.sort_by_key(|(penalty, _)| *penalty);
+
+        let mut intron_chains = vec![gene_introns];
+        let mut alternatives = Vec::new();
+
+        for (_, transcript) in candidates {
+            if alternatives.len() + 1 >= self.hmm.config.max_splice_ban_isoforms {
+                break;
+            }
+
+            let introns = HmmStateRegion::get_introns(&transcript.0);
+            if !intron_chains.contains(&introns) {
+                intron_chains.push(introns);
+                alternatives.push(transcript);
+            }
+        }
+
+        alternatives
+    }
+
     pub fn dump(&self, position: usize) {
         println!(
             "Solution Penalty: {} over {} bp starting at {}",
-            self.eval.accum_penalty,
+            self.get_final_eval().accum_penalty,
             self.hmm.class_pred_pen.len(),
             position
         );
@@ -2089,3 +4535,333 @@ Chr1    HelixerPost     CDS     24546   24655   .       +       0       ID=Athal
 
 
  */
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::genetic_code::GeneticCode;
+    use crate::analysis::hmm::{
+        CodonStates, HmmAnnotationLabel, HmmConfig, HmmEval, HmmState, IntronCandidateOrder,
+        PredictionHmm, START0_SLOTS, STOP0_SLOTS,
+    };
+    use crate::analysis::test_window::get_one_hot_window;
+    use crate::analysis::WindowBases;
+
+    // The codons matched by the start slots, in slot order
+    fn get_slot_start_codons(codons: &CodonStates) -> Vec<[u8; 3]> {
+        let mut start_codons = Vec::new();
+        for (start1_slot, (start0_slot, base1)) in codons.start1_prefixes.iter().enumerate() {
+            for base0 in codons.start0_bases[*start0_slot].iter() {
+                for base2 in codons.start2_bases[start1_slot].iter() {
+                    start_codons.push([*base0, *base1, *base2]);
+                }
+            }
+        }
+        start_codons.sort_unstable();
+        start_codons
+    }
+
+    // The codons matched by the stop slots, all or only those which are always stops
+    fn get_slot_stop_codons(codons: &CodonStates, hard: bool) -> Vec<[u8; 3]> {
+        let stop2_bases = if hard {
+            &codons.stop2_hard_bases
+        } else {
+            &codons.stop2_bases
+        };
+
+        let mut stop_codons = Vec::new();
+        for (stop1_slot, (stop0_slot, base1)) in codons.stop1_prefixes.iter().enumerate() {
+            for base2 in stop2_bases[stop1_slot].iter() {
+                stop_codons.push([codons.stop0_bases[*stop0_slot], *base1, *base2]);
+            }
+        }
+        stop_codons.sort_unstable();
+        stop_codons
+    }
+
+    #[test]
+    fn test_codon_slots_match_genetic_codes() {
+        for id in GeneticCode::get_all_ids() {
+            let genetic_code = GeneticCode::from_id(id).unwrap();
+
+            for alternative_starts in [false, true] {
+                let codons = CodonStates::new(&genetic_code, alternative_starts).unwrap();
+
+                let mut start_codons = if alternative_starts {
+                    genetic_code.get_start_codons()
+                } else {
+                    vec![*b"ATG"]
+                };
+                start_codons.sort_unstable();
+                assert_eq!(get_slot_start_codons(&codons), start_codons, "table {}", id);
+
+                let mut stop_codons = genetic_code.get_stop_codons();
+                stop_codons.sort_unstable();
+                assert_eq!(
+                    get_slot_stop_codons(&codons, false),
+                    stop_codons,
+                    "table {}",
+                    id
+                );
+
+                stop_codons.retain(|codon| !genetic_code.is_context_stop(codon));
+                assert_eq!(
+                    get_slot_stop_codons(&codons, true),
+                    stop_codons,
+                    "table {}",
+                    id
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_codon_slots_exact_starts() {
+        // NTN combinations of the bacterial starts, e.g. CTT, are not starts
+        let bacterial = GeneticCode::from_id(11).unwrap();
+        let codons = CodonStates::new(&bacterial, true).unwrap();
+        assert_eq!(codons.start0_bases, vec![b"TCG".to_vec(), b"A".to_vec()]);
+        assert!(!get_slot_start_codons(&codons).contains(b"CTT"));
+
+        // GTG is the only start beginning with G in the vertebrate mitochondrial code
+        let vertebrate_mito = GeneticCode::from_id(2).unwrap();
+        let codons = CodonStates::new(&vertebrate_mito, true).unwrap();
+        assert_eq!(codons.start0_bases.len(), 2);
+        assert!(!get_slot_start_codons(&codons).contains(b"GTT"));
+
+        // The mold code needs every start slot
+        let mold = GeneticCode::from_id(4).unwrap();
+        let codons = CodonStates::new(&mold, true).unwrap();
+        assert_eq!(codons.start0_bases.len(), START0_SLOTS);
+    }
+
+    #[test]
+    fn test_codon_slots_context_stops() {
+        // Every stop of the Condylostoma code may also be read through
+        let condylostoma = GeneticCode::from_id(28).unwrap();
+        let codons = CodonStates::new(&condylostoma, false).unwrap();
+        assert_eq!(codons.stop0_bases, vec![b'T']);
+        assert!(codons.stop2_hard_bases.iter().all(|bases| bases.is_empty()));
+        assert_eq!(get_slot_stop_codons(&codons, false).len(), 3);
+
+        let vertebrate_mito = GeneticCode::from_id(2).unwrap();
+        let codons = CodonStates::new(&vertebrate_mito, false).unwrap();
+        assert_eq!(codons.stop0_bases.len(), STOP0_SLOTS);
+        assert_eq!(codons.stop2_bases, codons.stop2_hard_bases);
+    }
+
+    // A gene with one intron, which has acceptors giving introns of 26, 43 and 63 bases, and predictions which
+    // leave the intron's end uncertain
+    fn get_intron_window() -> WindowBases {
+        let sequence = [
+            "CTCCTTCTCCTTCCTCTTCC",
+            "ATGGCCGCCGCCGCCGCCGCCGCCGCCGCCGCC",
+            "GTAAGTCTTCCTTCCTTCCTCTCCAGAACTTCCTCTTCCTCAGAACTTCCTTCCTCTTCTCAG",
+            "GCCGCCGCCGCCGCCGCCGCCGCCGCCGCCTAA",
+            "CCTTCTCCTTCTCCTTCCTC",
+        ]
+        .concat();
+
+        get_one_hot_window(&sequence, |position| match position {
+            20..=52 | 116..=148 => [0.1, 0.1, 0.7, 0.1],
+            53..=78 => [0.1, 0.1, 0.1, 0.7],
+            79..=115 => [0.1, 0.1, 0.4, 0.4],
+            _ => [0.7, 0.1, 0.1, 0.1],
+        })
+    }
+
+    // Configs with each kind of intron length model, for introns of at least 20 bases
+    fn get_intron_length_configs() -> Vec<HmmConfig> {
+        let table_path = std::env::temp_dir().join("helixer_post_test_intron_lengths.txt");
+        std::fs::write(&table_path, "20 1\n30 5\n45 1\n80 20\n").unwrap();
+
+        let settings: [&[(&str, &str)]; 6] = [
+            &[],
+            &[("max_intron_length", "45")],
+            &[
+                ("intron_length_distribution", "geometric"),
+                ("intron_mean_length", "30"),
+            ],
+            &[
+                ("intron_length_distribution", "geometric"),
+                ("intron_mean_length", "30"),
+                ("max_intron_length", "45"),
+            ],
+            &[("intron_length_distribution", table_path.to_str().unwrap())],
+            &[
+                ("intron_length_distribution", table_path.to_str().unwrap()),
+                ("max_intron_length", "40"),
+            ],
+        ];
+
+        settings
+            .iter()
+            .map(|settings| {
+                let mut config = HmmConfig::new();
+                config.set_value("min_intron_length", "20").unwrap();
+                config.set_value("min_u12_intron_length", "20").unwrap();
+                for (key, value) in settings.iter() {
+                    config.set_value(key, value).unwrap();
+                }
+                config
+            })
+            .collect()
+    }
+
+    // Start and end of each intron along a path
+    fn get_path_introns(path: &[HmmEval]) -> Vec<(usize, usize)> {
+        let mut introns: Vec<(usize, usize)> = Vec::new();
+
+        for eval in path.iter().skip(1) {
+            if eval.state.get_annotation_label() != HmmAnnotationLabel::Intron {
+                continue;
+            }
+
+            match introns.last_mut() {
+                Some((_, end)) if *end == eval.start_position => *end = eval.end_position,
+                _ => introns.push((eval.start_position, eval.end_position)),
+            }
+        }
+
+        introns
+    }
+
+    #[test]
+    fn test_solvers_apply_intron_lengths() {
+        let genetic_code = GeneticCode::from_id(1).unwrap();
+        let window = get_intron_window();
+
+        // Whole window, starting within the intron and ending within the intron
+        for config in get_intron_length_configs() {
+            for (start, end) in [(0, window.len()), (60, window.len()), (0, 75)] {
+                let mut hmm =
+                    PredictionHmm::new(window[start..end].to_vec(), &config, &genetic_code);
+                hmm.set_sequence_bounds(true, true);
+                hmm.partial_start = start > 0;
+                hmm.partial_end = end < window.len();
+
+                let best_first_path = hmm.solve_best_first().unwrap();
+                let viterbi_path = hmm.solve_viterbi().unwrap();
+
+                assert_eq!(
+                    best_first_path.last().unwrap().accum_penalty,
+                    viterbi_path.last().unwrap().accum_penalty
+                );
+
+                for path in [&best_first_path, &viterbi_path] {
+                    let introns = get_path_introns(path);
+                    if start == 0 && end == window.len() {
+                        assert_eq!(introns.len(), 1);
+                    }
+                    for (intron_start, intron_end) in introns {
+                        let partial = intron_start == 0 && start > 0;
+                        assert!(
+                            partial
+                                || config.max_intron_length == 0
+                                || intron_end - intron_start <= config.max_intron_length
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // State and span of each step along a path
+    fn get_path_steps(path: &[HmmEval]) -> Vec<(usize, usize, usize)> {
+        path.iter()
+            .map(|eval| (eval.state as usize, eval.start_position, eval.end_position))
+            .collect()
+    }
+
+    #[test]
+    fn test_solvers_find_identical_paths() {
+        let genetic_code = GeneticCode::from_id(1).unwrap();
+        let window = get_intron_window();
+        let two_genes = [window.clone(), window.clone()].concat();
+
+        let mut configs = vec![HmmConfig::new()];
+        configs.extend(get_intron_length_configs());
+
+        // The Viterbi solver must trace the same path as the default best first solver, on whole windows and on
+        // windows cut within a gene
+        for config in configs.iter() {
+            for fixture in [&window, &two_genes] {
+                for (start, end) in [(0, fixture.len()), (60, fixture.len()), (0, 75)] {
+                    let mut hmm =
+                        PredictionHmm::new(fixture[start..end].to_vec(), config, &genetic_code);
+                    hmm.set_sequence_bounds(true, true);
+                    hmm.partial_start = start > 0;
+                    hmm.partial_end = end < fixture.len();
+
+                    let best_first_path = hmm.solve_best_first().unwrap();
+                    let viterbi_path = hmm.solve_viterbi().unwrap();
+
+                    assert!(
+                        get_path_steps(&best_first_path) == get_path_steps(&viterbi_path),
+                        "Paths differ for the window from {} to {}",
+                        start,
+                        end
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_intron_candidates_keep_lowest_penalty() {
+        let config = &get_intron_length_configs()[2]; // Geometric, no maximum
+        let order = IntronCandidateOrder::new(config);
+
+        let candidate = |intron_start: usize, accum_penalty: u64| {
+            let mut eval = HmmEval::new_root(HmmState::UTR5IntronU2GtAg, accum_penalty);
+            eval.intron_start = intron_start;
+            eval
+        };
+
+        // The later intron is better once both leave, the earlier one if the window ends first
+        let early = candidate(100, 100_000);
+        let late = candidate(200, 100_000 + (order.u2_slope * 50.0) as u64);
+        let worse = candidate(100, 100_000 + (order.u2_slope * 60.0) as u64);
+
+        for (first, second) in [(early, late), (late, early)] {
+            let mut candidates = Vec::new();
+            assert!(order.add_candidate(&mut candidates, first));
+            assert!(order.add_candidate(&mut candidates, second));
+            assert!(!order.add_candidate(&mut candidates, worse));
+            assert_eq!(candidates.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_posteriors_sum_to_one() {
+        let genetic_code = GeneticCode::from_id(1).unwrap();
+        let window = get_intron_window();
+
+        for config in get_intron_length_configs() {
+            for start in [0, 60] {
+                let mut hmm = PredictionHmm::new(window[start..].to_vec(), &config, &genetic_code);
+                hmm.set_sequence_bounds(true, true);
+                hmm.partial_start = start > 0;
+
+                let label_posteriors = hmm.compute_label_posteriors().unwrap();
+                assert_eq!(label_posteriors.len(), window.len() - start);
+
+                for posteriors in label_posteriors.iter() {
+                    let sum: f32 = posteriors.iter().sum();
+                    assert!((sum - 1.0).abs() < 1e-3, "Posteriors sum to {}", sum);
+                }
+
+                // The intron is likely where its end is not in doubt
+                let intron = HmmAnnotationLabel::Intron as usize;
+                assert!(label_posteriors[70 - start][intron] > 0.5);
+
+                // Only the 63 base intron covers these bases with canonical splice sites, so they are
+                // unlikely to be intron with a lower maximum
+                if start == 0 && config.max_intron_length > 0 {
+                    for posteriors in label_posteriors[100..116].iter() {
+                        assert!(posteriors[intron] < 0.01);
+                    }
+                }
+            }
+        }
+    }
+}
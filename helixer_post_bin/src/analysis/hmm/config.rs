@@ -0,0 +1,1224 @@
+use super::site_models::SiteModels;
+use crate::analysis::genetic_code::GeneticCode;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+// Default values, used unless overridden by a config file or command line
+
+// Allow/Prevent introns within each state and/or at state transitions
+
+const CAN_SPLICE_UTR5: bool = true;
+const CAN_SPLICE_UTR5_START: bool = true;
+const CAN_SPLICE_START: bool = true;
+const CAN_SPLICE_START_CODING: bool = true;
+const CAN_SPLICE_CODING: bool = true;
+const CAN_SPLICE_CODING_STOP: bool = true;
+const CAN_SPLICE_STOP: bool = true;
+const CAN_SPLICE_STOP_UTR3: bool = true;
+const CAN_SPLICE_UTR3: bool = true;
+
+const START_WEIGHT: f64 = 1_000.0;
+
+const DONOR_U2_GT_AG_FIXED_PENALTY: f64 = 0.0;
+const DONOR_U2_GC_AG_FIXED_PENALTY: f64 = 0.0;
+const DONOR_U12_AT_AC_FIXED_PENALTY: f64 = 0.0;
+
+const DONOR_WEIGHT: f64 = 1.0;
+
+const ACCEPTOR_WEIGHT: f64 = 1.0;
+
+const STOP_WEIGHT: f64 = 1_000.0;
+
+const SOLVER: HmmSolver = HmmSolver::BestFirst;
+
+const POSTERIOR_SCORES: bool = false; // Forward-backward pass for GFF feature scores
+
+const MAX_SPLICE_BAN_ISOFORMS: usize = 1; // Only the best path unless raised. Alternatives ban one splice site each
+const SPLICE_BAN_PENALTY_MARGIN: f64 = 20.0;
+
+const ALTERNATIVE_STARTS: bool = false; // Only ATG unless enabled
+
+const PARTIAL_GENES: bool = false; // Windows must start intergenic unless enabled
+const PARTIAL_GENE_PENALTY: f64 = 20.0;
+
+// External hints, penalties per base for states disagreeing with a hint
+const INTRON_HINT_PENALTY: f64 = 2.0;
+const EXON_HINT_PENALTY: f64 = 2.0;
+const CDS_HINT_PENALTY: f64 = 2.0;
+const UNHINTED_SPLICE_PENALTY: f64 = 0.0; // Per splice site not matching an intron hint, when hints are given
+
+// Intron lengths, including the donor and acceptor sites
+const MIN_INTRON_LENGTH: usize = 50;
+const MIN_U12_INTRON_LENGTH: usize = 30;
+const MAX_INTRON_LENGTH: usize = 0; // No limit
+const INTRON_MEAN_LENGTH: f64 = 1_000.0; // For the geometric distribution
+
+const MIN_INTRON_LENGTH_LIMIT: usize = 10; // Room for the longest donor (U12) and acceptor contexts
+const INTRON_LENGTH_PROB_FLOOR: f64 = 0.000_000_001; // Lengths with no counts in a table
+
+const PHASE_RETAIN: f64 = 0.20; // Adjust as needed
+
+const CLASS_PRED_PROB_FLOOR: f64 = 0.000_000_001; // Prevent infinite penalties
+const PHASE_PRED_PROB_FLOOR: f64 = 0.000_000_001; // Prevent infinite penalties
+                                                  //const PHASE_PRED_PROB_FLOOR: f64 = 0.5; // Limit impact of incorrect phase
+const PRED_PROB_FLOOR: f64 = 0.000_000_001; // Prevent infinite penalties
+pub(super) const BASE_PROB_FLOOR: f64 = 0.000_000_001; // Prevent infinite penalties
+
+/// Method used to find the most likely path through the HMM
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum HmmSolver {
+    Viterbi,   // Dynamic programming over all positions, with bounded memory per position
+    BestFirst, // Original best-first search, can use excessive memory on long windows
+    Compare,   // Run both and report any difference, using the Viterbi result
+}
+
+impl HmmSolver {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HmmSolver::Viterbi => "viterbi",
+            HmmSolver::BestFirst => "best_first",
+            HmmSolver::Compare => "compare",
+        }
+    }
+}
+
+/// Intron length distribution, adding a penalty for each intron based on its length
+#[derive(Clone)]
+pub enum IntronLengthDistribution {
+    None,
+    Geometric, // Lengths above the minimum are geometrically distributed, with a given mean
+    Table(IntronLengthTable),
+}
+
+impl IntronLengthDistribution {
+    pub fn as_str(&self) -> &str {
+        match self {
+            IntronLengthDistribution::None => "none",
+            IntronLengthDistribution::Geometric => "geometric",
+            IntronLengthDistribution::Table(table) => &table.path,
+        }
+    }
+}
+
+/// Explicit intron length distribution, loaded from a file of 'length count' lines in increasing length order.
+/// Each count covers the lengths above the previous line's length, up to and including its own length
+#[derive(Clone)]
+pub struct IntronLengthTable {
+    path: String,
+    penalties: Vec<f64>, // -log2 probability of each length, longer lengths use the last entry
+}
+
+impl IntronLengthTable {
+    pub fn from_file(path: &Path) -> Result<IntronLengthTable> {
+        let contents = fs::read_to_string(path)?;
+        let bins = Self::parse_bins(&contents).map_err(|err| {
+            invalid_data(format!("{} in intron length file {}", err, path.display()))
+        })?;
+
+        let total: f64 = bins.iter().map(|(_, count)| count).sum();
+        if total <= 0.0 {
+            return Err(invalid_data(format!(
+                "No intron lengths counted in intron length file {}",
+                path.display()
+            )));
+        }
+
+        let mut penalties = vec![0.0];
+        for (length, count) in bins {
+            let width = length + 1 - penalties.len();
+            let prob = (count / total / width as f64).max(INTRON_LENGTH_PROB_FLOOR);
+            penalties.resize(length + 1, -prob.log2());
+        }
+
+        Ok(IntronLengthTable {
+            path: path.display().to_string(),
+            penalties,
+        })
+    }
+
+    fn parse_bins(contents: &str) -> Result<Vec<(usize, f64)>> {
+        let mut bins: Vec<(usize, f64)> = Vec::new();
+
+        for (idx, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let length = fields.next().and_then(|v| v.parse::<usize>().ok());
+            let count = fields.next().and_then(|v| v.parse::<f64>().ok());
+
+            match (length, count) {
+                (Some(length), Some(count))
+                    if count >= 0.0
+                        && bins.last().map_or(length > 0, |(last, _)| length > *last) =>
+                {
+                    bins.push((length, count))
+                }
+                _ => {
+                    return Err(invalid_data(format!(
+                        "Expected increasing 'length count' on line {}",
+                        idx + 1
+                    )))
+                }
+            }
+        }
+
+        Ok(bins)
+    }
+
+    // Difference between the highest and lowest penalties of any intron length
+    fn get_penalty_range(&self) -> f64 {
+        let (min, max) = self.penalties[1..]
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), penalty| {
+                (min.min(*penalty), max.max(*penalty))
+            });
+        (max - min).max(0.0)
+    }
+
+    // Runs of lengths with the same penalty, as the first length and penalty of each. The last run continues
+    // for all longer lengths
+    fn get_penalty_runs(&self) -> Vec<(usize, f64)> {
+        let mut runs: Vec<(usize, f64)> = Vec::new();
+
+        for (length, penalty) in self.penalties.iter().enumerate() {
+            if runs
+                .last()
+                .is_none_or(|(_, run_penalty)| run_penalty != penalty)
+            {
+                runs.push((length, *penalty));
+            }
+        }
+
+        runs
+    }
+
+    fn get_penalty(&self, length: usize) -> f64 {
+        match self.penalties.get(length) {
+            Some(penalty) => *penalty,
+            None => self.penalties[self.penalties.len() - 1],
+        }
+    }
+}
+
+/// Tuning parameters for the HMM, loadable from a TOML or JSON file and/or set as key=value pairs
+///
+/// Keys match the field names, e.g. `start_weight = 1000.0` (TOML) or `{"start_weight": 1000.0}` (JSON).
+/// Only flat key/value pairs are supported, TOML files with section headers are rejected.
+///
+/// The genetic code (NCBI translation table number) applies to all sequences, unless overridden
+/// for a specific sequence with a `genetic_code.<sequence>` key, e.g. `genetic_code.chrM = 2`.
+#[derive(Clone)]
+pub struct HmmConfig {
+    pub(super) can_splice_utr5: bool,
+    pub(super) can_splice_utr5_start: bool,
+    pub(super) can_splice_start: bool,
+    pub(super) can_splice_start_coding: bool,
+    pub(super) can_splice_coding: bool,
+    pub(super) can_splice_coding_stop: bool,
+    pub(super) can_splice_stop: bool,
+    pub(super) can_splice_stop_utr3: bool,
+    pub(super) can_splice_utr3: bool,
+
+    pub(super) start_weight: f64,
+    pub(super) stop_weight: f64,
+
+    pub(super) donor_weight: f64,
+    pub(super) acceptor_weight: f64,
+
+    pub(super) donor_u2_gt_ag_fixed_penalty: f64,
+    pub(super) donor_u2_gc_ag_fixed_penalty: f64,
+    pub(super) donor_u12_at_ac_fixed_penalty: f64,
+
+    pub(super) solver: HmmSolver,
+    pub(super) posterior_scores: bool,
+
+    pub(super) max_splice_ban_isoforms: usize,
+    pub(super) splice_ban_penalty_margin: f64,
+
+    pub(super) genetic_code: GeneticCode,
+    pub(super) sequence_genetic_codes: Vec<(String, GeneticCode)>,
+    pub(super) alternative_starts: bool,
+
+    pub(super) partial_genes: bool,
+    pub(super) partial_gene_penalty: f64,
+
+    pub(super) intron_hint_penalty: f64,
+    pub(super) exon_hint_penalty: f64,
+    pub(super) cds_hint_penalty: f64,
+    pub(super) unhinted_splice_penalty: f64,
+
+    pub(super) min_intron_length: usize,
+    pub(super) min_u12_intron_length: usize,
+    pub(super) max_intron_length: usize,
+    pub(super) intron_length_distribution: IntronLengthDistribution,
+    pub(super) intron_mean_length: f64,
+
+    pub(super) site_models: Option<SiteModels>,
+
+    pub(super) phase_retain: f64,
+
+    pub(super) class_pred_prob_floor: f64,
+    pub(super) phase_pred_prob_floor: f64,
+    pub(super) pred_prob_floor: f64,
+    pub(super) base_prob_floor: f64,
+}
+
+impl Default for HmmConfig {
+    fn default() -> Self {
+        HmmConfig {
+            can_splice_utr5: CAN_SPLICE_UTR5,
+            can_splice_utr5_start: CAN_SPLICE_UTR5_START,
+            can_splice_start: CAN_SPLICE_START,
+            can_splice_start_coding: CAN_SPLICE_START_CODING,
+            can_splice_coding: CAN_SPLICE_CODING,
+            can_splice_coding_stop: CAN_SPLICE_CODING_STOP,
+            can_splice_stop: CAN_SPLICE_STOP,
+            can_splice_stop_utr3: CAN_SPLICE_STOP_UTR3,
+            can_splice_utr3: CAN_SPLICE_UTR3,
+            start_weight: START_WEIGHT,
+            stop_weight: STOP_WEIGHT,
+            donor_weight: DONOR_WEIGHT,
+            acceptor_weight: ACCEPTOR_WEIGHT,
+            donor_u2_gt_ag_fixed_penalty: DONOR_U2_GT_AG_FIXED_PENALTY,
+            donor_u2_gc_ag_fixed_penalty: DONOR_U2_GC_AG_FIXED_PENALTY,
+            donor_u12_at_ac_fixed_penalty: DONOR_U12_AT_AC_FIXED_PENALTY,
+            solver: SOLVER,
+            posterior_scores: POSTERIOR_SCORES,
+            max_splice_ban_isoforms: MAX_SPLICE_BAN_ISOFORMS,
+            splice_ban_penalty_margin: SPLICE_BAN_PENALTY_MARGIN,
+            genetic_code: GeneticCode::default(),
+            sequence_genetic_codes: Vec::new(),
+            alternative_starts: ALTERNATIVE_STARTS,
+            partial_genes: PARTIAL_GENES,
+            partial_gene_penalty: PARTIAL_GENE_PENALTY,
+            intron_hint_penalty: INTRON_HINT_PENALTY,
+            exon_hint_penalty: EXON_HINT_PENALTY,
+            cds_hint_penalty: CDS_HINT_PENALTY,
+            unhinted_splice_penalty: UNHINTED_SPLICE_PENALTY,
+            min_intron_length: MIN_INTRON_LENGTH,
+            min_u12_intron_length: MIN_U12_INTRON_LENGTH,
+            max_intron_length: MAX_INTRON_LENGTH,
+            intron_length_distribution: IntronLengthDistribution::None,
+            intron_mean_length: INTRON_MEAN_LENGTH,
+            site_models: None,
+            phase_retain: PHASE_RETAIN,
+            class_pred_prob_floor: CLASS_PRED_PROB_FLOOR,
+            phase_pred_prob_floor: PHASE_PRED_PROB_FLOOR,
+            pred_prob_floor: PRED_PROB_FLOOR,
+            base_prob_floor: BASE_PROB_FLOOR,
+        }
+    }
+}
+
+fn invalid_data(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    value.parse().map_err(|_| {
+        invalid_data(format!(
+            "Invalid value '{}' for {}, expected true or false",
+            value, key
+        ))
+    })
+}
+
+fn parse_f64(key: &str, value: &str) -> Result<f64> {
+    value.parse().map_err(|_| {
+        invalid_data(format!(
+            "Invalid value '{}' for {}, expected a number",
+            value, key
+        ))
+    })
+}
+
+// Penalties, and the weights and lengths they are derived from, must be finite and not negative, as the best-first
+// solver relies on penalties only ever increasing
+fn parse_penalty(key: &str, value: &str) -> Result<f64> {
+    let penalty = parse_f64(key, value)?;
+    if penalty.is_finite() && penalty >= 0.0 {
+        Ok(penalty)
+    } else {
+        Err(invalid_data(format!(
+            "Invalid value '{}' for {}, expected a finite number of at least 0",
+            value, key
+        )))
+    }
+}
+
+fn parse_count(key: &str, value: &str) -> Result<usize> {
+    match value.parse() {
+        Ok(count) if count >= 1 => Ok(count),
+        _ => Err(invalid_data(format!(
+            "Invalid value '{}' for {}, expected a whole number of at least 1",
+            value, key
+        ))),
+    }
+}
+
+fn parse_min_intron_length(key: &str, value: &str) -> Result<usize> {
+    match value.parse() {
+        Ok(length) if length >= MIN_INTRON_LENGTH_LIMIT => Ok(length),
+        _ => Err(invalid_data(format!(
+            "Invalid value '{}' for {}, expected a whole number of at least {}",
+            value, key, MIN_INTRON_LENGTH_LIMIT
+        ))),
+    }
+}
+
+fn parse_max_intron_length(key: &str, value: &str) -> Result<usize> {
+    value.parse().map_err(|_| {
+        invalid_data(format!(
+            "Invalid value '{}' for {}, expected a whole number, or 0 for no limit",
+            value, key
+        ))
+    })
+}
+
+fn parse_intron_length_distribution(value: &str) -> Result<IntronLengthDistribution> {
+    match value {
+        "none" => Ok(IntronLengthDistribution::None),
+        "geometric" => Ok(IntronLengthDistribution::Geometric),
+        path => Ok(IntronLengthDistribution::Table(
+            IntronLengthTable::from_file(Path::new(path))?,
+        )),
+    }
+}
+
+fn parse_site_models(value: &str) -> Result<Option<SiteModels>> {
+    match value {
+        "none" => Ok(None),
+        path => Ok(Some(SiteModels::from_file(Path::new(path))?)),
+    }
+}
+
+fn parse_solver(key: &str, value: &str) -> Result<HmmSolver> {
+    match value {
+        "viterbi" => Ok(HmmSolver::Viterbi),
+        "best_first" => Ok(HmmSolver::BestFirst),
+        "compare" => Ok(HmmSolver::Compare),
+        _ => Err(invalid_data(format!(
+            "Invalid value '{}' for {}, expected viterbi, best_first or compare",
+            value, key
+        ))),
+    }
+}
+
+// Checks the start and stop codons of the genetic code fit the HMM's start and stop states
+fn check_genetic_code(genetic_code: &GeneticCode, alternative_starts: bool) -> Result<()> {
+    super::CodonStates::new(genetic_code, alternative_starts)
+        .map(|_| ())
+        .map_err(invalid_data)
+}
+
+fn parse_genetic_code(key: &str, value: &str) -> Result<GeneticCode> {
+    value
+        .parse()
+        .ok()
+        .and_then(GeneticCode::from_id)
+        .ok_or_else(|| {
+            invalid_data(format!(
+                "Invalid value '{}' for {}, expected an NCBI translation table number {:?}",
+                value,
+                key,
+                GeneticCode::get_all_ids()
+            ))
+        })
+}
+
+fn parse_probability(key: &str, value: &str) -> Result<f64> {
+    let prob = parse_f64(key, value)?;
+    if (0.0..=1.0).contains(&prob) {
+        Ok(prob)
+    } else {
+        Err(invalid_data(format!(
+            "Invalid value '{}' for {}, expected a number between 0 and 1",
+            value, key
+        )))
+    }
+}
+
+fn unquote(text: &str) -> &str {
+    text.trim().trim_matches('"')
+}
+
+// Reads a TOML basic ("...") or literal ('...') string from the start of text, returning it unescaped along with the
+// text after it. None if the string is not closed or has an invalid escape
+fn parse_toml_string(text: &str) -> Option<(String, &str)> {
+    parse_quoted_string(text, false)
+}
+
+// Reads a JSON string from the start of text, as parse_toml_string. JSON has the escapes of TOML basic strings
+// except '\U', plus '\/' and UTF-16 surrogate pairs
+fn parse_json_string(text: &str) -> Option<(String, &str)> {
+    if text.starts_with('"') {
+        parse_quoted_string(text, true)
+    } else {
+        None
+    }
+}
+
+fn parse_quoted_string(text: &str, json: bool) -> Option<(String, &str)> {
+    let mut chars = text.char_indices();
+    let quote = match chars.next() {
+        Some((_, quote)) if quote == '"' || quote == '\'' => quote,
+        _ => return None,
+    };
+
+    let mut string = String::new();
+    while let Some((idx, c)) = chars.next() {
+        if c == quote {
+            return Some((string, &text[idx + 1..]));
+        }
+        if c != '\\' || quote == '\'' {
+            string.push(c);
+            continue;
+        }
+
+        let escaped = match chars.next()?.1 {
+            'b' => '\u{8}',
+            't' => '\t',
+            'n' => '\n',
+            'f' => '\u{c}',
+            'r' => '\r',
+            '"' => '"',
+            '\\' => '\\',
+            '/' if json => '/',
+            'u' if json => {
+                let unit = read_hex(&mut chars, 4)?;
+                if (0xD800..0xDC00).contains(&unit) {
+                    if chars.next()?.1 != '\\' || chars.next()?.1 != 'u' {
+                        return None;
+                    }
+                    let low = read_hex(&mut chars, 4)?;
+                    if !(0xDC00..0xE000).contains(&low) {
+                        return None;
+                    }
+                    char::from_u32(0x10000 + ((unit - 0xD800) << 10) + (low - 0xDC00))?
+                } else {
+                    char::from_u32(unit)?
+                }
+            }
+            unicode @ ('u' | 'U') if !json => {
+                let digits = if unicode == 'u' { 4 } else { 8 };
+                char::from_u32(read_hex(&mut chars, digits)?)?
+            }
+            _ => return None,
+        };
+        string.push(escaped);
+    }
+
+    None
+}
+
+// Reads a code point of the given number of hex digits from an escape
+fn read_hex(chars: &mut std::str::CharIndices, digits: usize) -> Option<u32> {
+    let hex: String = chars.by_ref().take(digits).map(|(_, c)| c).collect();
+    if hex.len() != digits || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(&hex, 16).ok()
+}
+
+// Splits a flat JSON object into its keys and values, with strings unescaped. Values must be strings, numbers or
+// booleans, which are set as the same values would be from TOML
+fn parse_json_object(contents: &str) -> Result<Vec<(String, String)>> {
+    let invalid = |found: &str| {
+        invalid_data(format!(
+            "Expected '\"key\": value' in a single flat JSON object, found '{}'",
+            found.lines().next().unwrap_or("")
+        ))
+    };
+
+    let mut rest = contents
+        .trim()
+        .strip_prefix('{')
+        .ok_or_else(|| invalid(contents))?
+        .trim_start();
+    let mut entries = Vec::new();
+
+    match rest.strip_prefix('}') {
+        Some(after) => rest = after,
+        None => loop {
+            let (key, after) = parse_json_string(rest).ok_or_else(|| invalid(rest))?;
+            let after = after
+                .trim_start()
+                .strip_prefix(':')
+                .ok_or_else(|| invalid(rest))?
+                .trim_start();
+
+            let (value, after) = if after.starts_with('"') {
+                parse_json_string(after).ok_or_else(|| invalid(rest))?
+            } else {
+                let end = after
+                    .find(|c: char| c == ',' || c == '}' || c.is_whitespace())
+                    .unwrap_or(after.len());
+                let value = &after[..end];
+                if value.is_empty()
+                    || value == "null"
+                    || value.starts_with(['{', '['])
+                {
+                    return Err(invalid(rest));
+                }
+                (value.to_string(), &after[end..])
+            };
+            entries.push((key, value));
+
+            let after = after.trim_start();
+            if let Some(after) = after.strip_prefix(',') {
+                rest = after.trim_start();
+            } else if let Some(after) = after.strip_prefix('}') {
+                rest = after;
+                break;
+            } else {
+                return Err(invalid(rest));
+            }
+        },
+    }
+
+    if rest.trim().is_empty() {
+        Ok(entries)
+    } else {
+        Err(invalid(rest.trim()))
+    }
+}
+
+// Reads a TOML key, bare or quoted and possibly dotted, from the start of text, returning it with the segments
+// joined by '.' along with the text after it
+fn parse_toml_key(text: &str) -> Option<(String, &str)> {
+    let mut key = String::new();
+    let mut rest = text.trim_start();
+
+    loop {
+        let (segment, after) = if rest.starts_with('"') || rest.starts_with('\'') {
+            parse_toml_string(rest)?
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return None;
+            }
+            (rest[..end].to_string(), &rest[end..])
+        };
+
+        key.push_str(&segment);
+        rest = after.trim_start();
+
+        match rest.strip_prefix('.') {
+            Some(after_dot) => {
+                key.push('.');
+                rest = after_dot.trim_start();
+            }
+            None => return Some((key, rest)),
+        }
+    }
+}
+
+// Splits a 'key = value' TOML line, with quoted keys and values unescaped and any trailing comment removed
+fn parse_toml_line(line: &str) -> Option<(String, String)> {
+    let (key, rest) = parse_toml_key(line)?;
+    let rest = rest.strip_prefix('=')?.trim_start();
+
+    let (value, rest) = if rest.starts_with('"') || rest.starts_with('\'') {
+        parse_toml_string(rest)?
+    } else {
+        let end = rest.find('#').unwrap_or(rest.len());
+        let value = rest[..end].trim();
+        if value.is_empty() {
+            return None;
+        }
+        (value.to_string(), &rest[end..])
+    };
+
+    let rest = rest.trim_start();
+    if rest.is_empty() || rest.starts_with('#') {
+        Some((key, value))
+    } else {
+        None
+    }
+}
+
+// A TOML basic string, quoted and escaped
+fn to_toml_string(text: &str) -> String {
+    let mut string = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => string.push_str("\\\""),
+            '\\' => string.push_str("\\\\"),
+            '\n' => string.push_str("\\n"),
+            '\t' => string.push_str("\\t"),
+            '\r' => string.push_str("\\r"),
+            c if c.is_control() => string.push_str(&format!("\\u{:04X}", c as u32)),
+            c => string.push(c),
+        }
+    }
+    string.push('"');
+    string
+}
+
+// A TOML key, quoted unless it is a bare key
+fn to_toml_key(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if bare {
+        key.to_string()
+    } else {
+        to_toml_string(key)
+    }
+}
+
+impl HmmConfig {
+    pub fn new() -> HmmConfig {
+        Self::default()
+    }
+
+    /// Loads a config from a TOML or JSON file, with defaults for any values not given
+    pub fn from_file(path: &Path) -> Result<HmmConfig> {
+        let mut config = Self::default();
+        config.update_from_file(path)?;
+        Ok(config)
+    }
+
+    pub fn update_from_file(&mut self, path: &Path) -> Result<()> {
+        let contents = fs::read_to_string(path)?;
+        self.update_from_str(&contents)
+            .map_err(|err| invalid_data(format!("{} in HMM config file {}", err, path.display())))
+    }
+
+    /// Updates values from the contents of a TOML or JSON config, detected by a leading '{'
+    pub fn update_from_str(&mut self, contents: &str) -> Result<()> {
+        let contents = contents.trim();
+
+        if contents.starts_with('{') {
+            self.update_from_json(contents)
+        } else {
+            self.update_from_toml(contents)
+        }
+    }
+
+    fn update_from_toml(&mut self, contents: &str) -> Result<()> {
+        for line in contents.lines() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                return Err(invalid_data(format!(
+                    "TOML sections are not supported, found '{}'",
+                    line
+                )));
+            }
+
+            match parse_toml_line(line) {
+                Some((key, value)) => self.set_value(&key, &value)?,
+                None => {
+                    return Err(invalid_data(format!(
+                        "Expected 'key = value', found '{}'",
+                        line
+                    )))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn update_from_json(&mut self, contents: &str) -> Result<()> {
+        for (key, value) in parse_json_object(contents)? {
+            self.set_value(&key, &value)?;
+        }
+
+        Ok(())
+    }
+
+    /// The genetic code for the given sequence, either specific to the sequence or the global default
+    pub fn get_genetic_code(&self, sequence: &str) -> &GeneticCode {
+        self.sequence_genetic_codes
+            .iter()
+            .find(|(name, _)| name == sequence)
+            .map_or(&self.genetic_code, |(_, genetic_code)| genetic_code)
+    }
+
+    fn set_sequence_genetic_code(&mut self, sequence: &str, genetic_code: GeneticCode) {
+        match self
+            .sequence_genetic_codes
+            .iter_mut()
+            .find(|(name, _)| name == sequence)
+        {
+            Some(entry) => entry.1 = genetic_code,
+            None => self
+                .sequence_genetic_codes
+                .push((sequence.to_owned(), genetic_code)),
+        }
+    }
+
+    /// Sets a single value by key, as used in config files
+    pub fn set_value(&mut self, key: &str, value: &str) -> Result<()> {
+        if let Some(sequence) = key.strip_prefix("genetic_code.") {
+            let genetic_code = parse_genetic_code(key, value)?;
+            check_genetic_code(&genetic_code, self.alternative_starts)?;
+            self.set_sequence_genetic_code(unquote(sequence), genetic_code);
+            return Ok(());
+        }
+
+        match key {
+            "can_splice_utr5" => self.can_splice_utr5 = parse_bool(key, value)?,
+            "can_splice_utr5_start" => self.can_splice_utr5_start = parse_bool(key, value)?,
+            "can_splice_start" => self.can_splice_start = parse_bool(key, value)?,
+            "can_splice_start_coding" => self.can_splice_start_coding = parse_bool(key, value)?,
+            "can_splice_coding" => self.can_splice_coding = parse_bool(key, value)?,
+            "can_splice_coding_stop" => self.can_splice_coding_stop = parse_bool(key, value)?,
+            "can_splice_stop" => self.can_splice_stop = parse_bool(key, value)?,
+            "can_splice_stop_utr3" => self.can_splice_stop_utr3 = parse_bool(key, value)?,
+            "can_splice_utr3" => self.can_splice_utr3 = parse_bool(key, value)?,
+
+            "start_weight" => self.start_weight = parse_penalty(key, value)?,
+            "stop_weight" => self.stop_weight = parse_penalty(key, value)?,
+            "donor_weight" => self.donor_weight = parse_penalty(key, value)?,
+            "acceptor_weight" => self.acceptor_weight = parse_penalty(key, value)?,
+
+            "donor_u2_gt_ag_fixed_penalty" => {
+                self.donor_u2_gt_ag_fixed_penalty = parse_penalty(key, value)?
+            }
+            "donor_u2_gc_ag_fixed_penalty" => {
+                self.donor_u2_gc_ag_fixed_penalty = parse_penalty(key, value)?
+            }
+            // Only AT-AC U12 introns have their own states, GT-AG U12 introns are solved as U2 introns
+            "donor_u12_gt_ag_fixed_penalty" => {
+                return Err(invalid_data(format!(
+                    "Unsupported HMM config key '{}', U12 GT-AG introns share the U2 GT-AG penalty",
+                    key
+                )))
+            }
+            "donor_u12_at_ac_fixed_penalty" => {
+                self.donor_u12_at_ac_fixed_penalty = parse_penalty(key, value)?
+            }
+
+            "solver" => self.solver = parse_solver(key, value)?,
+            "posterior_scores" => self.posterior_scores = parse_bool(key, value)?,
+
+            "max_splice_ban_isoforms" => self.max_splice_ban_isoforms = parse_count(key, value)?,
+            "splice_ban_penalty_margin" => {
+                self.splice_ban_penalty_margin = parse_penalty(key, value)?
+            }
+
+            "genetic_code" => {
+                let genetic_code = parse_genetic_code(key, value)?;
+                check_genetic_code(&genetic_code, self.alternative_starts)?;
+                self.genetic_code = genetic_code;
+            }
+            "alternative_starts" => {
+                let alternative_starts = parse_bool(key, value)?;
+                check_genetic_code(&self.genetic_code, alternative_starts)?;
+                for (_, genetic_code) in self.sequence_genetic_codes.iter() {
+                    check_genetic_code(genetic_code, alternative_starts)?;
+                }
+                self.alternative_starts = alternative_starts;
+            }
+
+            "partial_genes" => self.partial_genes = parse_bool(key, value)?,
+            "partial_gene_penalty" => self.partial_gene_penalty = parse_penalty(key, value)?,
+
+            "intron_hint_penalty" => self.intron_hint_penalty = parse_penalty(key, value)?,
+            "exon_hint_penalty" => self.exon_hint_penalty = parse_penalty(key, value)?,
+            "cds_hint_penalty" => self.cds_hint_penalty = parse_penalty(key, value)?,
+            "unhinted_splice_penalty" => self.unhinted_splice_penalty = parse_penalty(key, value)?,
+
+            "min_intron_length" => self.min_intron_length = parse_min_intron_length(key, value)?,
+            "min_u12_intron_length" => {
+                self.min_u12_intron_length = parse_min_intron_length(key, value)?
+            }
+            "max_intron_length" => self.max_intron_length = parse_max_intron_length(key, value)?,
+            "intron_length_distribution" => {
+                self.intron_length_distribution = parse_intron_length_distribution(value)?
+            }
+            "intron_mean_length" => self.intron_mean_length = parse_penalty(key, value)?,
+
+            "site_models" => self.site_models = parse_site_models(value)?,
+
+            "phase_retain" => self.phase_retain = parse_probability(key, value)?,
+
+            "class_pred_prob_floor" => self.class_pred_prob_floor = parse_probability(key, value)?,
+            "phase_pred_prob_floor" => self.phase_pred_prob_floor = parse_probability(key, value)?,
+            "pred_prob_floor" => self.pred_prob_floor = parse_probability(key, value)?,
+            "base_prob_floor" => self.base_prob_floor = parse_probability(key, value)?,
+
+            _ => return Err(invalid_data(format!("Unknown HMM config key '{}'", key))),
+        }
+
+        Ok(())
+    }
+
+    /// Sets a single value from a 'key=value' string, as given on the command line
+    pub fn set_key_value(&mut self, key_value: &str) -> Result<()> {
+        match key_value.split_once('=') {
+            Some((key, value)) => self.set_value(key.trim(), value.trim()),
+            None => Err(invalid_data(format!(
+                "Expected key=value for HMM config, found '{}'",
+                key_value
+            ))),
+        }
+    }
+
+    /// All values as (key, value) pairs, in the same format as accepted by set_value
+    pub fn get_values(&self) -> Vec<(String, String)> {
+        let mut values = vec![
+            (
+                "can_splice_utr5".to_string(),
+                self.can_splice_utr5.to_string(),
+            ),
+            (
+                "can_splice_utr5_start".to_string(),
+                self.can_splice_utr5_start.to_string(),
+            ),
+            (
+                "can_splice_start".to_string(),
+                self.can_splice_start.to_string(),
+            ),
+            (
+                "can_splice_start_coding".to_string(),
+                self.can_splice_start_coding.to_string(),
+            ),
+            (
+                "can_splice_coding".to_string(),
+                self.can_splice_coding.to_string(),
+            ),
+            (
+                "can_splice_coding_stop".to_string(),
+                self.can_splice_coding_stop.to_string(),
+            ),
+            (
+                "can_splice_stop".to_string(),
+                self.can_splice_stop.to_string(),
+            ),
+            (
+                "can_splice_stop_utr3".to_string(),
+                self.can_splice_stop_utr3.to_string(),
+            ),
+            (
+                "can_splice_utr3".to_string(),
+                self.can_splice_utr3.to_string(),
+            ),
+            ("start_weight".to_string(), self.start_weight.to_string()),
+            ("stop_weight".to_string(), self.stop_weight.to_string()),
+            ("donor_weight".to_string(), self.donor_weight.to_string()),
+            (
+                "acceptor_weight".to_string(),
+                self.acceptor_weight.to_string(),
+            ),
+            (
+                "donor_u2_gt_ag_fixed_penalty".to_string(),
+                self.donor_u2_gt_ag_fixed_penalty.to_string(),
+            ),
+            (
+                "donor_u2_gc_ag_fixed_penalty".to_string(),
+                self.donor_u2_gc_ag_fixed_penalty.to_string(),
+            ),
+            (
+                "donor_u12_at_ac_fixed_penalty".to_string(),
+                self.donor_u12_at_ac_fixed_penalty.to_string(),
+            ),
+            ("solver".to_string(), self.solver.as_str().to_string()),
+            (
+                "posterior_scores".to_string(),
+                self.posterior_scores.to_string(),
+            ),
+            (
+                "max_splice_ban_isoforms".to_string(),
+                self.max_splice_ban_isoforms.to_string(),
+            ),
+            (
+                "splice_ban_penalty_margin".to_string(),
+                self.splice_ban_penalty_margin.to_string(),
+            ),
+            (
+                "genetic_code".to_string(),
+                self.genetic_code.get_id().to_string(),
+            ),
+            (
+                "alternative_starts".to_string(),
+                self.alternative_starts.to_string(),
+            ),
+            ("partial_genes".to_string(), self.partial_genes.to_string()),
+            (
+                "partial_gene_penalty".to_string(),
+                self.partial_gene_penalty.to_string(),
+            ),
+            (
+                "intron_hint_penalty".to_string(),
+                self.intron_hint_penalty.to_string(),
+            ),
+            (
+                "exon_hint_penalty".to_string(),
+                self.exon_hint_penalty.to_string(),
+            ),
+            (
+                "cds_hint_penalty".to_string(),
+                self.cds_hint_penalty.to_string(),
+            ),
+            (
+                "unhinted_splice_penalty".to_string(),
+                self.unhinted_splice_penalty.to_string(),
+            ),
+            (
+                "min_intron_length".to_string(),
+                self.min_intron_length.to_string(),
+            ),
+            (
+                "min_u12_intron_length".to_string(),
+                self.min_u12_intron_length.to_string(),
+            ),
+            (
+                "max_intron_length".to_string(),
+                self.max_intron_length.to_string(),
+            ),
+            (
+                "intron_length_distribution".to_string(),
+                self.intron_length_distribution.as_str().to_string(),
+            ),
+            (
+                "intron_mean_length".to_string(),
+                self.intron_mean_length.to_string(),
+            ),
+            (
+                "site_models".to_string(),
+                self.site_models
+                    .as_ref()
+                    .map_or("none", |site_models| site_models.get_path())
+                    .to_string(),
+            ),
+            ("phase_retain".to_string(), self.phase_retain.to_string()),
+            (
+                "class_pred_prob_floor".to_string(),
+                self.class_pred_prob_floor.to_string(),
+            ),
+            (
+                "phase_pred_prob_floor".to_string(),
+                self.phase_pred_prob_floor.to_string(),
+            ),
+            (
+                "pred_prob_floor".to_string(),
+                self.pred_prob_floor.to_string(),
+            ),
+            (
+                "base_prob_floor".to_string(),
+                self.base_prob_floor.to_string(),
+            ),
+        ];
+
+        for (sequence, genetic_code) in self.sequence_genetic_codes.iter() {
+            values.push((
+                format!("genetic_code.{}", sequence),
+                genetic_code.get_id().to_string(),
+            ));
+        }
+
+        values
+    }
+
+    /// Penalty (-log2 probability) for an intron of the given length, from the intron length distribution
+    ///
+    /// # Arguments
+    ///
+    /// * `length` - The intron length, including the donor and acceptor sites
+    /// * `min_length` - The minimum length for this type of intron
+    pub(super) fn get_intron_length_penalty(&self, length: usize, min_length: usize) -> f64 {
+        match &self.intron_length_distribution {
+            IntronLengthDistribution::None => 0.0,
+            IntronLengthDistribution::Geometric => {
+                // Chance of ending at each base beyond the minimum, at least 1 in 2
+                let end_prob = 1.0 / (self.intron_mean_length + 1.0 - min_length as f64).max(2.0);
+                let extra_length = length.saturating_sub(min_length) as f64;
+
+                -end_prob.log2() - extra_length * (1.0 - end_prob).log2()
+            }
+            IntronLengthDistribution::Table(table) => table.get_penalty(length),
+        }
+    }
+
+    /// Whether introns are limited or penalised by their length, so the solvers must track where each intron started
+    pub(super) fn has_intron_length_model(&self) -> bool {
+        self.max_intron_length > 0
+            || !matches!(
+                self.intron_length_distribution,
+                IntronLengthDistribution::None
+            )
+    }
+
+    /// Difference between the highest and lowest intron length penalties, apart from the steady rise given by
+    /// get_intron_length_penalty_slope
+    pub(super) fn get_intron_length_penalty_range(&self) -> f64 {
+        match &self.intron_length_distribution {
+            IntronLengthDistribution::None | IntronLengthDistribution::Geometric => 0.0,
+            IntronLengthDistribution::Table(table) => table.get_penalty_range(),
+        }
+    }
+
+    /// Rise in the intron length penalty for each base beyond the minimum length, 0 unless geometric
+    pub(super) fn get_intron_length_penalty_slope(&self, min_length: usize) -> f64 {
+        match &self.intron_length_distribution {
+            IntronLengthDistribution::Geometric => {
+                self.get_intron_length_penalty(min_length + 1, min_length)
+                    - self.get_intron_length_penalty(min_length, min_length)
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Intron length penalties as segments of `(start length, constant, slope)`, each applying from its start
+    /// length until the next segment with the penalty `constant + slope * length`
+    pub(super) fn get_intron_length_penalty_segments(
+        &self,
+        min_length: usize,
+    ) -> Vec<(usize, f64, f64)> {
+        match &self.intron_length_distribution {
+            IntronLengthDistribution::None => vec![(0, 0.0, 0.0)],
+            IntronLengthDistribution::Geometric => {
+                // Introns are never shorter than the minimum, so one segment covers every length
+                let at_min = self.get_intron_length_penalty(min_length, min_length);
+                let slope = self.get_intron_length_penalty_slope(min_length);
+                vec![(0, at_min - slope * min_length as f64, slope)]
+            }
+            IntronLengthDistribution::Table(table) => table
+                .get_penalty_runs()
+                .into_iter()
+                .map(|(start_length, penalty)| (start_length, penalty, 0.0))
+                .collect(),
+        }
+    }
+
+    /// Writes all values as a TOML config, which can be loaded again with from_file. Values other than numbers
+    /// and booleans are written as strings
+    pub fn to_toml(&self) -> String {
+        let mut toml = String::new();
+        for (key, value) in self.get_values() {
+            let key = match key.strip_prefix("genetic_code.") {
+                Some(sequence) => format!("genetic_code.{}", to_toml_key(sequence)),
+                None => key,
+            };
+            let value = if value.parse::<f64>().is_ok() || value == "true" || value == "false" {
+                value
+            } else {
+                to_toml_string(&value)
+            };
+            toml.push_str(&format!("{} = {}\n", key, value));
+        }
+        toml
+    }
+}
+
+// Single line of space separated key=value pairs, e.g. for the GFF header
+impl Display for HmmConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let values = self
+            .get_values()
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>();
+
+        f.write_str(&values.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::hmm::config::HmmConfig;
+
+    #[test]
+    fn test_toml_strings_round_trip() {
+        let table_path = std::env::temp_dir().join("helixer_post_test_lengths #1.txt");
+        std::fs::write(&table_path, "20 1\n100 4\n").unwrap();
+
+        let mut config = HmmConfig::new();
+        config
+            .set_value("intron_length_distribution", table_path.to_str().unwrap())
+            .unwrap();
+        config.set_value("genetic_code.chr M.1", "2").unwrap();
+
+        let toml = config.to_toml();
+        assert!(toml.contains("genetic_code.\"chr M.1\" = 2\n"));
+
+        let mut loaded = HmmConfig::new();
+        loaded.update_from_str(&toml).unwrap();
+        assert_eq!(loaded.get_values(), config.get_values());
+    }
+
+    #[test]
+    fn test_json_strings_are_unescaped() {
+        let table_path = std::env::temp_dir().join("helixer_post_test_lengths, \"json\".txt");
+        std::fs::write(&table_path, "20 1\n100 4\n").unwrap();
+        let json_path = table_path
+            .to_str()
+            .unwrap()
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"");
+
+        let mut config = HmmConfig::new();
+        config
+            .update_from_str(&format!(
+                "{{\n  \"intron_length_distribution\": \"{}\",\n  \"genetic_code.chr\\u0020M, 1\": 2,\n  \
+                 \"solver\": \"viterbi\", \"posterior_scores\": true, \"start_weight\": 1e2\n}}",
+                json_path
+            ))
+            .unwrap();
+
+        let mut expected = HmmConfig::new();
+        expected
+            .set_value("intron_length_distribution", table_path.to_str().unwrap())
+            .unwrap();
+        expected.set_value("genetic_code.chr M, 1", "2").unwrap();
+        expected.set_value("solver", "viterbi").unwrap();
+        expected.set_value("posterior_scores", "true").unwrap();
+        expected.set_value("start_weight", "100").unwrap();
+        assert_eq!(config.get_values(), expected.get_values());
+
+        for json in [
+            "{\"solver\": \"viterbi\"",
+            "{\"solver\": \"viterbi\"} {}",
+            "{\"solver\" \"viterbi\"}",
+            "{solver: \"viterbi\"}",
+            "{\"start_weight\": null}",
+            "{\"start_weight\": [10]}",
+            "{\"solver\": \"vit\\Uerbi\"}",
+            "{\"genetic_code.chr\\ud800\": 2}",
+        ] {
+            assert!(
+                config.update_from_str(json).is_err(),
+                "{} was accepted",
+                json
+            );
+        }
+    }
+
+    #[test]
+    fn test_toml_rejects_sections_and_invalid_values() {
+        let mut config = HmmConfig::new();
+        assert!(config.update_from_str("[hmm]\nstart_weight = 10").is_err());
+        assert!(config.update_from_str("solver = \"viterbi").is_err());
+
+        assert!(config
+            .set_value("donor_u12_gt_ag_fixed_penalty", "0")
+            .is_err());
+
+        for value in ["-1", "nan", "inf"] {
+            assert!(config.set_value("donor_weight", value).is_err());
+            assert!(config.set_value("intron_mean_length", value).is_err());
+        }
+
+        config
+            .update_from_str("solver = 'best_first' # comment\nstart_weight = 10 # comment")
+            .unwrap();
+        assert_eq!(config.start_weight, 10.0);
+        assert_eq!(config.solver.as_str(), "best_first");
+    }
+}
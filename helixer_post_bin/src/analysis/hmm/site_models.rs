@@ -0,0 +1,422 @@
+use super::BasesPenalty;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+const MODEL_BASES: &[u8; 4] = b"ACGT"; // Column order in model files
+const MAX_MODEL_ORDER: usize = 4;
+const MODEL_PROB_FLOOR: f64 = 0.000_000_001; // Prevent infinite penalties
+
+fn invalid_data(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Position specific model of the sequence around a site, either a position weight matrix (order 0),
+/// or a low order inhomogeneous Markov model, where each base depends on the preceding bases
+#[derive(Clone)]
+pub struct SiteModel {
+    upstream: usize, // Model positions before the site
+    order: usize,
+    weight: f64,
+    min_distance: usize, // Branch point only, range of distances from the model start to the acceptor
+    max_distance: usize,
+    penalties: Vec<Vec<f64>>, // By position, then context * 4 + base, relative to the best base for the context
+}
+
+impl SiteModel {
+    fn new() -> SiteModel {
+        SiteModel {
+            upstream: 0,
+            order: 0,
+            weight: 1.0,
+            min_distance: 0,
+            max_distance: 0,
+            penalties: Vec::new(),
+        }
+    }
+
+    pub fn get_upstream(&self) -> usize {
+        self.upstream
+    }
+
+    pub fn get_downstream(&self) -> usize {
+        self.penalties.len().saturating_sub(self.upstream)
+    }
+
+    pub fn get_length(&self) -> usize {
+        self.penalties.len()
+    }
+
+    pub fn get_distance_range(&self) -> (usize, usize) {
+        (self.min_distance, self.max_distance)
+    }
+
+    fn set_parameter(&mut self, key: &str, value: &str) -> Result<()> {
+        let parse_usize = |value: &str| {
+            value.parse::<usize>().map_err(|_| {
+                invalid_data(format!(
+                    "Invalid value '{}' for {}, expected a whole number",
+                    value, key
+                ))
+            })
+        };
+
+        match key {
+            "upstream" => self.upstream = parse_usize(value)?,
+            "order" => {
+                self.order = parse_usize(value)?;
+                if self.order > MAX_MODEL_ORDER {
+                    return Err(invalid_data(format!(
+                        "Invalid value '{}' for order, expected at most {}",
+                        value, MAX_MODEL_ORDER
+                    )));
+                }
+            }
+            "weight" => {
+                self.weight = match value.parse::<f64>() {
+                    Ok(weight) if weight >= 0.0 => weight,
+                    _ => {
+                        return Err(invalid_data(format!(
+                            "Invalid value '{}' for weight, expected a number of at least 0",
+                            value
+                        )))
+                    }
+                }
+            }
+            "min_distance" => self.min_distance = parse_usize(value)?,
+            "max_distance" => self.max_distance = parse_usize(value)?,
+            _ => {
+                return Err(invalid_data(format!(
+                    "Unknown site model parameter '{}'",
+                    key
+                )))
+            }
+        }
+
+        Ok(())
+    }
+
+    // Adds a row of probabilities (or counts) for the next position. Each position has 4 values for each context
+    // of up to 'order' preceding bases, fewer at the start of the model
+    fn add_position(&mut self, values: &[f64]) -> Result<()> {
+        let context_length = std::cmp::min(self.penalties.len(), self.order);
+        let expected = 4usize.pow(context_length as u32 + 1);
+
+        if values.len() != expected {
+            return Err(invalid_data(format!(
+                "Expected {} values for position {}, found {}",
+                expected,
+                self.penalties.len() + 1,
+                values.len()
+            )));
+        }
+        if values.iter().any(|value| value.is_nan() || *value < 0.0) {
+            return Err(invalid_data(format!(
+                "Negative or invalid value for position {}",
+                self.penalties.len() + 1
+            )));
+        }
+
+        let mut penalties = Vec::with_capacity(expected);
+        for context_values in values.chunks(4) {
+            let total: f64 = context_values.iter().sum();
+            let neg_log_probs = context_values
+                .iter()
+                .map(|value| {
+                    let prob = if total > 0.0 { value / total } else { 0.25 };
+                    -prob.max(MODEL_PROB_FLOOR).log2()
+                })
+                .collect::<Vec<_>>();
+
+            let best = neg_log_probs.iter().copied().fold(f64::INFINITY, f64::min);
+            penalties.extend(neg_log_probs.iter().map(|nlp| nlp - best));
+        }
+
+        self.penalties.push(penalties);
+        Ok(())
+    }
+
+    /// Weighted penalty for the model against the given bases, which must match the model length. Each base
+    /// is scored as the best combination of the base and model penalties, so uncertain bases (e.g. N) score
+    /// as the model's preferred base. Context is taken from the most likely preceding bases
+    pub fn get_penalty(&self, bases: &[BasesPenalty]) -> f64 {
+        let mut penalty = 0.0;
+        let mut context = 0;
+
+        for (position, (base_pen, model_pen)) in bases.iter().zip(self.penalties.iter()).enumerate()
+        {
+            let context_length = std::cmp::min(position, self.order);
+            let context_index = context % 4usize.pow(context_length as u32);
+            let model_pen = &model_pen[context_index * 4..context_index * 4 + 4];
+
+            let mut best_pen = f64::INFINITY;
+            let mut likely_base = 0;
+            let mut likely_base_pen = f64::INFINITY;
+
+            for (idx, base) in MODEL_BASES.iter().enumerate() {
+                let base_pen = base_pen.get_base(*base);
+                best_pen = best_pen.min(base_pen + model_pen[idx]);
+
+                if base_pen < likely_base_pen {
+                    likely_base = idx;
+                    likely_base_pen = base_pen;
+                }
+            }
+
+            penalty += best_pen;
+            context = context * 4 + likely_base;
+        }
+
+        penalty * self.weight
+    }
+}
+
+/// Site models for splice sites, branch points and translation start context, loaded from a file of
+/// [name] sections, each with 'key = value' parameters followed by one line of values per model position
+#[derive(Clone)]
+pub struct SiteModels {
+    path: String,
+    pub(super) donor: Option<SiteModel>,
+    pub(super) acceptor: Option<SiteModel>,
+    pub(super) u12_donor: Option<SiteModel>,
+    pub(super) u12_acceptor: Option<SiteModel>,
+    pub(super) branch_point: Option<SiteModel>,
+    pub(super) start: Option<SiteModel>,
+}
+
+impl SiteModels {
+    pub fn from_file(path: &Path) -> Result<SiteModels> {
+        let contents = fs::read_to_string(path)?;
+
+        let mut site_models = SiteModels {
+            path: path.display().to_string(),
+            donor: None,
+            acceptor: None,
+            u12_donor: None,
+            u12_acceptor: None,
+            branch_point: None,
+            start: None,
+        };
+
+        site_models.load(&contents).map_err(|err| {
+            invalid_data(format!("{} in site model file {}", err, path.display()))
+        })?;
+
+        Ok(site_models)
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    /// Names of the models which were loaded
+    pub fn get_model_names(&self) -> Vec<&'static str> {
+        [
+            ("donor", &self.donor),
+            ("acceptor", &self.acceptor),
+            ("u12_donor", &self.u12_donor),
+            ("u12_acceptor", &self.u12_acceptor),
+            ("branch_point", &self.branch_point),
+            ("start", &self.start),
+        ]
+        .iter()
+        .filter(|(_, model)| model.is_some())
+        .map(|(name, _)| *name)
+        .collect()
+    }
+
+    fn get_model_slot(&mut self, name: &str) -> Option<&mut Option<SiteModel>> {
+        match name {
+            "donor" => Some(&mut self.donor),
+            "acceptor" => Some(&mut self.acceptor),
+            "u12_donor" => Some(&mut self.u12_donor),
+            "u12_acceptor" => Some(&mut self.u12_acceptor),
+            "branch_point" => Some(&mut self.branch_point),
+            "start" => Some(&mut self.start),
+            _ => None,
+        }
+    }
+
+    fn load(&mut self, contents: &str) -> Result<()> {
+        let mut current: Option<(String, SiteModel)> = None;
+
+        for (idx, line) in contents.lines().enumerate() {
+            let line_no = idx + 1;
+            let line = match line.find('#') {
+                Some(comment_start) => &line[..comment_start],
+                None => line,
+            }
+            .trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                if let Some((name, model)) = current.take() {
+                    self.store_model(&name, model)?;
+                }
+                current = Some((name.trim().to_string(), SiteModel::new()));
+                continue;
+            }
+
+            let (_, model) = current.as_mut().ok_or_else(|| {
+                invalid_data(format!(
+                    "Expected a [model] section header on line {}",
+                    line_no
+                ))
+            })?;
+
+            let result = if let Some((key, value)) = line.split_once('=') {
+                if model.get_length() > 0 {
+                    Err(invalid_data(
+                        "Parameters must come before the model values".to_string(),
+                    ))
+                } else {
+                    model.set_parameter(key.trim(), value.trim())
+                }
+            } else {
+                line.split_whitespace()
+                    .map(|value| value.parse::<f64>())
+                    .collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(|_| invalid_data("Invalid number".to_string()))
+                    .and_then(|values| model.add_position(&values))
+            };
+
+            result.map_err(|err| invalid_data(format!("{} on line {}", err, line_no)))?;
+        }
+
+        if let Some((name, model)) = current.take() {
+            self.store_model(&name, model)?;
+        }
+
+        Ok(())
+    }
+
+    fn store_model(&mut self, name: &str, model: SiteModel) -> Result<()> {
+        if model.get_length() == 0 {
+            return Err(invalid_data(format!("No values for site model [{}]", name)));
+        }
+
+        if name == "branch_point" {
+            if model.min_distance < model.get_length() || model.max_distance < model.min_distance {
+                return Err(invalid_data(format!(
+                    "Site model [{}] needs min_distance of at least the model length, and max_distance of at least min_distance",
+                    name
+                )));
+            }
+        } else if model.upstream > model.get_length() {
+            return Err(invalid_data(format!(
+                "Site model [{}] has upstream longer than the model",
+                name
+            )));
+        }
+
+        match self.get_model_slot(name) {
+            Some(slot) => {
+                *slot = Some(model);
+                Ok(())
+            }
+            None => Err(invalid_data(format!(
+                "Unknown site model [{}], expected donor, acceptor, u12_donor, u12_acceptor, branch_point or start",
+                name
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::hmm::site_models::SiteModels;
+    use crate::analysis::hmm::BasesPenalty;
+
+    // Certain bases, or equally likely bases for N
+    fn get_bases(letters: &[u8]) -> Vec<BasesPenalty> {
+        letters
+            .iter()
+            .map(|letter| {
+                let mut penalty = [10.0; 4];
+                match b"CATG".iter().position(|base| base == letter) {
+                    Some(idx) => penalty[idx] = 0.0,
+                    None => penalty = [0.0; 4],
+                }
+                BasesPenalty { penalty }
+            })
+            .collect()
+    }
+
+    fn load_models(name: &str, contents: &str) -> std::io::Result<SiteModels> {
+        let path = std::env::temp_dir().join(format!("helixer_post_test_{}.txt", name));
+        std::fs::write(&path, contents).unwrap();
+        let result = SiteModels::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_site_model_penalties() {
+        let site_models = load_models(
+            "site_models",
+            "# A donor PWM and a first order start model\n\
+             [donor]\n\
+             upstream = 1\n\
+             weight = 2\n\
+             4 2 1 1\n\
+             0 0 1 0\n\
+             0 0 0 1 # GT\n\
+             \n\
+             [start]\n\
+             order = 1\n\
+             1 1 1 1\n\
+             1 0 0 0  0 1 0 0  0 0 1 0  0 0 0 1\n",
+        )
+        .unwrap();
+        assert_eq!(site_models.get_model_names(), vec!["donor", "start"]);
+
+        let donor = site_models.donor.as_ref().unwrap();
+        assert_eq!(donor.get_upstream(), 1);
+        assert_eq!(donor.get_downstream(), 2);
+
+        // Penalties are -log2 probabilities relative to the most likely base, times the weight
+        assert_eq!(donor.get_penalty(&get_bases(b"AGT")), 0.0);
+        assert!((donor.get_penalty(&get_bases(b"CGT")) - 2.0).abs() < 1e-9);
+        assert!((donor.get_penalty(&get_bases(b"TGT")) - 4.0).abs() < 1e-9);
+        // A mismatch costs no more than treating the base itself as uncertain
+        assert!((donor.get_penalty(&get_bases(b"AAT")) - 20.0).abs() < 1e-9);
+        assert_eq!(donor.get_penalty(&get_bases(b"NGT")), 0.0);
+
+        // Each base of the start model repeats the one before, with N taking the most likely base as context
+        let start = site_models.start.as_ref().unwrap();
+        assert_eq!(start.get_penalty(&get_bases(b"CC")), 0.0);
+        assert_eq!(start.get_penalty(&get_bases(b"NA")), 0.0);
+        assert!((start.get_penalty(&get_bases(b"CA")) - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_site_model_file_errors() {
+        for (contents, message) in [
+            ("4 2 1 1\n", "Expected a [model] section header on line 1"),
+            ("[donor]\n1 1 1\n", "Expected 4 values for position 1, found 3"),
+            ("[donor]\n1 1 1 1\nupstream = 1\n", "Parameters must come before"),
+            ("[donor]\norder = 5\n", "expected at most 4"),
+            ("[donor]\nupstream = 2\n1 1 1 1\n", "upstream longer than the model"),
+            ("[donor]\n", "No values for site model [donor]"),
+            ("[exon]\n1 1 1 1\n", "Unknown site model [exon]"),
+            (
+                "[branch_point]\nmin_distance = 3\nmax_distance = 10\n1 1 1 1\n1 1 1 1\n1 1 1 1\n1 1 1 1\n",
+                "needs min_distance of at least the model length",
+            ),
+        ] {
+            let err = match load_models("invalid_site_models", contents) {
+                Ok(_) => panic!("Loaded invalid site models: {}", contents),
+                Err(err) => err,
+            };
+            assert!(
+                err.to_string().contains(message),
+                "'{}' does not contain '{}'",
+                err,
+                message
+            );
+        }
+    }
+}
@@ -0,0 +1,433 @@
+use crate::analysis::report::{json_number, json_string};
+use crate::results::index::HelixerIndex;
+use crate::results::Sequence;
+use crate::{info, warn};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+// Strand being processed, with its position along the strand and the counts so far
+struct CurrentStrand {
+    sequence: String,
+    rev: bool,
+    length: u64,
+    block_count: usize,
+    position: u64,
+    window_count: usize,
+    gene_count: usize,
+}
+
+/// Reports how far through the genome an annotation run is, from the sequence lengths and block counts of the index.
+/// Progress is logged, and written to the progress file if set, at most once per interval
+pub struct ProgressReporter {
+    strand_bases: u64, // Each strand covers all the sequences
+    strand_count: usize,
+    block_count: usize,
+    sequence_block_counts: Vec<(usize, usize)>, // Forward, reverse blocks by sequence ID
+    done_bases: [u64; 2],                       // Forward, reverse
+    done_strands: usize,
+    done_blocks: usize,
+    window_count: usize,
+    gene_count: usize,
+    current: Option<CurrentStrand>,
+    start_time: Instant,
+    last_report: Instant,
+    interval: Duration,
+    progress_path: Option<PathBuf>,
+}
+
+// e.g. 1.2 Mbp, as the genome totals vary from kbp to Gbp
+fn format_bases(bases: f64) -> String {
+    if bases >= 1e9 {
+        format!("{:.2} Gbp", bases / 1e9)
+    } else if bases >= 1e6 {
+        format!("{:.1} Mbp", bases / 1e6)
+    } else if bases >= 1e3 {
+        format!("{:.1} kbp", bases / 1e3)
+    } else {
+        format!("{:.0} bp", bases)
+    }
+}
+
+// e.g. 1h 02m 03s
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    if seconds >= 3600 {
+        format!(
+            "{}h {:02}m {:02}s",
+            seconds / 3600,
+            (seconds / 60) % 60,
+            seconds % 60
+        )
+    } else if seconds >= 60 {
+        format!("{}m {:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+impl ProgressReporter {
+    pub fn new(
+        index: &HelixerIndex,
+        interval: Duration,
+        progress_path: Option<PathBuf>,
+    ) -> ProgressReporter {
+        let sequences = index.get_all_sequences();
+        let strand_bases = sequences.iter().map(|seq| seq.get_length()).sum();
+        let sequence_block_counts: Vec<(usize, usize)> = index
+            .get_all_block_ids()
+            .iter()
+            .map(|(fwd_block_ids, rev_block_ids)| (fwd_block_ids.len(), rev_block_ids.len()))
+            .collect();
+        let block_count = sequence_block_counts
+            .iter()
+            .map(|(fwd, rev)| fwd + rev)
+            .sum();
+
+        let now = Instant::now();
+
+        ProgressReporter {
+            strand_bases,
+            strand_count: sequences.len() * 2,
+            block_count,
+            sequence_block_counts,
+            done_bases: [0, 0],
+            done_strands: 0,
+            done_blocks: 0,
+            window_count: 0,
+            gene_count: 0,
+            current: None,
+            start_time: now,
+            last_report: now,
+            interval,
+            progress_path,
+        }
+    }
+
+    /// Logs the totals, and writes the initial progress file
+    pub fn start(&mut self) {
+        info!(
+            "Progress: {} sequences ({} per strand, {} blocks)",
+            self.strand_count / 2,
+            format_bases(self.strand_bases as f64),
+            self.block_count
+        );
+        self.write_progress_file("running");
+    }
+
+    pub fn start_strand(&mut self, seq: &Sequence, rev: bool) {
+        let (fwd_block_count, rev_block_count) = self.sequence_block_counts[seq.get_id().inner()];
+
+        self.current = Some(CurrentStrand {
+            sequence: seq.get_name().to_string(),
+            rev,
+            length: seq.get_length(),
+            block_count: if rev {
+                rev_block_count
+            } else {
+                fwd_block_count
+            },
+            position: 0,
+            window_count: 0,
+            gene_count: 0,
+        });
+    }
+
+    /// Updates the position along the current strand, and the windows solved and genes written on it so far,
+    /// reporting if the interval has passed
+    pub fn update(&mut self, position: usize, window_count: usize, gene_count: usize) {
+        if let Some(current) = self.current.as_mut() {
+            current.position = position as u64;
+            current.window_count = window_count;
+            current.gene_count = gene_count;
+        }
+
+        if self.last_report.elapsed() >= self.interval {
+            self.report("running");
+        }
+    }
+
+    /// Marks the current strand as done, with the windows solved and genes written on it
+    pub fn finish_strand(&mut self, window_count: usize, gene_count: usize) {
+        if let Some(current) = self.current.take() {
+            self.done_bases[current.rev as usize] += current.length;
+            self.done_strands += 1;
+            self.done_blocks += current.block_count;
+            self.window_count += window_count;
+            self.gene_count += gene_count;
+        }
+
+        if self.last_report.elapsed() >= self.interval {
+            self.report("running");
+        }
+    }
+
+    /// Reports the final progress
+    pub fn finish(&mut self) {
+        self.report("done");
+    }
+
+    /// Marks the progress file as failed, so monitoring doesn't wait on a run which has stopped
+    pub fn fail(&mut self) {
+        self.write_progress_file("failed");
+    }
+
+    // Bases done on each strand, including the current strand
+    fn get_strand_bases(&self) -> [u64; 2] {
+        let mut strand_bases = self.done_bases;
+        if let Some(current) = self.current.as_ref() {
+            strand_bases[current.rev as usize] += current.position;
+        }
+        strand_bases
+    }
+
+    fn get_counts(&self) -> (usize, usize) {
+        match self.current.as_ref() {
+            Some(current) => (
+                self.window_count + current.window_count,
+                self.gene_count + current.gene_count,
+            ),
+            None => (self.window_count, self.gene_count),
+        }
+    }
+
+    // Bases per second so far, and the estimated seconds remaining once any bases are done
+    fn get_rate(&self, done_bases: u64) -> (f64, Option<f64>) {
+        let elapsed = self.start_time.elapsed().as_secs_f64();
+        if done_bases == 0 || elapsed <= 0.0 {
+            return (0.0, None);
+        }
+
+        let bases_per_second = done_bases as f64 / elapsed;
+        let remaining_bases = (self.strand_bases * 2).saturating_sub(done_bases);
+        (
+            bases_per_second,
+            Some(remaining_bases as f64 / bases_per_second),
+        )
+    }
+
+    fn report(&mut self, state: &str) {
+        self.last_report = Instant::now();
+
+        let strand_bases = self.get_strand_bases();
+        let done_bases = strand_bases[0] + strand_bases[1];
+        let (window_count, gene_count) = self.get_counts();
+        let (bases_per_second, eta) = self.get_rate(done_bases);
+
+        info!(
+            "Progress: {:.1}% (+ {} / {}, - {} / {}), {} windows, {} genes, {}/s, ETA {}",
+            self.get_percent(done_bases),
+            format_bases(strand_bases[0] as f64),
+            format_bases(self.strand_bases as f64),
+            format_bases(strand_bases[1] as f64),
+            format_bases(self.strand_bases as f64),
+            window_count,
+            gene_count,
+            format_bases(bases_per_second),
+            eta.map_or_else(|| "unknown".to_string(), format_duration)
+        );
+
+        self.write_progress_file(state);
+    }
+
+    fn get_percent(&self, done_bases: u64) -> f64 {
+        if self.strand_bases == 0 {
+            100.0
+        } else {
+            done_bases as f64 * 100.0 / (self.strand_bases * 2) as f64
+        }
+    }
+
+    // The file is written alongside and renamed into place, so it's never read part written. It's only for monitoring,
+    // so on any error it's dropped with a warning rather than stopping the run
+    fn write_progress_file(&mut self, state: &str) {
+        let progress_path = match self.progress_path.as_ref() {
+            Some(progress_path) => progress_path,
+            None => return,
+        };
+
+        let mut temp_path = progress_path.clone().into_os_string();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+
+        let result = File::create(&temp_path)
+            .and_then(|file| self.write_json(&mut BufWriter::new(file), state))
+            .and_then(|_| std::fs::rename(&temp_path, progress_path));
+
+        if let Err(err) = result {
+            warn!(
+                "Could not write progress file {}: {} - no longer writing it",
+                progress_path.display(),
+                err
+            );
+            self.progress_path = None;
+        }
+    }
+
+    fn write_json<W: Write>(&self, writer: &mut BufWriter<W>, state: &str) -> std::io::Result<()> {
+        let strand_bases = self.get_strand_bases();
+        let done_bases = strand_bases[0] + strand_bases[1];
+        let (window_count, gene_count) = self.get_counts();
+        let (bases_per_second, eta) = self.get_rate(done_bases);
+
+        writeln!(writer, "{{")?;
+        writeln!(writer, "  \"state\": {},", json_string(Some(state)))?;
+        writeln!(
+            writer,
+            "  \"elapsed_seconds\": {},",
+            json_number(self.start_time.elapsed().as_secs_f64())
+        )?;
+        writeln!(
+            writer,
+            "  \"sequence\": {},",
+            json_string(
+                self.current
+                    .as_ref()
+                    .map(|current| current.sequence.as_str())
+            )
+        )?;
+        writeln!(
+            writer,
+            "  \"strand\": {},",
+            json_string(
+                self.current
+                    .as_ref()
+                    .map(|current| if current.rev { "-" } else { "+" })
+            )
+        )?;
+        writeln!(
+            writer,
+            "  \"percent\": {},",
+            json_number(self.get_percent(done_bases))
+        )?;
+        writeln!(writer, "  \"bases\": {},", done_bases)?;
+        writeln!(writer, "  \"total_bases\": {},", self.strand_bases * 2)?;
+        writeln!(writer, "  \"forward_bases\": {},", strand_bases[0])?;
+        writeln!(writer, "  \"reverse_bases\": {},", strand_bases[1])?;
+        writeln!(writer, "  \"strand_total_bases\": {},", self.strand_bases)?;
+        writeln!(writer, "  \"strands\": {},", self.done_strands)?;
+        writeln!(writer, "  \"total_strands\": {},", self.strand_count)?;
+        writeln!(writer, "  \"blocks\": {},", self.done_blocks)?;
+        writeln!(writer, "  \"total_blocks\": {},", self.block_count)?;
+        writeln!(writer, "  \"windows\": {},", window_count)?;
+        writeln!(writer, "  \"genes\": {},", gene_count)?;
+        writeln!(
+            writer,
+            "  \"bases_per_second\": {},",
+            json_number(bases_per_second)
+        )?;
+        writeln!(
+            writer,
+            "  \"eta_seconds\": {}",
+            eta.map_or_else(|| "null".to_string(), json_number)
+        )?;
+        writeln!(writer, "}}")?;
+
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::progress::{
+        format_bases, format_duration, CurrentStrand, ProgressReporter,
+    };
+    use std::io::BufWriter;
+    use std::time::{Duration, Instant};
+
+    // 1 kbp sequences in 2 blocks per strand, with 100s elapsed
+    fn get_reporter() -> ProgressReporter {
+        let now = Instant::now();
+
+        ProgressReporter {
+            strand_bases: 2000,
+            strand_count: 4,
+            block_count: 8,
+            sequence_block_counts: vec![(2, 2), (2, 2)],
+            done_bases: [0, 0],
+            done_strands: 0,
+            done_blocks: 0,
+            window_count: 0,
+            gene_count: 0,
+            current: None,
+            start_time: now.checked_sub(Duration::from_secs(100)).unwrap(),
+            last_report: now,
+            interval: Duration::from_secs(3600),
+            progress_path: None,
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_bases(999.0), "999 bp");
+        assert_eq!(format_bases(1500.0), "1.5 kbp");
+        assert_eq!(format_bases(2.25e6), "2.2 Mbp");
+        assert_eq!(format_bases(3.1e9), "3.10 Gbp");
+
+        assert_eq!(format_duration(59.4), "59s");
+        assert_eq!(format_duration(61.0), "1m 01s");
+        assert_eq!(format_duration(3723.0), "1h 02m 03s");
+    }
+
+    #[test]
+    fn test_rate_and_eta() {
+        let mut reporter = get_reporter();
+
+        assert_eq!(reporter.get_rate(0), (0.0, None));
+
+        reporter.done_bases = [1000, 0];
+        reporter.done_strands = 1;
+        reporter.current = Some(CurrentStrand {
+            sequence: "chr2".to_string(),
+            rev: false,
+            length: 1000,
+            block_count: 2,
+            position: 500,
+            window_count: 3,
+            gene_count: 1,
+        });
+        reporter.window_count = 4;
+        reporter.gene_count = 2;
+
+        assert_eq!(reporter.get_strand_bases(), [1500, 0]);
+        assert_eq!(reporter.get_counts(), (7, 3));
+        assert_eq!(reporter.get_percent(1500), 37.5);
+
+        // 1500 bases in 100s leaves 2500 bases for about 167s
+        let (bases_per_second, eta) = reporter.get_rate(1500);
+        assert!((bases_per_second - 15.0).abs() < 0.1);
+        assert!((eta.unwrap() - 2500.0 / 15.0).abs() < 1.0);
+
+        reporter.finish_strand(3, 1);
+        assert!(reporter.current.is_none());
+        assert_eq!(reporter.done_bases, [2000, 0]);
+        assert_eq!((reporter.done_strands, reporter.done_blocks), (2, 2));
+        assert_eq!(reporter.get_counts(), (7, 3));
+    }
+
+    #[test]
+    fn test_progress_file() {
+        let progress_path = std::env::temp_dir().join("helixer_post_test_progress.json");
+        let mut reporter = get_reporter();
+        reporter.progress_path = Some(progress_path.clone());
+
+        reporter.fail();
+        let contents = std::fs::read_to_string(&progress_path).unwrap();
+        std::fs::remove_file(&progress_path).unwrap();
+
+        assert!(contents.starts_with("{\n  \"state\": \"failed\",\n"));
+        assert!(contents.contains("  \"sequence\": null,\n  \"strand\": null,\n  \"percent\": 0"));
+        assert!(contents.contains("  \"total_bases\": 4000,\n"));
+        assert!(contents.contains("  \"total_strands\": 4,\n"));
+        assert!(contents.ends_with("  \"eta_seconds\": null\n}\n"));
+
+        let mut writer = BufWriter::new(Vec::new());
+        reporter.done_bases = [2000, 1000];
+        reporter.write_json(&mut writer, "running").unwrap();
+        let contents = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert!(contents.contains("  \"percent\": 75"));
+        assert!(contents.contains("  \"forward_bases\": 2000,\n  \"reverse_bases\": 1000,\n"));
+        assert!(!contents.contains("\"eta_seconds\": null"));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::analysis::extractor::ComparisonIterator;
+use crate::analysis::extractor::ComparisonItem;
 use crate::analysis::hmm::{HmmAnnotationLabel, HmmStateRegion};
 use crate::results::conv::{ClassPrediction, ClassReference, PhasePrediction, PhaseReference};
 
@@ -63,6 +63,10 @@ impl<const N: usize> ConfusionMatrix<N> {
         self.count[ref_idx][pred_idx] += 1;
     }
 
+    pub fn get_count(&self, ref_idx: usize, pred_idx: usize) -> u64 {
+        self.count[ref_idx][pred_idx]
+    }
+
     pub fn get_tp(&self, idx: usize) -> u64 {
         self.count[idx][idx]
     }
@@ -175,13 +179,15 @@ impl PhasePrediction {
     }
 }
 
-pub struct SequenceRater<'a> {
-    comp_iterator: ComparisonIterator<'a>,
+/// Rates the regions of a sequence (or a single window) against the reference and ML predictions given by the
+/// comparison iterator, usually a ComparisonIterator
+pub struct SequenceRater<I: Iterator<Item = ComparisonItem>> {
+    comp_iterator: I,
     annotation: Vec<Annotation>,
 }
 
-impl<'a> SequenceRater<'a> {
-    pub fn new(comp_iterator: ComparisonIterator<'a>, seq_length: usize) -> SequenceRater<'a> {
+impl<I: Iterator<Item = ComparisonItem>> SequenceRater<I> {
+    pub fn new(comp_iterator: I, seq_length: usize) -> SequenceRater<I> {
         let mut annotation = Vec::with_capacity(seq_length);
         annotation.resize(seq_length, Annotation::OutsideWindow);
 
@@ -248,10 +254,16 @@ impl<'a> SequenceRater<'a> {
     }
 
     pub fn calculate_stats(self) -> SequenceRating {
+        self.calculate_stats_with_iterator().0
+    }
+
+    /// As calculate_stats, also returning the comparison iterator so it can be checked for an error which ended it
+    /// early
+    pub fn calculate_stats_with_iterator(mut self) -> (SequenceRating, I) {
         let mut rating = SequenceRating::new();
-        rating.rate(self.comp_iterator, self.annotation);
+        rating.rate(&mut self.comp_iterator, self.annotation);
 
-        rating
+        (rating, self.comp_iterator)
     }
 }
 
@@ -259,6 +271,7 @@ impl<'a> SequenceRater<'a> {
 // Class = Intergenic / UTR / Coding / Intron
 // Phase = NonCoding / Ph0 / Ph1 / Ph2
 
+#[derive(Clone)]
 pub struct SequenceRating {
     ref_ml_class_confusion: ConfusionMatrix<4>,
     ref_ml_phase_confusion: ConfusionMatrix<4>,
@@ -307,7 +320,11 @@ impl SequenceRating {
         self.filtered_count += other.filtered_count;
     }
 
-    fn rate(&mut self, comp_iterator: ComparisonIterator, annotation: Vec<Annotation>) {
+    fn rate<I: Iterator<Item = ComparisonItem>>(
+        &mut self,
+        comp_iterator: &mut I,
+        annotation: Vec<Annotation>,
+    ) {
         for ((class_ref, phase_ref, class_ml, phase_ml), annotation) in
             comp_iterator.zip(annotation.into_iter())
         {
@@ -345,8 +362,89 @@ impl SequenceRating {
         }
     }
 
-    const CLASS_NAMES: [&'static str; 4] = ["Intergenic", "UTR", "Coding", "Intron"];
-    const PHASE_NAMES: [&'static str; 4] = ["Non Coding", "Phase 0", "Phase 1", "Phase 2"];
+    /// Precision, recall and F1 of the HelixerPost coding bases against the reference
+    pub fn get_coding_precision_recall_f1(&self) -> (f64, f64, f64) {
+        self.ref_hp_class_confusion.get_precision_recall_f1(2)
+    }
+
+    pub const CLASS_NAMES: [&'static str; 4] = ["Intergenic", "UTR", "Coding", "Intron"];
+    pub const PHASE_NAMES: [&'static str; 4] = ["Non Coding", "Phase 0", "Phase 1", "Phase 2"];
+
+    /// Reference genic bases outside any window
+    pub fn get_outside_window_count(&self) -> u64 {
+        self.outside_window_count
+    }
+
+    /// Reference genic bases in genes filtered out for their coding length
+    pub fn get_filtered_count(&self) -> u64 {
+        self.filtered_count
+    }
+
+    /// Class and phase confusion matrices for each comparison, in the order dumped. Comparisons against the
+    /// reference are only included if there is one
+    pub fn get_comparisons(
+        &self,
+        has_ref: bool,
+    ) -> Vec<(&'static str, &ConfusionMatrix<4>, &ConfusionMatrix<4>)> {
+        let mut comparisons = Vec::with_capacity(3);
+
+        if has_ref {
+            comparisons.push((
+                "Ref v ML",
+                &self.ref_ml_class_confusion,
+                &self.ref_ml_phase_confusion,
+            ));
+            comparisons.push((
+                "Ref v HP",
+                &self.ref_hp_class_confusion,
+                &self.ref_hp_phase_confusion,
+            ));
+        }
+        comparisons.push((
+            "ML v HP",
+            &self.ml_hp_class_confusion,
+            &self.ml_hp_phase_confusion,
+        ));
+
+        comparisons
+    }
+
+    /// Precision, recall and F1 of the Subgenic (coding and intron), Coding (any phase) and Genic (any but
+    /// intergenic) bases
+    pub fn get_summaries(
+        class_confusion: &ConfusionMatrix<4>,
+        phase_confusion: &ConfusionMatrix<4>,
+    ) -> [(&'static str, (f64, f64, f64)); 3] {
+        let subg_true_pos = class_confusion.get_tp(2) + class_confusion.get_tp(3);
+        let subg_false_pos = class_confusion.get_fp(2) + class_confusion.get_fp(3);
+        let subg_false_neg = class_confusion.get_fn(2) + class_confusion.get_fn(3);
+
+        let gen_true_pos = subg_true_pos + class_confusion.get_tp(1);
+        let gen_false_pos = subg_false_pos + class_confusion.get_fp(1);
+        let gen_false_neg = subg_false_neg + class_confusion.get_fn(1);
+
+        let coding_true_pos =
+            phase_confusion.get_tp(1) + phase_confusion.get_tp(2) + phase_confusion.get_tp(3);
+        let coding_false_pos =
+            phase_confusion.get_fp(1) + phase_confusion.get_fp(2) + phase_confusion.get_fp(3);
+        let coding_false_neg =
+            phase_confusion.get_fn(1) + phase_confusion.get_fn(2) + phase_confusion.get_fn(3);
+
+        [
+            (
+                "Subgenic",
+                calc_precision_recall_f1(subg_true_pos, subg_false_pos, subg_false_neg),
+            ),
+            (
+                "Coding",
+                calc_precision_recall_f1(coding_true_pos, coding_false_pos, coding_false_neg),
+            ),
+            (
+                "Genic",
+                calc_precision_recall_f1(gen_true_pos, gen_false_pos, gen_false_neg),
+            ),
+        ]
+    }
 
     pub fn show_confusion_matrices(
         class_confusion: &ConfusionMatrix<4>,
@@ -426,29 +524,8 @@ impl SequenceRating {
 
         println!();
 
-        let subg_true_pos = class_confusion.get_tp(2) + class_confusion.get_tp(3);
-        let subg_false_pos = class_confusion.get_fp(2) + class_confusion.get_fp(3);
-        let subg_false_neg = class_confusion.get_fn(2) + class_confusion.get_fn(3);
-
-        let (subg_prec, subg_rec, subg_f1) =
-            calc_precision_recall_f1(subg_true_pos, subg_false_pos, subg_false_neg);
-
-        let gen_true_pos = subg_true_pos + class_confusion.get_tp(1);
-        let gen_false_pos = subg_false_pos + class_confusion.get_fp(1);
-        let gen_false_neg = subg_false_neg + class_confusion.get_fn(1);
-
-        let (gen_prec, gen_rec, gen_f1) =
-            calc_precision_recall_f1(gen_true_pos, gen_false_pos, gen_false_neg);
-
-        let coding_true_pos =
-            phase_confusion.get_tp(1) + phase_confusion.get_tp(2) + phase_confusion.get_tp(3);
-        let coding_false_pos =
-            phase_confusion.get_fp(1) + phase_confusion.get_fp(2) + phase_confusion.get_fp(3);
-        let coding_false_neg =
-            phase_confusion.get_fn(1) + phase_confusion.get_fn(2) + phase_confusion.get_fn(3);
-
-        let (coding_prec, coding_rec, coding_f1) =
-            calc_precision_recall_f1(coding_true_pos, coding_false_pos, coding_false_neg);
+        let [(_, (subg_prec, subg_rec, subg_f1)), (_, (coding_prec, coding_rec, coding_f1)), (_, (gen_prec, gen_rec, gen_f1))] =
+            Self::get_summaries(class_confusion, phase_confusion);
 
         print!(
             "{:>16}\t{:12.5}\t{:12.5}\t{:12.5}\t{:>12}\t",
@@ -476,23 +553,11 @@ impl SequenceRating {
                 self.outside_window_count, self.filtered_count
             );
             println!();
+        }
 
-            Self::show_confusion_matrices(
-                &self.ref_ml_class_confusion,
-                &self.ref_ml_phase_confusion,
-                "Ref v ML",
-            );
-            Self::show_confusion_matrices(
-                &self.ref_hp_class_confusion,
-                &self.ref_hp_phase_confusion,
-                "Ref v HP",
-            );
+        for (corner_label, class_confusion, phase_confusion) in self.get_comparisons(has_ref) {
+            Self::show_confusion_matrices(class_confusion, phase_confusion, corner_label);
         }
-        Self::show_confusion_matrices(
-            &self.ml_hp_class_confusion,
-            &self.ml_hp_phase_confusion,
-            "ML v HP",
-        );
     }
 }
 
@@ -0,0 +1,216 @@
+use crate::analysis::gene_model::{load_gff_models, TranscriptModel};
+use crate::gff::GffStrand;
+use crate::results::conv::{ClassReference, PhaseReference};
+use std::collections::HashMap;
+use std::io::Result;
+use std::path::Path;
+
+// Class reference indices, see ClassReference
+const REF_UTR: u8 = 1;
+const REF_CODING: u8 = 2;
+const REF_INTRON: u8 = 3;
+
+// Per base labels pack the class index into the low 2 bits and the phase index (0 non coding, 1 - 3 for phase
+// 0 - 2) into the next 2. Where transcripts overlap the base takes the highest ranked class
+fn get_class_rank(label: u8) -> u8 {
+    match label & 3 {
+        REF_CODING => 3,
+        REF_UTR => 2,
+        REF_INTRON => 1,
+        _ => 0,
+    }
+}
+
+fn set_label(labels: &mut [u8], start: u64, end: u64, label: u8) {
+    for existing in labels[start as usize..end as usize].iter_mut() {
+        if get_class_rank(label) > get_class_rank(*existing) {
+            *existing = label;
+        }
+    }
+}
+
+/// A reference annotation from a GFF3 / GTF file, projected onto the per base class and phase encoding of data/y and
+/// data/phases, so sequences without a reference in the genome file can be rated. Exons outside the coding span are
+/// UTR and the bases between exons intron, while coding bases take their phase from the 5' CDS record. Transcripts
+/// without a coding span are left out, as Helixer only annotates protein coding genes
+pub struct ReferenceAnnotation {
+    transcripts: HashMap<String, Vec<TranscriptModel>>, // By sequence
+}
+
+impl ReferenceAnnotation {
+    pub fn new(models: Vec<TranscriptModel>) -> ReferenceAnnotation {
+        let mut transcripts: HashMap<String, Vec<TranscriptModel>> = HashMap::new();
+
+        for model in models
+            .into_iter()
+            .filter(|model| model.get_coding().is_some())
+        {
+            transcripts
+                .entry(model.get_sequence().to_string())
+                .or_default()
+                .push(model);
+        }
+
+        ReferenceAnnotation { transcripts }
+    }
+
+    pub fn load_file(path: &Path) -> Result<ReferenceAnnotation> {
+        Ok(ReferenceAnnotation::new(load_gff_models(path)?))
+    }
+
+    pub fn get_transcript_count(&self) -> usize {
+        self.transcripts.values().map(|models| models.len()).sum()
+    }
+
+    /// Packed per base labels of one strand of a sequence, in the strand's orientation (from the sequence end for the
+    /// reverse strand). Transcripts beyond the sequence length are ignored
+    pub fn get_labels(&self, sequence: &str, rev: bool, sequence_length: u64) -> Vec<u8> {
+        let mut labels = vec![0; sequence_length as usize];
+        let strand = GffStrand::from_rev(rev);
+
+        let models = self
+            .transcripts
+            .get(sequence)
+            .map_or(&[][..], |models| models.as_slice())
+            .iter()
+            .filter(|model| model.get_strand() == strand && model.get_end() <= sequence_length);
+
+        for model in models {
+            for (start, end) in model.get_introns() {
+                set_label(&mut labels, start - 1, end, REF_INTRON);
+            }
+
+            let (coding_start, coding_end) = model.get_coding().unwrap();
+
+            // Coding bases of each exon, from the 5' end, so the phase can be carried across introns
+            let mut coding_ranges = Vec::new();
+
+            for &(start, end) in model.get_exons() {
+                set_label(&mut labels, start - 1, end, REF_UTR);
+
+                let start = std::cmp::max(start, coding_start);
+                let end = std::cmp::min(end, coding_end);
+                if start <= end {
+                    coding_ranges.push((start, end));
+                }
+            }
+
+            if rev {
+                coding_ranges.reverse();
+            }
+
+            let mut phase = model.get_coding_phase();
+
+            for (start, end) in coding_ranges {
+                // Phase counts down through each codon, from 0 on its first base
+                for offset in 0..=end - start {
+                    let position = if rev {
+                        end - 1 - offset
+                    } else {
+                        start - 1 + offset
+                    };
+                    set_label(
+                        &mut labels,
+                        position,
+                        position + 1,
+                        REF_CODING | ((phase + 1) << 2),
+                    );
+                    phase = (phase + 2) % 3;
+                }
+            }
+        }
+
+        if rev {
+            labels.reverse();
+        }
+
+        labels
+    }
+}
+
+/// Class and phase reference for a packed label from ReferenceAnnotation::get_labels
+pub fn get_reference_from_label(label: u8) -> (ClassReference, PhaseReference) {
+    (
+        ClassReference::from_idx((label & 3) as usize),
+        PhaseReference::from_idx((label >> 2) as usize),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::gene_model::load_gff_models;
+    use crate::analysis::reference::{get_reference_from_label, ReferenceAnnotation};
+
+    #[test]
+    fn test_labels_carry_phase_across_introns() {
+        let path = std::env::temp_dir().join("helixer_post_test_reference.gff");
+        std::fs::write(
+            &path,
+            [
+                "chr1\t.\texon\t2\t4\t.\t+\t.\tParent=t1",
+                "chr1\t.\texon\t7\t10\t.\t+\t.\tParent=t1",
+                "chr1\t.\tCDS\t3\t4\t.\t+\t0\tParent=t1",
+                "chr1\t.\tCDS\t7\t9\t.\t+\t1\tParent=t1",
+                "chr1\t.\tfive_prime_UTR\t5\t6\t.\t-\t.\tParent=t2",
+                "chr1\t.\tCDS\t2\t4\t.\t-\t1\tParent=t2",
+                "chr1\t.\texon\t20\t30\t.\t+\t.\tParent=t3",
+            ]
+            .join("\n"),
+        )
+        .unwrap();
+        let reference = ReferenceAnnotation::new(load_gff_models(&path).unwrap());
+
+        // Non coding transcripts are left out
+        assert_eq!(reference.get_transcript_count(), 2);
+
+        // UTR 1, coding 2 with phase index << 2, intron 3. Phase counts down from 0 on the first base of a codon,
+        // continuing after the intron
+        let (utr, intron) = (1, 3);
+        let coding = |phase: u8| 2 | ((phase + 1) << 2);
+        assert_eq!(
+            reference.get_labels("chr1", false, 12),
+            vec![
+                0,
+                utr,
+                coding(0),
+                coding(2),
+                intron,
+                intron,
+                coding(1),
+                coding(0),
+                coding(2),
+                utr,
+                0,
+                0
+            ]
+        );
+
+        // Reverse strand labels run from the sequence end, with the phase from the 5' CDS end
+        assert_eq!(
+            reference.get_labels("chr1", true, 12),
+            vec![
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                utr,
+                utr,
+                coding(1),
+                coding(0),
+                coding(2),
+                0
+            ]
+        );
+
+        assert!(reference
+            .get_labels("chr2", false, 5)
+            .iter()
+            .all(|label| *label == 0));
+
+        let (class_ref, phase_ref) = get_reference_from_label(coding(2));
+        assert_eq!(class_ref.get_max_idx(), 2);
+        assert_eq!(phase_ref.get_max_idx(), 3);
+    }
+}
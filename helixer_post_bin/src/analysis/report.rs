@@ -0,0 +1,477 @@
+use crate::analysis::rater::{ConfusionMatrix, SequenceRating};
+use std::io::{BufWriter, Write};
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum ReportFormat {
+    Json,
+    Tsv,
+}
+
+impl ReportFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReportFormat::Json => "json",
+            ReportFormat::Tsv => "tsv",
+        }
+    }
+
+    pub fn from_name(format: &str) -> Option<ReportFormat> {
+        match format.to_ascii_lowercase().as_str() {
+            "json" => Some(ReportFormat::Json),
+            "tsv" => Some(ReportFormat::Tsv),
+            _ => None,
+        }
+    }
+
+    /// JSON for a .json file, otherwise TSV
+    pub fn from_path(path: &str) -> ReportFormat {
+        if path.to_ascii_lowercase().ends_with(".json") {
+            ReportFormat::Json
+        } else {
+            ReportFormat::Tsv
+        }
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum ReportLevel {
+    Sequence,
+    Species,
+    Total,
+}
+
+impl ReportLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReportLevel::Sequence => "sequence",
+            ReportLevel::Species => "species",
+            ReportLevel::Total => "total",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum ReportStrand {
+    Forward,
+    Reverse,
+    Both,
+}
+
+impl ReportStrand {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReportStrand::Forward => "+",
+            ReportStrand::Reverse => "-",
+            ReportStrand::Both => ".",
+        }
+    }
+}
+
+struct ReportEntry {
+    level: ReportLevel,
+    species: Option<String>,
+    sequence: Option<String>,
+    strand: ReportStrand,
+    rating: SequenceRating,
+}
+
+// JSON numbers can't be NaN (precision / recall / F1 with no bases), so those are null
+pub fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+pub fn json_string(value: Option<&str>) -> String {
+    match value {
+        Some(value) => {
+            let mut escaped = String::with_capacity(value.len() + 2);
+            escaped.push('"');
+            for c in value.chars() {
+                match c {
+                    '"' => escaped.push_str("\\\""),
+                    '\\' => escaped.push_str("\\\\"),
+                    c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+                    c => escaped.push(c),
+                }
+            }
+            escaped.push('"');
+            escaped
+        }
+        None => "null".to_string(),
+    }
+}
+
+fn json_list<T, F: Fn(&T) -> String>(values: &[T], to_json: F) -> String {
+    let values: Vec<String> = values.iter().map(to_json).collect();
+    format!("[{}]", values.join(", "))
+}
+
+// Precision, recall and F1 for each label of a confusion matrix
+fn get_label_stats(confusion: &ConfusionMatrix<4>) -> Vec<(f64, f64, f64)> {
+    (0..4)
+        .map(|idx| confusion.get_precision_recall_f1(idx))
+        .collect()
+}
+
+/// Collects the base level ratings printed for each sequence, species and the total, to write as a single JSON or TSV
+/// report. Each rating holds every confusion matrix (rows reference, columns prediction), with precision, recall and F1
+/// for each class and phase, the Subgenic / Coding / Genic summaries and, with a reference, the lost reference genic
+/// bases. The TSV has one value per line, as level, species, sequence, strand, comparison, statistic, label and value,
+/// with '.' for any not applicable
+pub struct StatsReport {
+    has_ref: bool,
+    entries: Vec<ReportEntry>,
+}
+
+impl StatsReport {
+    pub fn new(has_ref: bool) -> StatsReport {
+        StatsReport {
+            has_ref,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn add_rating(
+        &mut self,
+        level: ReportLevel,
+        species: Option<&str>,
+        sequence: Option<&str>,
+        strand: ReportStrand,
+        rating: &SequenceRating,
+    ) {
+        self.entries.push(ReportEntry {
+            level,
+            species: species.map(|species| species.to_string()),
+            sequence: sequence.map(|sequence| sequence.to_string()),
+            strand,
+            rating: rating.clone(),
+        });
+    }
+
+    pub fn write<W: Write>(
+        &self,
+        writer: &mut BufWriter<W>,
+        format: ReportFormat,
+    ) -> std::io::Result<()> {
+        match format {
+            ReportFormat::Json => self.write_json(writer)?,
+            ReportFormat::Tsv => self.write_tsv(writer)?,
+        }
+
+        writer.flush()
+    }
+
+    fn write_json<W: Write>(&self, writer: &mut BufWriter<W>) -> std::io::Result<()> {
+        writeln!(writer, "{{")?;
+        writeln!(writer, "  \"has_ref\": {},", self.has_ref)?;
+        writeln!(
+            writer,
+            "  \"class_labels\": {},",
+            json_list(&SequenceRating::CLASS_NAMES, |name| json_string(Some(name)))
+        )?;
+        writeln!(
+            writer,
+            "  \"phase_labels\": {},",
+            json_list(&SequenceRating::PHASE_NAMES, |name| json_string(Some(name)))
+        )?;
+        writeln!(writer, "  \"ratings\": [")?;
+
+        for (entry_idx, entry) in self.entries.iter().enumerate() {
+            writeln!(writer, "    {{")?;
+            writeln!(writer, "      \"level\": \"{}\",", entry.level.as_str())?;
+            writeln!(
+                writer,
+                "      \"species\": {},",
+                json_string(entry.species.as_deref())
+            )?;
+            writeln!(
+                writer,
+                "      \"sequence\": {},",
+                json_string(entry.sequence.as_deref())
+            )?;
+            writeln!(writer, "      \"strand\": \"{}\",", entry.strand.as_str())?;
+
+            if self.has_ref {
+                writeln!(
+                    writer,
+                    "      \"lost_ref_genic\": {{\"outside_window\": {}, \"filtered\": {}}},",
+                    entry.rating.get_outside_window_count(),
+                    entry.rating.get_filtered_count()
+                )?;
+            }
+
+            writeln!(writer, "      \"comparisons\": [")?;
+
+            let comparisons = entry.rating.get_comparisons(self.has_ref);
+            for (comparison_idx, (name, class_confusion, phase_confusion)) in
+                comparisons.iter().enumerate()
+            {
+                writeln!(writer, "        {{")?;
+                writeln!(writer, "          \"name\": {},", json_string(Some(name)))?;
+
+                for (matrix_name, confusion) in
+                    [("class", class_confusion), ("phase", phase_confusion)]
+                {
+                    let counts: Vec<Vec<u64>> = (0..4)
+                        .map(|r| (0..4).map(|p| confusion.get_count(r, p)).collect())
+                        .collect();
+                    let label_stats = get_label_stats(confusion);
+
+                    writeln!(writer, "          \"{}\": {{", matrix_name)?;
+                    writeln!(
+                        writer,
+                        "            \"confusion\": {},",
+                        json_list(&counts, |row| json_list(row, |count| count.to_string()))
+                    )?;
+                    writeln!(
+                        writer,
+                        "            \"precision\": {},",
+                        json_list(&label_stats, |(precision, _, _)| json_number(*precision))
+                    )?;
+                    writeln!(
+                        writer,
+                        "            \"recall\": {},",
+                        json_list(&label_stats, |(_, recall, _)| json_number(*recall))
+                    )?;
+                    writeln!(
+                        writer,
+                        "            \"f1\": {}",
+                        json_list(&label_stats, |(_, _, f1)| json_number(*f1))
+                    )?;
+                    writeln!(writer, "          }},")?;
+                }
+
+                let summaries: Vec<String> =
+                    SequenceRating::get_summaries(class_confusion, phase_confusion)
+                        .iter()
+                        .map(|(summary_name, (precision, recall, f1))| {
+                            format!(
+                                "\"{}\": {{\"precision\": {}, \"recall\": {}, \"f1\": {}}}",
+                                summary_name.to_ascii_lowercase(),
+                                json_number(*precision),
+                                json_number(*recall),
+                                json_number(*f1)
+                            )
+                        })
+                        .collect();
+                writeln!(
+                    writer,
+                    "          \"summary\": {{{}}}",
+                    summaries.join(", ")
+                )?;
+
+                let separator = if comparison_idx + 1 < comparisons.len() {
+                    ","
+                } else {
+                    ""
+                };
+                writeln!(writer, "        }}{}", separator)?;
+            }
+
+            writeln!(writer, "      ]")?;
+            let separator = if entry_idx + 1 < self.entries.len() {
+                ","
+            } else {
+                ""
+            };
+            writeln!(writer, "    }}{}", separator)?;
+        }
+
+        writeln!(writer, "  ]")?;
+        writeln!(writer, "}}")
+    }
+
+    fn write_tsv<W: Write>(&self, writer: &mut BufWriter<W>) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "level\tspecies\tsequence\tstrand\tcomparison\tstatistic\tlabel\tvalue"
+        )?;
+
+        for entry in self.entries.iter() {
+            let prefix = format!(
+                "{}\t{}\t{}\t{}",
+                entry.level.as_str(),
+                entry.species.as_deref().unwrap_or("."),
+                entry.sequence.as_deref().unwrap_or("."),
+                entry.strand.as_str()
+            );
+
+            if self.has_ref {
+                writeln!(
+                    writer,
+                    "{}\t.\tlost_ref_genic\tOutside Window\t{}",
+                    prefix,
+                    entry.rating.get_outside_window_count()
+                )?;
+                writeln!(
+                    writer,
+                    "{}\t.\tlost_ref_genic\tFiltered\t{}",
+                    prefix,
+                    entry.rating.get_filtered_count()
+                )?;
+            }
+
+            for (name, class_confusion, phase_confusion) in
+                entry.rating.get_comparisons(self.has_ref)
+            {
+                for (matrix_name, confusion, labels) in [
+                    ("class", class_confusion, &SequenceRating::CLASS_NAMES),
+                    ("phase", phase_confusion, &SequenceRating::PHASE_NAMES),
+                ] {
+                    // Confusion counts are labelled reference / prediction
+                    for (r, ref_label) in labels.iter().enumerate() {
+                        for (p, pred_label) in labels.iter().enumerate() {
+                            writeln!(
+                                writer,
+                                "{}\t{}\t{}_count\t{}/{}\t{}",
+                                prefix,
+                                name,
+                                matrix_name,
+                                ref_label,
+                                pred_label,
+                                confusion.get_count(r, p)
+                            )?;
+                        }
+                    }
+
+                    for (label, (precision, recall, f1)) in
+                        labels.iter().zip(get_label_stats(confusion))
+                    {
+                        for (statistic, value) in
+                            [("precision", precision), ("recall", recall), ("f1", f1)]
+                        {
+                            writeln!(
+                                writer,
+                                "{}\t{}\t{}_{}\t{}\t{}",
+                                prefix, name, matrix_name, statistic, label, value
+                            )?;
+                        }
+                    }
+                }
+
+                for (summary_name, (precision, recall, f1)) in
+                    SequenceRating::get_summaries(class_confusion, phase_confusion)
+                {
+                    for (statistic, value) in
+                        [("precision", precision), ("recall", recall), ("f1", f1)]
+                    {
+                        writeln!(
+                            writer,
+                            "{}\t{}\tsummary_{}\t{}\t{}",
+                            prefix, name, statistic, summary_name, value
+                        )?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::extractor::ComparisonItem;
+    use crate::analysis::rater::{SequenceRater, SequenceRating};
+    use crate::analysis::report::{ReportFormat, ReportLevel, ReportStrand, StatsReport};
+    use crate::results::conv::{
+        ArrayConvFrom, ClassPrediction, ClassReference, PhasePrediction, PhaseReference,
+    };
+    use ndarray::arr1;
+    use std::io::BufWriter;
+
+    // Rates bases with the given reference and ML classes, all outside any window
+    fn get_rating(ref_classes: &[usize], ml_classes: &[usize]) -> SequenceRating {
+        let one_hot = |idx: usize| {
+            let mut values = [0.0; 4];
+            values[idx] = 1.0;
+            arr1(&values)
+        };
+
+        let items: Vec<ComparisonItem> = ref_classes
+            .iter()
+            .zip(ml_classes)
+            .map(|(ref_class, ml_class)| {
+                (
+                    ClassReference::from_idx(*ref_class),
+                    PhaseReference::from_idx(0),
+                    <ClassPrediction as ArrayConvFrom<f32>>::from(one_hot(*ml_class).view()),
+                    <PhasePrediction as ArrayConvFrom<f32>>::from(one_hot(0).view()),
+                )
+            })
+            .collect();
+
+        SequenceRater::new(items.into_iter(), ref_classes.len()).calculate_stats()
+    }
+
+    fn write_report(format: ReportFormat) -> String {
+        let mut report = StatsReport::new(true);
+        report.add_rating(
+            ReportLevel::Sequence,
+            Some("Sp \"1\""),
+            Some("chr1"),
+            ReportStrand::Forward,
+            &get_rating(&[0, 2, 2, 3], &[0, 2, 3, 3]),
+        );
+        report.add_rating(
+            ReportLevel::Total,
+            None,
+            None,
+            ReportStrand::Both,
+            &SequenceRating::new(),
+        );
+
+        let mut writer = BufWriter::new(Vec::new());
+        report.write(&mut writer, format).unwrap();
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_json_report() {
+        let json = write_report(ReportFormat::Json);
+
+        for expected in [
+            "      \"level\": \"sequence\",\n      \"species\": \"Sp \\\"1\\\"\",\n      \
+             \"sequence\": \"chr1\",\n      \"strand\": \"+\",\n",
+            "      \"lost_ref_genic\": {\"outside_window\": 3, \"filtered\": 0},\n",
+            // Ref v ML class confusion, rows reference and columns prediction
+            "            \"confusion\": [[1, 0, 0, 0], [0, 0, 0, 0], [0, 0, 1, 1], [0, 0, 0, 1]],\n\
+             \x20           \"precision\": [1, null, 1, 0.5],\n\
+             \x20           \"recall\": [1, null, 0.5, 1],\n",
+            "      \"level\": \"total\",\n      \"species\": null,\n      \"sequence\": null,\n      \
+             \"strand\": \".\",\n",
+        ] {
+            assert!(json.contains(expected), "Missing {} in {}", expected, json);
+        }
+
+        // Undefined statistics of the empty total are null, never NaN
+        assert!(!json.contains("NaN"));
+        assert_eq!(json.matches('{').count(), json.matches('}').count());
+        assert_eq!(json.matches('[').count(), json.matches(']').count());
+        assert!(json.ends_with("    }\n  ]\n}\n"));
+    }
+
+    #[test]
+    fn test_tsv_report() {
+        let tsv = write_report(ReportFormat::Tsv);
+        let lines: Vec<&str> = tsv.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            "level\tspecies\tsequence\tstrand\tcomparison\tstatistic\tlabel\tvalue"
+        );
+        assert!(lines.iter().all(|line| line.split('\t').count() == 8));
+
+        for expected in [
+            "sequence\tSp \"1\"\tchr1\t+\t.\tlost_ref_genic\tOutside Window\t3",
+            "sequence\tSp \"1\"\tchr1\t+\tRef v ML\tclass_count\tCoding/Intron\t1",
+            "sequence\tSp \"1\"\tchr1\t+\tRef v ML\tclass_recall\tCoding\t0.5",
+            "total\t.\t.\t.\tML v HP\tclass_precision\tIntergenic\tNaN",
+        ] {
+            assert!(lines.contains(&expected), "Missing {} in {}", expected, tsv);
+        }
+    }
+}
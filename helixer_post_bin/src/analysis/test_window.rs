@@ -0,0 +1,29 @@
+use crate::analysis::WindowBases;
+use crate::results::conv::{ArrayConvFrom, Bases, ClassPrediction, PhasePrediction};
+use ndarray::arr1;
+
+/// A window of one hot bases for the sequence, with the class predictions given for each position and uniform phase
+/// predictions, for solving small test genes
+pub fn get_one_hot_window<F: Fn(usize) -> [f32; 4]>(
+    sequence: &str,
+    class_values: F,
+) -> WindowBases {
+    sequence
+        .bytes()
+        .enumerate()
+        .map(|(position, base)| {
+            let base_values: Vec<f32> = b"CATG"
+                .iter()
+                .map(|letter| if *letter == base { 1.0 } else { 0.0 })
+                .collect();
+
+            (
+                <Bases as ArrayConvFrom<f32>>::from(arr1(&base_values).view()),
+                <ClassPrediction as ArrayConvFrom<f32>>::from(arr1(&class_values(position)).view()),
+                <PhasePrediction as ArrayConvFrom<f32>>::from(
+                    arr1(&[0.25f32, 0.25, 0.25, 0.25]).view(),
+                ),
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,510 @@
+use crate::analysis::extractor::BasePredictionExtractor;
+use crate::analysis::window::BasePredictionWindowMeanIterator;
+use crate::info;
+use crate::results::conv::{ArrayConvInto, ClassPrediction, PhasePrediction};
+use crate::results::HelixerResults;
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum TrackFormat {
+    BedGraph,
+    Wig,
+}
+
+impl TrackFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TrackFormat::BedGraph => "bedgraph",
+            TrackFormat::Wig => "wig",
+        }
+    }
+
+    pub fn from_name(format: &str) -> Option<TrackFormat> {
+        match format.to_ascii_lowercase().as_str() {
+            "bedgraph" => Some(TrackFormat::BedGraph),
+            "wig" => Some(TrackFormat::Wig),
+            _ => None,
+        }
+    }
+}
+
+const TRACK_COUNT: usize = 8;
+
+// Track names and descriptions, in the order of the values from get_track_values
+const TRACKS: [(&str, &str); TRACK_COUNT] = [
+    ("intergenic", "Helixer intergenic probability"),
+    ("utr", "Helixer UTR probability"),
+    ("coding", "Helixer coding probability"),
+    ("intron", "Helixer intron probability"),
+    ("phase0", "Helixer phase 0 probability"),
+    ("phase1", "Helixer phase 1 probability"),
+    ("phase2", "Helixer phase 2 probability"),
+    (
+        "genic_mean",
+        "Mean genic probability of the window starting at each base",
+    ),
+];
+
+fn get_track_values(
+    class_pred: &ClassPrediction,
+    phase_pred: &PhasePrediction,
+    window_mean: Option<f32>,
+) -> [Option<f32>; TRACK_COUNT] {
+    [
+        Some(class_pred.get_intergenic()),
+        Some(class_pred.get_utr()),
+        Some(class_pred.get_coding()),
+        Some(class_pred.get_intron()),
+        Some(phase_pred.get_phase0()),
+        Some(phase_pred.get_phase1()),
+        Some(phase_pred.get_phase2()),
+        window_mean,
+    ]
+}
+
+// Run length encoder for the values of a track along one strand, with values rounded so that near constant
+// stretches form a single run. Runs are handed on as soon as they close, so only the open run is held
+struct TrackRuns {
+    run: Option<(u64, u64, f32)>, // Start, end (exclusive) and value, in strand coordinates
+}
+
+impl TrackRuns {
+    fn new() -> TrackRuns {
+        TrackRuns { run: None }
+    }
+
+    // Adds the value for the next position, None leaves a gap. Returns the run closed by this position, if any
+    fn push(&mut self, position: u64, value: Option<f32>) -> Option<(u64, u64, f32)> {
+        if let (Some(run), Some(value)) = (self.run.as_mut(), value) {
+            if run.1 == position && run.2 == value {
+                run.1 += 1;
+                return None;
+            }
+        }
+
+        std::mem::replace(
+            &mut self.run,
+            value.map(|value| (position, position + 1, value)),
+        )
+    }
+
+    // Closes the open run at the end of the strand
+    fn finish(&mut self) -> Option<(u64, u64, f32)> {
+        self.run.take()
+    }
+}
+
+// Converts a run in reverse strand coordinates to forward strand coordinates
+fn to_forward_run(run: (u64, u64, f32), sequence_length: u64) -> (u64, u64, f32) {
+    (sequence_length - run.1, sequence_length - run.0, run.2)
+}
+
+// Number of runs held in memory for each reverse strand track, before a block is spilled to a temporary file
+const REVERSE_BLOCK_RUNS: usize = 1 << 16;
+const RUN_BYTES: usize = 20;
+
+// Reverse strand runs of one track, in forward strand coordinates. These arrive in descending order, so they are held
+// in blocks of REVERSE_BLOCK_RUNS, with full blocks spilled to a temporary file, and written out in ascending order
+// once the strand is complete
+struct ReverseRuns {
+    runs: Vec<(u64, u64, f32)>,
+    spill_path: std::path::PathBuf,
+    spill: Option<File>,
+    spilled_blocks: u64,
+}
+
+impl ReverseRuns {
+    fn new(name: &str) -> ReverseRuns {
+        let spill_path =
+            std::env::temp_dir().join(format!("helixer_post_{}_{}.runs", std::process::id(), name));
+
+        ReverseRuns {
+            runs: Vec::with_capacity(REVERSE_BLOCK_RUNS),
+            spill_path,
+            spill: None,
+            spilled_blocks: 0,
+        }
+    }
+
+    fn push(&mut self, run: (u64, u64, f32)) -> std::io::Result<()> {
+        if self.runs.len() == REVERSE_BLOCK_RUNS {
+            self.spill_block()?;
+        }
+
+        self.runs.push(run);
+        Ok(())
+    }
+
+    fn spill_block(&mut self) -> std::io::Result<()> {
+        if self.spill.is_none() {
+            self.spill = Some(
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(&self.spill_path)?,
+            );
+        }
+
+        let mut block = Vec::with_capacity(self.runs.len() * RUN_BYTES);
+        for (start, end, value) in self.runs.drain(..) {
+            block.extend_from_slice(&start.to_le_bytes());
+            block.extend_from_slice(&end.to_le_bytes());
+            block.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let spill = self.spill.as_mut().unwrap();
+        spill.seek(SeekFrom::Start(
+            self.spilled_blocks * (REVERSE_BLOCK_RUNS * RUN_BYTES) as u64,
+        ))?;
+        spill.write_all(&block)?;
+        self.spilled_blocks += 1;
+
+        Ok(())
+    }
+
+    // Writes all runs in ascending order, leaving this empty for the next sequence
+    fn write_to<W: Write>(
+        &mut self,
+        writer: &mut TrackWriter<W>,
+        sequence: &str,
+    ) -> std::io::Result<()> {
+        for run in self.runs.drain(..).rev() {
+            writer.write_run(sequence, run)?;
+        }
+
+        let mut block = vec![0u8; REVERSE_BLOCK_RUNS * RUN_BYTES];
+        while self.spilled_blocks > 0 {
+            self.spilled_blocks -= 1;
+
+            let spill = self.spill.as_mut().unwrap();
+            spill.seek(SeekFrom::Start(self.spilled_blocks * block.len() as u64))?;
+            spill.read_exact(&mut block)?;
+
+            for record in block.chunks_exact(RUN_BYTES).rev() {
+                let start = u64::from_le_bytes(record[0..8].try_into().unwrap());
+                let end = u64::from_le_bytes(record[8..16].try_into().unwrap());
+                let value = f32::from_le_bytes(record[16..20].try_into().unwrap());
+                writer.write_run(sequence, (start, end, value))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for ReverseRuns {
+    fn drop(&mut self) {
+        if self.spill.take().is_some() {
+            let _ = std::fs::remove_file(&self.spill_path);
+        }
+    }
+}
+
+/// Writes a single bedGraph or WIG track. WIG output is run length encoded as variableStep sections, with a new
+/// section only where the sequence or the run length (the span) changes
+pub struct TrackWriter<W: Write> {
+    writer: BufWriter<W>,
+    format: TrackFormat,
+    wig_section: Option<(String, u64)>, // Sequence and span of the current variableStep section
+}
+
+impl<W: Write> TrackWriter<W> {
+    pub fn new(writer: BufWriter<W>, format: TrackFormat) -> TrackWriter<W> {
+        TrackWriter {
+            writer,
+            format,
+            wig_section: None,
+        }
+    }
+
+    pub fn write_header(&mut self, name: &str, description: &str) -> std::io::Result<()> {
+        let track_type = match self.format {
+            TrackFormat::BedGraph => "bedGraph",
+            TrackFormat::Wig => "wiggle_0",
+        };
+
+        writeln!(
+            self.writer,
+            "track type={} name=\"{}\" description=\"{}\"",
+            track_type, name, description
+        )
+    }
+
+    /// Writes one run, given as 0 based start, exclusive end and value. Runs of a sequence must be written in
+    /// ascending order
+    pub fn write_run(&mut self, sequence: &str, run: (u64, u64, f32)) -> std::io::Result<()> {
+        let (start, end, value) = run;
+
+        match self.format {
+            TrackFormat::BedGraph => {
+                writeln!(self.writer, "{}\t{}\t{}\t{}", sequence, start, end, value)
+            }
+            TrackFormat::Wig => {
+                let span = end - start;
+                let same_section =
+                    self.wig_section
+                        .as_ref()
+                        .is_some_and(|(section_seq, section_span)| {
+                            section_seq == sequence && *section_span == span
+                        });
+
+                if !same_section {
+                    writeln!(self.writer, "variableStep chrom={} span={}", sequence, span)?;
+                    self.wig_section = Some((sequence.to_string(), span));
+                }
+
+                writeln!(self.writer, "{}\t{}", start + 1, value)
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// Writes a closed run of either strand: forward runs go straight to the writer, reverse runs are held until the
+// strand is complete
+fn write_strand_run<W: Write>(
+    writer: &mut TrackWriter<W>,
+    reverse_runs: &mut ReverseRuns,
+    sequence: &str,
+    rev: bool,
+    sequence_length: u64,
+    run: (u64, u64, f32),
+) -> std::io::Result<()> {
+    if rev {
+        reverse_runs.push(to_forward_run(run, sequence_length))
+    } else {
+        writer.write_run(sequence, run)
+    }
+}
+
+/// Writes the per base class and phase probabilities, and the window genic mean, as one track per strand for each,
+/// to files named <prefix>.<track>.<plus|minus>.<bedgraph|wig>. Values are rounded to the given number of decimal
+/// places before run length encoding. Forward strand runs are written as they close, while reverse strand runs, which
+/// arrive in descending order, are held in bounded blocks (spilling to temporary files) until each sequence is done.
+/// Returns the names of the files written, or an InvalidData error for a block which can't be read
+pub fn export_tracks<TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>>(
+    helixer_res: &HelixerResults,
+    bp_extractor: &BasePredictionExtractor<TC, TP>,
+    window_size: usize,
+    prefix: &str,
+    format: TrackFormat,
+    precision: u32,
+) -> std::io::Result<Vec<String>> {
+    let round_scale = 10f32.powi(precision as i32);
+
+    let mut filenames = Vec::new();
+    let mut writers = Vec::new();
+    let mut reverse_runs: Vec<ReverseRuns> = TRACKS
+        .iter()
+        .map(|(name, _)| ReverseRuns::new(name))
+        .collect();
+
+    for strand in ["plus", "minus"] {
+        let mut strand_writers = Vec::with_capacity(TRACK_COUNT);
+
+        for (name, description) in TRACKS.iter() {
+            let filename = format!("{}.{}.{}.{}", prefix, name, strand, format.as_str());
+            let file = File::create(&filename).map_err(|err| {
+                std::io::Error::new(
+                    err.kind(),
+                    format!("Could not create track file {}: {}", filename, err),
+                )
+            })?;
+
+            let mut writer = TrackWriter::new(BufWriter::new(file), format);
+            writer.write_header(
+                &format!("helixer_{}_{}", name, strand),
+                &format!("{} ({} strand)", description, strand),
+            )?;
+
+            strand_writers.push(writer);
+            filenames.push(filename);
+        }
+
+        writers.push(strand_writers);
+    }
+
+    for species in helixer_res.get_all_species() {
+        for seq_id in helixer_res.get_sequences_for_species(species.get_id()) {
+            let seq = helixer_res.get_sequence_by_id(*seq_id);
+            info!("Exporting tracks for Sequence {}", seq.get_name());
+
+            for (&rev, strand_writers) in [false, true].iter().zip(writers.iter_mut()) {
+                let bp_iter = if rev {
+                    bp_extractor.rev_iterator(*seq_id)
+                } else {
+                    bp_extractor.fwd_iterator(*seq_id)
+                };
+
+                let mut mean_iter =
+                    match BasePredictionWindowMeanIterator::new(bp_iter, window_size) {
+                        Some(mean_iter) => mean_iter,
+                        None => continue,
+                    };
+
+                let mut track_runs: Vec<TrackRuns> =
+                    (0..TRACK_COUNT).map(|_| TrackRuns::new()).collect();
+
+                for (position, (_bases, class_pred, phase_pred, window_mean)) in
+                    mean_iter.by_ref().enumerate()
+                {
+                    let values = get_track_values(&class_pred, &phase_pred, window_mean);
+
+                    for (i, value) in values.iter().enumerate() {
+                        let value = value.map(|value| (value * round_scale).round() / round_scale);
+                        if let Some(run) = track_runs[i].push(position as u64, value) {
+                            write_strand_run(
+                                &mut strand_writers[i],
+                                &mut reverse_runs[i],
+                                seq.get_name(),
+                                rev,
+                                seq.get_length(),
+                                run,
+                            )?;
+                        }
+                    }
+                }
+
+                if let Some(error) = mean_iter.take_error() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        error.to_string(),
+                    ));
+                }
+
+                for (i, runs) in track_runs.iter_mut().enumerate() {
+                    if let Some(run) = runs.finish() {
+                        write_strand_run(
+                            &mut strand_writers[i],
+                            &mut reverse_runs[i],
+                            seq.get_name(),
+                            rev,
+                            seq.get_length(),
+                            run,
+                        )?;
+                    }
+                }
+
+                if rev {
+                    for (runs, writer) in reverse_runs.iter_mut().zip(strand_writers.iter_mut()) {
+                        runs.write_to(writer, seq.get_name())?;
+                    }
+                }
+            }
+        }
+    }
+
+    for writer in writers.iter_mut().flatten() {
+        writer.flush()?;
+    }
+
+    Ok(filenames)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::tracks::{
+        to_forward_run, ReverseRuns, TrackFormat, TrackRuns, TrackWriter, REVERSE_BLOCK_RUNS,
+    };
+    use std::io::BufWriter;
+
+    #[test]
+    fn test_track_runs() {
+        let mut track_runs = TrackRuns::new();
+        let values = [
+            Some(0.5),
+            Some(0.5),
+            Some(0.25),
+            None,
+            Some(0.25),
+            Some(0.25),
+        ];
+
+        let mut runs: Vec<_> = values
+            .iter()
+            .enumerate()
+            .filter_map(|(position, value)| track_runs.push(position as u64, *value))
+            .collect();
+        runs.extend(track_runs.finish());
+
+        // A gap closes the run even where the value carries on unchanged
+        assert_eq!(runs, vec![(0, 2, 0.5), (2, 3, 0.25), (4, 6, 0.25)]);
+        assert_eq!(to_forward_run((4, 6, 0.25), 10), (4, 6, 0.25));
+        assert_eq!(to_forward_run((0, 2, 0.5), 10), (8, 10, 0.5));
+    }
+
+    #[test]
+    fn test_track_formats() {
+        let runs = [(0, 2, 0.5), (2, 4, 0.25), (4, 5, 1.0)];
+
+        let mut bedgraph_writer =
+            TrackWriter::new(BufWriter::new(Vec::new()), TrackFormat::BedGraph);
+        let mut wig_writer = TrackWriter::new(BufWriter::new(Vec::new()), TrackFormat::Wig);
+        for writer in [&mut bedgraph_writer, &mut wig_writer] {
+            writer
+                .write_header("helixer_coding_plus", "Coding")
+                .unwrap();
+            for run in runs.iter() {
+                writer.write_run("chr1", *run).unwrap();
+            }
+            writer.write_run("chr2", (0, 1, 0.0)).unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(bedgraph_writer.writer.into_inner().unwrap()).unwrap(),
+            "track type=bedGraph name=\"helixer_coding_plus\" description=\"Coding\"\n\
+             chr1\t0\t2\t0.5\n\
+             chr1\t2\t4\t0.25\n\
+             chr1\t4\t5\t1\n\
+             chr2\t0\t1\t0\n"
+        );
+
+        // A new variableStep section starts wherever the span or the sequence changes
+        assert_eq!(
+            String::from_utf8(wig_writer.writer.into_inner().unwrap()).unwrap(),
+            "track type=wiggle_0 name=\"helixer_coding_plus\" description=\"Coding\"\n\
+             variableStep chrom=chr1 span=2\n\
+             1\t0.5\n\
+             3\t0.25\n\
+             variableStep chrom=chr1 span=1\n\
+             5\t1\n\
+             variableStep chrom=chr2 span=1\n\
+             1\t0\n"
+        );
+    }
+
+    #[test]
+    fn test_reverse_runs_spill_in_order() {
+        let run_count = REVERSE_BLOCK_RUNS as u64 * 2 + 10;
+        let mut reverse_runs = ReverseRuns::new("test_reverse_runs");
+
+        // Reverse strand runs arrive in descending forward strand order
+        for i in (0..run_count).rev() {
+            reverse_runs.push((i, i + 1, (i % 7) as f32)).unwrap();
+        }
+        assert_eq!(reverse_runs.spilled_blocks, 2);
+        let spill_path = reverse_runs.spill_path.clone();
+        assert!(spill_path.exists());
+
+        let mut writer = TrackWriter::new(BufWriter::new(Vec::new()), TrackFormat::BedGraph);
+        reverse_runs.write_to(&mut writer, "chr1").unwrap();
+        assert_eq!(reverse_runs.spilled_blocks, 0);
+
+        let output = String::from_utf8(writer.writer.into_inner().unwrap()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), run_count as usize);
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(*line, format!("chr1\t{}\t{}\t{}", i, i + 1, i % 7));
+        }
+
+        drop(reverse_runs);
+        assert!(!spill_path.exists());
+    }
+}
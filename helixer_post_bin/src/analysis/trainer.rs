@@ -0,0 +1,305 @@
+use crate::analysis::extractor::{BasePredictionExtractor, ComparisonExtractor, ComparisonItem};
+use crate::analysis::hmm::HmmConfig;
+use crate::analysis::rater::{SequenceRater, SequenceRating};
+use crate::analysis::window::BasePredictionWindowThresholdIterator;
+use crate::analysis::{solve_windows, RegionSettings, WindowBases, WindowJob};
+use crate::info;
+use crate::results::conv::{ArrayConvInto, ClassPrediction, PhasePrediction};
+use crate::results::{HelixerResults, Result};
+
+// Initial step sizes, halved after each round without improvement
+const WEIGHT_STEP: f64 = 2.0; // Weights are multiplied and divided by this
+const PENALTY_STEP: f64 = 4.0; // Penalties have this added and subtracted
+const FLOOR_STEP: f64 = 100.0; // Probability floors are multiplied and divided by this
+const FRACTION_STEP: f64 = 0.1; // Proportions have this added and subtracted
+
+const MIN_IMPROVEMENT: f64 = 0.000_001; // Smaller F1 changes are ignored
+
+// How each trained parameter is varied
+#[derive(Clone, Copy)]
+enum ParameterStep {
+    Weight,
+    Penalty,
+    Floor,
+    Fraction,
+}
+
+impl ParameterStep {
+    // Candidate values either side of the current value, for the given step scale (1.0 for the initial step)
+    fn get_candidates(self, value: f64, scale: f64) -> [f64; 2] {
+        match self {
+            ParameterStep::Weight => {
+                let factor = WEIGHT_STEP.powf(scale);
+                [value * factor, value / factor]
+            }
+            ParameterStep::Penalty => {
+                let step = PENALTY_STEP * scale;
+                [value + step, (value - step).max(0.0)]
+            }
+            ParameterStep::Floor => {
+                let factor = FLOOR_STEP.powf(scale);
+                [(value * factor).min(0.5), value / factor]
+            }
+            ParameterStep::Fraction => {
+                let step = FRACTION_STEP * scale;
+                [(value + step).min(1.0), (value - step).max(0.0)]
+            }
+        }
+    }
+}
+
+// HMM config keys which are trained, see HmmConfig::set_value
+const TRAINED_PARAMETERS: [(&str, ParameterStep); 10] = [
+    ("start_weight", ParameterStep::Weight),
+    ("stop_weight", ParameterStep::Weight),
+    ("donor_weight", ParameterStep::Weight),
+    ("acceptor_weight", ParameterStep::Weight),
+    ("donor_u2_gt_ag_fixed_penalty", ParameterStep::Penalty),
+    ("donor_u2_gc_ag_fixed_penalty", ParameterStep::Penalty),
+    ("donor_u12_at_ac_fixed_penalty", ParameterStep::Penalty),
+    ("phase_retain", ParameterStep::Fraction),
+    ("class_pred_prob_floor", ParameterStep::Floor),
+    ("pred_prob_floor", ParameterStep::Floor),
+];
+
+// A window from an annotated sequence, with the reference for each of its bases
+struct TrainingWindow {
+    sequence_name: String,
+    bp_vec: WindowBases,
+    reference: Vec<ComparisonItem>,
+    at_sequence_start: bool,
+    at_sequence_end: bool,
+}
+
+/// Trains HMM weights, penalties and floors against the reference annotation (data/y and data/phases) of a genome,
+/// by coordinate search maximising the coding F1 of the predicted genes within the candidate windows
+pub struct Trainer {
+    windows: Vec<TrainingWindow>,
+    min_coding_length: usize,
+    threads: usize,
+}
+
+impl Trainer {
+    /// Collects the candidate windows for training, from the forward and reverse strand of each sequence in turn
+    /// until the maximum number of bases is reached. Fails on a block which can't be read, or datasets of different
+    /// lengths
+    ///
+    /// # Arguments
+    ///
+    /// * `max_bases` - Maximum total length of the training windows, 0 for no limit
+    pub fn new<TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>>(
+        helixer_res: &HelixerResults,
+        bp_extractor: &BasePredictionExtractor<TC, TP>,
+        comp_extractor: &ComparisonExtractor,
+        settings: RegionSettings,
+        max_bases: usize,
+    ) -> Result<Trainer> {
+        let mut windows = Vec::new();
+        let mut total_bases = 0;
+
+        'sequences: for species in helixer_res.get_all_species() {
+            for seq_id in helixer_res.get_sequences_for_species(species.get_id()) {
+                let seq = helixer_res.get_sequence_by_id(*seq_id);
+                let sequence_length = seq.get_length() as usize;
+
+                for rev in [false, true] {
+                    let (bp_iter, mut comp_iter) = if rev {
+                        (
+                            bp_extractor.rev_iterator(*seq_id),
+                            comp_extractor.rev_iterator(*seq_id),
+                        )
+                    } else {
+                        (
+                            bp_extractor.fwd_iterator(*seq_id),
+                            comp_extractor.fwd_iterator(*seq_id),
+                        )
+                    };
+
+                    let mut window_iter = match BasePredictionWindowThresholdIterator::new(
+                        bp_iter,
+                        settings.window_size,
+                        settings.edge_threshold,
+                        settings.peak_threshold,
+                    ) {
+                        Some(window_iter) => window_iter,
+                        None => continue,
+                    };
+
+                    let mut comp_position = 0;
+
+                    for (bp_vec, _total_vec, start_pos, _peak) in window_iter.by_ref() {
+                        let end_pos = start_pos + bp_vec.len();
+
+                        // Windows are in order, so the reference is read alongside them
+                        if start_pos > comp_position {
+                            comp_iter.nth(start_pos - comp_position - 1);
+                        }
+                        let reference: Vec<ComparisonItem> =
+                            comp_iter.by_ref().take(bp_vec.len()).collect();
+                        comp_position = end_pos;
+
+                        total_bases += bp_vec.len();
+                        windows.push(TrainingWindow {
+                            sequence_name: seq.get_name().to_string(),
+                            bp_vec,
+                            reference,
+                            at_sequence_start: start_pos == 0,
+                            at_sequence_end: end_pos == sequence_length,
+                        });
+
+                        if max_bases > 0 && total_bases >= max_bases {
+                            break 'sequences;
+                        }
+                    }
+
+                    if let Some(error) = window_iter.take_error().or_else(|| comp_iter.take_error())
+                    {
+                        return Err(error);
+                    }
+                }
+            }
+        }
+
+        Ok(Trainer {
+            windows,
+            min_coding_length: settings.min_coding_length,
+            threads: std::cmp::max(settings.threads, 1),
+        })
+    }
+
+    pub fn get_window_count(&self) -> usize {
+        self.windows.len()
+    }
+
+    pub fn get_total_bases(&self) -> usize {
+        self.windows.iter().map(|window| window.bp_vec.len()).sum()
+    }
+
+    /// Solves all training windows with the given config, returning the rating against the reference
+    pub fn evaluate(&self, hmm_config: &HmmConfig) -> SequenceRating {
+        let mut rating = SequenceRating::new();
+
+        // Windows are grouped by sequence, which determines the genetic code
+        let mut first = 0;
+        while first < self.windows.len() {
+            let sequence_name = &self.windows[first].sequence_name;
+            let last = first
+                + self.windows[first..]
+                    .iter()
+                    .take_while(|window| &window.sequence_name == sequence_name)
+                    .count();
+
+            let jobs = self.windows[first..last]
+                .iter()
+                .map(|window| WindowJob {
+                    bp_vec: window.bp_vec.clone(),
+                    at_sequence_start: window.at_sequence_start,
+                    at_sequence_end: window.at_sequence_end,
+                    hint_flags: Vec::new(),
+                })
+                .collect();
+
+            let genetic_code = hmm_config.get_genetic_code(sequence_name);
+            let solutions = solve_windows(jobs, self.threads, hmm_config, genetic_code);
+
+            for (window, maybe_genes) in self.windows[first..last].iter().zip(solutions) {
+                let mut rater =
+                    SequenceRater::new(window.reference.iter().copied(), window.reference.len());
+
+                // Windows without a solution count as having no genes
+                if let Some(genes) = maybe_genes {
+                    for (gene_regions, coding_length) in
+                        genes.iter().map(|transcripts| &transcripts[0])
+                    {
+                        rater.rate_regions(
+                            0,
+                            gene_regions,
+                            *coding_length < self.min_coding_length,
+                        );
+                    }
+                }
+
+                rating.accumulate(&rater.calculate_stats());
+            }
+
+            first = last;
+        }
+
+        rating
+    }
+
+    // Coding F1 for the given config, 0 if undefined (no coding bases predicted or in the reference)
+    fn get_score(&self, hmm_config: &HmmConfig) -> f64 {
+        let (_, _, f1) = self.evaluate(hmm_config).get_coding_precision_recall_f1();
+        if f1.is_nan() {
+            0.0
+        } else {
+            f1
+        }
+    }
+
+    /// Coordinate search from the given config, trying each parameter a step either side of its current value
+    /// and keeping any improvement. The steps are halved after each round which finds no improvement.
+    /// Returns the best config found, with its coding F1
+    pub fn train(&self, initial_config: &HmmConfig, rounds: usize) -> (HmmConfig, f64) {
+        let mut best_config = initial_config.clone();
+        let mut best_score = self.get_score(&best_config);
+        info!("Initial coding F1 {:.5}", best_score);
+
+        let mut scale = 1.0;
+
+        for round in 1..=rounds {
+            let mut improved = false;
+
+            for (key, step) in TRAINED_PARAMETERS.iter() {
+                let value = match get_config_value(&best_config, key) {
+                    Some(value) => value,
+                    None => continue,
+                };
+
+                for candidate in step.get_candidates(value, scale).map(round_value) {
+                    if candidate == value {
+                        continue;
+                    }
+
+                    let mut config = best_config.clone();
+                    if config.set_value(key, &candidate.to_string()).is_err() {
+                        continue;
+                    }
+
+                    let score = self.get_score(&config);
+                    info!(
+                        "Round {}: {}={} coding F1 {:.5} (best {:.5})",
+                        round, key, candidate, score, best_score
+                    );
+
+                    if score > best_score + MIN_IMPROVEMENT {
+                        best_config = config;
+                        best_score = score;
+                        improved = true;
+                        break;
+                    }
+                }
+            }
+
+            if !improved {
+                scale /= 2.0;
+            }
+        }
+
+        (best_config, best_score)
+    }
+}
+
+// Rounds to 6 significant figures, to keep the written config readable
+fn round_value(value: f64) -> f64 {
+    format!("{:.5e}", value).parse().unwrap_or(value)
+}
+
+fn get_config_value(hmm_config: &HmmConfig, key: &str) -> Option<f64> {
+    hmm_config
+        .get_values()
+        .into_iter()
+        .find(|(k, _)| k == key)
+        .and_then(|(_, value)| value.parse().ok())
+}
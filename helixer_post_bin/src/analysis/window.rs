@@ -1,5 +1,6 @@
 use crate::analysis::extractor::BasePredictionIterator;
 use crate::results::conv::{ArrayConvInto, Bases, ClassPrediction, PhasePrediction};
+use crate::results::Error;
 use std::collections::vec_deque::Iter;
 use std::collections::VecDeque;
 
@@ -84,6 +85,11 @@ impl<'a, TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>>
     pub fn get_window_iter(&self) -> Iter<(Bases, ClassPrediction, PhasePrediction)> {
         self.window.iter()
     }
+
+    /// The error which ended the base predictions early, if any
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.bp_iter.take_error()
+    }
 }
 
 pub struct BasePredictionWindowThresholdScanner<
@@ -170,6 +176,57 @@ impl<'a, TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>>
 
 const THRESHOLD_SCALE: f32 = 1_000_000.0;
 
+/// Iterates the bases of a sequence with the mean genic score of the window starting at each base, as compared with
+/// the edge and peak thresholds. The mean is None where the window would run past the end of the sequence
+pub struct BasePredictionWindowMeanIterator<
+    'a,
+    TC: ArrayConvInto<ClassPrediction>,
+    TP: ArrayConvInto<PhasePrediction>,
+> {
+    bp_window: BasePredictionWindow<'a, TC, TP>,
+    mean_scale: f32,
+}
+
+impl<'a, TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>>
+    BasePredictionWindowMeanIterator<'a, TC, TP>
+{
+    pub fn new(
+        bp_iter: BasePredictionIterator<'a, TC, TP>,
+        window_size: usize,
+    ) -> Option<BasePredictionWindowMeanIterator<'a, TC, TP>> {
+        let bp_window = BasePredictionWindow::new(bp_iter, window_size, THRESHOLD_SCALE)?;
+
+        Some(BasePredictionWindowMeanIterator {
+            bp_window,
+            mean_scale: 1.0 / (THRESHOLD_SCALE * window_size as f32),
+        })
+    }
+
+    /// The error which ended the base predictions early, if any
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.bp_window.take_error()
+    }
+}
+
+impl<'a, TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>> Iterator
+    for BasePredictionWindowMeanIterator<'a, TC, TP>
+{
+    type Item = (Bases, ClassPrediction, PhasePrediction, Option<f32>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mean = if self.bp_window.is_window_full() {
+            Some(self.bp_window.get_window_total() as f32 * self.mean_scale)
+        } else {
+            None
+        };
+
+        let (bases, class_pred, phase_pred) = self.bp_window.pop()?;
+        self.bp_window.push();
+
+        Some((bases, class_pred, phase_pred, mean))
+    }
+}
+
 pub struct BasePredictionWindowThresholdIterator<
     'a,
     TC: ArrayConvInto<ClassPrediction>,
@@ -178,6 +235,7 @@ pub struct BasePredictionWindowThresholdIterator<
     bp_scanner: BasePredictionWindowThresholdScanner<'a, TC, TP>,
     peak_threshold: u64,
     peak_scale: f32,
+    rejected: Option<Vec<(usize, usize, f32)>>, // Start, end and peak of regions below the peak threshold, if kept
 }
 
 impl<'a, TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>>
@@ -206,8 +264,24 @@ impl<'a, TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>>
             bp_scanner,
             peak_threshold,
             peak_scale,
+            rejected: None,
         })
     }
+
+    /// Keeps the regions which pass the edge threshold but not the peak threshold, see take_rejected
+    pub fn keep_rejected(&mut self) {
+        self.rejected = Some(Vec::new());
+    }
+
+    /// Start, end and peak of the regions rejected by the peak threshold since the last call, if they are kept
+    pub fn take_rejected(&mut self) -> Vec<(usize, usize, f32)> {
+        self.rejected.as_mut().map_or(Vec::new(), std::mem::take)
+    }
+
+    /// The error which ended the base predictions early, if any. The last window before an error ends at it
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.bp_scanner.bp_window.take_error()
+    }
 }
 
 impl<'a, TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>> Iterator
@@ -233,6 +307,14 @@ impl<'a, TC: ArrayConvInto<ClassPrediction>, TP: ArrayConvInto<PhasePrediction>>
                 let peak = (peak as f32) * self.peak_scale;
                 return Some((bp_accum, total_accum, position, peak));
             }
+
+            if let Some(rejected) = self.rejected.as_mut() {
+                rejected.push((
+                    position,
+                    position + bp_accum.len(),
+                    (peak as f32) * self.peak_scale,
+                ));
+            }
         }
     }
 }
@@ -0,0 +1,374 @@
+use crate::gff::{GffFeature, GffRecord, GffStrand};
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+
+/*
+
+1	chrom	The name of the sequence
+2	chromStart	Start of the transcript, 0 based
+3	chromEnd	End of the transcript, exclusive
+4	name	The transcript ID
+5	score	0 to 1000, here the mean posterior of the transcript scaled by 1000
+6	strand	+ or -
+7	thickStart	Start of the coding region, equal to chromStart / chromEnd if there is none
+8	thickEnd	End of the coding region
+9	itemRgb	Always 0
+10	blockCount	Number of exons
+11	blockSizes	Comma separated exon lengths
+12	blockStarts	Comma separated exon starts, relative to chromStart
+
+ */
+
+/// A transcript as a single BED12 line
+pub struct BedRecord {
+    chrom: String,
+    start: u64,
+    end: u64,
+    name: String,
+    score: u32,
+    strand: &'static str,
+    thick_start: u64,
+    thick_end: u64,
+    blocks: Vec<(u64, u64)>, // Exon start (0 based) and end (exclusive), in ascending order
+}
+
+impl BedRecord {
+    // Builds a transcript from its mRNA record, with exon and CDS / codon records added afterwards
+    fn from_mrna(rec: &GffRecord) -> BedRecord {
+        BedRecord {
+            chrom: rec.get_sequence().clone(),
+            start: rec.get_start() - 1,
+            end: rec.get_end(),
+            name: rec.get_attribute("ID").unwrap_or(".").to_owned(),
+            score: rec.get_score().map_or(0, |score| {
+                (score * 1000.0).round().clamp(0.0, 1000.0) as u32
+            }),
+            strand: rec.get_strand().map_or(".", |strand| strand.as_str()),
+            thick_start: rec.get_start() - 1,
+            thick_end: rec.get_start() - 1,
+            blocks: Vec::new(),
+        }
+    }
+
+    fn add_child(&mut self, rec: &GffRecord) {
+        match rec.get_feature() {
+            GffFeature::Exon => self.blocks.push((rec.get_start() - 1, rec.get_end())),
+            GffFeature::CDS | GffFeature::StartCodon | GffFeature::StopCodon => {
+                if self.thick_start == self.thick_end {
+                    self.thick_start = rec.get_start() - 1;
+                    self.thick_end = rec.get_end();
+                } else {
+                    self.thick_start = std::cmp::min(self.thick_start, rec.get_start() - 1);
+                    self.thick_end = std::cmp::max(self.thick_end, rec.get_end());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn get_chrom(&self) -> &str {
+        &self.chrom
+    }
+
+    pub fn get_start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn get_end(&self) -> u64 {
+        self.end
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Writes transcripts as BED12, sorted by sequence name (byte order) then start, as needed by bedToBigBed. Records
+/// are held until finish is called
+pub struct BedWriter<W: Write> {
+    writer: BufWriter<W>,
+    records: Vec<BedRecord>,
+}
+
+impl<W: Write> BedWriter<W> {
+    pub fn new(writer: BufWriter<W>) -> BedWriter<W> {
+        BedWriter {
+            writer,
+            records: Vec::new(),
+        }
+    }
+
+    /// Adds a transcript for each mRNA record, using the exon, CDS and start / stop codon records which follow it
+    pub fn add_gff_records(&mut self, recs: &[GffRecord]) {
+        for rec in recs {
+            match rec.get_feature() {
+                GffFeature::Gene => {}
+                GffFeature::MRNA => self.records.push(BedRecord::from_mrna(rec)),
+                _ => {
+                    if let Some(bed_rec) = self.records.last_mut() {
+                        bed_rec.add_child(rec);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sorts and writes all transcripts added
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        self.records.sort_by(|a, b| {
+            a.chrom
+                .as_bytes()
+                .cmp(b.chrom.as_bytes())
+                .then(a.start.cmp(&b.start))
+                .then(a.end.cmp(&b.end))
+        });
+
+        for rec in self.records.iter_mut() {
+            rec.blocks.sort();
+        }
+
+        for rec in self.records.iter() {
+            let block_sizes: String = rec
+                .blocks
+                .iter()
+                .map(|(start, end)| format!("{},", end - start))
+                .collect();
+            let block_starts: String = rec
+                .blocks
+                .iter()
+                .map(|(start, _)| format!("{},", start - rec.start))
+                .collect();
+
+            writeln!(
+                self.writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t0\t{}\t{}\t{}",
+                rec.chrom,
+                rec.start,
+                rec.end,
+                rec.name,
+                rec.score,
+                rec.strand,
+                rec.thick_start,
+                rec.thick_end,
+                rec.blocks.len(),
+                block_sizes,
+                block_starts
+            )?;
+        }
+
+        self.records.clear();
+        self.writer.flush()
+    }
+}
+
+/// A candidate gene region, where the window genic mean passes the edge threshold, in forward strand coordinates
+struct RegionBedRecord {
+    chrom: String,
+    start: u64,
+    end: u64,
+    strand: &'static str,
+    peak: f32,
+    gene_count: Option<usize>, // None if rejected by the peak threshold
+}
+
+/// Writes candidate regions as BED6+3, with the peak window genic mean, length and number of genes solved (0 for
+/// regions rejected by the peak threshold) after the standard columns. Sorted as for BedWriter, once finish is called
+pub struct RegionBedWriter<W: Write> {
+    writer: BufWriter<W>,
+    include_rejected: bool,
+    records: Vec<RegionBedRecord>,
+}
+
+impl<W: Write> RegionBedWriter<W> {
+    pub fn new(writer: BufWriter<W>, include_rejected: bool) -> RegionBedWriter<W> {
+        RegionBedWriter {
+            writer,
+            include_rejected,
+            records: Vec::new(),
+        }
+    }
+
+    /// True if regions rejected by the peak threshold should be added too
+    pub fn get_include_rejected(&self) -> bool {
+        self.include_rejected
+    }
+
+    /// Adds a region
+    ///
+    /// # Arguments
+    ///
+    /// * `rev` - True if the region is on the reverse strand, with positions counting from the sequence end
+    /// * `positions` - Start and end (exclusive) of the region in its strand's coordinates
+    /// * `gene_count` - Genes found in the region, None if it was rejected by the peak threshold
+    pub fn add_region(
+        &mut self,
+        sequence: &str,
+        rev: bool,
+        sequence_length: u64,
+        positions: Range<usize>,
+        peak: f32,
+        gene_count: Option<usize>,
+    ) {
+        let (start, end) = if rev {
+            (
+                sequence_length - positions.end as u64,
+                sequence_length - positions.start as u64,
+            )
+        } else {
+            (positions.start as u64, positions.end as u64)
+        };
+
+        self.records.push(RegionBedRecord {
+            chrom: sequence.to_owned(),
+            start,
+            end,
+            strand: GffStrand::from_rev(rev).as_str(),
+            peak,
+            gene_count,
+        });
+    }
+
+    /// Sorts and writes all regions added
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        self.records.sort_by(|a, b| {
+            a.chrom
+                .as_bytes()
+                .cmp(b.chrom.as_bytes())
+                .then(a.start.cmp(&b.start))
+                .then(a.end.cmp(&b.end))
+        });
+
+        for rec in self.records.iter() {
+            let name = if rec.gene_count.is_some() {
+                "candidate"
+            } else {
+                "rejected"
+            };
+
+            writeln!(
+                self.writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{:.3}\t{}\t{}",
+                rec.chrom,
+                rec.start,
+                rec.end,
+                name,
+                (rec.peak * 1000.0).round().clamp(0.0, 1000.0) as u32,
+                rec.strand,
+                rec.peak,
+                rec.end - rec.start,
+                rec.gene_count.unwrap_or(0)
+            )?;
+        }
+
+        self.records.clear();
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bed::{BedWriter, RegionBedWriter};
+    use crate::gff::{GffFeature, GffPhase, GffRecord, GffStrand};
+    use std::io::BufWriter;
+
+    fn record(
+        sequence: &str,
+        feature: GffFeature,
+        start: u64,
+        end: u64,
+        strand: GffStrand,
+        attributes: &str,
+    ) -> GffRecord {
+        let phase = match feature {
+            GffFeature::CDS => Some(GffPhase::Zero),
+            _ => None,
+        };
+        GffRecord::new(
+            sequence.to_string(),
+            "Helixer".to_string(),
+            feature,
+            start,
+            end,
+            Some(0.5),
+            Some(strand),
+            phase,
+            attributes.to_string(),
+        )
+    }
+
+    #[test]
+    fn test_bed12_blocks() {
+        let mut bed_writer = BedWriter::new(BufWriter::new(Vec::new()));
+
+        let forward = GffStrand::Forward;
+        bed_writer.add_gff_records(&[
+            record("chr2", GffFeature::Gene, 11, 40, forward, "ID=g1"),
+            record(
+                "chr2",
+                GffFeature::MRNA,
+                11,
+                40,
+                forward,
+                "ID=g1.1;Parent=g1",
+            ),
+            record("chr2", GffFeature::Exon, 11, 20, forward, "Parent=g1.1"),
+            record(
+                "chr2",
+                GffFeature::FivePrimeUTR,
+                11,
+                14,
+                forward,
+                "Parent=g1.1",
+            ),
+            record("chr2", GffFeature::CDS, 15, 20, forward, "Parent=g1.1"),
+            record("chr2", GffFeature::Exon, 31, 40, forward, "Parent=g1.1"),
+            record("chr2", GffFeature::CDS, 31, 36, forward, "Parent=g1.1"),
+        ]);
+
+        // Records for the reverse strand run from the 3' end, and non coding transcripts have an empty thick span
+        let reverse = GffStrand::Reverse;
+        bed_writer.add_gff_records(&[
+            record(
+                "chr10",
+                GffFeature::MRNA,
+                101,
+                150,
+                reverse,
+                "ID=g2.1;Parent=g2",
+            ),
+            record("chr10", GffFeature::Exon, 131, 150, reverse, "Parent=g2.1"),
+            record("chr10", GffFeature::Exon, 101, 110, reverse, "Parent=g2.1"),
+            record("chr1", GffFeature::MRNA, 5, 9, forward, "ID=g3.1;Parent=g3"),
+            record("chr1", GffFeature::Exon, 5, 9, forward, "Parent=g3.1"),
+        ]);
+
+        bed_writer.finish().unwrap();
+        let output = String::from_utf8(bed_writer.writer.get_ref().clone()).unwrap();
+
+        // Sorted by sequence name bytes, so chr10 comes before chr2
+        assert_eq!(
+            output,
+            "chr1\t4\t9\tg3.1\t500\t+\t4\t4\t0\t1\t5,\t0,\n\
+             chr10\t100\t150\tg2.1\t500\t-\t100\t100\t0\t2\t10,20,\t0,30,\n\
+             chr2\t10\t40\tg1.1\t500\t+\t14\t36\t0\t2\t10,10,\t0,20,\n"
+        );
+    }
+
+    #[test]
+    fn test_region_bed_coordinates() {
+        let mut region_writer = RegionBedWriter::new(BufWriter::new(Vec::new()), true);
+
+        region_writer.add_region("chr1", false, 1000, 100..200, 0.9, Some(2));
+        region_writer.add_region("chr1", true, 1000, 100..200, 0.5, None);
+
+        region_writer.finish().unwrap();
+        let output = String::from_utf8(region_writer.writer.get_ref().clone()).unwrap();
+
+        // Reverse strand positions count from the sequence end
+        assert_eq!(
+            output,
+            "chr1\t100\t200\tcandidate\t900\t+\t0.900\t100\t2\n\
+             chr1\t800\t900\trejected\t500\t-\t0.500\t100\t0\n"
+        );
+    }
+}
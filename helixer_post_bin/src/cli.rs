@@ -0,0 +1,442 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+// Options are listed in help with their descriptions aligned to this column
+const HELP_COLUMN: usize = 36;
+
+/// A named option of a command, given as --name <value> or --name=<value>, or a flag without a value. Flags may also
+/// have a short form (e.g. -v, or -vv for twice)
+pub struct OptionSpec {
+    name: &'static str,
+    short: Option<char>,
+    value_name: Option<&'static str>, // None for flags
+    default: Option<&'static str>,
+    required: bool,
+    repeated: bool,
+    help: &'static str,
+}
+
+impl OptionSpec {
+    /// An option which must be given, by name or position
+    pub const fn required(
+        name: &'static str,
+        value_name: &'static str,
+        help: &'static str,
+    ) -> OptionSpec {
+        OptionSpec {
+            name,
+            short: None,
+            value_name: Some(value_name),
+            default: None,
+            required: true,
+            repeated: false,
+            help,
+        }
+    }
+
+    /// An option which may be left out, taking the default if there is one
+    pub const fn optional(
+        name: &'static str,
+        value_name: &'static str,
+        default: Option<&'static str>,
+        help: &'static str,
+    ) -> OptionSpec {
+        OptionSpec {
+            name,
+            short: None,
+            value_name: Some(value_name),
+            default,
+            required: false,
+            repeated: false,
+            help,
+        }
+    }
+
+    /// An option which may be given any number of times, with the values kept in order
+    pub const fn repeated(
+        name: &'static str,
+        value_name: &'static str,
+        help: &'static str,
+    ) -> OptionSpec {
+        OptionSpec {
+            name,
+            short: None,
+            value_name: Some(value_name),
+            default: None,
+            required: false,
+            repeated: true,
+            help,
+        }
+    }
+
+    pub const fn flag(name: &'static str, help: &'static str) -> OptionSpec {
+        OptionSpec {
+            name,
+            short: None,
+            value_name: None,
+            default: None,
+            required: false,
+            repeated: false,
+            help,
+        }
+    }
+
+    /// A flag which may be given any number of times, also by its short form, see ParsedArgs::get_count
+    pub const fn counted(name: &'static str, short: char, help: &'static str) -> OptionSpec {
+        OptionSpec {
+            name,
+            short: Some(short),
+            value_name: None,
+            default: None,
+            required: false,
+            repeated: true,
+            help,
+        }
+    }
+
+    fn get_usage(&self) -> String {
+        let usage = match self.value_name {
+            Some(value_name) => format!("--{} {}", self.name, value_name),
+            None => format!("--{}", self.name),
+        };
+
+        match self.short {
+            Some(short) => format!("-{}, {}", short, usage),
+            None => usage,
+        }
+    }
+}
+
+/// A subcommand, with its options in groups (so groups can be shared between commands). Options named in
+/// 'positional' can also be given without their name, in that order, as in the original command line. Any given by
+/// name are skipped over by the positional values
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub about: &'static str,
+    pub positional: &'static [&'static str],
+    pub option_groups: &'static [&'static [OptionSpec]],
+}
+
+impl CommandSpec {
+    fn options(&self) -> impl Iterator<Item = &OptionSpec> {
+        self.option_groups.iter().flat_map(|group| group.iter())
+    }
+
+    fn find_option(&self, name: &str) -> Option<&OptionSpec> {
+        self.options().find(|option| option.name == name)
+    }
+
+    // The flags given by a group of short forms (e.g. -vq), if all are known
+    fn find_short_options(&self, shorts: &str) -> Option<Vec<&OptionSpec>> {
+        shorts
+            .chars()
+            .map(|short| self.options().find(|option| option.short == Some(short)))
+            .collect()
+    }
+
+    /// One line usage, with the positional options in order. Required options show their value (e.g. <genome.h5>),
+    /// others their name (e.g. [window_size])
+    pub fn get_usage(&self, program: &str) -> String {
+        let mut usage = format!("{} {} [options]", program, self.name);
+
+        for name in self.positional {
+            if let Some(option) = self.find_option(name) {
+                match option.value_name {
+                    Some(value_name) if option.required => {
+                        usage.push_str(&format!(" {}", value_name))
+                    }
+                    _ => usage.push_str(&format!(" [{}]", option.name.replace('-', "_"))),
+                }
+            }
+        }
+
+        usage
+    }
+
+    pub fn show_help(&self, program: &str) {
+        println!("{} {} - {}", program, self.name, self.about);
+        println!();
+        println!("Usage: {}", self.get_usage(program));
+
+        for group in self.option_groups {
+            println!();
+
+            for option in group.iter() {
+                let mut help = option.help.to_string();
+                if option.required {
+                    help.push_str(" (required)");
+                }
+                if let Some(default) = option.default {
+                    help.push_str(&format!(" (default {})", default));
+                }
+                if option.repeated {
+                    help.push_str(" (repeatable)");
+                }
+
+                let usage = option.get_usage();
+                if usage.len() + 2 < HELP_COLUMN {
+                    println!("  {:width$}{}", usage, help, width = HELP_COLUMN - 2);
+                } else {
+                    println!("  {}", usage);
+                    println!("{:width$}{}", "", help, width = HELP_COLUMN);
+                }
+            }
+        }
+
+        println!();
+        println!(
+            "  {:width$}Show this help",
+            "-h, --help",
+            width = HELP_COLUMN - 2
+        );
+    }
+
+    /// Parses the arguments following the command name. Returns Ok(None) if help was requested
+    pub fn parse(&self, args: &[String]) -> Result<Option<ParsedArgs>, String> {
+        let mut values: HashMap<&'static str, Vec<String>> = HashMap::new();
+        let mut positional_values = Vec::new();
+
+        let mut arg_iter = args.iter();
+        while let Some(arg) = arg_iter.next() {
+            if arg == "-h" || arg == "--help" {
+                return Ok(None);
+            }
+
+            let name_value = match arg.strip_prefix("--") {
+                Some(name_value) => name_value,
+                None => {
+                    // Anything else starting with '-' which isn't a known short flag is positional
+                    let short_options = arg
+                        .strip_prefix('-')
+                        .filter(|shorts| !shorts.is_empty())
+                        .and_then(|shorts| self.find_short_options(shorts));
+
+                    match short_options {
+                        Some(short_options) => {
+                            for option in short_options {
+                                values.entry(option.name).or_default().push(String::new());
+                            }
+                        }
+                        None => positional_values.push(arg.clone()),
+                    }
+                    continue;
+                }
+            };
+
+            let (name, inline_value) = match name_value.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (name_value, None),
+            };
+
+            let option = self
+                .find_option(name)
+                .ok_or_else(|| format!("Unknown option --{} for {}", name, self.name))?;
+
+            let value = match (option.value_name, inline_value) {
+                (None, None) => String::new(),
+                (None, Some(_)) => return Err(format!("Option --{} does not take a value", name)),
+                (Some(_), Some(value)) => value,
+                (Some(value_name), None) => arg_iter
+                    .next()
+                    .cloned()
+                    .ok_or_else(|| format!("Option --{} needs a value {}", name, value_name))?,
+            };
+
+            let option_values = values.entry(option.name).or_default();
+            if !option_values.is_empty() && !option.repeated {
+                return Err(format!("Option --{} given more than once", name));
+            }
+            option_values.push(value);
+        }
+
+        // Positional values fill the positional options not given by name, in order
+        let unnamed: Vec<&'static str> = self
+            .positional
+            .iter()
+            .copied()
+            .filter(|name| !values.contains_key(name))
+            .collect();
+
+        if positional_values.len() > unnamed.len() {
+            return Err(format!(
+                "Too many arguments for {}, unexpected '{}'",
+                self.name,
+                positional_values[unnamed.len()]
+            ));
+        }
+
+        for (name, value) in unnamed.into_iter().zip(positional_values) {
+            values.insert(name, vec![value]);
+        }
+
+        for option in self.options().filter(|option| option.required) {
+            if !values.contains_key(option.name) {
+                return Err(format!(
+                    "Missing {} (--{})",
+                    option.value_name.unwrap_or(option.name),
+                    option.name
+                ));
+            }
+        }
+
+        let defaults = self
+            .options()
+            .filter_map(|option| option.default.map(|default| (option.name, default)))
+            .collect();
+
+        Ok(Some(ParsedArgs { values, defaults }))
+    }
+}
+
+/// The option values given to a command
+pub struct ParsedArgs {
+    values: HashMap<&'static str, Vec<String>>,
+    defaults: HashMap<&'static str, &'static str>,
+}
+
+impl ParsedArgs {
+    pub fn get_flag(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
+
+    /// Number of times a flag was given
+    pub fn get_count(&self, name: &str) -> usize {
+        self.values.get(name).map_or(0, |values| values.len())
+    }
+
+    /// The value given, or the default
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.values
+            .get(name)
+            .and_then(|values| values.last())
+            .map(|value| value.as_str())
+            .or_else(|| self.defaults.get(name).copied())
+    }
+
+    /// All values of a repeated option, in the order given
+    pub fn get_all(&self, name: &str) -> &[String] {
+        self.values
+            .get(name)
+            .map_or(&[], |values| values.as_slice())
+    }
+
+    /// The parsed value given, or the default, if either
+    pub fn get_optional<T: FromStr>(&self, name: &str) -> Result<Option<T>, String>
+    where
+        T::Err: Display,
+    {
+        self.get_str(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|err| format!("Invalid value '{}' for --{}: {}", value, name, err))
+            })
+            .transpose()
+    }
+
+    /// The parsed value of an option which is required or has a default
+    pub fn get_required<T: FromStr>(&self, name: &str) -> Result<T, String>
+    where
+        T::Err: Display,
+    {
+        self.get_optional(name)?
+            .ok_or_else(|| format!("Missing --{}", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::{CommandSpec, OptionSpec, ParsedArgs};
+
+    const TEST_OPTIONS: &[OptionSpec] = &[
+        OptionSpec::required("genome", "<genome.h5>", "Genome"),
+        OptionSpec::optional("window-size", "<bases>", Some("100"), "Window size"),
+        OptionSpec::optional("threads", "<threads>", None, "Threads"),
+        OptionSpec::repeated("hmm-set", "<key=value>", "HMM parameter"),
+        OptionSpec::flag("skip-failed-windows", "Skip failed windows"),
+        OptionSpec::counted("verbose", 'v', "More logging"),
+    ];
+
+    const TEST_COMMAND: CommandSpec = CommandSpec {
+        name: "test",
+        about: "Test command",
+        positional: &["genome", "window-size", "threads"],
+        option_groups: &[TEST_OPTIONS],
+    };
+
+    fn parse(args: &[&str]) -> Result<Option<ParsedArgs>, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        TEST_COMMAND.parse(&args)
+    }
+
+    #[test]
+    fn test_parse_named_and_positional() {
+        let args = parse(&[
+            "--window-size=200",
+            "-vv",
+            "genome.h5",
+            "4",
+            "--hmm-set",
+            "a=1",
+            "--hmm-set=b=2",
+            "--skip-failed-windows",
+        ])
+        .unwrap()
+        .unwrap();
+
+        // Positional values skip the options given by name
+        assert_eq!(args.get_str("genome"), Some("genome.h5"));
+        assert_eq!(args.get_required::<usize>("window-size"), Ok(200));
+        assert_eq!(args.get_required::<usize>("threads"), Ok(4));
+        assert_eq!(args.get_all("hmm-set"), ["a=1", "b=2"]);
+        assert!(args.get_flag("skip-failed-windows"));
+        assert_eq!(args.get_count("verbose"), 2);
+
+        let args = parse(&["genome.h5"]).unwrap().unwrap();
+        assert_eq!(args.get_required::<usize>("window-size"), Ok(100));
+        assert_eq!(args.get_optional::<usize>("threads"), Ok(None));
+        assert!(args.get_all("hmm-set").is_empty());
+        assert!(!args.get_flag("skip-failed-windows"));
+        assert_eq!(args.get_count("verbose"), 0);
+
+        assert!(parse(&["genome.h5", "--help"]).unwrap().is_none());
+        assert_eq!(
+            TEST_COMMAND.get_usage("HelixerPost"),
+            "HelixerPost test [options] <genome.h5> [window_size] [threads]"
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (args, message) in [
+            (
+                &["--window", "1", "genome.h5"][..],
+                "Unknown option --window for test",
+            ),
+            (
+                &["--skip-failed-windows=yes", "genome.h5"],
+                "does not take a value",
+            ),
+            (
+                &["genome.h5", "--threads"],
+                "Option --threads needs a value <threads>",
+            ),
+            (
+                &["--threads", "1", "--threads", "2", "g.h5"],
+                "given more than once",
+            ),
+            (&["genome.h5", "100", "1", "extra"], "unexpected 'extra'"),
+            (&["--window-size", "100"], "Missing <genome.h5> (--genome)"),
+        ] {
+            match parse(args) {
+                Err(err) => assert!(err.contains(message), "'{}' for {:?}", err, args),
+                Ok(_) => panic!("Parsed invalid arguments {:?}", args),
+            }
+        }
+
+        let args = parse(&["genome.h5", "-5"]).unwrap().unwrap();
+        let err = args.get_required::<usize>("window-size").unwrap_err();
+        assert!(err.starts_with("Invalid value '-5' for --window-size"));
+    }
+}
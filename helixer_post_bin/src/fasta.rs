@@ -0,0 +1,69 @@
+use std::io::{BufWriter, Write};
+
+const FASTA_LINE_LENGTH: usize = 60;
+
+pub struct FastaWriter<W: Write> {
+    writer: BufWriter<W>,
+}
+
+impl<W: Write> FastaWriter<W> {
+    pub fn new(writer: BufWriter<W>) -> FastaWriter<W> {
+        FastaWriter { writer }
+    }
+
+    /// Writes a single sequence, wrapped at 60 characters per line
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The sequence ID, the first word of the header line
+    /// * `description` - Optional text following the ID, empty for none
+    /// * `sequence` - The sequence letters
+    pub fn write_record(
+        &mut self,
+        id: &str,
+        description: &str,
+        sequence: &[u8],
+    ) -> std::io::Result<()> {
+        if description.is_empty() {
+            writeln!(self.writer, ">{}", id)?;
+        } else {
+            writeln!(self.writer, ">{} {}", id, description)?;
+        }
+
+        for line in sequence.chunks(FASTA_LINE_LENGTH) {
+            self.writer.write_all(line)?;
+            self.writer.write_all(b"\n")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fasta::FastaWriter;
+    use std::io::BufWriter;
+
+    #[test]
+    fn test_write_wrapped_records() {
+        let mut fasta_writer = FastaWriter::new(BufWriter::new(Vec::new()));
+
+        let sequence = [b'A'; 60]
+            .iter()
+            .chain([b'C'; 61].iter())
+            .copied()
+            .collect::<Vec<u8>>();
+        fasta_writer.write_record("t1", "", &sequence).unwrap();
+        fasta_writer.write_record("t2", "gene=g2", b"MA").unwrap();
+
+        let output = String::from_utf8(fasta_writer.writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            format!(
+                ">t1\n{}\n{}\nC\n>t2 gene=g2\nMA\n",
+                "A".repeat(60),
+                "C".repeat(60)
+            )
+        );
+    }
+}
@@ -9,6 +9,8 @@ pub enum GffFeature {
     FivePrimeUTR,
     CDS,
     ThreePrimeUTR,
+    StartCodon,
+    StopCodon,
 }
 
 impl GffFeature {
@@ -20,6 +22,18 @@ impl GffFeature {
             GffFeature::FivePrimeUTR => "five_prime_UTR",
             GffFeature::CDS => "CDS",
             GffFeature::ThreePrimeUTR => "three_prime_UTR",
+            GffFeature::StartCodon => "start_codon",
+            GffFeature::StopCodon => "stop_codon",
+        }
+    }
+
+    /// Feature name in GTF (2.2) output
+    pub fn as_gtf_str(self) -> &'static str {
+        match self {
+            GffFeature::MRNA => "transcript",
+            GffFeature::FivePrimeUTR => "5UTR",
+            GffFeature::ThreePrimeUTR => "3UTR",
+            _ => self.as_str(),
         }
     }
 }
@@ -30,6 +44,40 @@ impl Display for GffFeature {
     }
 }
 
+/// Output format of a GffWriter. GTF records are written with gene_id / transcript_id attributes, and gene models
+/// include start_codon / stop_codon records, with the stop codon excluded from the CDS
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum GffFormat {
+    Gff3,
+    Gtf,
+}
+
+impl GffFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GffFormat::Gff3 => "gff3",
+            GffFormat::Gtf => "gtf",
+        }
+    }
+
+    pub fn from_name(format: &str) -> Option<GffFormat> {
+        match format.to_ascii_lowercase().as_str() {
+            "gff" | "gff3" => Some(GffFormat::Gff3),
+            "gtf" => Some(GffFormat::Gtf),
+            _ => None,
+        }
+    }
+
+    /// Format implied by the file extension, GTF for .gtf and GFF3 for anything else
+    pub fn from_path(path: &str) -> GffFormat {
+        if path.to_ascii_lowercase().ends_with(".gtf") {
+            GffFormat::Gtf
+        } else {
+            GffFormat::Gff3
+        }
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub enum GffStrand {
     Forward,
@@ -151,6 +199,21 @@ impl GffRecord {
         };
     }
 
+    /// Appends a key=value pair to the attributes
+    pub fn add_attribute(&mut self, key: &str, value: &str) {
+        if !self.attributes.is_empty() {
+            self.attributes.push(';');
+        }
+        self.attributes.push_str(key);
+        self.attributes.push('=');
+        self.attributes.push_str(value);
+    }
+
+    /// Moves the end of the record, e.g. to exclude the stop codon from a CDS
+    pub fn set_end(&mut self, end: u64) {
+        self.end = end;
+    }
+
     pub fn get_sequence(&self) -> &String {
         &self.sequence
     }
@@ -159,6 +222,10 @@ impl GffRecord {
         &self.source
     }
 
+    pub fn get_feature(&self) -> GffFeature {
+        self.feature
+    }
+
     pub fn get_start(&self) -> u64 {
         self.start
     }
@@ -182,18 +249,41 @@ impl GffRecord {
     pub fn get_attributes(&self) -> &String {
         &self.attributes
     }
+
+    /// Value of a key=value attribute, if present
+    pub fn get_attribute(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .split(';')
+            .filter_map(|attribute| attribute.split_once('='))
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| value)
+    }
 }
 
 pub struct GffWriter<W: Write> {
     writer: BufWriter<W>,
+    format: GffFormat,
+    gtf_gene_id: String, // Gene of the most recent gene record, for the gene_id of its transcripts in GTF
 }
 
 impl<W: Write> GffWriter<W> {
     pub fn new(writer: BufWriter<W>) -> GffWriter<W> {
-        GffWriter { writer }
+        GffWriter::new_with_format(writer, GffFormat::Gff3)
     }
 
-    /// Writes header for the top of the GFF3 file
+    pub fn new_with_format(writer: BufWriter<W>, format: GffFormat) -> GffWriter<W> {
+        GffWriter {
+            writer,
+            format,
+            gtf_gene_id: String::new(),
+        }
+    }
+
+    pub fn get_format(&self) -> GffFormat {
+        self.format
+    }
+
+    /// Writes header for the top of the GFF3 (or GTF) file
     /// and optionally includes species name and Helixer model info
     ///
     /// # Arguments
@@ -207,9 +297,13 @@ impl<W: Write> GffWriter<W> {
         helixer_model_md5sum: Option<String>,
     ) -> std::io::Result<()> {
         const GFF_VERSION: &'static str = "3.2.1";
-        write!(self.writer, "##gff-version {}\n", GFF_VERSION)?;
+        const GTF_VERSION: &str = "2.2";
+        match self.format {
+            GffFormat::Gff3 => writeln!(self.writer, "##gff-version {}", GFF_VERSION)?,
+            GffFormat::Gtf => writeln!(self.writer, "#gtf-version {}", GTF_VERSION)?,
+        }
         if let Some(species) = species {
-            write!(self.writer, "##species {}\n", species)?;
+            self.write_species_header(species)?;
         }
         if let Some(helixer_model_md5sum) = helixer_model_md5sum {
             write!(self.writer, "# {}\n", helixer_model_md5sum)?;
@@ -217,7 +311,27 @@ impl<W: Write> GffWriter<W> {
         Ok(())
     }
 
+    /// Writes a species directive, used at the start of each species section when
+    /// several species share one GFF3 file
+    ///
+    /// # Arguments
+    ///
+    /// * `species` - The species name
+    pub fn write_species_header(&mut self, species: &str) -> std::io::Result<()> {
+        writeln!(self.writer, "##species {}", species)
+    }
+
+    /// Writes a single line comment, e.g. to record the settings used to generate the file
+    ///
+    /// # Arguments
+    ///
+    /// * `comment` - The comment text, without the leading '#'
+    pub fn write_comment(&mut self, comment: &str) -> std::io::Result<()> {
+        writeln!(self.writer, "# {}", comment)
+    }
+
     /// Writes header for each region in the GFF3 file indicating the name and boundaries of the region. For Helixer output, regions always start at 1.
+    /// GTF has no region headers, so nothing is written
     ///
     /// # Arguments
     ///
@@ -228,6 +342,10 @@ impl<W: Write> GffWriter<W> {
         sequence_name: &str,
         sequence_length: u64,
     ) -> std::io::Result<()> {
+        if self.format == GffFormat::Gtf {
+            return Ok(());
+        }
+
         let region_info = format!("{} {} {}", sequence_name, 1, sequence_length);
         write!(self.writer, "##sequence-region {}\n", region_info)
     }
@@ -237,21 +355,52 @@ impl<W: Write> GffWriter<W> {
         let strand = rec.strand.map_or(".", |v| v.as_str());
         let phase = rec.phase.map_or(".", |v| v.as_str());
 
+        let (feature, attributes) = match self.format {
+            GffFormat::Gff3 => (rec.feature.as_str(), rec.attributes.clone()),
+            GffFormat::Gtf => (rec.feature.as_gtf_str(), self.get_gtf_attributes(rec)),
+        };
+
         write!(
             self.writer,
             "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
-            rec.sequence,
-            rec.source,
-            rec.feature,
-            rec.start,
-            rec.end,
-            score,
-            strand,
-            phase,
-            rec.attributes
+            rec.sequence, rec.source, feature, rec.start, rec.end, score, strand, phase, attributes
         )
     }
 
+    // Converts GFF3 'key=value' attributes to GTF 'key "value";' attributes. ID and Parent are replaced by the
+    // gene_id and transcript_id, taken from the gene record and the record's mRNA
+    fn get_gtf_attributes(&mut self, rec: &GffRecord) -> String {
+        let mut id = "";
+        let mut parent = "";
+        let mut others = Vec::new();
+
+        for attribute in rec.attributes.split(';').filter(|a| !a.is_empty()) {
+            match attribute.split_once('=') {
+                Some(("ID", value)) => id = value,
+                Some(("Parent", value)) => parent = value,
+                Some((key, value)) => others.push(format!(" {} \"{}\";", key, value)),
+                None => others.push(format!(" {} \"\";", attribute)),
+            }
+        }
+
+        let ids = match rec.feature {
+            GffFeature::Gene => {
+                self.gtf_gene_id = id.to_owned();
+                format!("gene_id \"{}\";", id)
+            }
+            GffFeature::MRNA => format!(
+                "gene_id \"{}\"; transcript_id \"{}\";",
+                self.gtf_gene_id, id
+            ),
+            _ => format!(
+                "gene_id \"{}\"; transcript_id \"{}\";",
+                self.gtf_gene_id, parent
+            ),
+        };
+
+        ids + &others.concat()
+    }
+
     pub fn write_records(&mut self, recs: &[GffRecord]) -> std::io::Result<()> {
         for rec in recs {
             self.write_record(rec)?
@@ -260,3 +409,107 @@ impl<W: Write> GffWriter<W> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::gff::{GffFeature, GffFormat, GffPhase, GffRecord, GffStrand, GffWriter};
+    use std::io::BufWriter;
+
+    fn get_record(feature: GffFeature, start: u64, end: u64, attributes: &str) -> GffRecord {
+        let phase = match feature {
+            GffFeature::CDS | GffFeature::StopCodon => Some(GffPhase::Zero),
+            _ => None,
+        };
+
+        GffRecord::new(
+            "chr1".to_string(),
+            "Helixer".to_string(),
+            feature,
+            start,
+            end,
+            None,
+            Some(GffStrand::Forward),
+            phase,
+            attributes.to_string(),
+        )
+    }
+
+    #[test]
+    fn test_gtf_attributes() {
+        let mut gff_writer = GffWriter::new_with_format(BufWriter::new(Vec::new()), GffFormat::Gtf);
+
+        gff_writer.write_global_header(None, None).unwrap();
+        gff_writer.write_region_header("chr1", 1000).unwrap();
+        for (gene, start) in [("g1", 100), ("g2", 500)] {
+            let transcript = format!("{}.1", gene);
+            gff_writer
+                .write_records(&[
+                    get_record(
+                        GffFeature::Gene,
+                        start,
+                        start + 99,
+                        &format!("ID={};neg_log_prob=1.50", gene),
+                    ),
+                    get_record(
+                        GffFeature::MRNA,
+                        start,
+                        start + 99,
+                        &format!("ID={};Parent={}", transcript, gene),
+                    ),
+                    get_record(
+                        GffFeature::FivePrimeUTR,
+                        start,
+                        start + 9,
+                        &format!("ID={}.five_prime_UTR.1;Parent={}", transcript, transcript),
+                    ),
+                    get_record(
+                        GffFeature::CDS,
+                        start + 10,
+                        start + 96,
+                        &format!("ID={}.CDS.1;Parent={};partial=true", transcript, transcript),
+                    ),
+                    get_record(
+                        GffFeature::StopCodon,
+                        start + 97,
+                        start + 99,
+                        &format!("ID={}.stop_codon;Parent={}", transcript, transcript),
+                    ),
+                ])
+                .unwrap();
+        }
+
+        // ID and Parent become the gene_id and transcript_id, other attributes are kept in GTF form, and there are
+        // no region headers
+        let mut expected = String::from("#gtf-version 2.2\n");
+        for (gene, start) in [("g1", 100), ("g2", 500)] {
+            let ids = format!("gene_id \"{}\"; transcript_id \"{}.1\";", gene, gene);
+            expected.push_str(&format!(
+                "chr1\tHelixer\tgene\t{}\t{}\t.\t+\t.\tgene_id \"{}\"; neg_log_prob \"1.50\";\n\
+                 chr1\tHelixer\ttranscript\t{}\t{}\t.\t+\t.\t{}\n\
+                 chr1\tHelixer\t5UTR\t{}\t{}\t.\t+\t.\t{}\n\
+                 chr1\tHelixer\tCDS\t{}\t{}\t.\t+\t0\t{} partial \"true\";\n\
+                 chr1\tHelixer\tstop_codon\t{}\t{}\t.\t+\t0\t{}\n",
+                start,
+                start + 99,
+                gene,
+                start,
+                start + 99,
+                ids,
+                start,
+                start + 9,
+                ids,
+                start + 10,
+                start + 96,
+                ids,
+                start + 97,
+                start + 99,
+                ids
+            ));
+        }
+
+        assert_eq!(
+            String::from_utf8(gff_writer.writer.into_inner().unwrap()).unwrap(),
+            expected
+        );
+    }
+}
@@ -1,3 +1,7 @@
 pub mod analysis;
+pub mod bed;
+pub mod cli;
+pub mod fasta;
 pub mod gff;
+pub mod logging;
 pub mod results;
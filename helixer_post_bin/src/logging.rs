@@ -0,0 +1,120 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Log levels, from the least to the most detailed. Results (ratings, evaluations and dumps) are printed to stdout,
+/// while the log goes to stderr, or a log file if set
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum LogLevel {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+    Trace = 4,
+}
+
+impl LogLevel {
+    const ALL: [LogLevel; 5] = [
+        LogLevel::Error,
+        LogLevel::Warn,
+        LogLevel::Info,
+        LogLevel::Debug,
+        LogLevel::Trace,
+    ];
+
+    /// Info, moved up or down a level for each given verbose / quiet flag
+    pub fn from_verbosity(verbose: usize, quiet: usize) -> LogLevel {
+        let level = (LogLevel::Info as usize + verbose).saturating_sub(quiet);
+        LogLevel::ALL[std::cmp::min(level, LogLevel::Trace as usize)]
+    }
+
+    // Prefix of each line, with info lines left as they are
+    fn get_prefix(self) -> &'static str {
+        match self {
+            LogLevel::Error => "Error: ",
+            LogLevel::Warn => "Warning: ",
+            LogLevel::Info => "",
+            LogLevel::Debug => "Debug: ",
+            LogLevel::Trace => "Trace: ",
+        }
+    }
+}
+
+static LOG_LEVEL: AtomicUsize = AtomicUsize::new(LogLevel::Info as usize);
+static LOG_FILE: Mutex<Option<BufWriter<File>>> = Mutex::new(None);
+
+pub fn set_level(level: LogLevel) {
+    LOG_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+pub fn is_enabled(level: LogLevel) -> bool {
+    level as usize <= LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Writes the log to the file (replacing it) instead of stderr
+pub fn set_log_file(path: &Path) -> std::io::Result<()> {
+    let file = File::create(path)?;
+    *LOG_FILE.lock().unwrap() = Some(BufWriter::new(file));
+    Ok(())
+}
+
+/// Writes a line to the log, whatever the level. Use the error! / warn! / info! / debug! / trace! macros, which skip
+/// formatting the message if the level is disabled
+pub fn log(level: LogLevel, args: fmt::Arguments) {
+    let mut log_file = LOG_FILE.lock().unwrap();
+
+    // A log which can't be written isn't worth stopping for, so errors are ignored
+    match log_file.as_mut() {
+        Some(writer) => {
+            let _ = writeln!(writer, "{}{}", level.get_prefix(), args);
+            if level <= LogLevel::Warn {
+                let _ = writer.flush();
+            }
+        }
+        None => eprintln!("{}{}", level.get_prefix(), args),
+    }
+}
+
+/// Flushes the log file, if there is one. Called before exiting, as the log file isn't dropped
+pub fn flush() {
+    if let Some(writer) = LOG_FILE.lock().unwrap().as_mut() {
+        let _ = writer.flush();
+    }
+}
+
+#[macro_export]
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::logging::is_enabled($level) {
+            $crate::logging::log($level, format_args!($($arg)+));
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log_at!($crate::logging::LogLevel::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::log_at!($crate::logging::LogLevel::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log_at!($crate::logging::LogLevel::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log_at!($crate::logging::LogLevel::Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::log_at!($crate::logging::LogLevel::Trace, $($arg)+) };
+}
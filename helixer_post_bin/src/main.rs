@@ -1,105 +1,1040 @@
+use helixer_post_bin::analysis::evaluation::FeatureEvaluation;
 use helixer_post_bin::analysis::extractor::{BasePredictionExtractor, ComparisonExtractor};
-use helixer_post_bin::analysis::hmm::show_hmm_config;
+use helixer_post_bin::analysis::fasta_conv::TranscriptFastaWriter;
+use helixer_post_bin::analysis::gene_model::{
+    load_gff_models, models_from_reference, TranscriptModel,
+};
+use helixer_post_bin::analysis::hints::Hints;
+use helixer_post_bin::analysis::hmm::{show_hmm_config, HmmConfig};
+use helixer_post_bin::analysis::progress::ProgressReporter;
 use helixer_post_bin::analysis::rater::SequenceRating;
-use helixer_post_bin::analysis::Analyzer;
-use helixer_post_bin::gff::GffWriter;
-use helixer_post_bin::results::raw::RawHelixerPredictions;
-use helixer_post_bin::results::HelixerResults;
+use helixer_post_bin::analysis::reference::ReferenceAnnotation;
+use helixer_post_bin::analysis::report::{ReportFormat, ReportLevel, ReportStrand, StatsReport};
+use helixer_post_bin::analysis::tracks::{export_tracks, TrackFormat};
+use helixer_post_bin::analysis::trainer::Trainer;
+use helixer_post_bin::analysis::{Analyzer, AnnotationOutputs, RegionSettings};
+use helixer_post_bin::bed::{BedWriter, RegionBedWriter};
+use helixer_post_bin::cli::{CommandSpec, OptionSpec, ParsedArgs};
+use helixer_post_bin::fasta::FastaWriter;
+use helixer_post_bin::gff::{GffFormat, GffWriter};
+use helixer_post_bin::logging::{self, LogLevel};
+use helixer_post_bin::results::{Error, ErrorKind, HelixerResults, Sequence};
+use helixer_post_bin::{error, info, warn};
+use std::fmt::Display;
 use std::fs::File;
-use std::io::BufWriter;
-use std::path::Path;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::time::Duration;
 
-fn main() {
-    let arg_vec = std::env::args().collect::<Vec<_>>(); // Arg iterator into vector
+const SPECIES_PLACEHOLDER: &str = "{species}";
+
+// An I/O error creating or writing an output file, with the file
+fn output_error(err: std::io::Error, filename: &str) -> Error {
+    Error::new(ErrorKind::Io(err)).with_file(Path::new(filename))
+}
+
+fn create_output_file(filename: &str) -> Result<File, Error> {
+    File::create(filename).map_err(|err| output_error(err, filename))
+}
+
+fn create_gff_writer(gff_filename: &str, format: GffFormat) -> Result<GffWriter<File>, Error> {
+    let gff_file = create_output_file(gff_filename)?;
+    Ok(GffWriter::new_with_format(BufWriter::new(gff_file), format))
+}
+
+fn create_fasta_writer(fasta_filename: &str) -> Result<FastaWriter<File>, Error> {
+    let fasta_file = create_output_file(fasta_filename)?;
+    Ok(FastaWriter::new(BufWriter::new(fasta_file)))
+}
+
+fn create_bed_writer(bed_filename: &str) -> Result<BedWriter<File>, Error> {
+    let bed_file = create_output_file(bed_filename)?;
+    Ok(BedWriter::new(BufWriter::new(bed_file)))
+}
+
+fn create_region_writer(
+    regions_filename: &str,
+    include_rejected: bool,
+) -> Result<RegionBedWriter<File>, Error> {
+    let regions_file = create_output_file(regions_filename)?;
+    Ok(RegionBedWriter::new(
+        BufWriter::new(regions_file),
+        include_rejected,
+    ))
+}
+
+const PROGRAM: &str = "HelixerPost";
+
+const INPUT_OPTIONS: &[OptionSpec] = &[
+    OptionSpec::required(
+        "genome",
+        "<genome.h5>",
+        "HDF5 genome used as input to Helixer",
+    ),
+    OptionSpec::required(
+        "predictions",
+        "<predictions.h5>",
+        "HDF5 base level predictions from Helixer",
+    ),
+];
+
+const WINDOW_OPTIONS: &[OptionSpec] = &[OptionSpec::optional(
+    "window-size",
+    "<bases>",
+    Some("100"),
+    "Bases averaged by the sliding window for genic detection",
+)];
+
+const REGION_OPTIONS: &[OptionSpec] = &[
+    OptionSpec::optional(
+        "edge-threshold",
+        "<0-1>",
+        Some("0.1"),
+        "Window genic mean at which candidate regions start and end",
+    ),
+    OptionSpec::optional(
+        "peak-threshold",
+        "<0-1>",
+        Some("0.8"),
+        "Window genic mean which a candidate region must reach",
+    ),
+    OptionSpec::optional(
+        "min-coding-length",
+        "<bases>",
+        Some("60"),
+        "Minimum coding length of the genes written",
+    ),
+    OptionSpec::optional(
+        "threads",
+        "<threads>",
+        Some("1"),
+        "Threads solving candidate regions, 0 for all available cores",
+    ),
+];
+
+const HMM_OPTIONS: &[OptionSpec] = &[
+    OptionSpec::optional(
+        "hmm-config",
+        "<config.toml|config.json>",
+        None,
+        "HMM parameters overriding the defaults",
+    ),
+    OptionSpec::repeated(
+        "hmm-set",
+        "<key=value>",
+        "A single HMM parameter, applied after --hmm-config. \
+         GFF scores are opt-in with posterior_scores=true",
+    ),
+];
+
+const HINTS_OPTIONS: &[OptionSpec] = &[OptionSpec::repeated(
+    "hints",
+    "<hints.bed|hints.gff>",
+    "Extrinsic evidence for the HMM",
+)];
+
+const ANNOTATE_OUTPUT_OPTIONS: &[OptionSpec] = &[
+    OptionSpec::required(
+        "output",
+        "<output.gff|gtf>",
+        "Gene models, one file per species if the name contains {species}",
+    ),
+    OptionSpec::optional(
+        "output-format",
+        "<gff3|gtf>",
+        None,
+        "Gene model format, by default from the --output extension",
+    ),
+    OptionSpec::optional("cds-fasta", "<cds.fa>", None, "Coding sequence of each transcript"),
+    OptionSpec::optional("cdna-fasta", "<cdna.fa>", None, "Spliced transcript sequence of each transcript"),
+    OptionSpec::optional("protein-fasta", "<protein.fa>", None, "Protein sequence of each transcript"),
+    OptionSpec::optional("bed", "<genes.bed>", None, "BED12 line for each transcript, sorted"),
+    OptionSpec::optional("regions", "<regions.bed>", None, "Candidate regions, with their peak and gene count"),
+    OptionSpec::flag("include-rejected", "Also write the regions rejected by --peak-threshold to --regions"),
+    OptionSpec::optional(
+        "stats",
+        "<stats.json|stats.tsv>",
+        None,
+        "Base level ratings of each sequence, species and the total",
+    ),
+    OptionSpec::optional(
+        "stats-format",
+        "<json|tsv>",
+        None,
+        "Stats format, by default from the --stats extension",
+    ),
+    OptionSpec::flag(
+        "skip-failed-windows",
+        "Log and skip windows without an HMM solution, and strands with unreadable data, instead of stopping",
+    ),
+];
+
+const PROGRESS_OPTIONS: &[OptionSpec] = &[
+    OptionSpec::optional(
+        "progress-file",
+        "<progress.json>",
+        None,
+        "Progress as JSON, rewritten at each --progress-interval, for job monitoring",
+    ),
+    OptionSpec::optional(
+        "progress-interval",
+        "<seconds>",
+        Some("60"),
+        "Seconds between progress reports",
+    ),
+];
+
+const REFERENCE_OPTIONS: &[OptionSpec] = &[OptionSpec::optional(
+    "reference",
+    "<reference.gff|gtf>",
+    None,
+    "Reference annotation rated against, in place of data/y in the genome",
+)];
+
+const TRAIN_OPTIONS: &[OptionSpec] = &[
+    OptionSpec::required("output", "<trained_config.toml>", "Trained HMM config"),
+    OptionSpec::optional(
+        "train-bases",
+        "<bases>",
+        Some("10000000"),
+        "Limit on the bases of the training windows, 0 for no limit",
+    ),
+    OptionSpec::optional(
+        "train-rounds",
+        "<rounds>",
+        Some("4"),
+        "Rounds of coordinate search",
+    ),
+];
+
+const TRACK_OPTIONS: &[OptionSpec] = &[
+    OptionSpec::required(
+        "output-prefix",
+        "<output_prefix>",
+        "Tracks are written to <output_prefix>.<track>.<strand>.<format>",
+    ),
+    OptionSpec::optional(
+        "track-format",
+        "<bedgraph|wig>",
+        Some("bedgraph"),
+        "Track format",
+    ),
+    OptionSpec::optional(
+        "track-precision",
+        "<digits>",
+        Some("3"),
+        "Decimal places of track values",
+    ),
+];
+
+const EVALUATE_OPTIONS: &[OptionSpec] = &[
+    OptionSpec::required("predicted", "<predicted.gff|gtf>", "Predicted gene models"),
+    OptionSpec::optional(
+        "reference",
+        "<reference.gff|gtf>",
+        None,
+        "Reference gene models",
+    ),
+    OptionSpec::optional(
+        "genome",
+        "<genome.h5>",
+        None,
+        "Genome with data/y, to rebuild the reference models from, in place of --reference",
+    ),
+    OptionSpec::optional(
+        "predictions",
+        "<predictions.h5>",
+        None,
+        "Predictions, needed with --genome",
+    ),
+];
+
+const LOG_OPTIONS: &[OptionSpec] = &[
+    OptionSpec::counted(
+        "verbose",
+        'v',
+        "Log more detail: each window, or with -vv each HMM region",
+    ),
+    OptionSpec::counted(
+        "quiet",
+        'q',
+        "Log less: only warnings and errors, or with -qq only errors",
+    ),
+    OptionSpec::optional(
+        "log-file",
+        "<run.log>",
+        None,
+        "Write the log to a file instead of stderr",
+    ),
+];
+
+const ANNOTATE_COMMAND: CommandSpec = CommandSpec {
+    name: "annotate",
+    about: "Predicts gene models from the Helixer base level predictions",
+    positional: &[
+        "genome",
+        "predictions",
+        "window-size",
+        "edge-threshold",
+        "peak-threshold",
+        "min-coding-length",
+        "output",
+        "threads",
+    ],
+    option_groups: &[
+        INPUT_OPTIONS,
+        WINDOW_OPTIONS,
+        REGION_OPTIONS,
+        ANNOTATE_OUTPUT_OPTIONS,
+        PROGRESS_OPTIONS,
+        HMM_OPTIONS,
+        HINTS_OPTIONS,
+        REFERENCE_OPTIONS,
+        LOG_OPTIONS,
+    ],
+};
+
+const TRAIN_COMMAND: CommandSpec = CommandSpec {
+    name: "train",
+    about: "Trains the HMM parameters against a reference annotation",
+    positional: &[
+        "genome",
+        "predictions",
+        "window-size",
+        "edge-threshold",
+        "peak-threshold",
+        "min-coding-length",
+        "output",
+        "threads",
+    ],
+    option_groups: &[
+        INPUT_OPTIONS,
+        WINDOW_OPTIONS,
+        REGION_OPTIONS,
+        TRAIN_OPTIONS,
+        HMM_OPTIONS,
+        REFERENCE_OPTIONS,
+        LOG_OPTIONS,
+    ],
+};
 
-    if arg_vec.len() != 8 {
-        println!("HelixerPost <genome.h5> <predictions.h5> <windowSize> <edgeThresh> <peakThresh> <minCodingLength> <gff>");
-        exit(1);
+const EVALUATE_COMMAND: CommandSpec = CommandSpec {
+    name: "evaluate",
+    about: "Compares predicted gene models with a reference at the feature level",
+    positional: &["predicted", "reference"],
+    option_groups: &[EVALUATE_OPTIONS, LOG_OPTIONS],
+};
+
+const EXPORT_TRACKS_COMMAND: CommandSpec = CommandSpec {
+    name: "export-tracks",
+    about: "Writes the base level predictions as bedGraph / WIG tracks",
+    positional: &["genome", "predictions", "window-size", "output-prefix"],
+    option_groups: &[INPUT_OPTIONS, WINDOW_OPTIONS, TRACK_OPTIONS, LOG_OPTIONS],
+};
+
+const INDEX_DUMP_COMMAND: CommandSpec = CommandSpec {
+    name: "index-dump",
+    about: "Lists the species, sequences and blocks of the genome",
+    positional: &["genome", "predictions"],
+    option_groups: &[INPUT_OPTIONS, LOG_OPTIONS],
+};
+
+const COMMANDS: [&CommandSpec; 5] = [
+    &ANNOTATE_COMMAND,
+    &TRAIN_COMMAND,
+    &EVALUATE_COMMAND,
+    &EXPORT_TRACKS_COMMAND,
+    &INDEX_DUMP_COMMAND,
+];
+
+fn show_usage() {
+    println!(
+        "{} - Gene models from Helixer base level predictions",
+        PROGRAM
+    );
+    println!();
+    println!("Usage: {} <command> [options]", PROGRAM);
+    println!(
+        "       {} <genome.h5> <predictions.h5> <window_size> <edge_thresh> <peak_thresh> <min_coding_length> <output.gff> [threads]",
+        PROGRAM
+    );
+    println!();
+    println!("Commands:");
+    for command in COMMANDS.iter() {
+        println!("  {:16}{}", command.name, command.about);
     }
+    println!();
+    println!(
+        "Run '{} <command> --help' for the options of each command",
+        PROGRAM
+    );
+}
+
+// Prints a command line error with a pointer to the help, and exits
+fn exit_with_usage_error(command: &CommandSpec, err: &str) -> ! {
+    error!("{}", err);
+    eprintln!("Usage: {}", command.get_usage(PROGRAM));
+    eprintln!("Run '{} {} --help' for all options", PROGRAM, command.name);
+    logging::flush();
+    exit(1);
+}
 
-    let genome_path = arg_vec[1].as_str();
-    let predictions_path = arg_vec[2].as_str();
-    let window_size = arg_vec[3].parse().unwrap();
-    let edge_threshold = arg_vec[4].parse().unwrap();
-    let peak_threshold = arg_vec[5].parse().unwrap();
-    let min_coding_length = arg_vec[6].parse().unwrap();
-    let gff_filename = &arg_vec[7];
+// For errors once the arguments have been checked
+fn exit_with_error(err: impl Display) -> ! {
+    error!("{}", err);
+    logging::flush();
+    exit(1);
+}
 
-    let helixer_res = HelixerResults::new(predictions_path.as_ref(), genome_path.as_ref())
-        .expect("Failed to open input files");
+// Sets the log level from the -v / -q flags, and the log file if given
+fn setup_logging(args: &ParsedArgs) {
+    logging::set_level(LogLevel::from_verbosity(
+        args.get_count("verbose"),
+        args.get_count("quiet"),
+    ));
+
+    if let Some(log_path) = args.get_str("log-file") {
+        if let Err(err) = logging::set_log_file(log_path.as_ref()) {
+            exit_with_error(format_args!(
+                "Could not create log file {}: {}",
+                log_path, err
+            ));
+        }
+    }
+}
+
+fn parse_command(command: &CommandSpec, args: &[String]) -> ParsedArgs {
+    match command.parse(args) {
+        Ok(Some(parsed_args)) => parsed_args,
+        Ok(None) => {
+            command.show_help(PROGRAM);
+            exit(0);
+        }
+        Err(err) => exit_with_usage_error(command, &err),
+    }
+}
+
+fn get_threads(args: &ParsedArgs) -> Result<usize, String> {
+    Ok(match args.get_required("threads")? {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        threads => threads,
+    })
+}
+
+fn get_window_size(args: &ParsedArgs) -> Result<usize, String> {
+    match args.get_required("window-size")? {
+        0 => Err("--window-size must be at least 1".to_string()),
+        window_size => Ok(window_size),
+    }
+}
+
+fn get_progress_interval(args: &ParsedArgs) -> Result<Duration, String> {
+    match args.get_required("progress-interval")? {
+        0 => Err("--progress-interval must be at least 1".to_string()),
+        seconds => Ok(Duration::from_secs(seconds)),
+    }
+}
 
-    let bp_extractor = BasePredictionExtractor::new_from_prediction(&helixer_res)
-        .expect("Failed to open Base / ClassPrediction / PhasePrediction Datasets");
+fn get_threshold(args: &ParsedArgs, name: &str) -> Result<f32, String> {
+    let threshold: f32 = args.get_required(name)?;
+    if (0.0..=1.0).contains(&threshold) {
+        Ok(threshold)
+    } else {
+        Err(format!("--{} must be between 0 and 1", name))
+    }
+}
+
+// An output file can be created if its directory exists and it is not a directory itself. A {species} placeholder
+// in the directory is only known once the species are read, so not checked
+fn check_output_path(name: &str, path: &str) -> Result<(), String> {
+    let path = Path::new(path);
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+
+    if path.is_dir() {
+        Err(format!("--{} {} is a directory", name, path.display()))
+    } else if !directory.is_dir() && !directory.to_string_lossy().contains(SPECIES_PLACEHOLDER) {
+        Err(format!(
+            "--{} {}: directory {} does not exist",
+            name,
+            path.display(),
+            directory.display()
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+// Checks the named input files can be read and the named output files created, before any work is done
+fn check_paths(args: &ParsedArgs, inputs: &[&str], outputs: &[&str]) -> Result<(), String> {
+    for name in inputs {
+        for path in args.get_all(name) {
+            File::open(path).map_err(|err| format!("Cannot read --{} {}: {}", name, path, err))?;
+        }
+    }
+
+    for name in outputs {
+        for path in args.get_all(name) {
+            check_output_path(name, path)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn get_region_settings(args: &ParsedArgs) -> Result<RegionSettings, String> {
+    let settings = RegionSettings {
+        window_size: get_window_size(args)?,
+        edge_threshold: get_threshold(args, "edge-threshold")?,
+        peak_threshold: get_threshold(args, "peak-threshold")?,
+        min_coding_length: args.get_required("min-coding-length")?,
+        threads: get_threads(args)?,
+    };
+
+    if settings.peak_threshold < settings.edge_threshold {
+        return Err("--peak-threshold must be at least --edge-threshold".to_string());
+    }
+
+    Ok(settings)
+}
+
+// Loads the HMM config, applying the config file first, followed by any individual values in the order given
+fn load_hmm_config(args: &ParsedArgs) -> HmmConfig {
+    let mut hmm_config = HmmConfig::default();
+
+    if let Some(config_path) = args.get_str("hmm-config") {
+        if let Err(err) = hmm_config.update_from_file(config_path.as_ref()) {
+            exit_with_error(format_args!(
+                "Could not load HMM config {}: {}",
+                config_path, err
+            ));
+        }
+    }
+
+    for key_value in args.get_all("hmm-set") {
+        if let Err(err) = hmm_config.set_key_value(key_value) {
+            exit_with_error(err);
+        }
+    }
+
+    hmm_config
+}
+
+// Loads the hints files in the order given
+fn load_hints(args: &ParsedArgs) -> Hints {
+    let mut hints = Hints::new();
+
+    for hints_path in args.get_all("hints") {
+        match hints.load_file(hints_path.as_ref()) {
+            Ok(count) => info!("Loaded {} hints from {}", count, hints_path),
+            Err(err) => {
+                exit_with_error(format_args!("Could not load hints {}: {}", hints_path, err));
+            }
+        }
+    }
+
+    if !hints.is_empty() {
+        let kind_counts: Vec<String> = hints
+            .get_kind_counts()
+            .iter()
+            .map(|(kind, count)| format!("{} {}", kind.as_str(), count))
+            .collect();
+        info!("Hints: {}", kind_counts.join(", "));
+    }
+
+    hints
+}
+
+// Loads the reference annotation, if given
+fn load_reference_annotation(args: &ParsedArgs) -> Option<ReferenceAnnotation> {
+    args.get_str("reference").map(|reference_path| {
+        match ReferenceAnnotation::load_file(reference_path.as_ref()) {
+            Ok(reference_annotation) => {
+                info!(
+                    "Loaded {} coding reference transcripts from {}",
+                    reference_annotation.get_transcript_count(),
+                    reference_path
+                );
+                reference_annotation
+            }
+            Err(err) => {
+                exit_with_error(format_args!(
+                    "Could not load reference {}: {}",
+                    reference_path, err
+                ));
+            }
+        }
+    })
+}
+
+fn open_results(genome_path: &str, predictions_path: &str) -> HelixerResults {
+    HelixerResults::new(predictions_path.as_ref(), genome_path.as_ref())
+        .unwrap_or_else(|err| exit_with_error(err))
+}
+
+fn create_prediction_extractor(
+    helixer_res: &HelixerResults,
+) -> BasePredictionExtractor<'_, f32, f32> {
+    BasePredictionExtractor::new_from_prediction(helixer_res)
+        .unwrap_or_else(|err| exit_with_error(err))
+}
+
+// Compares with the reference annotation if given, otherwise any reference in the genome file
+fn create_comparison_extractor(
+    helixer_res: &HelixerResults,
+    reference_annotation: Option<ReferenceAnnotation>,
+) -> ComparisonExtractor<'_> {
+    match reference_annotation {
+        Some(reference_annotation) => {
+            ComparisonExtractor::new_with_reference(helixer_res, reference_annotation)
+        }
+        None => ComparisonExtractor::new(helixer_res),
+    }
+    .unwrap_or_else(|err| exit_with_error(err))
+}
+
+// Outputs besides the GFF, and the GFF format if given
+struct OutputOptions {
+    format: Option<GffFormat>,
+    cds_fasta: Option<String>,
+    cdna_fasta: Option<String>,
+    protein_fasta: Option<String>,
+    bed: Option<String>,
+    regions: Option<String>,
+    include_rejected: bool,
+    stats: Option<String>,
+    stats_format: Option<ReportFormat>,
+}
+
+fn get_output_options(args: &ParsedArgs) -> Result<OutputOptions, String> {
+    let format = match args.get_str("output-format") {
+        Some(format) => Some(GffFormat::from_name(format).ok_or_else(|| {
+            format!("Unknown --output-format '{}', expected gff3 or gtf", format)
+        })?),
+        None => None,
+    };
+
+    let stats_format =
+        match args.get_str("stats-format") {
+            Some(format) => Some(ReportFormat::from_name(format).ok_or_else(|| {
+                format!("Unknown --stats-format '{}', expected json or tsv", format)
+            })?),
+            None => None,
+        };
+
+    let include_rejected = args.get_flag("include-rejected");
+    if include_rejected && args.get_str("regions").is_none() {
+        return Err("--include-rejected needs --regions".to_string());
+    }
+
+    Ok(OutputOptions {
+        format,
+        cds_fasta: args.get_str("cds-fasta").map(|v| v.to_string()),
+        cdna_fasta: args.get_str("cdna-fasta").map(|v| v.to_string()),
+        protein_fasta: args.get_str("protein-fasta").map(|v| v.to_string()),
+        bed: args.get_str("bed").map(|v| v.to_string()),
+        regions: args.get_str("regions").map(|v| v.to_string()),
+        include_rejected,
+        stats: args.get_str("stats").map(|v| v.to_string()),
+        stats_format,
+    })
+}
+
+// Trains the HMM parameters against the reference annotation in the genome file, starting from the given config,
+// and writes the best config found
+fn train(args: &ParsedArgs) -> Result<(), String> {
+    let genome_path: String = args.get_required("genome")?;
+    let predictions_path: String = args.get_required("predictions")?;
+    let settings = get_region_settings(args)?;
+    let config_filename: String = args.get_required("output")?;
+    let train_bases = args.get_required("train-bases")?;
+    let train_rounds = args.get_required("train-rounds")?;
+    check_paths(
+        args,
+        &["genome", "predictions", "hmm-config", "reference"],
+        &["output"],
+    )?;
+
+    let hmm_config = load_hmm_config(args);
+    let reference_annotation = load_reference_annotation(args);
+
+    let helixer_res = open_results(&genome_path, &predictions_path);
+
+    let bp_extractor = create_prediction_extractor(&helixer_res);
+    let comp_extractor = create_comparison_extractor(&helixer_res, reference_annotation);
+
+    if !comp_extractor.has_ref() {
+        exit_with_error(format_args!("Training needs the reference annotation (data/y and data/phases) in {}, or --reference", genome_path));
+    }
+
+    show_hmm_config(&hmm_config);
+
+    let trainer = Trainer::new(
+        &helixer_res,
+        &bp_extractor,
+        &comp_extractor,
+        settings,
+        train_bases,
+    )
+    .unwrap_or_else(|err| {
+        exit_with_error(err);
+    });
+    info!(
+        "Training on {} windows ({}bp)",
+        trainer.get_window_count(),
+        trainer.get_total_bases()
+    );
+
+    let (trained_config, coding_f1) = trainer.train(&hmm_config, train_rounds);
+
+    info!("Trained coding F1 {:.5}", coding_f1);
+    show_hmm_config(&trained_config);
+
+    let mut config_file =
+        create_output_file(&config_filename).unwrap_or_else(|err| exit_with_error(err));
+    write!(
+        config_file,
+        "# HelixerPost HMM config, trained on {} windows ({}bp) with coding F1 {:.5}\n{}",
+        trainer.get_window_count(),
+        trainer.get_total_bases(),
+        coding_f1,
+        trained_config.to_toml()
+    )
+    .unwrap_or_else(|err| exit_with_error(output_error(err, &config_filename)));
+
+    Ok(())
+}
+
+// Writes the per base predictions and window genic mean as bedGraph / WIG tracks, for viewing next to the models
+fn export_prediction_tracks(args: &ParsedArgs) -> Result<(), String> {
+    let genome_path: String = args.get_required("genome")?;
+    let predictions_path: String = args.get_required("predictions")?;
+    let window_size = get_window_size(args)?;
+    let prefix: String = args.get_required("output-prefix")?;
+    let precision = args.get_required("track-precision")?;
+    let format = args
+        .get_str("track-format")
+        .and_then(TrackFormat::from_name)
+        .ok_or_else(|| "Unknown --track-format, expected bedgraph or wig".to_string())?;
+    check_paths(args, &["genome", "predictions"], &["output-prefix"])?;
+
+    let helixer_res = open_results(&genome_path, &predictions_path);
+    let bp_extractor = create_prediction_extractor(&helixer_res);
+
+    match export_tracks(
+        &helixer_res,
+        &bp_extractor,
+        window_size,
+        &prefix,
+        format,
+        precision,
+    ) {
+        Ok(filenames) => {
+            for filename in filenames {
+                info!("Wrote {}", filename);
+            }
+        }
+        Err(err) => {
+            exit_with_error(format_args!("Could not write tracks: {}", err));
+        }
+    }
+
+    Ok(())
+}
+
+// Lists the species, sequences and blocks found in the genome, to check the inputs match
+fn dump_index(args: &ParsedArgs) -> Result<(), String> {
+    let genome_path: String = args.get_required("genome")?;
+    let predictions_path: String = args.get_required("predictions")?;
+    check_paths(args, &["genome", "predictions"], &[])?;
+
+    let helixer_res = open_results(&genome_path, &predictions_path);
+    helixer_res.get_index().dump();
+
+    Ok(())
+}
+
+fn load_models(gff_filename: &str) -> Vec<TranscriptModel> {
+    load_gff_models(gff_filename.as_ref()).unwrap_or_else(|err| {
+        exit_with_error(format_args!(
+            "Could not load gene models {}: {}",
+            gff_filename, err
+        ));
+    })
+}
+
+// Rebuilds the reference gene models from the reference annotation (data/y) in the genome file
+fn rebuild_reference_models(genome_path: &str, predictions_path: &str) -> Vec<TranscriptModel> {
+    let helixer_res = open_results(genome_path, predictions_path);
+    let comp_extractor = create_comparison_extractor(&helixer_res, None);
+
+    if !comp_extractor.has_ref() {
+        exit_with_error(format_args!(
+            "Evaluation needs the reference annotation (data/y and data/phases) in {}",
+            genome_path
+        ));
+    }
+
+    let mut reference = Vec::new();
+    for species in helixer_res.get_all_species() {
+        for seq_id in helixer_res.get_sequences_for_species(species.get_id()) {
+            let seq = helixer_res.get_sequence_by_id(*seq_id);
+
+            for rev in [false, true] {
+                let mut comp_iter = if rev {
+                    comp_extractor.rev_iterator(*seq_id)
+                } else {
+                    comp_extractor.fwd_iterator(*seq_id)
+                };
+
+                reference.extend(models_from_reference(
+                    &mut comp_iter,
+                    seq.get_name(),
+                    rev,
+                    seq.get_length(),
+                ));
+
+                if let Some(err) = comp_iter.take_error() {
+                    exit_with_error(err);
+                }
+            }
+        }
+    }
+
+    reference
+}
+
+// Compares predicted gene models with a reference GFF / GTF, or with the models rebuilt from the reference
+// annotation (data/y) in the genome file
+fn evaluate(args: &ParsedArgs) -> Result<(), String> {
+    let predicted_filename: String = args.get_required("predicted")?;
+    check_paths(
+        args,
+        &["predicted", "reference", "genome", "predictions"],
+        &[],
+    )?;
+
+    let reference = match (
+        args.get_str("reference"),
+        args.get_str("genome"),
+        args.get_str("predictions"),
+    ) {
+        (Some(reference_filename), None, None) => load_models(reference_filename),
+        (None, Some(genome_path), Some(predictions_path)) => {
+            rebuild_reference_models(genome_path, predictions_path)
+        }
+        _ => {
+            return Err(
+                "Expected either a reference GFF / GTF, or --genome and --predictions".to_string(),
+            )
+        }
+    };
+    let predicted = load_models(&predicted_filename);
+
+    info!(
+        "Evaluating {} predicted transcripts against {} reference transcripts",
+        predicted.len(),
+        reference.len()
+    );
+    FeatureEvaluation::evaluate(&reference, &predicted).dump();
+
+    Ok(())
+}
+
+// Predicts the gene models for each sequence of each species, writing them and any other requested outputs
+fn annotate(args: &ParsedArgs) -> Result<(), String> {
+    let genome_path: String = args.get_required("genome")?;
+    let predictions_path: String = args.get_required("predictions")?;
+    let settings = get_region_settings(args)?;
+    let gff_filename: String = args.get_required("output")?;
+    let output_options = get_output_options(args)?;
+    let progress_interval = get_progress_interval(args)?;
+    check_paths(
+        args,
+        &["genome", "predictions", "hmm-config", "hints", "reference"],
+        &[
+            "output",
+            "cds-fasta",
+            "cdna-fasta",
+            "protein-fasta",
+            "bed",
+            "regions",
+            "stats",
+            "progress-file",
+        ],
+    )?;
+
+    let hmm_config = load_hmm_config(args);
+    let hints = load_hints(args);
+    let reference_annotation = load_reference_annotation(args);
+
+    let genome_path = genome_path.as_str();
+    let predictions_path = predictions_path.as_str();
+    let gff_filename = &gff_filename;
+    // GTF if requested, or if the output file ends in .gtf
+    let format = output_options
+        .format
+        .unwrap_or_else(|| GffFormat::from_path(gff_filename));
+
+    let helixer_res = open_results(genome_path, predictions_path);
+
+    let bp_extractor = create_prediction_extractor(&helixer_res);
     //let bp_extractor = BasePredictionExtractor::new_from_pseudo_predictions(&helixer_res).expect("Failed to open Base / ClassPrediction / PhasePrediction Datasets");
 
-    let comp_extractor = ComparisonExtractor::new(&helixer_res).expect("Failed to open ClassReference / PhaseReference / ClassPrediction / PhasePrediction Datasets");
+    let comp_extractor = create_comparison_extractor(&helixer_res, reference_annotation);
 
     let analyzer = Analyzer::new(
         bp_extractor,
         comp_extractor,
-        window_size,
-        edge_threshold,
-        peak_threshold,
-        min_coding_length,
+        settings,
+        hmm_config.clone(),
+        hints,
+        args.get_flag("skip-failed-windows"),
     );
 
     let mut total_count = 0;
     let mut total_length = 0;
 
-    show_hmm_config();
+    show_hmm_config(&hmm_config);
+    info!("Output: {} ({})", gff_filename, format.as_str());
 
-    let gff_file = File::create(gff_filename).unwrap();
-    let mut gff_writer = GffWriter::new(BufWriter::new(gff_file));
+    let mut progress = ProgressReporter::new(
+        helixer_res.get_index(),
+        progress_interval,
+        args.get_str("progress-file").map(PathBuf::from),
+    );
+    progress.start();
+    let hmm_config_comment = format!("HMM config: {}", hmm_config);
+
+    let model_md5sum = helixer_res.get_raw_predictions().get_model_md5sum().ok();
 
-    // There should only ever be one species for the gff output
-    assert_eq!(
-        helixer_res.get_all_species().len(),
-        1,
-        "Error: Multiple Species are not allowed for GFF output."
+    // A '{species}' placeholder in the output path gives one GFF per species,
+    // otherwise all species share one GFF with a '##species' section for each
+    let per_species_output = gff_filename.contains(SPECIES_PLACEHOLDER);
+    let multiple_species = helixer_res.get_all_species().len() > 1;
+
+    // Sequences for all species share each FASTA file
+    let create_optional_fasta_writer = |fasta_filename: &Option<String>| {
+        fasta_filename
+            .as_deref()
+            .map(create_fasta_writer)
+            .transpose()
+            .unwrap_or_else(|err| exit_with_error(err))
+    };
+    let mut fasta_writer = TranscriptFastaWriter::new(
+        create_optional_fasta_writer(&output_options.cds_fasta),
+        create_optional_fasta_writer(&output_options.cdna_fasta),
+        create_optional_fasta_writer(&output_options.protein_fasta),
     );
-    let rhg = RawHelixerPredictions::new(&Path::new(predictions_path))
-        .expect("Error: Something went wrong while accessing the predictions.");
-    let model_md5sum = rhg.get_model_md5sum().ok();
-    let species_name = helixer_res.get_all_species().first().map(|x| x.get_name());
-
-    gff_writer
-        .write_global_header(species_name, model_md5sum)
-        .expect(&*format!(
-            "Error: Could not write header to file {}.",
-            gff_filename
-        ));
+
+    // BED output is sorted, so written once all sequences are done
+    let mut bed_writer = output_options
+        .bed
+        .as_deref()
+        .map(create_bed_writer)
+        .transpose()
+        .unwrap_or_else(|err| exit_with_error(err));
+    let mut region_writer = output_options
+        .regions
+        .as_deref()
+        .map(|regions_filename| {
+            create_region_writer(regions_filename, output_options.include_rejected)
+        })
+        .transpose()
+        .unwrap_or_else(|err| exit_with_error(err));
+
+    // The stats report is written once all sequences are rated
+    let mut stats_report = output_options
+        .stats
+        .as_ref()
+        .map(|_| StatsReport::new(analyzer.has_ref()));
+    let mut total_fwd_rating = SequenceRating::new();
+    let mut total_rev_rating = SequenceRating::new();
+
+    let mut combined_gff_writer = if per_species_output {
+        None
+    } else {
+        let mut gff_writer =
+            create_gff_writer(gff_filename, format).unwrap_or_else(|err| exit_with_error(err));
+
+        // Single species files keep the species in the global header
+        let species_name = if multiple_species {
+            None
+        } else {
+            helixer_res.get_all_species().first().map(|x| x.get_name())
+        };
+
+        gff_writer
+            .write_global_header(species_name, model_md5sum.clone())
+            .and_then(|_| gff_writer.write_comment(&hmm_config_comment))
+            .unwrap_or_else(|err| exit_with_error(output_error(err, gff_filename)));
+
+        Some(gff_writer)
+    };
 
     for species in helixer_res.get_all_species() {
         let mut fwd_species_rating = SequenceRating::new();
         let mut rev_species_rating = SequenceRating::new();
 
         let id = species.get_id();
-        println!(
+        info!(
             "Sequences for Species {} - {}",
             species.get_name(),
             id.inner()
         );
-        for seq_id in helixer_res.get_sequences_for_species(id) {
-            let seq = helixer_res.get_sequence_by_id(*seq_id);
+
+        let species_gff_filename = gff_filename.replace(SPECIES_PLACEHOLDER, species.get_name());
+        let mut species_gff_writer;
+
+        let gff_writer = if let Some(gff_writer) = combined_gff_writer.as_mut() {
+            if multiple_species {
+                gff_writer
+                    .write_species_header(species.get_name())
+                    .unwrap_or_else(|err| exit_with_error(output_error(err, gff_filename)));
+            }
             gff_writer
-                .write_region_header(seq.get_name(), seq.get_length())
-                .expect(&*format!(
-                    "Error: Could not write sequence-region header to file {}.",
-                    gff_filename
-                ));
+        } else {
+            species_gff_writer = create_gff_writer(&species_gff_filename, format)
+                .unwrap_or_else(|err| exit_with_error(err));
+            species_gff_writer
+                .write_global_header(Some(species.get_name()), model_md5sum.clone())
+                .and_then(|_| species_gff_writer.write_comment(&hmm_config_comment))
+                .unwrap_or_else(|err| exit_with_error(output_error(err, &species_gff_filename)));
+            &mut species_gff_writer
+        };
+
+        let seqs: Vec<&Sequence> = helixer_res
+            .get_sequences_for_species(id)
+            .iter()
+            .map(|seq_id| helixer_res.get_sequence_by_id(*seq_id))
+            .collect();
 
-            let (count, length) = analyzer.process_sequence(
+        let mut outputs = AnnotationOutputs {
+            gff_writer,
+            fasta_writer: &mut fasta_writer,
+            bed_writer: &mut bed_writer,
+            region_writer: &mut region_writer,
+            stats_report: &mut stats_report,
+        };
+        let (count, length) = analyzer
+            .process_sequences(
                 species,
-                seq,
+                &seqs,
                 &mut fwd_species_rating,
                 &mut rev_species_rating,
-                &mut gff_writer,
-            );
+                &mut outputs,
+                &mut progress,
+            )
+            .unwrap_or_else(|err| {
+                progress.fail();
+                exit_with_error(err);
+            });
 
-            total_count += count;
-            total_length += length;
-        }
+        total_count += count;
+        total_length += length;
 
         println!(
             "Forward for Species {} - {}",
@@ -121,7 +1056,122 @@ fn main() {
 
         println!("Total for Species {} - {}", species.get_name(), id.inner());
         species_rating.dump(analyzer.has_ref());
+
+        if let Some(stats_report) = stats_report.as_mut() {
+            for (strand, rating) in [
+                (ReportStrand::Forward, &fwd_species_rating),
+                (ReportStrand::Reverse, &rev_species_rating),
+                (ReportStrand::Both, &species_rating),
+            ] {
+                stats_report.add_rating(
+                    ReportLevel::Species,
+                    Some(species.get_name()),
+                    None,
+                    strand,
+                    rating,
+                );
+            }
+        }
+
+        total_fwd_rating.accumulate(&fwd_species_rating);
+        total_rev_rating.accumulate(&rev_species_rating);
+    }
+
+    if let (Some(bed_writer), Some(bed_filename)) =
+        (bed_writer.as_mut(), output_options.bed.as_ref())
+    {
+        bed_writer
+            .finish()
+            .unwrap_or_else(|err| exit_with_error(output_error(err, bed_filename)));
+    }
+
+    if let (Some(region_writer), Some(regions_filename)) =
+        (region_writer.as_mut(), output_options.regions.as_ref())
+    {
+        region_writer
+            .finish()
+            .unwrap_or_else(|err| exit_with_error(output_error(err, regions_filename)));
+    }
+
+    if let (Some(stats_report), Some(stats_filename)) =
+        (stats_report.as_mut(), output_options.stats.as_ref())
+    {
+        let mut total_rating = SequenceRating::new();
+        total_rating.accumulate(&total_fwd_rating);
+        total_rating.accumulate(&total_rev_rating);
+
+        for (strand, rating) in [
+            (ReportStrand::Forward, &total_fwd_rating),
+            (ReportStrand::Reverse, &total_rev_rating),
+            (ReportStrand::Both, &total_rating),
+        ] {
+            stats_report.add_rating(ReportLevel::Total, None, None, strand, rating);
+        }
+
+        // JSON if requested, or if the stats file ends in .json
+        let stats_format = output_options
+            .stats_format
+            .unwrap_or_else(|| ReportFormat::from_path(stats_filename));
+        let stats_file =
+            create_output_file(stats_filename).unwrap_or_else(|err| exit_with_error(err));
+        let mut stats_writer = BufWriter::new(stats_file);
+        stats_report
+            .write(&mut stats_writer, stats_format)
+            .and_then(|_| stats_writer.flush())
+            .unwrap_or_else(|err| exit_with_error(output_error(err, stats_filename)));
+        info!("Stats: {} ({})", stats_filename, stats_format.as_str());
+    }
+
+    progress.finish();
+
+    info!("Total: {}bp across {} windows", total_length, total_count);
+
+    if analyzer.get_skipped_count() > 0 {
+        warn!(
+            "Skipped {} failed windows / strands",
+            analyzer.get_skipped_count()
+        );
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let arg_vec: Vec<String> = std::env::args().collect(); // Arg iterator into vector
+
+    let first_arg = match arg_vec.get(1) {
+        Some(first_arg) => first_arg.as_str(),
+        None => {
+            show_usage();
+            exit(1);
+        }
+    };
+
+    if first_arg == "help" || first_arg == "-h" || first_arg == "--help" {
+        show_usage();
+        return;
+    }
+
+    // Without a command, the arguments are for annotate, as in the original positional command line
+    let (command, command_args) = match COMMANDS.iter().find(|command| command.name == first_arg) {
+        Some(command) => (*command, &arg_vec[2..]),
+        None => (&ANNOTATE_COMMAND, &arg_vec[1..]),
+    };
+
+    let args = parse_command(command, command_args);
+    setup_logging(&args);
+
+    let result = match command.name {
+        "train" => train(&args),
+        "evaluate" => evaluate(&args),
+        "export-tracks" => export_prediction_tracks(&args),
+        "index-dump" => dump_index(&args),
+        _ => annotate(&args),
+    };
+
+    if let Err(err) = result {
+        exit_with_usage_error(command, &err);
     }
 
-    println!("Total: {}bp across {} windows", total_length, total_count);
+    logging::flush();
 }
@@ -6,7 +6,7 @@ pub mod index;
 pub mod iter;
 pub mod raw;
 
-pub use crate::results::error::Error;
+pub use crate::results::error::{DataLocation, Error, ErrorKind};
 pub type Result<T> = std::result::Result<T, Error>;
 
 use self::index::HelixerIndex;
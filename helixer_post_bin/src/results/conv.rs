@@ -151,6 +151,15 @@ impl Bases {
     pub fn get(&self) -> &[f32; 4] {
         &self.values
     }
+
+    /// The base as a letter, 'N' unless one base is more likely than not
+    pub fn get_letter(&self) -> u8 {
+        b"CATG"
+            .iter()
+            .zip(self.values.iter())
+            .find(|(_, value)| **value > 0.5)
+            .map_or(b'N', |(base, _)| *base)
+    }
 }
 
 impl ArrayConvFrom<f32> for Bases {
@@ -178,11 +187,19 @@ impl ArrayConvFrom<i8> for Transitions {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct PhaseReference {
     values: [i8; 4], // Ordering is Non-Coding, Phase 0, Phase 1, Phase2
 }
 
 impl PhaseReference {
+    /// One hot reference for the given index
+    pub fn from_idx(idx: usize) -> Self {
+        let mut values: [i8; 4] = [0; 4];
+        values[idx] = i8::MAX;
+        PhaseReference { values }
+    }
+
     pub fn get(&self) -> &[i8; 4] {
         &self.values
     }
@@ -216,11 +233,19 @@ impl ArrayConvFrom<i8> for PhaseReference {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct ClassReference {
     values: [i8; 4], // Ordering is intergenic, utr, coding, intron
 }
 
 impl ClassReference {
+    /// One hot reference for the given index
+    pub fn from_idx(idx: usize) -> Self {
+        let mut values: [i8; 4] = [0; 4];
+        values[idx] = i8::MAX;
+        ClassReference { values }
+    }
+
     pub fn get(&self) -> &[i8; 4] {
         &self.values
     }
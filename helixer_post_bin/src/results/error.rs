@@ -1,6 +1,52 @@
-use std::fmt::{self, Debug, Display};
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
 
-pub enum Error {
+/// Where in the data an error was found, as the sequence, strand and base position along the strand (from the
+/// sequence end for the reverse strand, as the windows are)
+#[derive(Clone, Debug)]
+pub struct DataLocation {
+    sequence: String,
+    rev: bool,
+    position: usize,
+}
+
+impl DataLocation {
+    pub fn new(sequence: &str, rev: bool, position: usize) -> DataLocation {
+        DataLocation {
+            sequence: sequence.to_string(),
+            rev,
+            position,
+        }
+    }
+
+    pub fn get_sequence(&self) -> &str {
+        &self.sequence
+    }
+
+    pub fn get_rev(&self) -> bool {
+        self.rev
+    }
+
+    pub fn get_position(&self) -> usize {
+        self.position
+    }
+}
+
+impl Display for DataLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({} strand) at {}",
+            self.sequence,
+            if self.rev { "-" } else { "+" },
+            self.position
+        )
+    }
+}
+
+/// What went wrong, with any underlying HDF5 / I/O error. Dimension, count and size mismatches are found, expected
+#[derive(Debug)]
+pub enum ErrorKind {
     Hdf5(hdf5::Error),
     MismatchedDimensions(usize, usize),
     MismatchedBlockCount(usize, usize),
@@ -8,78 +54,152 @@ pub enum Error {
     MismatchedDataSize(usize, usize),
     InvalidValue(String),
     DuplicateValue(String),
+    BlockRead(hdf5::Error),
+    TruncatedDataset,   // The datasets which ended early are given as the dataset
+    HmmFailure(usize),  // Length of the window, which starts at the location
+    Io(std::io::Error), // Writing the output for the window at the location
 }
 
-impl Display for Error {
+impl Display for ErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Error::Hdf5(err) => Display::fmt(err, f),
-            Error::MismatchedDimensions(found, expected) => write!(
+            ErrorKind::Hdf5(err) => Display::fmt(err, f),
+            ErrorKind::MismatchedDimensions(found, expected) => write!(
                 f,
                 "Mismatched Dimensions Found {}, expected {}",
                 found, expected
             ),
-            Error::MismatchedBlockCount(found, expected) => write!(
+            ErrorKind::MismatchedBlockCount(found, expected) => write!(
                 f,
                 "Mismatched Block Count: Found {}, expected {}",
                 found, expected
             ),
-            Error::MismatchedBlockSize(found, expected) => write!(
+            ErrorKind::MismatchedBlockSize(found, expected) => write!(
                 f,
                 "Mismatched Block Size: Found {}, expected {}",
                 found, expected
             ),
-            Error::MismatchedDataSize(found, expected) => write!(
+            ErrorKind::MismatchedDataSize(found, expected) => write!(
                 f,
                 "Mismatched Data Size: Found {}, expected {}",
                 found, expected
             ),
-            Error::InvalidValue(msg) => write!(f, "Invalid Value: {}", msg),
-            Error::DuplicateValue(msg) => write!(f, "Duplicate Value: {}", msg),
+            ErrorKind::InvalidValue(msg) => write!(f, "Invalid Value: {}", msg),
+            ErrorKind::DuplicateValue(msg) => write!(f, "Duplicate Value: {}", msg),
+            ErrorKind::BlockRead(err) => write!(f, "Failed to read block: {}", err),
+            ErrorKind::TruncatedDataset => write!(f, "Truncated Dataset: Ended early"),
+            ErrorKind::HmmFailure(length) => {
+                write!(
+                    f,
+                    "HMM Failure: No solution for window (length: {})",
+                    length
+                )
+            }
+            ErrorKind::Io(err) => write!(f, "I/O Error: {}", err),
         }
     }
 }
 
-impl Debug for Error {
+/// An error reading or analysing the Helixer results, with the HDF5 file, dataset and location in the data it was
+/// found at where known. Context is added from the inside out, so the first given is kept
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    file: Option<PathBuf>,
+    dataset: Option<String>,
+    location: Option<DataLocation>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Error {
+        Error {
+            kind,
+            file: None,
+            dataset: None,
+            location: None,
+        }
+    }
+
+    pub fn with_file(mut self, file: &Path) -> Error {
+        self.file.get_or_insert_with(|| file.to_path_buf());
+        self
+    }
+
+    pub fn with_dataset(mut self, dataset: &str) -> Error {
+        self.dataset.get_or_insert_with(|| dataset.to_string());
+        self
+    }
+
+    /// Adds the dataset's path and the name of the file holding it
+    pub fn with_hdf5_dataset(self, dataset: &hdf5::Dataset) -> Error {
+        self.with_dataset(&dataset.name())
+            .with_file(Path::new(&dataset.filename()))
+    }
+
+    pub fn with_location(mut self, location: DataLocation) -> Error {
+        self.location.get_or_insert(location);
+        self
+    }
+
+    pub fn get_kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    pub fn get_file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    pub fn get_dataset(&self) -> Option<&str> {
+        self.dataset.as_deref()
+    }
+
+    pub fn get_location(&self) -> Option<&DataLocation> {
+        self.location.as_ref()
+    }
+}
+
+impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Error::Hdf5(err) => Display::fmt(err, f),
-            Error::MismatchedDimensions(found, expected) => write!(
-                f,
-                "Mismatched Dimensions Found {}, expected {}",
-                found, expected
-            ),
-            Error::MismatchedBlockCount(found, expected) => write!(
-                f,
-                "Mismatched Block Count: Found {}, expected {}",
-                found, expected
-            ),
-            Error::MismatchedBlockSize(found, expected) => write!(
-                f,
-                "Mismatched Block Size: Found {}, expected {}",
-                found, expected
-            ),
-            Error::MismatchedDataSize(found, expected) => write!(
-                f,
-                "Mismatched Data Size: Found {}, expected {}",
-                found, expected
-            ),
-            Error::InvalidValue(msg) => write!(f, "Invalid Value: {}", msg),
-            Error::DuplicateValue(msg) => write!(f, "Duplicate Value: {}", msg),
+        Display::fmt(&self.kind, f)?;
+
+        if let Some(dataset) = &self.dataset {
+            write!(f, " in {}", dataset)?;
+        }
+        if let Some(file) = &self.file {
+            write!(f, " of {}", file.display())?;
         }
+        if let Some(location) = &self.location {
+            write!(f, ", {}", location)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::Hdf5(err) | ErrorKind::BlockRead(err) => Some(err),
+            ErrorKind::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self::new(kind)
     }
 }
 
 impl From<hdf5::Error> for Error {
     fn from(hdf5_error: hdf5::Error) -> Self {
-        Self::Hdf5(hdf5_error)
+        Self::new(ErrorKind::Hdf5(hdf5_error))
     }
 }
 
 impl From<&str> for Error {
     fn from(msg: &str) -> Self {
-        Self::InvalidValue(msg.to_string())
+        Self::new(ErrorKind::InvalidValue(msg.to_string()))
     }
 }
-
-impl Error {}
@@ -1,6 +1,7 @@
 use std::collections::{BTreeMap, HashMap};
 use std::fmt::Write;
 
+use super::error::ErrorKind;
 use super::{Error, Result};
 
 use super::raw::RawHelixerGenome;
@@ -24,7 +25,11 @@ impl HelixerIndex {
         let all_sequences = genome.get_seqids()?;
         let all_startends = genome.get_start_ends()?;
 
-        Self::build_from_slices(&all_species, &all_sequences, &all_startends)
+        // The blocks are checked, so any error is in their start / ends
+        Self::build_from_slices(&all_species, &all_sequences, &all_startends).map_err(|err| {
+            err.with_dataset("data/start_ends")
+                .with_file(&genome.get_path())
+        })
     }
 
     fn build_from_slices(
@@ -101,7 +106,7 @@ impl HelixerIndex {
                         start, block_id.0, prev_id.0
                     )
                     .unwrap();
-                    return Err(Error::DuplicateValue(msg));
+                    return Err(Error::new(ErrorKind::DuplicateValue(msg)));
                 }
             } else if start > end {
                 if let Some(prev_id) = sequence_blocks_trees[sequence_id.0]
@@ -115,7 +120,7 @@ impl HelixerIndex {
                         end, block_id.0, prev_id.0
                     )
                     .unwrap();
-                    return Err(Error::DuplicateValue(msg));
+                    return Err(Error::new(ErrorKind::DuplicateValue(msg)));
                 }
             } else {
                 return Err("Zero length block".into());
@@ -171,7 +176,7 @@ impl HelixerIndex {
                         prev, curr.0, id.0
                     )
                     .unwrap();
-                    return Err(Error::InvalidValue(msg));
+                    return Err(Error::new(ErrorKind::InvalidValue(msg)));
                 }
                 maybe_prev = Some(curr.1);
             }
@@ -5,7 +5,7 @@ use ndarray::{s, Array1, Array2, ArrayView1, Axis};
 
 use super::HelixerIndex;
 use crate::results::conv::ArrayConvInto;
-use crate::results::SequenceID;
+use crate::results::{Error, ErrorKind, SequenceID};
 use std::marker::PhantomData;
 use std::ops::Range;
 
@@ -60,6 +60,7 @@ pub struct BlockedDataset1DIter<'a, T: H5Type + Clone + Copy> {
     block_iter: std::slice::Iter<'a, BlockID>,
 
     block: Option<(BlockID, Array1<T>, Range<usize>)>,
+    error: Option<hdf5::Error>, // Ends the iteration, see take_error
 }
 
 impl<'a, T: H5Type + Clone + Copy> BlockedDataset1DIter<'a, T> {
@@ -74,26 +75,41 @@ impl<'a, T: H5Type + Clone + Copy> BlockedDataset1DIter<'a, T> {
             block_offsets,
             block_iter,
             block: None,
+            error: None,
         };
         iter.block = iter.next_block();
         iter
     }
 
+    /// The error reading a block which ended the iteration early, if any
+    pub fn take_error(&mut self) -> Option<Error> {
+        let dataset = self.blocked_dataset.get_dataset();
+        self.error
+            .take()
+            .map(|err| Error::new(ErrorKind::BlockRead(err)).with_hdf5_dataset(dataset))
+    }
+
+    pub fn get_dataset(&self) -> &Dataset {
+        self.blocked_dataset.get_dataset()
+    }
+
     fn next_block(&mut self) -> Option<(BlockID, Array1<T>, Range<usize>)> {
-        self.block_iter.next().map(|id| {
-            let data = self
-                .blocked_dataset
-                .get_data_for_block(*id)
-                .expect("Failed to read block");
-            let (start, end) = self.block_offsets[id.inner()];
-            let length = if start < end {
-                end - start
-            } else {
-                start - end
-            } as usize;
-
-            (*id, data, 0..length)
-        })
+        let id = self.block_iter.next()?;
+        let data = match self.blocked_dataset.get_data_for_block(*id) {
+            Ok(data) => data,
+            Err(err) => {
+                self.error = Some(err);
+                return None;
+            }
+        };
+        let (start, end) = self.block_offsets[id.inner()];
+        let length = if start < end {
+            end - start
+        } else {
+            start - end
+        } as usize;
+
+        Some((*id, data, 0..length))
     }
 
     fn next_position(&mut self) -> (bool, Option<usize>) {
@@ -174,6 +190,7 @@ pub struct BlockedDataset2DIter<'a, T: ArrayConvInto<O>, O> {
     block_iter: std::slice::Iter<'a, BlockID>,
 
     block: Option<(BlockID, Array2<T>, Range<usize>)>,
+    error: Option<hdf5::Error>, // Ends the iteration, see take_error
 }
 
 impl<'a, T: ArrayConvInto<O>, O> BlockedDataset2DIter<'a, T, O> {
@@ -188,26 +205,41 @@ impl<'a, T: ArrayConvInto<O>, O> BlockedDataset2DIter<'a, T, O> {
             block_offsets,
             block_iter,
             block: None,
+            error: None,
         };
         iter.block = iter.next_block();
         iter
     }
 
+    /// The error reading a block which ended the iteration early, if any
+    pub fn take_error(&mut self) -> Option<Error> {
+        let dataset = self.blocked_dataset.get_dataset();
+        self.error
+            .take()
+            .map(|err| Error::new(ErrorKind::BlockRead(err)).with_hdf5_dataset(dataset))
+    }
+
+    pub fn get_dataset(&self) -> &Dataset {
+        self.blocked_dataset.get_dataset()
+    }
+
     fn next_block(&mut self) -> Option<(BlockID, Array2<T>, Range<usize>)> {
-        self.block_iter.next().map(|id| {
-            let data = self
-                .blocked_dataset
-                .get_data_for_block(*id)
-                .expect("Failed to read block");
-            let (start, end) = self.block_offsets[id.inner()];
-            let length = if start < end {
-                end - start
-            } else {
-                start - end
-            } as usize;
-
-            (*id, data, 0..length)
-        })
+        let id = self.block_iter.next()?;
+        let data = match self.blocked_dataset.get_data_for_block(*id) {
+            Ok(data) => data,
+            Err(err) => {
+                self.error = Some(err);
+                return None;
+            }
+        };
+        let (start, end) = self.block_offsets[id.inner()];
+        let length = if start < end {
+            end - start
+        } else {
+            start - end
+        } as usize;
+
+        Some((*id, data, 0..length))
     }
 
     fn next_position(&mut self) -> (bool, Option<usize>) {
@@ -3,3 +3,12 @@ mod predictions;
 
 pub use genome::RawHelixerGenome;
 pub use predictions::RawHelixerPredictions;
+
+use super::error::ErrorKind;
+use super::Error;
+use hdf5::Dataset;
+
+// Shape mismatch, with the dataset's name and file
+fn shape_error(dataset: &Dataset, kind: ErrorKind) -> Error {
+    Error::new(kind).with_hdf5_dataset(dataset)
+}
@@ -1,7 +1,9 @@
+use super::super::error::ErrorKind;
 use super::super::{Error, Result};
+use super::shape_error;
 use hdf5::types::FixedAscii;
 use hdf5::{Dataset, File};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 //use ndarray::iter::Iter;
 
@@ -37,7 +39,8 @@ impl RawHelixerGenome {
         blocks: usize,
         blocksize: usize,
     ) -> Result<RawHelixerGenome> {
-        let genome_file = File::open(genome_file_path)?;
+        let genome_file = File::open(genome_file_path)
+            .map_err(|err| Error::from(err).with_file(genome_file_path))?;
         Ok(RawHelixerGenome {
             genome_file,
             blocks,
@@ -45,15 +48,34 @@ impl RawHelixerGenome {
         })
     }
 
+    pub fn get_path(&self) -> PathBuf {
+        PathBuf::from(self.genome_file.filename())
+    }
+
+    // Opens a dataset, with its name and the file in any error
+    fn get_dataset(&self, name: &str) -> Result<Dataset> {
+        self.genome_file.dataset(name).map_err(|err| {
+            Error::from(err)
+                .with_dataset(name)
+                .with_file(&self.get_path())
+        })
+    }
+
     // Should be 1D - [Blocks]
     fn validate_dataset_shape_scalar(&self, dataset: &Dataset) -> Result<()> {
         let shape = dataset.shape();
         if shape.len() != 1 {
-            return Err(Error::MismatchedDimensions(shape.len(), 1));
+            return Err(shape_error(
+                dataset,
+                ErrorKind::MismatchedDimensions(shape.len(), 1),
+            ));
         }
 
         if shape[0] != self.blocks {
-            return Err(Error::MismatchedBlockCount(shape[0], self.blocks));
+            return Err(shape_error(
+                dataset,
+                ErrorKind::MismatchedBlockCount(shape[0], self.blocks),
+            ));
         }
 
         Ok(())
@@ -63,15 +85,24 @@ impl RawHelixerGenome {
     fn validate_dataset_shape_array(&self, dataset: &Dataset, data_size: usize) -> Result<()> {
         let shape = dataset.shape();
         if shape.len() != 2 {
-            return Err(Error::MismatchedDimensions(shape.len(), 2));
+            return Err(shape_error(
+                dataset,
+                ErrorKind::MismatchedDimensions(shape.len(), 2),
+            ));
         }
 
         if shape[0] != self.blocks {
-            return Err(Error::MismatchedBlockCount(shape[0], self.blocks));
+            return Err(shape_error(
+                dataset,
+                ErrorKind::MismatchedBlockCount(shape[0], self.blocks),
+            ));
         }
 
         if shape[1] != data_size {
-            return Err(Error::MismatchedDataSize(shape[2], data_size));
+            return Err(shape_error(
+                dataset,
+                ErrorKind::MismatchedDataSize(shape[1], data_size),
+            ));
         }
 
         Ok(())
@@ -81,15 +112,24 @@ impl RawHelixerGenome {
     fn validate_dataset_shape_blocksize_scalar(&self, dataset: &Dataset) -> Result<()> {
         let shape = dataset.shape();
         if shape.len() != 2 {
-            return Err(Error::MismatchedDimensions(shape.len(), 2));
+            return Err(shape_error(
+                dataset,
+                ErrorKind::MismatchedDimensions(shape.len(), 2),
+            ));
         }
 
         if shape[0] != self.blocks {
-            return Err(Error::MismatchedBlockCount(shape[0], self.blocks));
+            return Err(shape_error(
+                dataset,
+                ErrorKind::MismatchedBlockCount(shape[0], self.blocks),
+            ));
         }
 
         if shape[1] != self.blocksize {
-            return Err(Error::MismatchedBlockSize(shape[1], self.blocksize));
+            return Err(shape_error(
+                dataset,
+                ErrorKind::MismatchedBlockSize(shape[1], self.blocksize),
+            ));
         }
 
         Ok(())
@@ -103,39 +143,53 @@ impl RawHelixerGenome {
     ) -> Result<()> {
         let shape = dataset.shape();
         if shape.len() != 3 {
-            return Err(Error::MismatchedDimensions(shape.len(), 3));
+            return Err(shape_error(
+                dataset,
+                ErrorKind::MismatchedDimensions(shape.len(), 3),
+            ));
         }
 
         if shape[0] != self.blocks {
-            return Err(Error::MismatchedBlockCount(shape[0], self.blocks));
+            return Err(shape_error(
+                dataset,
+                ErrorKind::MismatchedBlockCount(shape[0], self.blocks),
+            ));
         }
 
         if shape[1] != self.blocksize {
-            return Err(Error::MismatchedBlockSize(shape[1], self.blocksize));
+            return Err(shape_error(
+                dataset,
+                ErrorKind::MismatchedBlockSize(shape[1], self.blocksize),
+            ));
         }
 
         if shape[2] != data_size {
-            return Err(Error::MismatchedDataSize(shape[2], data_size));
+            return Err(shape_error(
+                dataset,
+                ErrorKind::MismatchedDataSize(shape[2], data_size),
+            ));
         }
 
         Ok(())
     }
 
     pub fn get_x_raw(&self) -> Result<Dataset> {
-        let dataset = self.genome_file.dataset("data/X")?;
+        let dataset = self.get_dataset("data/X")?;
         self.validate_dataset_shape_blocksize_array(&dataset, X_DATASIZE)?;
         Ok(dataset)
     }
 
     pub fn get_err_samples_raw(&self) -> Result<Dataset> {
-        let dataset = self.genome_file.dataset("data/err_samples")?;
+        let dataset = self.get_dataset("data/err_samples")?;
         self.validate_dataset_shape_scalar(&dataset)?;
         Ok(dataset)
     }
 
     pub fn get_err_samples(&self) -> Result<Vec<bool>> {
         let err_samples_ds = self.get_err_samples_raw()?;
-        let err_samples_array = err_samples_ds.read_1d::<bool>()?;
+        let err_samples_array = err_samples_ds
+            .read_1d::<bool>()
+            .map_err(|err| Error::from(err).with_hdf5_dataset(&err_samples_ds))?;
         Ok(err_samples_array.to_vec())
 
         //      let err_samples_array = err_samples_ds.read_1d::<ErrSamples>()?;
@@ -143,34 +197,38 @@ impl RawHelixerGenome {
     }
 
     pub fn get_fully_intergenic_samples_raw(&self) -> Result<Dataset> {
-        let dataset = self.genome_file.dataset("data/fully_intergenic_samples")?;
+        let dataset = self.get_dataset("data/fully_intergenic_samples")?;
         self.validate_dataset_shape_scalar(&dataset)?;
         Ok(dataset)
     }
 
     pub fn get_fully_intergenic_samples(&self) -> Result<Vec<bool>> {
         let fully_ig_samples_ds = self.get_fully_intergenic_samples_raw()?;
-        let fully_ig_samples_array = fully_ig_samples_ds.read_1d::<bool>()?;
+        let fully_ig_samples_array = fully_ig_samples_ds
+            .read_1d::<bool>()
+            .map_err(|err| Error::from(err).with_hdf5_dataset(&fully_ig_samples_ds))?;
         Ok(fully_ig_samples_array.to_vec())
     }
 
     // For pre-annotated
     pub fn get_gene_lengths_raw(&self) -> Result<Dataset> {
-        let dataset = self.genome_file.dataset("data/gene_lengths")?;
+        let dataset = self.get_dataset("data/gene_lengths")?;
         self.validate_dataset_shape_blocksize_scalar(&dataset)?;
         Ok(dataset)
     }
 
     // For pre-annotated
     pub fn get_is_annotated_raw(&self) -> Result<Dataset> {
-        let dataset = self.genome_file.dataset("data/is_annotated")?;
+        let dataset = self.get_dataset("data/is_annotated")?;
         self.validate_dataset_shape_scalar(&dataset)?;
         Ok(dataset)
     }
 
     pub fn get_is_annotated(&self) -> Result<Vec<bool>> {
         let is_annotated_ds = self.get_is_annotated_raw()?;
-        let is_annotated_array = is_annotated_ds.read_1d::<bool>()?;
+        let is_annotated_array = is_annotated_ds
+            .read_1d::<bool>()
+            .map_err(|err| Error::from(err).with_hdf5_dataset(&is_annotated_ds))?;
         Ok(is_annotated_array.to_vec())
     }
 
@@ -180,52 +238,58 @@ impl RawHelixerGenome {
             return Ok(None);
         }
 
-        let dataset = self.genome_file.dataset("data/phases")?;
+        let dataset = self.get_dataset("data/phases")?;
         self.validate_dataset_shape_blocksize_array(&dataset, PHASE_DATASIZE)?;
         Ok(Some(dataset))
     }
 
     pub fn get_sample_weights_raw(&self) -> Result<Dataset> {
-        let dataset = self.genome_file.dataset("data/sample_weights")?;
+        let dataset = self.get_dataset("data/sample_weights")?;
         self.validate_dataset_shape_blocksize_scalar(&dataset)?;
         Ok(dataset)
     }
 
     pub fn get_seqids_raw(&self) -> Result<Dataset> {
-        let dataset = self.genome_file.dataset("data/seqids")?;
+        let dataset = self.get_dataset("data/seqids")?;
         self.validate_dataset_shape_scalar(&dataset)?;
         Ok(dataset)
     }
 
     pub fn get_seqids(&self) -> Result<Vec<String>> {
         let seqids_ds = self.get_seqids_raw()?;
-        let seqids_array = seqids_ds.read_1d::<SeqidsType>()?;
+        let seqids_array = seqids_ds
+            .read_1d::<SeqidsType>()
+            .map_err(|err| Error::from(err).with_hdf5_dataset(&seqids_ds))?;
 
         Ok(seqids_array.iter().map(|x| x.to_string()).collect())
     }
 
     pub fn get_species_raw(&self) -> Result<Dataset> {
-        let dataset = self.genome_file.dataset("data/species")?;
+        let dataset = self.get_dataset("data/species")?;
         self.validate_dataset_shape_scalar(&dataset)?;
         Ok(dataset)
     }
 
     pub fn get_species(&self) -> Result<Vec<String>> {
         let species_ds = self.get_species_raw()?;
-        let species_array = species_ds.read_1d::<SpeciesType>()?;
+        let species_array = species_ds
+            .read_1d::<SpeciesType>()
+            .map_err(|err| Error::from(err).with_hdf5_dataset(&species_ds))?;
 
         Ok(species_array.iter().map(|x| x.to_string()).collect())
     }
 
     pub fn get_start_ends_raw(&self) -> Result<Dataset> {
-        let dataset = self.genome_file.dataset("data/start_ends")?;
+        let dataset = self.get_dataset("data/start_ends")?;
         self.validate_dataset_shape_array(&dataset, STARTENDS_DATASIZE)?;
         Ok(dataset)
     }
 
     pub fn get_start_ends(&self) -> Result<Vec<(u64, u64)>> {
         let startends_ds = self.get_start_ends_raw()?;
-        let startends_array = startends_ds.read_2d::<i64>()?;
+        let startends_array = startends_ds
+            .read_2d::<i64>()
+            .map_err(|err| Error::from(err).with_hdf5_dataset(&startends_ds))?;
 
         Ok(startends_array
             .outer_iter()
@@ -235,7 +299,7 @@ impl RawHelixerGenome {
 
     // For pre-annotated
     pub fn get_transitions_raw(&self) -> Result<Dataset> {
-        let dataset = self.genome_file.dataset("data/transitions")?;
+        let dataset = self.get_dataset("data/transitions")?;
         self.validate_dataset_shape_blocksize_array(&dataset, TRANSITIONS_DATASIZE)?;
         Ok(dataset)
     }
@@ -246,7 +310,7 @@ impl RawHelixerGenome {
             return Ok(None);
         }
 
-        let dataset = self.genome_file.dataset("data/y")?;
+        let dataset = self.get_dataset("data/y")?;
         self.validate_dataset_shape_blocksize_array(&dataset, Y_DATASIZE)?;
         Ok(Some(dataset))
     }
@@ -1,6 +1,8 @@
+use super::super::error::ErrorKind;
 use super::super::{Error, Result};
+use super::shape_error;
 use hdf5::{types::VarLenUnicode, Dataset, File};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 pub struct RawHelixerPredictions {
     predictions_file: File,
@@ -11,51 +13,90 @@ const PHASE_DATASIZE: usize = 4;
 
 impl RawHelixerPredictions {
     pub fn new(predictions_file_path: &Path) -> Result<RawHelixerPredictions> {
-        let predictions_file = File::open(predictions_file_path)?;
+        let predictions_file = File::open(predictions_file_path)
+            .map_err(|err| Error::from(err).with_file(predictions_file_path))?;
         Ok(RawHelixerPredictions { predictions_file })
     }
 
+    pub fn get_path(&self) -> PathBuf {
+        PathBuf::from(self.predictions_file.filename())
+    }
+
+    // Opens a dataset, with its name and the file in any error
+    fn get_dataset(&self, name: &str) -> Result<Dataset> {
+        self.predictions_file.dataset(name).map_err(|err| {
+            Error::from(err)
+                .with_dataset(name)
+                .with_file(&self.get_path())
+        })
+    }
+
     pub fn get_model_md5sum(&self) -> Result<String> {
-        let attr = self.predictions_file.attr("model_md5sum")?;
-        let r: VarLenUnicode = attr.as_reader().read_scalar()?;
+        let r: VarLenUnicode = self
+            .predictions_file
+            .attr("model_md5sum")
+            .and_then(|attr| attr.as_reader().read_scalar())
+            .map_err(|err| {
+                Error::from(err)
+                    .with_dataset("model_md5sum")
+                    .with_file(&self.get_path())
+            })?;
         Ok(r.to_string())
     }
 
     pub fn get_class_raw(&self) -> Result<Dataset> {
-        let pred_dataset = self.predictions_file.dataset("predictions")?;
+        let pred_dataset = self.get_dataset("predictions")?;
 
         let shape = pred_dataset.shape();
         if shape.len() != 3 {
-            return Err(Error::MismatchedDimensions(shape.len(), 3));
+            return Err(shape_error(
+                &pred_dataset,
+                ErrorKind::MismatchedDimensions(shape.len(), 3),
+            ));
         }
 
         if shape[2] != CLASS_DATASIZE {
-            return Err(Error::MismatchedDataSize(shape[2], CLASS_DATASIZE));
+            return Err(shape_error(
+                &pred_dataset,
+                ErrorKind::MismatchedDataSize(shape[2], CLASS_DATASIZE),
+            ));
         }
 
         Ok(pred_dataset)
     }
 
     pub fn get_phase_raw(&self) -> Result<Dataset> {
-        let phase_dataset = self.predictions_file.dataset("predictions_phase")?;
+        let phase_dataset = self.get_dataset("predictions_phase")?;
 
         let shape = phase_dataset.shape();
         if shape.len() != 3 {
-            return Err(Error::MismatchedDimensions(shape.len(), 3));
+            return Err(shape_error(
+                &phase_dataset,
+                ErrorKind::MismatchedDimensions(shape.len(), 3),
+            ));
         }
 
         let (blocks, blocksize) = self.get_blocks_and_blocksize()?;
 
         if shape[0] != blocks {
-            return Err(Error::MismatchedBlockCount(shape[0], blocks));
+            return Err(shape_error(
+                &phase_dataset,
+                ErrorKind::MismatchedBlockCount(shape[0], blocks),
+            ));
         }
 
         if shape[1] != blocksize {
-            return Err(Error::MismatchedDataSize(shape[1], blocksize));
+            return Err(shape_error(
+                &phase_dataset,
+                ErrorKind::MismatchedBlockSize(shape[1], blocksize),
+            ));
         }
 
         if shape[2] != PHASE_DATASIZE {
-            return Err(Error::MismatchedDataSize(shape[2], PHASE_DATASIZE));
+            return Err(shape_error(
+                &phase_dataset,
+                ErrorKind::MismatchedDataSize(shape[2], PHASE_DATASIZE),
+            ));
         }
 
         Ok(phase_dataset)